@@ -0,0 +1,49 @@
+//! The `asm` and `disasm` subcommands: converts between raw STP data and its text form.
+//!
+//! See `stp_core::asm` for the syntax.
+
+use crate::convert::{parse_id, unframe};
+use crate::{get_input, CliError, Result};
+use clap::ArgMatches;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::result;
+use stp_core::asm;
+
+pub fn asm(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let text = read_input(sub_m)?;
+    let bytes = asm::assemble_bytes(&String::from_utf8_lossy(&text))
+        .map_err(|e| CliError(Some(format!("{}", e))))?;
+    write_output(sub_m, &bytes)
+}
+
+pub fn disasm(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let data = read_input(sub_m)?;
+    let data = match sub_m.value_of("from").unwrap() {
+        "twp" => {
+            let id = match sub_m.value_of("id") {
+                Some(id) => Some(parse_id(id)?),
+                None => None,
+            };
+            unframe(sub_m, &data, id)?
+        }
+        _ => data,
+    };
+    write_output(sub_m, asm::disassemble_bytes(&data).as_bytes())
+}
+
+fn read_input(sub_m: &ArgMatches) -> result::Result<Vec<u8>, CliError> {
+    let mut data = Vec::new();
+    get_input(sub_m)?
+        .read_to_end(&mut data)
+        .map_err(|e| CliError(Some(format!("{}", e))))?;
+    Ok(data)
+}
+
+fn write_output(sub_m: &ArgMatches, data: &[u8]) -> Result {
+    let r = match sub_m.value_of("output") {
+        Some(path) => File::create(path).and_then(|mut f| f.write_all(data)),
+        None => io::stdout().write_all(data),
+    };
+    r.map_err(|e| CliError(Some(format!("{}", e))))
+}
@@ -0,0 +1,171 @@
+//! The `convert` subcommand: converts between raw STP, TWP framed and hex text data.
+//!
+//! Hex input may be a plain hex dump (`ff ff 7f`, `ffff7f` or `0xff, 0x7f`) or `xxd` output;
+//! hex output is in `xxd` format, so `xxd -r` turns it back into binary.
+
+use crate::{event_text, get_frame_decoder, get_input, parse_offset, CliError, Result};
+use clap::ArgMatches;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::result;
+use twp::builders::frame_stream;
+use twp::parsers;
+
+pub fn convert(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let mut data = Vec::new();
+    if let Err(e) = input.read_to_end(&mut data) {
+        return Err(CliError(Some(format!("{}", e))));
+    }
+    let id = match sub_m.value_of("id") {
+        Some(id) => Some(parse_id(id)?),
+        None => None,
+    };
+
+    let data = match sub_m.value_of("from").unwrap() {
+        "hex" => parse_hex(&data)?,
+        "twp" => unframe(sub_m, &data, id)?,
+        _ => data,
+    };
+
+    let out = match sub_m.value_of("to").unwrap() {
+        "hex" => to_hex(&data).into_bytes(),
+        "twp" => {
+            let interval = match sub_m.value_of("fsync_interval") {
+                Some(n) => parse_offset(n)?,
+                None => 0,
+            };
+            frame_stream(&data, id.unwrap_or(1), interval)
+                .map_err(|e| CliError(Some(format!("{:?}", e))))?
+        }
+        _ => data,
+    };
+
+    let r = match sub_m.value_of("output") {
+        Some(path) => File::create(path).and_then(|mut f| f.write_all(&out)),
+        None => io::stdout().write_all(&out),
+    };
+    r.map_err(|e| CliError(Some(format!("{}", e))))
+}
+
+pub fn parse_id(s: &str) -> result::Result<u8, CliError> {
+    match parse_offset(s)? {
+        id if id < 0x70 => Ok(id as u8),
+        _ => Err(CliError(Some(format!("invalid stream id: {}", s)))),
+    }
+}
+
+// Extract a single stream from TWP frames.  Without an ID the input must hold a single stream.
+pub fn unframe(
+    sub_m: &ArgMatches,
+    data: &[u8],
+    id: Option<u8>,
+) -> result::Result<Vec<u8>, CliError> {
+    let mut streams: BTreeMap<Option<u8>, Vec<u8>> = BTreeMap::new();
+    let mut handler = |r: parsers::Result<parsers::Data>| {
+        match r {
+            Ok(d) => match d.event {
+                Some(e) => eprintln!("{}", event_text(&e, d.offset)),
+                None => streams.entry(d.id).or_default().push(d.data),
+            },
+            Err(e) => eprintln!("** {}", e),
+        }
+        Ok(())
+    };
+    let mut decoder = get_frame_decoder(sub_m);
+    decoder.decode(data, &mut handler)?;
+    decoder.finish(&mut handler)?;
+
+    match id {
+        Some(id) => Ok(streams.remove(&Some(id)).unwrap_or_default()),
+        None if streams.len() <= 1 => Ok(streams.into_values().next().unwrap_or_default()),
+        None => {
+            let ids: Vec<String> = streams
+                .keys()
+                .map(|id| match id {
+                    Some(id) => format!("{:#04x}", id),
+                    None => "None".to_string(),
+                })
+                .collect();
+            Err(CliError(Some(format!(
+                "several streams found, select one with --id: {}",
+                ids.join(", ")
+            ))))
+        }
+    }
+}
+
+/// Parse a hex dump: plain hex bytes or `xxd` output.
+pub fn parse_hex(text: &[u8]) -> result::Result<Vec<u8>, CliError> {
+    let text = String::from_utf8_lossy(text);
+    let mut bytes = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let mut line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // xxd: an offset, then the hex, then (after two spaces) an ASCII rendering.
+        if let Some((offset, rest)) = line.split_once(':') {
+            if !offset.is_empty() && offset.chars().all(|c| c.is_ascii_hexdigit()) {
+                line = rest.trim_start();
+                line = line.split("  ").next().unwrap_or("");
+            }
+        }
+
+        for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+            let token = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token);
+            if !token.len().is_multiple_of(2) {
+                return Err(CliError(Some(format!(
+                    "line {}: odd number of hex digits: {}",
+                    n + 1,
+                    token
+                ))));
+            }
+            for i in (0..token.len()).step_by(2) {
+                match u8::from_str_radix(&token[i..i + 2], 16) {
+                    Ok(b) => bytes.push(b),
+                    Err(_) => {
+                        return Err(CliError(Some(format!(
+                            "line {}: invalid hex: {}",
+                            n + 1,
+                            token
+                        ))))
+                    }
+                }
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Format bytes like `xxd`.
+pub fn to_hex(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        out.push_str(&format!("{:08x}:", i * 16));
+        for (j, b) in line.iter().enumerate() {
+            if j % 2 == 0 {
+                out.push(' ');
+            }
+            out.push_str(&format!("{:02x}", b));
+        }
+        // Pad short lines so the ASCII column lines up:
+        let width = line.len() * 2 + line.len().div_ceil(2);
+        out.push_str(&" ".repeat(40 - width));
+        out.push_str("  ");
+        for b in line {
+            out.push(if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
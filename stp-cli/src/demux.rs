@@ -0,0 +1,181 @@
+//! The `demux` subcommand: splits a TWP capture into one file per stream ID.
+//!
+//! Every selected stream is written to `id_0x10.bin` (or `id_none.bin` for data seen before the
+//! first stream ID), alongside `id_0x10.ranges.csv` recording where each run of the stream's
+//! bytes was found in the input.
+
+use crate::output::{get_format, Format, Record, RecordWriter};
+use crate::{event_text, get_frame_decoder, get_input, is_event, parse_offset};
+use crate::{CliError, Result, BUF_SIZE};
+use clap::ArgMatches;
+use colored::*;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{prelude::*, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::result;
+use twp::parsers::{self, OffsetMap};
+
+pub fn demux(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let dir = Path::new(sub_m.value_of("output").unwrap());
+    let ids = match sub_m.values_of("id") {
+        Some(values) => Some(
+            values
+                .map(|v| {
+                    u8::try_from(parse_offset(v)?)
+                        .map_err(|_| CliError(Some(format!("invalid stream ID: {}", v))))
+                })
+                .collect::<result::Result<Vec<u8>, CliError>>()?,
+        ),
+        None => None,
+    };
+    if let Err(e) = fs::create_dir_all(dir) {
+        return Err(io_error(e, dir));
+    }
+
+    let mut demux = Demux {
+        dir: dir.to_path_buf(),
+        ids,
+        files: BTreeMap::new(),
+        offsets: OffsetMap::new(),
+        io_error: None,
+        writer: match get_format(sub_m) {
+            Format::Text => None,
+            f => Some(RecordWriter::new(f)),
+        },
+    };
+    let mut decoder = get_frame_decoder(sub_m);
+    let mut buf = [0; BUF_SIZE];
+
+    loop {
+        let r = match input.read(&mut buf) {
+            Ok(0) => {
+                decoder.finish(|r| demux.handle(r))?;
+                break;
+            }
+            Ok(len) if input.new_capture() => decoder
+                .restart(|r| demux.handle(r))
+                .and_then(|_| decoder.decode(&buf[..len], |r| demux.handle(r))),
+            Ok(len) => decoder.decode(&buf[..len], |r| demux.handle(r)),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CliError(Some(format!("{}", e)))),
+        };
+        if let Some(e) = demux.io_error.take() {
+            return Err(e);
+        }
+        r?;
+    }
+    if let Some(e) = demux.io_error.take() {
+        return Err(e);
+    }
+    demux.finish()
+}
+
+fn io_error(e: std::io::Error, path: &Path) -> CliError {
+    CliError(Some(format!("{}: {}", e, path.display())))
+}
+
+fn file_stem(id: Option<u8>) -> String {
+    match id {
+        None => "id_none".to_string(),
+        Some(id) => format!("id_{:#04x}", id),
+    }
+}
+
+struct Demux {
+    dir: PathBuf,
+    ids: Option<Vec<u8>>,
+    files: BTreeMap<Option<u8>, BufWriter<File>>,
+    offsets: OffsetMap,
+    io_error: Option<CliError>, // Reported once the decoder returns.
+    writer: Option<RecordWriter>,
+}
+
+impl Demux {
+    fn selected(&self, id: Option<u8>) -> bool {
+        match (&self.ids, id) {
+            (None, _) => true,
+            (Some(ids), Some(id)) => ids.contains(&id),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn handle(&mut self, r: parsers::Result<parsers::Data>) -> parsers::Result<()> {
+        match r {
+            Ok(parsers::Data {
+                event: Some(e),
+                offset,
+                ..
+            }) => match &mut self.writer {
+                Some(writer) => writer.write(&Record::from_twp_event(&e, offset)),
+                None => println!("{}", event_text(&e, offset).yellow().bold()),
+            },
+            Ok(d) => {
+                if !self.selected(d.id) {
+                    return Ok(());
+                }
+                if let Err(e) = self.write(&d) {
+                    let offset = d.offset;
+                    self.io_error = Some(e);
+                    return Err(parsers::Error {
+                        offset,
+                        reason: parsers::ErrorReason::Stop,
+                    });
+                }
+                self.offsets.record(&d);
+            }
+            Err(e) => {
+                let event = is_event(&e.reason);
+                match &mut self.writer {
+                    Some(writer) => writer.write(&Record::from_twp_error(&e, event)),
+                    None if event => println!("{}", format!("** {}", e).yellow().bold()),
+                    None => println!("{}", format!("** {}", e).red().bold()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, d: &parsers::Data) -> result::Result<(), CliError> {
+        if !self.files.contains_key(&d.id) {
+            let path = self.dir.join(format!("{}.bin", file_stem(d.id)));
+            let file = File::create(&path).map_err(|e| io_error(e, &path))?;
+            self.files.insert(d.id, BufWriter::new(file));
+        }
+        let file = self.files.get_mut(&d.id).unwrap();
+        file.write_all(&[d.data])
+            .map_err(|e| CliError(Some(format!("{}", e))))
+    }
+
+    // Flush the stream files and write their sidecars:
+    fn finish(&mut self) -> Result {
+        for (id, file) in self.files.iter_mut() {
+            file.flush().map_err(|e| CliError(Some(format!("{}", e))))?;
+
+            let path = self.dir.join(format!("{}.ranges.csv", file_stem(*id)));
+            let mut text = String::from("offset,file_offset,length\n");
+            for (start, range) in self.offsets.runs(*id) {
+                text.push_str(&format!("{},{},{}\n", start, range.start, range.len()));
+            }
+            fs::write(&path, text).map_err(|e| io_error(e, &path))?;
+
+            let len = self.offsets.stream_len(*id);
+            match &mut self.writer {
+                Some(writer) => writer.write(&Record {
+                    stream: Some(*id),
+                    stat: Some("stream_bytes"),
+                    count: Some(len as u64),
+                    ..Record::new("stat")
+                }),
+                None => println!(
+                    "{}: {} bytes",
+                    self.dir.join(format!("{}.bin", file_stem(*id))).display(),
+                    len
+                ),
+            }
+        }
+        Ok(())
+    }
+}
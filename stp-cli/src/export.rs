@@ -0,0 +1,314 @@
+//! The `export` subcommand: exports messages for trace viewers (Perfetto or Chrome's
+//! about:tracing), packets as a CTF trace or pcapng file, or channel activity as a VCD file.
+//!
+//! Times are in microseconds once the timestamp frequency is known; until then, timestamp ticks are
+//! exported as microseconds.  CTF traces keep timestamps in ticks, with the clock's frequency in
+//! their metadata.  pcapng timestamps are in nanoseconds, and VCD times in the chosen timescale,
+//! converted the same way; without a frequency, VCD times are in ticks.
+
+use crate::messages::parse_number;
+use crate::{decode_pipeline, get_frame_decoder, get_input, get_names, is_event};
+use crate::{CliError, Result, BUF_SIZE};
+use clap::ArgMatches;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufWriter};
+use std::path::Path;
+use std::result;
+use stp_core::ctf::{CtfWriter, StreamPer};
+use stp_core::export::{ChromeJson, Perfetto, TraceExporter, TraceSink};
+use stp_core::message::MessageTracker;
+use stp_core::names::Names;
+use stp_core::pcapng::{PcapngWriter, Record};
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::vcd::{Timescale, VcdWriter};
+use twp::parsers;
+
+pub fn export(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    if sub_m.is_present("ctf") {
+        return ctf(sub_m);
+    }
+    let out: Box<dyn Write> = match sub_m.value_of("output") {
+        Some(path) => match File::create(path) {
+            Ok(f) => Box::new(BufWriter::new(f)),
+            Err(e) => return Err(CliError(Some(format!("{}: {}", e, path)))),
+        },
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    let names = get_names(sub_m)?;
+    if sub_m.is_present("pcapng") {
+        pcapng(sub_m, out, names)
+    } else if sub_m.is_present("vcd") {
+        vcd(sub_m, out, names)
+    } else if sub_m.is_present("perfetto") {
+        run(sub_m, TraceExporter::new(Perfetto::new(out)).names(names))
+    } else {
+        run(sub_m, TraceExporter::new(ChromeJson::new(out)).names(names))
+    }
+}
+
+fn frequency(sub_m: &ArgMatches) -> result::Result<Option<f64>, CliError> {
+    match sub_m.value_of("frequency") {
+        Some(f) => Ok(Some(parse_number(f)?)),
+        None => Ok(None),
+    }
+}
+
+fn run<S: TraceSink>(sub_m: &ArgMatches, mut exporter: TraceExporter<S>) -> Result {
+    let mut input = get_input(sub_m)?;
+    let frequency = frequency(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    let mut trackers: BTreeMap<Option<u8>, MessageTracker> = BTreeMap::new();
+    let mut times: BTreeMap<Option<u8>, f64> = BTreeMap::new(); // Last time of each stream.
+    let mut io_error = None;
+
+    decode_pipeline(&mut input, &mut pipeline, |r, _| {
+        let (id, p) = match r {
+            Ok(Output::Packet(id, Ok(p))) => (id, p),
+            Ok(Output::Packet(id, Err(e))) => {
+                trackers.entry(id).or_default().reset();
+                eprintln!("** {}", e);
+                return Ok(());
+            }
+            Ok(Output::Event(..)) => return Ok(()),
+            Err(e) => {
+                if !is_event(&e.reason) {
+                    eprintln!("** {}", e);
+                }
+                return Ok(());
+            }
+        };
+
+        let tracker = trackers.entry(id).or_default();
+        let m = match tracker.process(&p) {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        let f = frequency
+            .or_else(|| tracker.frequency().map(|f| f as f64))
+            .filter(|f| *f > 0.0);
+        let last = times.entry(id).or_insert(0.0);
+        if let Some(ts) = m.timestamp {
+            *last = match f {
+                Some(f) => ts as f64 * 1e6 / f,
+                None => ts as f64,
+            };
+        }
+        match exporter.message(id, &m, *last) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                io_error = Some(e);
+                Err(parsers::Error {
+                    offset: 0,
+                    reason: parsers::ErrorReason::Stop,
+                })
+            }
+        }
+    })?;
+
+    if let Some(e) = io_error {
+        return Err(CliError(Some(format!("{}", e))));
+    }
+    exporter
+        .finish()
+        .map(|_| ())
+        .map_err(|e| CliError(Some(format!("{}", e))))
+}
+
+fn ctf(sub_m: &ArgMatches) -> Result {
+    let dir = match sub_m.value_of("output") {
+        Some(dir) => dir,
+        None => {
+            return Err(CliError(Some(
+                "--ctf needs an output directory (-o)".to_string(),
+            )))
+        }
+    };
+    if let Err(e) = fs::create_dir_all(dir) {
+        return Err(CliError(Some(format!("{}: {}", e, dir))));
+    }
+    let per = match sub_m.value_of("ctf_streams") {
+        Some("id") => StreamPer::TwpId,
+        _ => StreamPer::Master,
+    };
+    let mut writer = CtfWriter::new(per, |name| {
+        File::create(Path::new(dir).join(name)).map(BufWriter::new)
+    });
+    if let Some(f) = frequency(sub_m)? {
+        writer.set_frequency(f.round() as u64);
+    }
+    if sub_m.is_present("names") {
+        writer.set_names(get_names(sub_m)?);
+    }
+
+    let mut input = get_input(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    let mut io_error = None;
+    decode_pipeline(&mut input, &mut pipeline, |r, _| {
+        let written = match r {
+            Ok(Output::Packet(id, Ok(p))) => writer.packet(id, &p),
+            Ok(Output::Packet(id, Err(e))) => {
+                eprintln!("** {}", e);
+                writer.error(id, &e)
+            }
+            Ok(Output::Event(..)) => Ok(()),
+            Err(e) => {
+                if !is_event(&e.reason) {
+                    eprintln!("** {}", e);
+                }
+                Ok(())
+            }
+        };
+        written.map_err(|e| {
+            io_error = Some(e);
+            parsers::Error {
+                offset: 0,
+                reason: parsers::ErrorReason::Stop,
+            }
+        })
+    })?;
+
+    if let Some(e) = io_error {
+        return Err(CliError(Some(format!("{}", e))));
+    }
+    writer
+        .finish()
+        .map_err(|e| CliError(Some(format!("{}", e))))
+}
+
+fn pcapng(sub_m: &ArgMatches, out: Box<dyn Write>, names: Names) -> Result {
+    let messages = sub_m.value_of("pcapng_records") == Some("messages");
+    let frequency = frequency(sub_m)?;
+    let mut writer = PcapngWriter::new(out).map_err(|e| CliError(Some(format!("{}", e))))?;
+    let mut input = get_input(sub_m)?;
+    // Results are handled after each read, so keep enough bytes for everything read since:
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m)).keep_bytes(2 * BUF_SIZE);
+    let mut trackers: BTreeMap<Option<u8>, MessageTracker> = BTreeMap::new();
+    let mut times: BTreeMap<Option<u8>, u64> = BTreeMap::new(); // Last time of each stream, in ns.
+    let mut io_error = None;
+
+    decode_pipeline(&mut input, &mut pipeline, |r, pipeline| {
+        let (id, r) = match r {
+            Ok(Output::Packet(id, r)) => (id, r),
+            Ok(Output::Event(..)) => return Ok(()),
+            Err(e) => {
+                if !is_event(&e.reason) {
+                    eprintln!("** {}", e);
+                }
+                return Ok(());
+            }
+        };
+        let (start, span) = match &r {
+            Ok(p) => (p.start, p.span),
+            Err(e) => (e.start, e.span),
+        };
+        let bytes = pipeline.stream_bytes(id, start, span).unwrap_or_default();
+
+        let tracker = trackers.entry(id).or_default();
+        let mut record = match &r {
+            Ok(p) => {
+                let m = tracker.process(p);
+                match m {
+                    Some(m) if messages => Record::message(&m, &bytes),
+                    _ if messages => return Ok(()),
+                    m => Record {
+                        channel: m.map(|m| (m.master, m.channel)),
+                        timestamp: tracker.timestamp(),
+                        ..Record::packet(p, &bytes)
+                    },
+                }
+            }
+            Err(e) => {
+                tracker.reset();
+                eprintln!("** {}", e);
+                if messages {
+                    return Ok(());
+                }
+                Record::error(e, &bytes)
+            }
+        };
+
+        if !names.is_empty() {
+            record = record.names(&names);
+        }
+
+        let f = frequency
+            .or_else(|| tracker.frequency().map(|f| f as f64))
+            .filter(|f| *f > 0.0);
+        let last = times.entry(id).or_insert(0);
+        if let Some(ts) = tracker.timestamp() {
+            *last = match f {
+                Some(f) => (ts as f64 * 1e9 / f) as u64,
+                None => ts.saturating_mul(1000),
+            };
+        }
+        writer.write(id, *last, &record).map_err(|e| {
+            io_error = Some(e);
+            parsers::Error {
+                offset: 0,
+                reason: parsers::ErrorReason::Stop,
+            }
+        })
+    })?;
+
+    if let Some(e) = io_error {
+        return Err(CliError(Some(format!("{}", e))));
+    }
+    writer.flush().map_err(|e| CliError(Some(format!("{}", e))))
+}
+
+fn vcd(sub_m: &ArgMatches, out: Box<dyn Write>, names: Names) -> Result {
+    let timescale: Timescale = match sub_m.value_of("timescale") {
+        Some(t) => t.parse().map_err(|e| CliError(Some(e)))?,
+        None => Timescale::default(),
+    };
+    let frequency = frequency(sub_m)?;
+    let mut writer = VcdWriter::new(timescale).names(names);
+    let mut input = get_input(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    let mut trackers: BTreeMap<Option<u8>, MessageTracker> = BTreeMap::new();
+    let mut times: BTreeMap<Option<u8>, u64> = BTreeMap::new(); // Last time of each stream.
+
+    decode_pipeline(&mut input, &mut pipeline, |r, _| {
+        let (id, r) = match r {
+            Ok(Output::Packet(id, r)) => (id, r),
+            Ok(Output::Event(..)) => return Ok(()),
+            Err(e) => {
+                if !is_event(&e.reason) {
+                    eprintln!("** {}", e);
+                }
+                return Ok(());
+            }
+        };
+        let tracker = trackers.entry(id).or_default();
+        let last = times.entry(id).or_insert(0);
+        let p = match r {
+            Ok(p) => p,
+            Err(e) => {
+                tracker.reset();
+                eprintln!("** {}", e);
+                writer.error(id, *last);
+                return Ok(());
+            }
+        };
+
+        let m = tracker.process(&p);
+        let f = frequency
+            .or_else(|| tracker.frequency().map(|f| f as f64))
+            .filter(|f| *f > 0.0);
+        if let Some(ts) = tracker.timestamp() {
+            *last = match f {
+                Some(f) => timescale.units(ts as f64 / f),
+                None => ts,
+            };
+        }
+        if let Some(m) = m {
+            writer.message(id, &m, *last);
+        }
+        Ok(())
+    })?;
+
+    writer
+        .finish(out)
+        .map_err(|e| CliError(Some(format!("{}", e))))
+}
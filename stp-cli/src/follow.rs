@@ -0,0 +1,126 @@
+//! `--follow`: keeps reading a file as it grows, like `tail -f`.
+
+use crate::output::{Format, Notices};
+use std::cell::Cell;
+use std::fs::{self, File, Metadata};
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A reader that waits for more data instead of returning EOF.
+///
+/// Once the end of the file is reached it polls for appended data.  If the file is truncated it
+/// starts again from the beginning; if it is replaced (e.g. rotated) the new file is read from the
+/// beginning once the old one is exhausted.  Either way a new capture starts, as counted by
+/// `captures`.  Pipes and stdin are read until the writer closes them.
+///
+/// Truncation and replacement are reported through `Notices`.  Standard output is flushed whenever
+/// the reader may wait, so partial lines are not held back.
+pub struct Follow {
+    input: Box<dyn Read>,
+    file: Option<FollowedFile>,
+}
+
+struct FollowedFile {
+    path: PathBuf,
+    file: File,
+    position: u64,
+    offset: usize,             // Bytes read, over every truncation and replacement.
+    captures: Rc<Cell<usize>>, // The file, then each truncation or replacement.
+    notices: Notices,
+}
+
+impl Follow {
+    pub fn new(input: Box<dyn Read>) -> Follow {
+        Follow { input, file: None }
+    }
+
+    /// Follow a file.  Anything other than a regular file is read like a pipe.
+    pub fn file(path: PathBuf, file: File, format: Format) -> io::Result<Follow> {
+        if !file.metadata()?.is_file() {
+            return Ok(Follow::new(Box::new(file)));
+        }
+        Ok(Follow {
+            input: Box::new(io::empty()),
+            file: Some(FollowedFile {
+                path,
+                file,
+                position: 0,
+                offset: 0,
+                captures: Rc::new(Cell::new(1)),
+                notices: Notices::new(format),
+            }),
+        })
+    }
+
+    /// The number of captures read so far, as it changes.
+    pub fn captures(&self) -> Rc<Cell<usize>> {
+        match &self.file {
+            Some(f) => f.captures.clone(),
+            None => Rc::new(Cell::new(1)),
+        }
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::stdout().flush()?;
+        let f = match &mut self.file {
+            Some(f) => f,
+            None => return self.input.read(buf),
+        };
+
+        loop {
+            let len = f.file.read(buf)?;
+            if len > 0 {
+                f.position += len as u64;
+                f.offset += len;
+                return Ok(len);
+            }
+            thread::sleep(POLL_INTERVAL);
+            f.check()?;
+        }
+    }
+}
+
+impl FollowedFile {
+    // Handle truncation and replacement, once all of the current file has been read:
+    fn check(&mut self) -> io::Result<()> {
+        let current = self.file.metadata()?;
+        if current.len() < self.position {
+            let notice = format!("{}: file truncated", self.path.display());
+            self.notices.event(notice, self.offset);
+            self.file.seek(SeekFrom::Start(0))?;
+            self.position = 0;
+            self.captures.set(self.captures.get() + 1);
+            return Ok(());
+        }
+
+        // The path may briefly not exist while a file is being rotated:
+        if let Ok(latest) = fs::metadata(&self.path) {
+            if !same_file(&current, &latest) && current.len() == self.position {
+                let notice = format!("{}: file replaced", self.path.display());
+                self.notices.event(notice, self.offset);
+                self.file = File::open(&self.path)?;
+                self.position = 0;
+                self.captures.set(self.captures.get() + 1);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &Metadata, _b: &Metadata) -> bool {
+    true
+}
@@ -0,0 +1,136 @@
+//! The `logs` subcommand: formats dictionary-based logs with the format strings of a firmware ELF
+//! file.
+
+use crate::output::{get_format, Format, Lines, Record};
+use crate::{decode_pipeline, get_frame_decoder, get_input};
+use crate::{CliError, Result, OFFSET_WINDOW};
+use clap::ArgMatches;
+use colored::*;
+use std::path::Path;
+use stp_core::dictionary::Dictionary;
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::reassembler::{self, Incomplete, Message, Reassembler};
+use twp::parsers;
+
+pub fn logs(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    if get_format(sub_m) != Format::Text {
+        pipeline = pipeline.track_recent_offsets(OFFSET_WINDOW);
+    }
+
+    let dictionary = Dictionary::load(
+        Path::new(sub_m.value_of("dictionary").unwrap()),
+        sub_m.value_of("section").unwrap(),
+    )
+    .map_err(|e| CliError(Some(e)))?;
+    let mut display = LogDisplay {
+        dictionary,
+        lines: Lines::new(sub_m)?,
+    };
+
+    decode_pipeline(&mut input, &mut pipeline, |r, p| {
+        display.display(r, p);
+        Ok(())
+    })?;
+
+    // Logs still being written when the input ends:
+    for (id, i) in display.lines.finish(|r| r.reset()) {
+        display.display_log(id, Err(i), &pipeline);
+    }
+    Ok(())
+}
+
+struct LogDisplay {
+    dictionary: Dictionary,
+    lines: Lines<Reassembler>,
+}
+
+fn hex(data: &[u8]) -> String {
+    let bytes: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+    bytes.join(" ")
+}
+
+impl LogDisplay {
+    fn display_log(&mut self, id: Option<u8>, r: reassembler::Result, pipeline: &Pipeline) {
+        let (master, channel) = match &r {
+            Ok(m) => (m.master, m.channel),
+            Err(i) => (i.master, i.channel),
+        };
+        if !self.lines.shows(master, channel) {
+            return;
+        }
+        match r {
+            Ok(m) => self.display_message(id, m, pipeline),
+            Err(i) => self.display_incomplete(id, &i, pipeline),
+        }
+    }
+
+    fn display_message(&mut self, id: Option<u8>, m: Message, pipeline: &Pipeline) {
+        let text = self.dictionary.decode(&m.data);
+        let time = self.lines.time(id, m.timestamp);
+        let frequency = self.lines.tracker(id).frequency();
+        let names = &self.lines.names;
+        if let Some(writer) = &mut self.lines.writer {
+            writer.write(&Record {
+                stream: Some(id),
+                offset: Some(m.start),
+                file_offset: pipeline.file_offset(id, m.start),
+                master: Some(m.master),
+                channel: Some(m.channel),
+                timestamp: m.timestamp,
+                frequency,
+                master_name: names.master(m.master).map(|n| n.name.clone()),
+                channel_name: names.channel(m.master, m.channel).map(|n| n.name.clone()),
+                text: Some(match &text {
+                    Ok(text) => text.clone(),
+                    Err(_) => hex(&m.data),
+                }),
+                error: text.err().map(|e| e.to_string()),
+                ..Record::new("log")
+            });
+            return;
+        }
+
+        // Logs that can't be formatted are shown raw, with the reason:
+        let (text, error) = match text {
+            Ok(text) => (text, String::new()),
+            Err(e) => (hex(&m.data), format!(" ** {}", e)),
+        };
+        println!(
+            "{} {} {} {}{}",
+            time,
+            names.master_label(m.master),
+            names.channel_label(m.master, m.channel),
+            text,
+            error.red().bold()
+        );
+    }
+
+    fn display_incomplete(&mut self, id: Option<u8>, i: &Incomplete, pipeline: &Pipeline) {
+        match &mut self.lines.writer {
+            Some(writer) => writer.write(&Record {
+                stream: Some(id),
+                offset: Some(i.start),
+                file_offset: pipeline.file_offset(id, i.start),
+                master: Some(i.master),
+                channel: Some(i.channel),
+                error: Some(i.to_string()),
+                ..Record::new("error")
+            }),
+            None => println!("{}", format!("** {}", i).red().bold()),
+        }
+    }
+
+    fn display(&mut self, r: parsers::Result<Output>, pipeline: &Pipeline) {
+        if let Ok(Output::Packet(id, r)) = &r {
+            let mut logs = Vec::new();
+            let reassembler = self.lines.streams.entry(*id).or_default();
+            reassembler.process(r, |l| logs.push(l));
+            for l in logs {
+                self.display_log(*id, l, pipeline);
+            }
+        }
+        self.lines.report(&r, pipeline);
+    }
+}
@@ -1,13 +1,38 @@
 #[macro_use]
 extern crate clap;
 
-use clap::ArgMatches;
-use colored::*;
-use std::collections::HashMap;
+mod asm;
+mod convert;
+mod demux;
+mod export;
+mod follow;
+mod logs;
+mod messages;
+mod net;
+mod nibbles;
+mod output;
+mod packets;
+mod stats;
+mod systs;
+mod text;
+mod tui;
+
+use clap::{Arg, ArgMatches};
+use follow::Follow;
+use net::{Endpoint, NetInput};
+use std::cell::Cell;
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io::{self, prelude::*, ErrorKind};
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::rc::Rc;
 use std::result;
-use twp::parsers::{self, FrameDecoder};
+use stp_core::names::Names;
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::symbols::Symbols;
+use twp::parsers::{self, FrameDecoder, IdOptions, PortWidth};
 
 const PROG_NAME: &str = crate_name!();
 
@@ -25,26 +50,199 @@ fn main() {
 }
 
 fn run() -> Result {
+    let nibbles_cmd = clap_app!(nibbles =>
+        (about: "Displays trace data as nibbles")
+        (@arg FILE: "STP file")
+        (@arg bail: -b --bail "Stop on first error.")
+        (@arg file_offsets: --("file-offsets") "Display offsets relative to the file.")
+        (@arg wrapped: --wrapped "The input is a wrapped circular (ETB/ETR) buffer.")
+        (@arg write_pointer: --("write-pointer") +takes_value "Circular buffer write pointer (offset within the input).")
+        (@arg follow: -f --follow conflicts_with[wrapped write_pointer] "Keep reading as the file grows, like tail -f.")
+    )
+    .args(&twp_args())
+    .args(&net_args())
+    .arg(output::format_arg());
+
+    let packets_cmd = clap_app!(packets =>
+        (about: "Displays STP packets")
+        (@arg FILE: "STP file")
+        (@arg bail: -b --bail "Stop on first error.")
+        (@arg file_offsets: --("file-offsets") "Display offsets relative to the file.")
+        (@arg follow: -f --follow "Keep reading as the file grows, like tail -f.")
+    )
+    .arg(names_arg())
+    .arg(elf_arg())
+    .args(&twp_args())
+    .args(&net_args())
+    .arg(output::format_arg());
+
+    let messages_cmd = clap_app!(messages =>
+        (about: "Displays data writes with their master, channel and time")
+        (@arg FILE: "STP file")
+        (@arg bail: -b --bail "Stop on first error.")
+        (@arg master: --master +takes_value "Only show these masters (e.g. 1,4-6).")
+        (@arg channel: --channel +takes_value "Only show these channels (e.g. 0x10-0x1f).")
+        (@arg opcode: --opcode +takes_value "Only show these opcodes (e.g. D32M,FLAG).")
+        (@arg start: --start +takes_value "Only show messages at or after this time (us, or ticks if the frequency is unknown).")
+        (@arg end: --end +takes_value "Only show messages at or before this time (us, or ticks if the frequency is unknown).")
+        (@arg frequency: --frequency +takes_value "Timestamp frequency in Hz (overrides FREQ packets).")
+        (@arg follow: -f --follow "Keep reading as the file grows, like tail -f.")
+    )
+    .arg(names_arg())
+    .arg(elf_arg())
+    .args(&twp_args())
+    .args(&net_args())
+    .arg(output::format_arg());
+
+    let systs_cmd = clap_app!(systs =>
+        (about: "Displays MIPI SyS-T messages as log lines")
+        (@arg FILE: "STP file")
+        (@arg frequency: --frequency +takes_value "Timestamp frequency in Hz (overrides FREQ packets).")
+        (@arg follow: -f --follow "Keep reading as the file grows, like tail -f.")
+        (@arg catalog: --catalog +takes_value +multiple number_of_values(1) "SyS-T collateral file with catalog format strings (may be repeated).")
+        (@arg pointer_size: --("pointer-size") +takes_value possible_values(&["4", "8"]) "Size in bytes of longs and pointers in catalog arguments (default: from each message).")
+    )
+    .arg(names_arg())
+    .args(&twp_args())
+    .args(&net_args())
+    .arg(output::format_arg());
+
+    let text_cmd = clap_app!(text =>
+        (about: "Displays the text written to channels, like a merged dmesg")
+        (@arg FILE: "STP file")
+        (@arg master: --master +takes_value "Only show these masters (e.g. 1,4-6).")
+        (@arg channel: --channel +takes_value "Only show these channels (e.g. 0x10-0x1f).")
+        (@arg frequency: --frequency +takes_value "Timestamp frequency in Hz (overrides FREQ packets).")
+        (@arg follow: -f --follow "Keep reading as the file grows, like tail -f.")
+        (@arg merge: --merge conflicts_with[follow connect listen] "Interleave the lines of all TWP streams in time order, once the input ends (for streams sharing a clock).")
+    )
+    .arg(names_arg())
+    .args(&twp_args())
+    .args(&net_args())
+    .arg(output::format_arg());
+
+    let logs_cmd = clap_app!(logs =>
+        (about: "Displays dictionary-based logs with the format strings of a firmware ELF file")
+        (@arg FILE: "STP file")
+        (@arg dictionary: --dictionary +takes_value +required value_name("ELF") "Firmware ELF file with the format strings.")
+        (@arg section: --section +takes_value default_value(".stp_log") "Section of the ELF file holding the format strings.")
+        (@arg master: --master +takes_value "Only show these masters (e.g. 1,4-6).")
+        (@arg channel: --channel +takes_value "Only show these channels (e.g. 0x10-0x1f).")
+        (@arg frequency: --frequency +takes_value "Timestamp frequency in Hz (overrides FREQ packets).")
+        (@arg follow: -f --follow "Keep reading as the file grows, like tail -f.")
+    )
+    .arg(names_arg())
+    .args(&twp_args())
+    .args(&net_args())
+    .arg(output::format_arg());
+
+    let stats_cmd = clap_app!(stats =>
+        (about: "Summarizes a capture")
+        (@arg FILE: "STP file")
+        (@arg top: --top +takes_value "Number of busiest masters and channels to show (default 10).")
+    )
+    .arg(names_arg())
+    .args(&twp_args())
+    .args(&net_args())
+    .arg(output::format_arg());
+
+    let demux_cmd = clap_app!(demux =>
+        (about: "Splits a TWP capture into one file per stream ID")
+        (@arg FILE: "TWP file")
+        (@arg output: -o --output +takes_value +required "Output directory.")
+        (@arg id: --id +takes_value +multiple number_of_values(1) "Only write this stream ID (may be repeated).")
+    )
+    .args(&twp_args())
+    .args(&net_args())
+    .arg(output::format_arg());
+
+    let convert_cmd = clap_app!(convert =>
+        (about: "Converts between raw STP, TWP framed and hex text data")
+        (@arg FILE: "Input file")
+        (@arg from: --from +takes_value +required possible_values(&["raw", "twp", "hex"]) "Input format.")
+        (@arg to: --to +takes_value +required possible_values(&["raw", "twp", "hex"]) "Output format.")
+        (@arg output: -o --output +takes_value "Output file (default: stdout).")
+        (@arg id: --id +takes_value "Stream ID to extract from, or write to, TWP frames (default: 0x01 when writing).")
+        (@arg fsync_interval: --("fsync-interval") +takes_value "Frames between FSYNCs when writing TWP (default: 0, a single FSYNC).")
+    )
+    .args(&twp_args());
+
+    let export_cmd = clap_app!(export =>
+        (about: "Exports messages for trace viewers")
+        (@arg FILE: "STP file")
+        (@group format +required =>
+            (@arg perfetto: --perfetto "Write a Perfetto protobuf trace.")
+            (@arg chrome_json: --("chrome-json") "Write a Chrome JSON trace (about:tracing).")
+            (@arg ctf: --ctf "Write a CTF trace (Babeltrace, Trace Compass) to the output directory.")
+            (@arg pcapng: --pcapng "Write a pcapng file (Wireshark).")
+            (@arg vcd: --vcd "Write channel activity as a VCD file (GTKWave).")
+        )
+        (@arg output: -o --output +takes_value "Output file, or directory for CTF (default: stdout).")
+        (@arg ctf_streams: --("ctf-streams") +takes_value possible_values(&["master", "id"]) requires[ctf] "Write a CTF stream per master or per TWP ID (default: master).")
+        (@arg pcapng_records: --("pcapng-records") +takes_value possible_values(&["packets", "messages"]) requires[pcapng] "Write a pcapng record per packet or per message (default: packets).")
+        (@arg timescale: --timescale +takes_value requires[vcd] "VCD time unit, e.g. 10ns or 1us (default: 1ns).")
+        (@arg frequency: --frequency +takes_value "Timestamp frequency in Hz (overrides FREQ packets).")
+    )
+    .arg(names_arg())
+    .args(&twp_args())
+    .args(&net_args());
+
+    let tui_cmd = clap_app!(tui =>
+        (about: "Browses a capture interactively")
+        (@arg FILE: +required "STP file")
+    )
+    .arg(names_arg())
+    .args(&twp_args());
+
+    let asm_cmd = clap_app!(asm =>
+        (about: "Assembles STP text into raw STP data")
+        (@arg FILE: "Text file")
+        (@arg output: -o --output +takes_value "Output file (default: stdout).")
+    );
+
+    let disasm_cmd = clap_app!(disasm =>
+        (about: "Disassembles STP data into text that `asm` accepts")
+        (@arg FILE: "STP file")
+        (@arg from: --from +takes_value default_value("raw") possible_values(&["raw", "twp"]) "Input format.")
+        (@arg output: -o --output +takes_value "Output file (default: stdout).")
+        (@arg id: --id +takes_value "Stream ID to extract from TWP frames.")
+    )
+    .args(&twp_args());
+
     let app_m = clap_app!(stp =>
         (version: crate_version!())
         (author: crate_authors!())
         (about: crate_description!())
-        (@subcommand nibbles =>
-            (about: "Displays trace data as nibbles")
-            (@arg FILE: "STP file")
-            (@arg bail: -b --bail "Stop on first error.")
-            (@arg file_offsets: --("file-offsets") "Display offsets relative to the file.")
-        )
-        (@subcommand packets =>
-            (about: "Displays STP packets")
-            (@arg FILE: "STP file")
-        )
     )
+    .subcommand(nibbles_cmd)
+    .subcommand(packets_cmd)
+    .subcommand(messages_cmd)
+    .subcommand(systs_cmd)
+    .subcommand(text_cmd)
+    .subcommand(logs_cmd)
+    .subcommand(stats_cmd)
+    .subcommand(demux_cmd)
+    .subcommand(convert_cmd)
+    .subcommand(export_cmd)
+    .subcommand(tui_cmd)
+    .subcommand(asm_cmd)
+    .subcommand(disasm_cmd)
     .get_matches();
 
     match app_m.subcommand() {
-        ("nibbles", Some(sub_m)) => nibbles(&app_m, sub_m),
-        ("packets", Some(sub_m)) => packets(&app_m, sub_m),
+        ("nibbles", Some(sub_m)) => nibbles::nibbles(&app_m, sub_m),
+        ("packets", Some(sub_m)) => packets::packets(&app_m, sub_m),
+        ("messages", Some(sub_m)) => messages::messages(&app_m, sub_m),
+        ("systs", Some(sub_m)) => systs::systs(&app_m, sub_m),
+        ("text", Some(sub_m)) => text::text(&app_m, sub_m),
+        ("logs", Some(sub_m)) => logs::logs(&app_m, sub_m),
+        ("stats", Some(sub_m)) => stats::stats(&app_m, sub_m),
+        ("demux", Some(sub_m)) => demux::demux(&app_m, sub_m),
+        ("convert", Some(sub_m)) => convert::convert(&app_m, sub_m),
+        ("export", Some(sub_m)) => export::export(&app_m, sub_m),
+        ("tui", Some(sub_m)) => tui::tui(&app_m, sub_m),
+        ("asm", Some(sub_m)) => asm::asm(&app_m, sub_m),
+        ("disasm", Some(sub_m)) => asm::disasm(&app_m, sub_m),
         _ => {
             println!("{}", app_m.usage());
             Ok(())
@@ -52,13 +250,231 @@ fn run() -> Result {
     }
 }
 
-fn get_input(sub_m: &ArgMatches) -> result::Result<Box<dyn Read>, CliError> {
-    match sub_m.value_of("FILE") {
+/// The input of a subcommand: a file, stdin or TCP connections.
+struct Input {
+    reader: Box<dyn Read>,
+    captures: Option<Rc<Cell<usize>>>, // TCP connections, or followed files, started so far.
+    seen: usize,
+}
+
+impl Input {
+    fn new(reader: Box<dyn Read>) -> Input {
+        Input {
+            reader,
+            captures: None,
+            seen: 0,
+        }
+    }
+
+    /// Did the data just read start a new capture, other than the first?  Each TCP connection is
+    /// a capture of its own, and so is a followed file once it is truncated or replaced, so
+    /// decoders should start over.
+    fn new_capture(&mut self) -> bool {
+        let captures = self.captures.as_ref().map_or(0, |c| c.get());
+        let new = captures > 1 && captures != self.seen;
+        self.seen = captures;
+        new
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+fn get_input(sub_m: &ArgMatches) -> result::Result<Input, CliError> {
+    if let Some(endpoint) = get_endpoint(sub_m)? {
+        let net = NetInput::new(
+            endpoint,
+            sub_m.is_present("once"),
+            output::get_format(sub_m),
+            sub_m.is_present("chunk_times"),
+        );
+        return Ok(Input {
+            captures: Some(net.connections()),
+            ..Input::new(Box::new(net))
+        });
+    }
+
+    let follow = sub_m.is_present("follow");
+    let reader: Box<dyn Read> = match sub_m.value_of("FILE") {
         Some(path) => match File::open(path) {
-            Ok(f) => Ok(Box::new(f)),
-            Err(e) => Err(CliError(Some(format!("{}: {}", e, path)))),
+            Ok(f) if follow => match Follow::file(path.into(), f, output::get_format(sub_m)) {
+                Ok(f) => {
+                    return Ok(Input {
+                        captures: Some(f.captures()),
+                        ..Input::new(Box::new(f))
+                    })
+                }
+                Err(e) => return Err(CliError(Some(format!("{}: {}", e, path)))),
+            },
+            Ok(f) => Box::new(f),
+            Err(e) => return Err(CliError(Some(format!("{}: {}", e, path)))),
         },
-        None => Ok(Box::new(io::stdin())),
+        None if follow => Box::new(Follow::new(Box::new(io::stdin()))),
+        None => Box::new(io::stdin()),
+    };
+    Ok(Input::new(reader))
+}
+
+// Arguments for reading from a TCP socket instead of a file.
+fn net_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("connect")
+            .long("connect")
+            .takes_value(true)
+            .value_name("HOST:PORT")
+            .conflicts_with_all(&["FILE", "listen"])
+            .help("Read from a TCP server (e.g. an OpenOCD trace server), reconnecting as needed."),
+        Arg::with_name("listen")
+            .long("listen")
+            .takes_value(true)
+            .value_name("[ADDR:]PORT")
+            .conflicts_with("FILE")
+            .help("Accept TCP connections on this port (of 127.0.0.1 by default) and read from them, one at a time."),
+        Arg::with_name("once")
+            .long("once")
+            .help("Stop when the first TCP connection is closed."),
+        Arg::with_name("chunk_times")
+            .long("chunk-times")
+            .help("Report when each chunk of TCP data arrived."),
+    ]
+}
+
+fn get_endpoint(sub_m: &ArgMatches) -> result::Result<Option<Endpoint>, CliError> {
+    if let Some(addr) = sub_m.value_of("connect") {
+        return Ok(Some(Endpoint::Connect(addr.to_string())));
+    }
+    let listen = match sub_m.value_of("listen") {
+        Some(listen) => listen,
+        None => return Ok(None),
+    };
+    let (addr, port) = match listen.rsplit_once(':') {
+        Some((addr, port)) => (addr.trim_matches(|c| c == '[' || c == ']'), port),
+        None => ("127.0.0.1", listen),
+    };
+    let port = u16::try_from(parse_offset(port)?)
+        .map_err(|_| CliError(Some(format!("invalid port: {}", port))))?;
+    match TcpListener::bind((addr, port)) {
+        Ok(l) => Ok(Some(Endpoint::Listen(l))),
+        Err(e) => Err(CliError(Some(format!("{}: {}", e, listen)))),
+    }
+}
+
+// Arguments controlling how TWP frames are decoded.
+fn twp_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("null_data")
+            .long("null-data")
+            .help("Display data written to the null stream (ID 0x00)."),
+        Arg::with_name("no_reserved")
+            .long("no-reserved-warnings")
+            .help("Do not warn when a reserved stream ID is used."),
+        Arg::with_name("halfword_sync")
+            .long("halfword-sync")
+            .help("Strip TPIU halfword syncs (continuous mode)."),
+        Arg::with_name("port_width")
+            .long("port-width")
+            .takes_value(true)
+            .possible_values(&["8", "16", "32"])
+            .help("TPIU port width in bits."),
+    ]
+}
+
+// The file naming masters and channels.
+fn names_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("names")
+        .long("names")
+        .takes_value(true)
+        .help("Name masters and channels from a TOML or JSON file, or an stm policy directory.")
+}
+
+fn get_names(sub_m: &ArgMatches) -> result::Result<Names, CliError> {
+    match sub_m.value_of("names") {
+        Some(path) => Names::load(Path::new(path)).map_err(|e| CliError(Some(e))),
+        None => Ok(Names::new()),
+    }
+}
+
+// The ELF files that code addresses are resolved with.
+fn elf_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("elf")
+        .long("elf")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .value_name("FILE[:OFFSET[:MASTERS]]")
+        .help("Resolve code addresses with an ELF file, loaded at OFFSET for MASTERS (e.g. 32-47; may be repeated).")
+}
+
+fn get_symbols(sub_m: &ArgMatches) -> result::Result<Symbols, CliError> {
+    let mut symbols = Symbols::new();
+    for value in sub_m.values_of("elf").into_iter().flatten() {
+        let mut parts = value.splitn(3, ':');
+        let path = parts.next().unwrap();
+        let offset = match parts.next() {
+            Some(offset) => parse_offset(offset)? as u64,
+            None => 0,
+        };
+        let masters = match parts.next() {
+            Some(masters) => {
+                let (start, end) = masters.split_once('-').unwrap_or((masters, masters));
+                let master = |m| {
+                    u16::try_from(parse_offset(m)?)
+                        .map_err(|_| CliError(Some(format!("invalid master: {}", m))))
+                };
+                Some(master(start)?..=master(end)?)
+            }
+            None => None,
+        };
+        symbols
+            .load(Path::new(path), masters, offset)
+            .map_err(|e| CliError(Some(e)))?;
+    }
+    Ok(symbols)
+}
+
+fn get_id_options(sub_m: &ArgMatches) -> IdOptions {
+    IdOptions {
+        null_data: sub_m.is_present("null_data"),
+        reserved_warnings: !sub_m.is_present("no_reserved"),
+        ..IdOptions::default()
+    }
+}
+
+fn get_frame_decoder(sub_m: &ArgMatches) -> FrameDecoder {
+    FrameDecoder::with_options(false, None, get_id_options(sub_m))
+        .port_width(get_port_width(sub_m))
+        .halfword_sync(sub_m.is_present("halfword_sync"))
+}
+
+fn parse_offset(s: &str) -> result::Result<usize, CliError> {
+    let r = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    r.map_err(|e| CliError(Some(format!("{}: {}", e, s))))
+}
+
+// Parse a comma separated list of values and inclusive ranges, e.g. "1,4-6,0x10-0x1f".
+fn parse_ranges(s: &str) -> result::Result<Vec<RangeInclusive<usize>>, CliError> {
+    s.split(',')
+        .map(|r| match r.split_once('-') {
+            Some((start, end)) => Ok(parse_offset(start.trim())?..=parse_offset(end.trim())?),
+            None => {
+                let v = parse_offset(r.trim())?;
+                Ok(v..=v)
+            }
+        })
+        .collect()
+}
+
+fn get_port_width(sub_m: &ArgMatches) -> PortWidth {
+    match sub_m.value_of("port_width") {
+        // Wider ports sync on 16 bit boundaries too:
+        Some("16") | Some("32") => PortWidth::Bits16,
+        _ => PortWidth::Bits8,
     }
 }
 
@@ -72,117 +488,60 @@ impl std::convert::From<parsers::Error> for CliError {
     }
 }
 
+// Is a TWP error only a notice?  A wrapped capture buffer lost earlier data, but nothing that
+// follows is wrong.
+fn is_event(reason: &parsers::ErrorReason) -> bool {
+    *reason == parsers::ErrorReason::Discontinuity
+}
+
+// A TWP event, as text:
+fn event_text(event: &parsers::Event, offset: usize) -> String {
+    format!("** {}, offset: {:#x}", event, offset)
+}
+
 const BUF_SIZE: usize = 4 * 1024;
 
-fn nibbles(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
-    let mut input = get_input(sub_m)?;
-    let bail = sub_m.is_present("bail");
-    let file_offset = sub_m.is_present("file_offsets");
+// Stream bytes whose input offsets are kept for `file_offset`s, so that following a file or a
+// socket doesn't grow memory without bound:
+const OFFSET_WINDOW: usize = 1024 * 1024;
+
+/// Decode the input through the pipeline, passing each result to the handler.
+///
+/// Results are passed on once the pipeline returns, so the handler can look up their file offsets.
+fn decode_pipeline<H>(input: &mut Input, pipeline: &mut Pipeline, mut handler: H) -> Result
+where
+    H: FnMut(parsers::Result<Output>, &Pipeline) -> parsers::Result<()>,
+{
     let mut buf = [0; BUF_SIZE];
-    let mut display = NibbleDisplay::new(bail, file_offset);
-    let mut decoder = FrameDecoder::new(false, None);
+    let mut pending = Vec::new();
 
     loop {
-        match input.read(&mut buf) {
+        let done = match input.read(&mut buf) {
             Ok(0) => {
-                decoder.finish(|r| display.display(r))?;
-                print!("\n");
-                break;
+                pipeline.finish(|r| push(&mut pending, r))?;
+                true
+            }
+            Ok(len) => {
+                if input.new_capture() {
+                    pipeline.restart(|r| push(&mut pending, r))?;
+                }
+                pipeline.decode(&buf[..len], |r| push(&mut pending, r))?;
+                false
             }
-            Ok(len) => decoder.decode(&buf[..len], |r| display.display(r))?,
             Err(e) if e.kind() == ErrorKind::Interrupted => continue,
             Err(e) => return Err(CliError(Some(format!("{}", e)))),
         };
-    }
-    Ok(())
-}
-
-struct NibbleDisplay {
-    offsets: HashMap<Option<u8>, usize>,
-    cur_id: Option<u8>,
-    cur_offset: usize,
-    column: usize,
-    bail: bool,
-    file_offset: bool,
-    first_stream: bool,
-}
-
-impl NibbleDisplay {
-    fn new(bail: bool, file_offset: bool) -> NibbleDisplay {
-        NibbleDisplay {
-            offsets: HashMap::new(),
-            cur_id: Some(0xFF), // Intentionally set to an invalid Stream ID.
-            cur_offset: 0,
-            column: 0,
-            bail,
-            file_offset,
-            first_stream: true,
-        }
-    }
-
-    fn display_data(&mut self, id: Option<u8>, data: u8, offset: usize) {
-        if id != self.cur_id {
-            self.offsets.insert(self.cur_id, self.cur_offset);
-            self.cur_offset = *self.offsets.entry(id).or_insert(0);
-            self.column = 0;
-            self.cur_id = id;
-            if self.first_stream {
-                self.first_stream = false;
-            } else {
-                print!("\n\n");
-            }
-            match id {
-                None => print!("Stream None:"),
-                Some(id) => print!("Stream {:#X}:", id),
-            }
+        for r in pending.drain(..) {
+            handler(r, pipeline)?;
         }
-
-        if self.column % 16 == 0 {
-            let o = if self.file_offset {
-                offset
-            } else {
-                self.cur_offset * 2
-            };
-            print!("\n{:012X} |", o);
-            self.column = 0;
-        } else if self.column == 8 {
-            print!(" ");
-        }
-        print!(" {:x} {:x}", data & 0xF, data >> 4);
-
-        self.column += 1;
-        self.cur_offset += 1;
-    }
-
-    fn display_error(&mut self, err: parsers::Error) {
-        let msg = format!("** {}", err);
-        print!("\n\n{}\n", msg.red().bold());
-        self.column = 0;
-    }
-
-    fn display(&mut self, r: parsers::Result<parsers::Data>) -> parsers::Result<()> {
-        match r {
-            Ok(d) => {
-                self.display_data(d.id, d.data, d.offset);
-                Ok(())
-            }
-            Err(e) => {
-                let offset = e.offset;
-                self.display_error(e);
-                if self.bail {
-                    Err(parsers::Error {
-                        offset,
-                        reason: parsers::ErrorReason::Stop,
-                    })
-                } else {
-                    Ok(())
-                }
-            }
+        if done {
+            break;
         }
     }
+    Ok(())
 }
 
-fn packets(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
-    let mut _input = get_input(sub_m)?;
+fn push<T>(pending: &mut Vec<T>, r: T) -> parsers::Result<()> {
+    pending.push(r);
     Ok(())
 }
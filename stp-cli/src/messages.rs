@@ -0,0 +1,157 @@
+//! The `messages` subcommand: displays data writes with their master, channel and time.
+
+use crate::output::{get_format, Format, Lines, Record};
+use crate::{decode_pipeline, get_frame_decoder, get_input, get_symbols};
+use crate::{CliError, Result, OFFSET_WINDOW};
+use clap::ArgMatches;
+use std::result;
+use stp_core::message::{Message, MessageTracker};
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::stp::OpCode;
+use stp_core::symbols::Symbols;
+use twp::parsers;
+
+pub fn messages(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    if get_format(sub_m) != Format::Text {
+        pipeline = pipeline.track_recent_offsets(OFFSET_WINDOW);
+    }
+
+    let mut display = MessageDisplay {
+        bail: sub_m.is_present("bail"),
+        filter: Filter::new(sub_m)?,
+        symbols: get_symbols(sub_m)?,
+        lines: Lines::new(sub_m)?,
+    };
+
+    decode_pipeline(&mut input, &mut pipeline, |r, p| display.display(r, p))
+}
+
+pub fn parse_number(s: &str) -> result::Result<f64, CliError> {
+    s.parse()
+        .map_err(|e| CliError(Some(format!("{}: {}", e, s))))
+}
+
+// Which messages are shown, besides their master and channel:
+struct Filter {
+    opcodes: Option<Vec<OpCode>>,
+    start: Option<f64>, // Microseconds, or ticks if the frequency is unknown.
+    end: Option<f64>,
+}
+
+impl Filter {
+    fn new(sub_m: &ArgMatches) -> result::Result<Filter, CliError> {
+        let number = |name| sub_m.value_of(name).map(parse_number).transpose();
+        let opcodes = match sub_m.value_of("opcode") {
+            Some(list) => Some(
+                list.split(',')
+                    .map(|op| op.trim().parse().map_err(|e| CliError(Some(e))))
+                    .collect::<result::Result<Vec<OpCode>, CliError>>()?,
+            ),
+            None => None,
+        };
+        Ok(Filter {
+            opcodes,
+            start: number("start")?,
+            end: number("end")?,
+        })
+    }
+
+    fn matches(&self, m: &Message, time: Option<f64>) -> bool {
+        if let Some(opcodes) = &self.opcodes {
+            if !opcodes.contains(&m.opcode) {
+                return false;
+            }
+        }
+        if self.start.is_none() && self.end.is_none() {
+            return true;
+        }
+        match time {
+            Some(t) => self.start.is_none_or(|s| t >= s) && self.end.is_none_or(|e| t <= e),
+            None => false,
+        }
+    }
+}
+
+struct MessageDisplay {
+    bail: bool,
+    filter: Filter,
+    symbols: Symbols,
+    lines: Lines<MessageTracker>,
+}
+
+impl MessageDisplay {
+    fn display_message(&mut self, id: Option<u8>, m: Message, pipeline: &Pipeline) {
+        let time = self.lines.time(id, m.timestamp);
+        if !self.lines.shows(m.master, m.channel) || !self.filter.matches(&m, time.value()) {
+            return;
+        }
+
+        let frequency = self.lines.tracker(id).frequency();
+        let names = &self.lines.names;
+        let value = match (m.data, m.opcode.data_bits()) {
+            (Some(d), Some(bits)) => Some(names.format_data(m.master, m.channel, d, bits)),
+            _ => None,
+        };
+        let symbol = self.symbols.resolve_message(&m);
+        if let Some(writer) = &mut self.lines.writer {
+            writer.write(&Record {
+                stream: Some(id),
+                offset: Some(m.start),
+                span: Some(m.span),
+                file_offset: pipeline.file_offset(id, m.start),
+                opcode: Some(format!("{:?}", m.opcode)),
+                master: Some(m.master),
+                channel: Some(m.channel),
+                payload: m.data,
+                timestamp: m.timestamp,
+                frequency,
+                master_name: names.master(m.master).map(|n| n.name.clone()),
+                channel_name: names.channel(m.master, m.channel).map(|n| n.name.clone()),
+                value: value.filter(|_| !names.is_empty()),
+                symbol: symbol.map(|s| s.to_string()),
+                ..Record::new("message")
+            });
+            return;
+        }
+
+        println!(
+            "{} {} {} {:?}{}{}",
+            time,
+            names.master_label(m.master),
+            names.channel_label(m.master, m.channel),
+            m.opcode,
+            value.map_or(String::new(), |v| format!(" {}", v)),
+            symbol.map_or(String::new(), |s| format!(" <{}>", s))
+        );
+    }
+
+    fn display(&mut self, r: parsers::Result<Output>, pipeline: &Pipeline) -> parsers::Result<()> {
+        match &r {
+            Ok(Output::Packet(id, Ok(p))) => {
+                let m = self.lines.streams.entry(*id).or_default().process(p);
+                if let Some(m) = m {
+                    self.display_message(*id, m, pipeline);
+                }
+            }
+            Ok(Output::Packet(id, Err(_))) => self.lines.streams.entry(*id).or_default().reset(),
+            _ => (),
+        }
+        match self.lines.report(&r, pipeline) {
+            Some(offset) => self.check_bail(offset),
+            None => Ok(()),
+        }
+    }
+
+    fn check_bail(&self, offset: usize) -> parsers::Result<()> {
+        if self.bail {
+            Err(parsers::Error {
+                offset,
+                reason: parsers::ErrorReason::Stop,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! `--connect` and `--listen`: reads trace data from a TCP socket (e.g. an OpenOCD trace server).
+
+use crate::output::{Format, Notices, Record, RecordWriter};
+use std::cell::Cell;
+use std::io::{self, prelude::*, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum Endpoint {
+    Connect(String), // host:port
+    Listen(TcpListener),
+}
+
+/// A reader that receives data over TCP, reconnecting (or accepting a new client) whenever the
+/// connection is closed.  With `once`, the end of the first connection is the end of the input.
+///
+/// Connections made and lost are reported as `event` and `error` records (or on stderr).  With
+/// `chunk_times`, the arrival time of every chunk read is also reported before it is decoded.
+pub struct NetInput {
+    endpoint: Endpoint,
+    stream: Option<TcpStream>,
+    once: bool,
+    connections: Rc<Cell<usize>>,         // Connections made so far.
+    offset: usize,                        // Bytes received over all connections.
+    chunks: Option<Option<RecordWriter>>, // Some(None) reports chunks as text.
+    notices: Notices,
+}
+
+impl NetInput {
+    pub fn new(endpoint: Endpoint, once: bool, format: Format, chunk_times: bool) -> NetInput {
+        NetInput {
+            endpoint,
+            stream: None,
+            once,
+            connections: Rc::new(Cell::new(0)),
+            offset: 0,
+            chunks: match (chunk_times, format) {
+                (false, _) => None,
+                (true, Format::Text) => Some(None),
+                (true, f) => Some(Some(RecordWriter::continued(f))),
+            },
+            notices: Notices::new(format),
+        }
+    }
+
+    /// The number of connections made so far, as it changes.
+    pub fn connections(&self) -> Rc<Cell<usize>> {
+        self.connections.clone()
+    }
+
+    fn connect(&mut self) -> io::Result<TcpStream> {
+        match &self.endpoint {
+            Endpoint::Connect(addr) => loop {
+                match TcpStream::connect(addr.as_str()) {
+                    Ok(s) => {
+                        let notice = format!("connected to {}", addr);
+                        self.notices.event(notice, self.offset);
+                        return Ok(s);
+                    }
+                    // The server may not be up yet, or may be restarting:
+                    Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                        thread::sleep(RETRY_INTERVAL)
+                    }
+                    Err(e) => return Err(e),
+                }
+            },
+            Endpoint::Listen(listener) => {
+                let (s, peer) = listener.accept()?;
+                self.notices
+                    .event(format!("connection from {}", peer), self.offset);
+                Ok(s)
+            }
+        }
+    }
+
+    fn report_chunk(&mut self, len: usize) {
+        let arrival = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        match &mut self.chunks {
+            None => {}
+            Some(Some(writer)) => writer.write(&Record {
+                file_offset: Some(self.offset),
+                count: Some(len as u64),
+                arrival: Some(arrival.as_micros() as u64),
+                ..Record::new("chunk")
+            }),
+            Some(None) => println!(
+                "** chunk: {} bytes at {:#X}, received {}.{:06}",
+                len,
+                self.offset,
+                arrival.as_secs(),
+                arrival.subsec_micros()
+            ),
+        }
+    }
+}
+
+impl Read for NetInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::stdout().flush()?;
+        loop {
+            if self.stream.is_none() {
+                if self.once && self.connections.get() > 0 {
+                    return Ok(0);
+                }
+                self.stream = Some(self.connect()?);
+                self.connections.set(self.connections.get() + 1);
+            }
+
+            match self.stream.as_mut().unwrap().read(buf) {
+                Ok(0) => self
+                    .notices
+                    .event("connection closed".to_string(), self.offset),
+                Ok(len) => {
+                    self.report_chunk(len);
+                    self.offset += len;
+                    return Ok(len);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => self
+                    .notices
+                    .error(format!("connection lost: {}", e), self.offset),
+            }
+            self.stream = None;
+            if !self.once && matches!(self.endpoint, Endpoint::Connect(_)) {
+                thread::sleep(RETRY_INTERVAL);
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! The `nibbles` subcommand: displays the demultiplexed TWP streams as nibbles.
+
+use crate::output::{get_format, Format, Record, RecordWriter};
+use crate::{event_text, get_frame_decoder, get_id_options, get_input, is_event, parse_offset};
+use crate::{CliError, Result, BUF_SIZE};
+use clap::ArgMatches;
+use colored::*;
+use std::collections::HashMap;
+use std::io::{prelude::*, ErrorKind};
+use twp::parsers::{self, CircularBuffer};
+
+pub fn nibbles(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let bail = sub_m.is_present("bail");
+    let file_offset = sub_m.is_present("file_offsets");
+    let mut buf = [0; BUF_SIZE];
+    let mut display = NibbleDisplay::new(bail, file_offset, get_format(sub_m));
+    let wrapped = sub_m.is_present("wrapped");
+    let write_ptr = match sub_m.value_of("write_pointer") {
+        Some(s) => Some(parse_offset(s)?),
+        None => None,
+    };
+    if wrapped || write_ptr.is_some() {
+        let mut buffer = Vec::new();
+        if let Err(e) = input.read_to_end(&mut buffer) {
+            return Err(CliError(Some(format!("{}", e))));
+        }
+        CircularBuffer::new(&buffer, write_ptr, wrapped)
+            .decode_with(get_id_options(sub_m), |r| display.display(r))?;
+        display.end();
+        return Ok(());
+    }
+
+    let mut decoder = get_frame_decoder(sub_m);
+
+    loop {
+        match input.read(&mut buf) {
+            Ok(0) => {
+                decoder.finish(|r| display.display(r))?;
+                display.end();
+                break;
+            }
+            Ok(len) => {
+                if input.new_capture() {
+                    decoder.restart(|r| display.display(r))?;
+                }
+                decoder.decode(&buf[..len], |r| display.display(r))?
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CliError(Some(format!("{}", e)))),
+        };
+    }
+    Ok(())
+}
+
+struct NibbleDisplay {
+    offsets: HashMap<Option<u8>, usize>,
+    cur_id: Option<u8>,
+    cur_offset: usize,
+    column: usize,
+    bail: bool,
+    file_offset: bool,
+    first_stream: bool,
+    writer: Option<RecordWriter>,
+}
+
+impl NibbleDisplay {
+    fn new(bail: bool, file_offset: bool, format: Format) -> NibbleDisplay {
+        NibbleDisplay {
+            offsets: HashMap::new(),
+            cur_id: Some(0xFF), // Intentionally set to an invalid Stream ID.
+            cur_offset: 0,
+            column: 0,
+            bail,
+            file_offset,
+            first_stream: true,
+            writer: match format {
+                Format::Text => None,
+                f => Some(RecordWriter::new(f)),
+            },
+        }
+    }
+
+    fn display_data(&mut self, id: Option<u8>, data: u8, offset: usize) {
+        if let Some(writer) = &mut self.writer {
+            let o = self.offsets.entry(id).or_insert(0);
+            writer.write(&Record {
+                stream: Some(id),
+                offset: Some(*o * 2),
+                span: Some(2),
+                file_offset: Some(offset),
+                payload: Some(data as u64),
+                ..Record::new("data")
+            });
+            *o += 1;
+            return;
+        }
+
+        if id != self.cur_id {
+            self.offsets.insert(self.cur_id, self.cur_offset);
+            self.cur_offset = *self.offsets.entry(id).or_insert(0);
+            self.column = 0;
+            self.cur_id = id;
+            if self.first_stream {
+                self.first_stream = false;
+            } else {
+                print!("\n\n");
+            }
+            match id {
+                None => print!("Stream None:"),
+                Some(id) => print!("Stream {:#X}:", id),
+            }
+        }
+
+        if self.column.is_multiple_of(16) {
+            let o = if self.file_offset {
+                offset
+            } else {
+                self.cur_offset * 2
+            };
+            print!("\n{:012X} |", o);
+            self.column = 0;
+        } else if self.column == 8 {
+            print!(" ");
+        }
+        print!(" {:x} {:x}", data & 0xF, data >> 4);
+
+        self.column += 1;
+        self.cur_offset += 1;
+    }
+
+    fn display_error(&mut self, err: parsers::Error, event: bool) {
+        if let Some(writer) = &mut self.writer {
+            writer.write(&Record::from_twp_error(&err, event));
+            return;
+        }
+
+        let msg = format!("** {}", err);
+        let msg = if event {
+            msg.yellow().bold()
+        } else {
+            msg.red().bold()
+        };
+        print!("\n\n{}\n", msg);
+        self.column = 0;
+    }
+
+    fn display_event(&mut self, event: &parsers::Event, offset: usize) {
+        match &mut self.writer {
+            Some(writer) => writer.write(&Record::from_twp_event(event, offset)),
+            None => {
+                print!("\n\n{}\n", event_text(event, offset).yellow().bold());
+                self.column = 0;
+            }
+        }
+    }
+
+    fn display(&mut self, r: parsers::Result<parsers::Data>) -> parsers::Result<()> {
+        match r {
+            Ok(parsers::Data {
+                event: Some(e),
+                offset,
+                ..
+            }) => {
+                self.display_event(&e, offset);
+                Ok(())
+            }
+            Ok(d) => {
+                self.display_data(d.id, d.data, d.offset);
+                Ok(())
+            }
+            // Events are informational and never stop the decode.
+            Err(e) if is_event(&e.reason) => {
+                self.display_error(e, true);
+                Ok(())
+            }
+            Err(e) => {
+                let offset = e.offset;
+                self.display_error(e, false);
+                if self.bail {
+                    Err(parsers::Error {
+                        offset,
+                        reason: parsers::ErrorReason::Stop,
+                    })
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn end(&self) {
+        if self.writer.is_none() {
+            println!();
+        }
+    }
+}
@@ -0,0 +1,575 @@
+//! Machine-readable output records, and the display the log line subcommands share.
+//!
+//! Every subcommand can emit its results as JSON Lines or CSV instead of text.  Each result is one
+//! record with the columns listed in `COLUMNS`, after a `schema` record giving `SCHEMA_VERSION`;
+//! see the README for their meaning.
+
+use crate::messages::parse_number;
+use crate::{event_text, get_names, is_event, parse_ranges, CliError};
+use clap::{Arg, ArgMatches};
+use colored::*;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::result;
+use stp_core::message::MessageTracker;
+use stp_core::names::Names;
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::reassembler::Reassembler;
+use stp_core::stp::{self, Timestamp};
+use stp_core::stp_decoder;
+use stp_core::syst;
+use stp_core::text::TextCollector;
+use twp::parsers;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Jsonl,
+    Csv,
+}
+
+pub fn format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("format")
+        .long("format")
+        .takes_value(true)
+        .possible_values(&["text", "jsonl", "csv"])
+        .default_value("text")
+        .help("Output format.")
+}
+
+pub fn get_format(sub_m: &ArgMatches) -> Format {
+    match sub_m.value_of("format") {
+        Some("jsonl") => Format::Jsonl,
+        Some("csv") => Format::Csv,
+        _ => Format::Text,
+    }
+}
+
+/// The version of the record schema, written in the `schema` record that starts the output.  It
+/// changes when a column is removed, renamed or changes meaning; new columns are only appended.
+pub const SCHEMA_VERSION: &str = "1";
+
+pub const COLUMNS: [&str; 28] = [
+    "record",
+    "stream",
+    "offset",
+    "span",
+    "file_offset",
+    "opcode",
+    "master",
+    "channel",
+    "payload",
+    "length",
+    "timestamp",
+    "ts_type",
+    "ts_length",
+    "version",
+    "is_le",
+    "frequency",
+    "error",
+    "stat",
+    "count",
+    "arrival",
+    "master_name",
+    "channel_name",
+    "value",
+    "severity",
+    "origin",
+    "location",
+    "text",
+    "symbol",
+];
+
+pub enum Value {
+    Int(u64),
+    Str(String),
+    Bool(bool),
+    Null,
+}
+
+/// A single output record.  Fields that are None are omitted (JSON) or left empty (CSV).
+#[derive(Default)]
+pub struct Record {
+    pub record: &'static str,
+    pub stream: Option<Option<u8>>, // Some(None) if the stream ID is unknown.
+    pub offset: Option<usize>,
+    pub span: Option<usize>,
+    pub file_offset: Option<usize>,
+    pub opcode: Option<String>,
+    pub master: Option<u16>,
+    pub channel: Option<u16>,
+    pub payload: Option<u64>,
+    pub length: Option<u8>,
+    pub timestamp: Option<u64>,
+    pub ts_type: Option<&'static str>,
+    pub ts_length: Option<u8>,
+    pub version: Option<&'static str>,
+    pub is_le: Option<bool>,
+    pub frequency: Option<u64>,
+    pub error: Option<String>,
+    pub stat: Option<&'static str>,
+    pub count: Option<u64>,
+    pub arrival: Option<u64>, // Microseconds since the Unix epoch.
+    pub master_name: Option<String>,
+    pub channel_name: Option<String>,
+    pub value: Option<String>, // The payload in its configured format.
+    pub severity: Option<String>,
+    pub origin: Option<String>,
+    pub location: Option<String>,
+    pub text: Option<String>,
+    pub symbol: Option<String>, // The function a code address is in.
+}
+
+impl Record {
+    pub fn new(record: &'static str) -> Record {
+        Record {
+            record,
+            ..Record::default()
+        }
+    }
+
+    fn values(&self) -> [Option<Value>; 28] {
+        let int = |v: Option<u64>| v.map(Value::Int);
+        [
+            Some(Value::Str(self.record.to_string())),
+            self.stream
+                .map(|s| s.map_or(Value::Null, |id| Value::Int(id as u64))),
+            int(self.offset.map(|v| v as u64)),
+            int(self.span.map(|v| v as u64)),
+            int(self.file_offset.map(|v| v as u64)),
+            self.opcode.clone().map(Value::Str),
+            int(self.master.map(|v| v as u64)),
+            int(self.channel.map(|v| v as u64)),
+            int(self.payload),
+            int(self.length.map(|v| v as u64)),
+            int(self.timestamp),
+            self.ts_type.map(|v| Value::Str(v.to_string())),
+            int(self.ts_length.map(|v| v as u64)),
+            self.version.map(|v| Value::Str(v.to_string())),
+            self.is_le.map(Value::Bool),
+            int(self.frequency),
+            self.error.clone().map(Value::Str),
+            self.stat.map(|v| Value::Str(v.to_string())),
+            int(self.count),
+            int(self.arrival),
+            self.master_name.clone().map(Value::Str),
+            self.channel_name.clone().map(Value::Str),
+            self.value.clone().map(Value::Str),
+            self.severity.clone().map(Value::Str),
+            self.origin.clone().map(Value::Str),
+            self.location.clone().map(Value::Str),
+            self.text.clone().map(Value::Str),
+            self.symbol.clone().map(Value::Str),
+        ]
+    }
+
+    /// Build a record from a TWP error.  Buffer wrap discontinuities are reported as "event"
+    /// records.
+    pub fn from_twp_error(err: &parsers::Error, event: bool) -> Record {
+        Record {
+            file_offset: Some(err.offset),
+            error: Some(err.reason.to_string()),
+            ..Record::new(if event { "event" } else { "error" })
+        }
+    }
+
+    /// Build a record from a TWP event (a trigger or reserved stream ID).
+    pub fn from_twp_event(event: &parsers::Event, offset: usize) -> Record {
+        Record {
+            file_offset: Some(offset),
+            error: Some(event.to_string()),
+            ..Record::new("event")
+        }
+    }
+
+    /// Build a record from an STP decoder error.
+    pub fn from_stp_error(id: Option<u8>, err: &stp_decoder::Error) -> Record {
+        Record {
+            stream: Some(id),
+            offset: Some(err.start),
+            span: Some(err.span),
+            error: Some(err.reason.to_string()),
+            ..Record::new("error")
+        }
+    }
+
+    /// Build a record from an STP packet.
+    pub fn from_packet(id: Option<u8>, packet: &stp_decoder::Packet) -> Record {
+        let mut r = Record {
+            stream: Some(id),
+            offset: Some(packet.start),
+            span: Some(packet.span),
+            ..Record::new("packet")
+        };
+
+        r.opcode = Some(opcode_name(&packet.packet));
+        let timestamp = match &packet.packet {
+            stp::Packet::Async => &None,
+            stp::Packet::Null { timestamp } | stp::Packet::Flag { timestamp } => timestamp,
+            stp::Packet::Version {
+                version,
+                ts_type,
+                is_le,
+            } => {
+                r.version = Some(version_name(version));
+                r.ts_type = Some(ts_type_name(*ts_type));
+                r.is_le = Some(*is_le);
+                &None
+            }
+            stp::Packet::Master { master, .. } => {
+                r.master = Some(*master);
+                &None
+            }
+            stp::Packet::Channel { channel, .. } => {
+                r.channel = Some(*channel);
+                &None
+            }
+            stp::Packet::Data {
+                data, timestamp, ..
+            } => {
+                r.payload = Some(*data);
+                timestamp
+            }
+            stp::Packet::User {
+                length,
+                payload,
+                timestamp,
+            } => {
+                r.payload = Some(*payload);
+                r.length = Some(*length);
+                timestamp
+            }
+            stp::Packet::Frequency {
+                frequency,
+                timestamp,
+                ..
+            } => {
+                r.frequency = Some(*frequency);
+                timestamp
+            }
+            stp::Packet::Error { data, .. } => {
+                r.payload = Some(*data as u64);
+                &None
+            }
+        };
+
+        if let Some(ts) = timestamp {
+            let (ts_type, length, value) = timestamp_fields(ts);
+            r.ts_type = Some(ts_type);
+            r.ts_length = length;
+            r.timestamp = Some(value);
+        }
+        r
+    }
+}
+
+/// The packet's opcode name, "ASYNC" for ASYNC packets.
+pub fn opcode_name(packet: &stp::Packet) -> String {
+    match packet.opcode() {
+        Some(opcode) => format!("{:?}", opcode),
+        None => "ASYNC".to_string(),
+    }
+}
+
+pub fn version_name(version: &stp::StpVersion) -> &'static str {
+    match version {
+        stp::StpVersion::STPv1 => "STPv1",
+        stp::StpVersion::STPv2_1 => "STPv2.1",
+        stp::StpVersion::STPv2_2 => "STPv2.2",
+    }
+}
+
+pub fn ts_type_name(ts_type: stp::TimestampType) -> &'static str {
+    match ts_type {
+        stp::TimestampType::STPv1LEGACY => "STPv1LEGACY",
+        stp::TimestampType::STPv2NATDELTA => "STPv2NATDELTA",
+        stp::TimestampType::STPv2NAT => "STPv2NAT",
+        stp::TimestampType::STPv2GRAY => "STPv2GRAY",
+    }
+}
+
+// Returns the timestamp's type name, length (in nibbles) and value.
+pub fn timestamp_fields(ts: &Timestamp) -> (&'static str, Option<u8>, u64) {
+    match ts {
+        Timestamp::STPv1 { value } => ("STPv1LEGACY", None, *value as u64),
+        Timestamp::STPv2NATDELTA { length, value } => ("STPv2NATDELTA", Some(*length), *value),
+        Timestamp::STPv2NAT { length, value } => ("STPv2NAT", Some(*length), *value),
+        Timestamp::STPv2GRAY { length, value } => ("STPv2GRAY", Some(*length), *value),
+    }
+}
+
+/// Writes records to stdout in the chosen format.
+pub struct RecordWriter {
+    format: Format,
+}
+
+impl RecordWriter {
+    pub fn new(format: Format) -> RecordWriter {
+        if format == Format::Csv {
+            println!("{}", COLUMNS.join(","));
+        }
+        let mut writer = RecordWriter { format };
+        writer.write(&Record {
+            version: Some(SCHEMA_VERSION),
+            ..Record::new("schema")
+        });
+        writer
+    }
+
+    /// A writer adding records to output another writer has started (no CSV header).
+    pub fn continued(format: Format) -> RecordWriter {
+        RecordWriter { format }
+    }
+
+    pub fn write(&mut self, record: &Record) {
+        match self.format {
+            Format::Jsonl => println!("{}", to_json(record)),
+            Format::Csv => println!("{}", to_csv(record)),
+            Format::Text => panic!("Records are not written as text"),
+        }
+    }
+}
+
+/// Reports what happens to the input itself, e.g. a followed file being truncated, as `event` and
+/// `error` records, or as text on stderr.
+pub struct Notices {
+    writer: Option<RecordWriter>,
+}
+
+impl Notices {
+    pub fn new(format: Format) -> Notices {
+        Notices {
+            writer: match format {
+                Format::Text => None,
+                f => Some(RecordWriter::continued(f)),
+            },
+        }
+    }
+
+    /// Report an event, after `offset` bytes of input.
+    pub fn event(&mut self, text: String, offset: usize) {
+        self.write("event", text, offset);
+    }
+
+    /// Report an error, after `offset` bytes of input.
+    pub fn error(&mut self, text: String, offset: usize) {
+        self.write("error", text, offset);
+    }
+
+    fn write(&mut self, record: &'static str, text: String, offset: usize) {
+        match &mut self.writer {
+            Some(writer) => writer.write(&Record {
+                file_offset: Some(offset),
+                error: Some(text),
+                ..Record::new(record)
+            }),
+            None => eprintln!("** {}", text),
+        }
+    }
+}
+
+fn to_json(record: &Record) -> String {
+    let fields: Vec<String> = COLUMNS
+        .iter()
+        .zip(record.values().iter())
+        .filter_map(|(name, value)| {
+            value.as_ref().map(|v| {
+                let v = match v {
+                    Value::Int(i) => JsonValue::from(*i),
+                    Value::Str(s) => JsonValue::from(s.as_str()),
+                    Value::Bool(b) => JsonValue::from(*b),
+                    Value::Null => JsonValue::Null,
+                };
+                format!("\"{}\":{}", name, v)
+            })
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn to_csv(record: &Record) -> String {
+    let fields: Vec<String> = record
+        .values()
+        .iter()
+        .map(|value| match value {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::Int(i)) => i.to_string(),
+            Some(Value::Bool(b)) => b.to_string(),
+            Some(Value::Str(s)) => csv_string(s),
+        })
+        .collect();
+    fields.join(",")
+}
+
+fn csv_string(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// The decoding state the log line subcommands keep for each TWP stream.
+pub trait StreamState: Default {
+    /// Where `Lines` finds the timestamp frequency to show the stream's times in microseconds.
+    fn tracker(&self) -> &MessageTracker;
+}
+
+impl StreamState for MessageTracker {
+    fn tracker(&self) -> &MessageTracker {
+        self
+    }
+}
+
+impl StreamState for TextCollector {
+    fn tracker(&self) -> &MessageTracker {
+        self.tracker()
+    }
+}
+
+impl StreamState for Reassembler {
+    fn tracker(&self) -> &MessageTracker {
+        self.tracker()
+    }
+}
+
+impl StreamState for syst::Decoder {
+    fn tracker(&self) -> &MessageTracker {
+        self.tracker()
+    }
+}
+
+/// The time at the start of a log line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Time {
+    Micros(f64),
+    Ticks(u64),
+    Unknown,
+}
+
+impl Time {
+    /// Microseconds, or ticks if the frequency is unknown.
+    pub fn value(self) -> Option<f64> {
+        match self {
+            Time::Micros(us) => Some(us),
+            Time::Ticks(ts) => Some(ts as f64),
+            Time::Unknown => None,
+        }
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Time::Micros(us) => write!(f, "[{:>14.6} us]", us),
+            Time::Ticks(ts) => write!(f, "[{:>14} ts]", ts),
+            Time::Unknown => write!(f, "[{:>14}   ]", "?"),
+        }
+    }
+}
+
+/// What the log line subcommands (`messages`, `systs`, `text` and `logs`) share: the decoding
+/// state of each TWP stream, the masters and channels shown, the frequency and names, and the
+/// record writer if records are written instead of text.
+///
+/// Lines are shown as each stream completes them, so the lines of different streams follow the
+/// order of the input, not of their timestamps.
+pub struct Lines<T> {
+    pub streams: BTreeMap<Option<u8>, T>,
+    pub names: Names,
+    pub writer: Option<RecordWriter>,
+    masters: Option<Vec<RangeInclusive<usize>>>,
+    channels: Option<Vec<RangeInclusive<usize>>>,
+    frequency: Option<f64>, // Overrides the frequency reported by the trace.
+}
+
+impl<T: StreamState> Lines<T> {
+    pub fn new(sub_m: &ArgMatches) -> result::Result<Self, CliError> {
+        let ranges = |name| sub_m.value_of(name).map(parse_ranges).transpose();
+        Ok(Lines {
+            streams: BTreeMap::new(),
+            names: get_names(sub_m)?,
+            writer: match get_format(sub_m) {
+                Format::Text => None,
+                f => Some(RecordWriter::new(f)),
+            },
+            masters: ranges("master")?,
+            channels: ranges("channel")?,
+            frequency: sub_m.value_of("frequency").map(parse_number).transpose()?,
+        })
+    }
+
+    /// Are lines written to the master and channel shown?
+    pub fn shows(&self, master: u16, channel: u16) -> bool {
+        let in_ranges = |ranges: &Option<Vec<RangeInclusive<usize>>>, v: u16| match ranges {
+            Some(ranges) => ranges.iter().any(|r| r.contains(&(v as usize))),
+            None => true,
+        };
+        in_ranges(&self.masters, master) && in_ranges(&self.channels, channel)
+    }
+
+    /// The master, channel and timestamp state of a stream.
+    pub fn tracker(&self, id: Option<u8>) -> &MessageTracker {
+        self.streams[&id].tracker()
+    }
+
+    /// A timestamp of a stream: in microseconds if the frequency is known, otherwise in ticks.
+    pub fn time(&self, id: Option<u8>, timestamp: Option<u64>) -> Time {
+        let frequency = self
+            .frequency
+            .or_else(|| self.tracker(id).frequency().map(|f| f as f64))
+            .filter(|f| *f > 0.0);
+        match (timestamp, frequency) {
+            (Some(ts), Some(f)) => Time::Micros(ts as f64 * 1e6 / f),
+            (Some(ts), None) => Time::Ticks(ts),
+            (None, _) => Time::Unknown,
+        }
+    }
+
+    /// Report a decoding error or TWP event from the pipeline, returning the offset of errors.
+    pub fn report(&mut self, r: &parsers::Result<Output>, pipeline: &Pipeline) -> Option<usize> {
+        match r {
+            Ok(Output::Packet(_, Ok(_))) => None,
+            Ok(Output::Packet(id, Err(e))) => {
+                match &mut self.writer {
+                    Some(writer) => writer.write(&Record {
+                        file_offset: pipeline.file_offset(*id, e.start),
+                        ..Record::from_stp_error(*id, e)
+                    }),
+                    None => println!("{}", format!("** {}", e).red().bold()),
+                }
+                Some(e.start)
+            }
+            Ok(Output::Event(e, offset)) => {
+                match &mut self.writer {
+                    Some(writer) => writer.write(&Record::from_twp_event(e, *offset)),
+                    None => println!("{}", event_text(e, *offset).yellow().bold()),
+                }
+                None
+            }
+            Err(e) => {
+                let event = is_event(&e.reason);
+                match &mut self.writer {
+                    Some(writer) => writer.write(&Record::from_twp_error(e, event)),
+                    None if event => println!("{}", format!("** {}", e).yellow().bold()),
+                    None => println!("{}", format!("** {}", e).red().bold()),
+                }
+                Some(e.offset).filter(|_| !event)
+            }
+        }
+    }
+
+    /// Finish every stream when the input ends, returning what each one was still holding.
+    pub fn finish<R, F>(&mut self, mut f: F) -> Vec<(Option<u8>, R)>
+    where
+        F: FnMut(&mut T) -> Vec<R>,
+    {
+        let mut results = Vec::new();
+        for (id, s) in self.streams.iter_mut() {
+            results.extend(f(s).into_iter().map(|r| (*id, r)));
+        }
+        results
+    }
+}
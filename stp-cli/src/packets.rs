@@ -0,0 +1,233 @@
+//! The `packets` subcommand: displays the STP packets found on each TWP stream.
+
+use crate::output::{get_format, Format, Record, RecordWriter};
+use crate::{decode_pipeline, event_text, get_frame_decoder, get_input, get_names, get_symbols};
+use crate::{is_event, Result, OFFSET_WINDOW};
+use clap::ArgMatches;
+use colored::*;
+use std::collections::BTreeMap;
+use stp_core::message::{Message, MessageTracker};
+use stp_core::names::Names;
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::stp;
+use stp_core::symbols::{Symbol, Symbols};
+use twp::parsers;
+
+pub fn packets(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let format = get_format(sub_m);
+    let file_offset = sub_m.is_present("file_offsets");
+    let mut display = PacketDisplay::new(sub_m.is_present("bail"), file_offset, format);
+    display.symbols = get_symbols(sub_m)?;
+    display.names = get_names(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    if file_offset || format != Format::Text {
+        pipeline = pipeline.track_recent_offsets(OFFSET_WINDOW);
+    }
+
+    decode_pipeline(&mut input, &mut pipeline, |r, p| display.display(r, p))
+}
+
+type Named = (Option<u16>, Option<u16>, Option<String>); // Master, channel and value.
+
+struct PacketDisplay {
+    bail: bool,
+    file_offset: bool,
+    symbols: Symbols,
+    names: Names,
+    trackers: BTreeMap<Option<u8>, MessageTracker>, // To know the master and channel written to.
+    writer: Option<RecordWriter>,
+}
+
+impl PacketDisplay {
+    fn new(bail: bool, file_offset: bool, format: Format) -> PacketDisplay {
+        PacketDisplay {
+            bail,
+            file_offset,
+            symbols: Symbols::new(),
+            names: Names::new(),
+            trackers: BTreeMap::new(),
+            writer: match format {
+                Format::Text => None,
+                f => Some(RecordWriter::new(f)),
+            },
+        }
+    }
+
+    fn display_stream(id: Option<u8>) -> String {
+        match id {
+            None => "None".to_string(),
+            Some(id) => format!("{:#04X}", id),
+        }
+    }
+
+    fn display_offset(&self, id: Option<u8>, start: usize, pipeline: &Pipeline) -> String {
+        if !self.file_offset {
+            format!("{:012X}", start)
+        } else {
+            match pipeline.file_offset(id, start) {
+                Some(o) => format!("{:012X}", o),
+                None => format!("{:>12}", "?"),
+            }
+        }
+    }
+
+    // The message a data packet writes, if ELF files or names were given:
+    fn message(&mut self, r: &parsers::Result<Output>) -> Option<Message> {
+        if self.symbols.is_empty() && self.names.is_empty() {
+            return None;
+        }
+        match r {
+            Ok(Output::Packet(id, Ok(p))) => self.trackers.entry(*id).or_default().process(p),
+            Ok(Output::Packet(id, Err(_))) => {
+                self.trackers.entry(*id).or_default().reset();
+                None
+            }
+            Ok(Output::Event(..)) | Err(_) => None,
+        }
+    }
+
+    // The master and channel a packet selects or writes to, and the data written, if names were
+    // given:
+    fn named(&self, r: &parsers::Result<Output>, m: &Option<Message>) -> Named {
+        if self.names.is_empty() {
+            return (None, None, None);
+        }
+        let (id, p) = match r {
+            Ok(Output::Packet(id, Ok(p))) => (id, p),
+            _ => return (None, None, None),
+        };
+        match (&p.packet, m) {
+            (stp::Packet::Master { master, .. }, _) => (Some(*master), None, None),
+            (stp::Packet::Channel { channel, .. }, _) => {
+                let master = self.trackers.get(id).map_or(0, |t| t.master());
+                (Some(master), Some(*channel), None)
+            }
+            (_, Some(m)) => {
+                let value = match (m.data, m.opcode.data_bits()) {
+                    (Some(d), Some(bits)) => {
+                        Some(self.names.format_data(m.master, m.channel, d, bits))
+                    }
+                    _ => None,
+                };
+                (Some(m.master), Some(m.channel), value)
+            }
+            _ => (None, None, None),
+        }
+    }
+
+    fn write_record(
+        &mut self,
+        r: &parsers::Result<Output>,
+        symbol: &Option<Symbol>,
+        (master, channel, value): &Named,
+        pipeline: &Pipeline,
+    ) -> bool {
+        let names = &self.names;
+        let writer = match &mut self.writer {
+            Some(w) => w,
+            None => return false,
+        };
+        let record = match r {
+            Ok(Output::Packet(id, Ok(p))) => Record {
+                file_offset: pipeline.file_offset(*id, p.start),
+                symbol: symbol.as_ref().map(|s| s.to_string()),
+                master_name: master.and_then(|m| names.master(m)).map(|n| n.name.clone()),
+                channel_name: master
+                    .zip(*channel)
+                    .and_then(|(m, c)| names.channel(m, c))
+                    .map(|n| n.name.clone()),
+                value: value.clone(),
+                ..Record::from_packet(*id, p)
+            },
+            Ok(Output::Packet(id, Err(e))) => Record {
+                file_offset: pipeline.file_offset(*id, e.start),
+                ..Record::from_stp_error(*id, e)
+            },
+            Ok(Output::Event(e, offset)) => Record::from_twp_event(e, *offset),
+            Err(e) => Record::from_twp_error(e, is_event(&e.reason)),
+        };
+        writer.write(&record);
+        true
+    }
+
+    // How the master and channel are named, e.g. " modem tx 12 mV":
+    fn display_names(&self, (master, channel, value): &Named) -> String {
+        let mut labels = Vec::new();
+        if let Some(m) = *master {
+            labels.push(self.names.master_label(m));
+            if let Some(c) = *channel {
+                labels.push(self.names.channel_label(m, c));
+            }
+        }
+        labels.extend(value.clone());
+        labels.iter().map(|l| format!(" {}", l)).collect()
+    }
+
+    fn display(&mut self, r: parsers::Result<Output>, pipeline: &Pipeline) -> parsers::Result<()> {
+        let message = self.message(&r);
+        let symbol = message
+            .as_ref()
+            .and_then(|m| self.symbols.resolve_message(m));
+        let named = self.named(&r, &message);
+        let written = self.write_record(&r, &symbol, &named, pipeline);
+        match r {
+            Ok(Output::Packet(id, Ok(p))) => {
+                if !written {
+                    println!(
+                        "{:>4} | {} | {:?}{}{}",
+                        Self::display_stream(id),
+                        self.display_offset(id, p.start, pipeline),
+                        p.packet,
+                        self.display_names(&named),
+                        symbol.map_or(String::new(), |s| format!(" <{}>", s))
+                    );
+                }
+                Ok(())
+            }
+            Ok(Output::Packet(id, Err(e))) => {
+                if !written {
+                    let msg = format!("** {}", e.reason);
+                    println!(
+                        "{:>4} | {} | {}",
+                        Self::display_stream(id),
+                        self.display_offset(id, e.start, pipeline),
+                        msg.red().bold()
+                    );
+                }
+                self.check_bail(e.start)
+            }
+            Ok(Output::Event(e, offset)) => {
+                if !written {
+                    println!("{}", event_text(&e, offset).yellow().bold());
+                }
+                Ok(())
+            }
+            Err(e) if is_event(&e.reason) => {
+                if !written {
+                    let msg = format!("** {}", e);
+                    println!("{}", msg.yellow().bold());
+                }
+                Ok(())
+            }
+            Err(e) => {
+                if !written {
+                    let msg = format!("** {}", e);
+                    println!("{}", msg.red().bold());
+                }
+                self.check_bail(e.offset)
+            }
+        }
+    }
+
+    fn check_bail(&self, offset: usize) -> parsers::Result<()> {
+        if self.bail {
+            Err(parsers::Error {
+                offset,
+                reason: parsers::ErrorReason::Stop,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! The `stats` subcommand: summarizes a capture.
+
+use crate::output::{get_format, ts_type_name, version_name, Format, Record, RecordWriter};
+use crate::{decode_pipeline, get_frame_decoder, get_input, get_names, parse_offset, Result};
+use clap::ArgMatches;
+use std::collections::BTreeMap;
+use stp_core::names::{Name, Names};
+use stp_core::pipeline::Pipeline;
+use stp_core::stats::{Stats, StpStats, Usage};
+
+pub fn stats(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let top = match sub_m.value_of("top") {
+        Some(n) => parse_offset(n)?,
+        None => 10,
+    };
+    let names = get_names(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    let mut stats = Stats::new();
+
+    decode_pipeline(&mut input, &mut pipeline, |r, _| {
+        stats.record(&r);
+        Ok(())
+    })?;
+    stats.finish(&pipeline);
+
+    match get_format(sub_m) {
+        Format::Text => display(&stats, top, &names),
+        f => write_records(&mut RecordWriter::new(f), &stats, top, &names),
+    }
+    Ok(())
+}
+
+fn stream_name(id: Option<u8>) -> String {
+    match id {
+        None => "None".to_string(),
+        Some(id) => format!("{:#04X}", id),
+    }
+}
+
+// The `top` busiest entries, by bytes written:
+fn busiest<K: Copy>(usage: &BTreeMap<K, Usage>, top: usize) -> Vec<(K, Usage)> {
+    let mut v: Vec<(K, Usage)> = usage.iter().map(|(k, u)| (*k, *u)).collect();
+    v.sort_by(|a, b| b.1.bits.cmp(&a.1.bits).then(b.1.writes.cmp(&a.1.writes)));
+    v.truncate(top);
+    v
+}
+
+fn display(stats: &Stats, top: usize, names: &Names) {
+    let frames = &stats.frames;
+    println!("TWP");
+    println!("  {:<24}{:>12}", "Frames", frames.frames);
+    println!("  {:<24}{:>12}", "FSYNCs", frames.fsyncs);
+    println!("  {:<24}{:>12}", "Halfword syncs", frames.hsyncs);
+    for (name, count) in stats.twp_errors.iter().chain(&stats.twp_events) {
+        println!("  {:<24}{:>12}", name, count);
+    }
+
+    println!();
+    println!("  {:<12}{:>12}{:>12}", "Stream", "Frames", "Bytes");
+    for (id, s) in &frames.streams {
+        println!("  {:<12}{:>12}{:>12}", stream_name(*id), s.frames, s.bytes);
+    }
+
+    for (id, s) in &stats.streams {
+        println!();
+        println!("STP stream {}", stream_name(*id));
+        display_stream(s, top, names);
+    }
+}
+
+fn display_stream(s: &StpStats, top: usize, names: &Names) {
+    println!("  {:<24}{:>12}", "ASYNC", s.asyncs);
+    for ((version, ts_type), count) in &s.versions {
+        let name = format!("{} {}", version_name(version), ts_type_name(*ts_type));
+        println!("  {:<24}{:>12}", name, count);
+    }
+
+    if !s.packets.is_empty() {
+        println!("  Packets");
+        for (opcode, count) in &s.packets {
+            println!("    {:<22}{:>12}", format!("{:?}", opcode), count);
+        }
+    }
+
+    if !s.errors.is_empty() {
+        println!("  Errors");
+        for (name, count) in &s.errors {
+            println!("    {:<22}{:>12}", name, count);
+        }
+    }
+
+    if !s.masters.is_empty() {
+        // Names, if any, follow the numbers:
+        let named = |name: Option<&Name>| match name {
+            Some(n) => format!("  {}", n.name),
+            None => String::new(),
+        };
+        let heading = if names.is_empty() { "" } else { "  Name" };
+        println!("  Busiest masters");
+        println!(
+            "    {:<8}{:>8}{:>12}{:>12}{}",
+            "Master", "", "Writes", "Bytes", heading
+        );
+        for (master, u) in busiest(&s.masters, top) {
+            println!(
+                "    {:<8}{:>8}{:>12}{:>12}{}",
+                master,
+                "",
+                u.writes,
+                u.bits / 8,
+                named(names.master(master))
+            );
+        }
+        println!("  Busiest channels");
+        println!(
+            "    {:<8}{:>8}{:>12}{:>12}{}",
+            "Master", "Channel", "Writes", "Bytes", heading
+        );
+        for ((master, channel), u) in busiest(&s.channels, top) {
+            println!(
+                "    {:<8}{:>8}{:>12}{:>12}{}",
+                master,
+                channel,
+                u.writes,
+                u.bits / 8,
+                named(names.channel(master, channel))
+            );
+        }
+    }
+}
+
+fn write_records(writer: &mut RecordWriter, stats: &Stats, top: usize, names: &Names) {
+    let stat = |stat, count: usize| Record {
+        stat: Some(stat),
+        count: Some(count as u64),
+        ..Record::new("stat")
+    };
+
+    writer.write(&stat("frames", stats.frames.frames));
+    writer.write(&stat("fsyncs", stats.frames.fsyncs));
+    writer.write(&stat("hsyncs", stats.frames.hsyncs));
+    for (stat_name, counts) in [
+        ("twp_errors", &stats.twp_errors),
+        ("twp_events", &stats.twp_events),
+    ] {
+        for (name, count) in counts {
+            writer.write(&Record {
+                error: Some(name.to_string()),
+                ..stat(stat_name, *count)
+            });
+        }
+    }
+    for (id, s) in &stats.frames.streams {
+        for (name, count) in [("stream_frames", s.frames), ("stream_bytes", s.bytes)] {
+            writer.write(&Record {
+                stream: Some(*id),
+                ..stat(name, count)
+            });
+        }
+    }
+
+    for (id, s) in &stats.streams {
+        let stream = Some(*id);
+        writer.write(&Record {
+            stream,
+            ..stat("asyncs", s.asyncs)
+        });
+        for ((version, ts_type), count) in &s.versions {
+            writer.write(&Record {
+                stream,
+                version: Some(version_name(version)),
+                ts_type: Some(ts_type_name(*ts_type)),
+                ..stat("versions", *count)
+            });
+        }
+        for (opcode, count) in &s.packets {
+            writer.write(&Record {
+                stream,
+                opcode: Some(format!("{:?}", opcode)),
+                ..stat("packets", *count)
+            });
+        }
+        for (name, count) in &s.errors {
+            writer.write(&Record {
+                stream,
+                error: Some(name.to_string()),
+                ..stat("stp_errors", *count)
+            });
+        }
+        for (master, u) in busiest(&s.masters, top) {
+            for (name, count) in [
+                ("master_writes", u.writes),
+                ("master_bytes", u.bits as usize / 8),
+            ] {
+                writer.write(&Record {
+                    stream,
+                    master: Some(master),
+                    master_name: names.master(master).map(|n| n.name.clone()),
+                    ..stat(name, count)
+                });
+            }
+        }
+        for ((master, channel), u) in busiest(&s.channels, top) {
+            for (name, count) in [
+                ("channel_writes", u.writes),
+                ("channel_bytes", u.bits as usize / 8),
+            ] {
+                writer.write(&Record {
+                    stream,
+                    master: Some(master),
+                    channel: Some(channel),
+                    master_name: names.master(master).map(|n| n.name.clone()),
+                    channel_name: names.channel(master, channel).map(|n| n.name.clone()),
+                    ..stat(name, count)
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! The `systs` subcommand: displays MIPI SyS-T messages as log lines.
+
+use crate::output::{get_format, Format, Lines, Record};
+use crate::{decode_pipeline, get_frame_decoder, get_input, CliError, Result, OFFSET_WINDOW};
+use clap::ArgMatches;
+use colored::*;
+use std::path::Path;
+use std::result;
+use stp_core::catalog::Catalog;
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::syst::{self, Decoder, Location, Severity};
+use twp::parsers;
+
+pub fn systs(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    if get_format(sub_m) != Format::Text {
+        pipeline = pipeline.track_recent_offsets(OFFSET_WINDOW);
+    }
+
+    let mut display = SystDisplay {
+        catalog: get_catalog(sub_m)?,
+        lines: Lines::new(sub_m)?,
+    };
+
+    decode_pipeline(&mut input, &mut pipeline, |r, p| {
+        display.display(r, p);
+        Ok(())
+    })?;
+
+    // Messages still being written when the input ends:
+    for (id, e) in display.lines.finish(|d| d.reset()) {
+        display.display_error(id, &e, &pipeline);
+    }
+    Ok(())
+}
+
+fn get_catalog(sub_m: &ArgMatches) -> result::Result<Catalog, CliError> {
+    let mut catalog = Catalog::new();
+    if let Some(size) = sub_m.value_of("pointer_size") {
+        catalog = catalog.pointer_size(size.parse().unwrap());
+    }
+    for path in sub_m.values_of("catalog").into_iter().flatten() {
+        catalog
+            .load(Path::new(path))
+            .map_err(|e| CliError(Some(e)))?;
+    }
+    Ok(catalog)
+}
+
+struct SystDisplay {
+    catalog: Catalog,
+    lines: Lines<Decoder>,
+}
+
+impl SystDisplay {
+    fn display_message(&mut self, id: Option<u8>, r: syst::Result, pipeline: &Pipeline) {
+        let m = match r {
+            Ok(m) => m,
+            Err(e) => return self.display_error(id, &e, pipeline),
+        };
+        let text = self.catalog.text(&m);
+        let location = m.location.map(|l| match l {
+            Location::File { file, line } => match self.catalog.file(&m.origin, file) {
+                Some(file) => format!("{}:{}", file, line),
+                None => l.to_string(),
+            },
+            l => l.to_string(),
+        });
+        let time = self.lines.time(id, m.stp_timestamp);
+        let frequency = self.lines.tracker(id).frequency();
+        let names = &self.lines.names;
+        if let Some(writer) = &mut self.lines.writer {
+            writer.write(&Record {
+                stream: Some(id),
+                offset: Some(m.start),
+                file_offset: pipeline.file_offset(id, m.start),
+                master: Some(m.master),
+                channel: Some(m.channel),
+                timestamp: m.stp_timestamp,
+                frequency,
+                master_name: names.master(m.master).map(|n| n.name.clone()),
+                channel_name: names.channel(m.master, m.channel).map(|n| n.name.clone()),
+                severity: Some(m.header.severity.to_string()),
+                origin: Some(m.origin.to_string()),
+                location,
+                text: Some(match &text {
+                    Ok(text) => text.clone(),
+                    Err(_) => m.payload.to_string(),
+                }),
+                error: text.err().map(|e| e.to_string()),
+                ..Record::new("syst")
+            });
+            return;
+        }
+
+        let location = match location {
+            Some(l) => format!(" ({})", l),
+            None => String::new(),
+        };
+        // Messages that can't be formatted are shown raw, with the reason:
+        let (text, error) = match text {
+            Ok(text) => (text, String::new()),
+            Err(e) => (m.payload.to_string(), format!(" ** {}", e)),
+        };
+        let line = format!(
+            "{} {} {} {:<7} {} {}{}",
+            time,
+            names.master_label(m.master),
+            names.channel_label(m.master, m.channel),
+            m.header.severity,
+            m.origin,
+            text,
+            location
+        );
+        match m.header.severity {
+            Severity::Fatal | Severity::Error => println!("{}{}", line.red(), error.red().bold()),
+            Severity::Warning => println!("{}{}", line.yellow(), error.red().bold()),
+            _ => println!("{}{}", line, error.red().bold()),
+        }
+    }
+
+    fn display_error(&mut self, id: Option<u8>, e: &syst::Error, pipeline: &Pipeline) {
+        match &mut self.lines.writer {
+            Some(writer) => writer.write(&Record {
+                stream: Some(id),
+                offset: Some(e.start),
+                file_offset: pipeline.file_offset(id, e.start),
+                master: Some(e.master),
+                channel: Some(e.channel),
+                error: Some(e.reason.to_string()),
+                ..Record::new("error")
+            }),
+            None => println!("{}", format!("** {}", e).red().bold()),
+        }
+    }
+
+    fn display(&mut self, r: parsers::Result<Output>, pipeline: &Pipeline) {
+        if let Ok(Output::Packet(id, r)) = &r {
+            let mut results = Vec::new();
+            let decoder = self.lines.streams.entry(*id).or_default();
+            decoder.process(r, |m| results.push(m));
+            for m in results {
+                self.display_message(*id, m, pipeline);
+            }
+        }
+        self.lines.report(&r, pipeline);
+    }
+}
@@ -0,0 +1,130 @@
+//! The `text` subcommand: displays the text written to channels as log lines.
+
+use crate::output::{get_format, Format, Lines, Record};
+use crate::Result;
+use crate::{decode_pipeline, get_frame_decoder, get_input, OFFSET_WINDOW};
+use clap::ArgMatches;
+use std::collections::{BTreeMap, VecDeque};
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::text::{Line, TextCollector};
+use twp::parsers;
+
+pub fn text(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let mut input = get_input(sub_m)?;
+    let mut pipeline = Pipeline::new(get_frame_decoder(sub_m));
+    if get_format(sub_m) != Format::Text {
+        pipeline = pipeline.track_recent_offsets(OFFSET_WINDOW);
+    }
+
+    let mut display = TextDisplay {
+        lines: Lines::new(sub_m)?,
+        held: match sub_m.is_present("merge") {
+            true => Some(BTreeMap::new()),
+            false => None,
+        },
+    };
+
+    decode_pipeline(&mut input, &mut pipeline, |r, p| {
+        display.display(r, p);
+        Ok(())
+    })?;
+
+    // Lines still being written when the input ends:
+    for (id, line) in display.lines.finish(|c| c.finish()) {
+        display.add_line(id, line, &pipeline);
+    }
+    display.show_held();
+    Ok(())
+}
+
+// A line held back by --merge until the input ends, with its place in the input and its time (or
+// -inf if unknown):
+struct Held {
+    line: Line,
+    file_offset: Option<usize>,
+    time: f64,
+}
+
+struct TextDisplay {
+    lines: Lines<TextCollector>,
+    held: Option<BTreeMap<Option<u8>, VecDeque<Held>>>, // With --merge, by stream.
+}
+
+impl TextDisplay {
+    fn add_line(&mut self, id: Option<u8>, line: Line, pipeline: &Pipeline) {
+        if !self.lines.shows(line.master, line.channel) {
+            return;
+        }
+
+        let file_offset = pipeline.file_offset(id, line.start);
+        let time = self.lines.time(id, line.timestamp).value();
+        match &mut self.held {
+            None => self.display_line(id, line, file_offset),
+            Some(held) => {
+                held.entry(id).or_default().push_back(Held {
+                    line,
+                    file_offset,
+                    time: time.unwrap_or(f64::NEG_INFINITY),
+                });
+            }
+        }
+    }
+
+    // Show the held lines in time order, merging the streams: each stream's lines stay in the
+    // order it wrote them, even if its timestamps go back.
+    fn show_held(&mut self) {
+        while let Some(held) = &mut self.held {
+            let next = held
+                .iter()
+                .filter_map(|(id, lines)| lines.front().map(|h| (*id, h.time)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let (id, h) = match next {
+                Some((id, _)) => (id, held.get_mut(&id).unwrap().pop_front().unwrap()),
+                None => return,
+            };
+            self.display_line(id, h.line, h.file_offset);
+        }
+    }
+
+    fn display_line(&mut self, id: Option<u8>, line: Line, file_offset: Option<usize>) {
+        let time = self.lines.time(id, line.timestamp);
+        let frequency = self.lines.tracker(id).frequency();
+        let names = &self.lines.names;
+        match &mut self.lines.writer {
+            Some(writer) => writer.write(&Record {
+                stream: Some(id),
+                offset: Some(line.start),
+                file_offset,
+                master: Some(line.master),
+                channel: Some(line.channel),
+                timestamp: line.timestamp,
+                frequency,
+                master_name: names.master(line.master).map(|n| n.name.clone()),
+                channel_name: names
+                    .channel(line.master, line.channel)
+                    .map(|n| n.name.clone()),
+                text: Some(line.text),
+                ..Record::new("text")
+            }),
+            None => println!(
+                "{} {} {} {}",
+                time,
+                names.master_label(line.master),
+                names.channel_label(line.master, line.channel),
+                line.text
+            ),
+        }
+    }
+
+    fn display(&mut self, r: parsers::Result<Output>, pipeline: &Pipeline) {
+        if let Ok(Output::Packet(id, r)) = &r {
+            let mut lines = Vec::new();
+            let c = self.lines.streams.entry(*id).or_default();
+            c.process(r, |l| lines.push(l));
+            for line in lines {
+                self.add_line(*id, line, pipeline);
+            }
+        }
+        self.lines.report(&r, pipeline);
+    }
+}
@@ -0,0 +1,648 @@
+//! The `tui` subcommand: a full-screen, interactive capture browser.
+//!
+//! The screen shows the decoded packet list, the nibbles of the selected packet's stream (the
+//! packet's own nibbles highlighted) and the selected packet's details.  Captures are decoded a
+//! page at a time (see `stp_core::pages`), so they do not need to fit in memory.
+
+use crate::{event_text, get_frame_decoder, get_names, is_event, parse_offset, parse_ranges};
+use crate::{CliError, Result};
+use clap::ArgMatches;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::fs::File;
+use std::io::{self, prelude::*, Stdout};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::result;
+use stp_core::asm::format_packet;
+use stp_core::names::Names;
+use stp_core::pages::{Entry, Page, Pages, Pos, Row};
+use stp_core::stp;
+
+const HEX_LINES: usize = 6;
+const DETAIL_LINES: usize = 4;
+const HELP: &str = "q quit  arrows/PgUp/PgDn/Home/End move  g goto offset  / search  \
+                    n/N next/previous match  e/E next/previous error  f filter";
+
+pub fn tui(_app_m: &ArgMatches, sub_m: &ArgMatches) -> Result {
+    let path = sub_m.value_of("FILE").unwrap();
+    let file = File::open(path).map_err(|e| CliError(Some(format!("{}: {}", e, path))))?;
+    let pages = Pages::new(file, get_frame_decoder(sub_m)).map_err(io_error)?;
+    let mut browser = Browser::new(path, pages, get_names(sub_m)?);
+
+    let _terminal = Terminal::enter().map_err(io_error)?;
+    browser.run().map_err(io_error)
+}
+
+fn io_error(e: io::Error) -> CliError {
+    CliError(Some(format!("{}", e)))
+}
+
+// Restores the terminal when dropped, even on error.
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[derive(Default)]
+struct Filter {
+    masters: Option<Vec<RangeInclusive<usize>>>,
+    channels: Option<Vec<RangeInclusive<usize>>>,
+}
+
+impl Filter {
+    fn parse(s: &str) -> result::Result<Option<Filter>, CliError> {
+        let mut filter = Filter::default();
+        for token in s.split_whitespace() {
+            match token.split_once('=') {
+                Some(("m", r)) => filter.masters = Some(parse_ranges(r)?),
+                Some(("c", r)) => filter.channels = Some(parse_ranges(r)?),
+                _ => return Err(CliError(Some(format!("invalid filter: {}", token)))),
+            }
+        }
+        Ok(if filter.masters.is_none() && filter.channels.is_none() {
+            None
+        } else {
+            Some(filter)
+        })
+    }
+
+    // Errors are always shown; packets only if they are writes to a selected master and channel.
+    fn matches(&self, row: &Row) -> bool {
+        let in_ranges = |ranges: &Option<Vec<RangeInclusive<usize>>>, v: u16| {
+            ranges
+                .as_ref()
+                .is_none_or(|r| r.iter().any(|r| r.contains(&(v as usize))))
+        };
+        match (&row.entry, row.channel) {
+            (Entry::Packet(_), Some((m, c))) => {
+                in_ranges(&self.masters, m) && in_ranges(&self.channels, c)
+            }
+            (Entry::Packet(_), None) => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Prompt {
+    Goto,
+    Search,
+    Filter,
+}
+
+struct Browser {
+    path: String,
+    pages: Pages,
+    names: Names,
+    cursor: Option<Pos>, // None if there is nothing to show.
+    top: Option<Pos>,    // First row shown.
+    filter: Option<Filter>,
+    filter_text: String,
+    search: Option<String>,
+    prompt: Option<(Prompt, String)>,
+    message: String,
+    width: usize,
+    height: usize,
+}
+
+impl Browser {
+    fn new(path: &str, pages: Pages, names: Names) -> Browser {
+        Browser {
+            path: path.to_string(),
+            pages,
+            names,
+            cursor: None,
+            top: None,
+            filter: None,
+            filter_text: String::new(),
+            search: None,
+            prompt: None,
+            message: HELP.to_string(),
+            width: 80,
+            height: 24,
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        self.cursor = self.next(None)?;
+        loop {
+            let (w, h) = terminal::size()?;
+            self.width = w as usize;
+            self.height = h as usize;
+            self.draw()?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Release && !self.handle_key(key)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn list_height(&self) -> usize {
+        // Title, list, separator, hex, separator, details, status:
+        self.height
+            .saturating_sub(HEX_LINES + DETAIL_LINES + 4)
+            .max(1)
+    }
+
+    fn visible(&self, row: &Row) -> bool {
+        self.filter.as_ref().is_none_or(|f| f.matches(row))
+    }
+
+    /// The first visible row at or after `start` matching `pred`.
+    fn seek_forward<P>(&mut self, start: Pos, mut pred: P) -> io::Result<Option<Pos>>
+    where
+        P: FnMut(&Row) -> bool,
+    {
+        let filter = &self.filter;
+        self.pages.find_forward(start, |r| {
+            filter.as_ref().is_none_or(|f| f.matches(r)) && pred(r)
+        })
+    }
+
+    /// The last visible row before `end` matching `pred`.
+    fn seek_backward<P>(&mut self, end: Pos, mut pred: P) -> io::Result<Option<Pos>>
+    where
+        P: FnMut(&Row) -> bool,
+    {
+        let filter = &self.filter;
+        self.pages.find_backward(end, |r| {
+            filter.as_ref().is_none_or(|f| f.matches(r)) && pred(r)
+        })
+    }
+
+    // The next and previous visible rows, from the cursor (or the ends of the capture):
+    fn next(&mut self, pos: Option<Pos>) -> io::Result<Option<Pos>> {
+        self.seek_forward(pos.map_or(Pos::START, Pos::after), |_| true)
+    }
+
+    fn prev(&mut self, pos: Option<Pos>) -> io::Result<Option<Pos>> {
+        self.seek_backward(pos.unwrap_or(Pos::END), |_| true)
+    }
+
+    // Move the cursor `n` visible rows, stopping at either end.
+    fn step(&mut self, n: isize) -> io::Result<()> {
+        for _ in 0..n.unsigned_abs() {
+            let next = if n > 0 {
+                self.next(self.cursor)?
+            } else {
+                self.prev(self.cursor)?
+            };
+            match next {
+                Some(p) => self.cursor = Some(p),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    // Show a status message before a potentially long scan.
+    fn busy(&mut self, msg: &str) -> io::Result<()> {
+        self.message = msg.to_string();
+        self.draw()
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        if self.prompt.is_some() {
+            self.handle_prompt_key(key)?;
+            return Ok(true);
+        }
+
+        let page = self.list_height() as isize;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(false)
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.step(1)?,
+            KeyCode::Up | KeyCode::Char('k') => self.step(-1)?,
+            KeyCode::PageDown => self.step(page)?,
+            KeyCode::PageUp => self.step(-page)?,
+            KeyCode::Home => self.cursor = self.next(None)?,
+            KeyCode::End => {
+                self.busy("Scanning...")?;
+                self.cursor = self.prev(None)?.or(self.cursor);
+                self.message = HELP.to_string();
+            }
+            KeyCode::Char('g') => self.prompt = Some((Prompt::Goto, String::new())),
+            KeyCode::Char('/') => self.prompt = Some((Prompt::Search, String::new())),
+            KeyCode::Char('f') => {
+                self.prompt = Some((Prompt::Filter, self.filter_text.clone()));
+            }
+            KeyCode::Char('n') => self.find(true)?,
+            KeyCode::Char('N') => self.find(false)?,
+            KeyCode::Char('e') => self.find_error(true)?,
+            KeyCode::Char('E') => self.find_error(false)?,
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn handle_prompt_key(&mut self, key: KeyEvent) -> io::Result<()> {
+        let (prompt, mut text) = self.prompt.take().unwrap();
+        match key.code {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Enter => {}
+            KeyCode::Backspace => {
+                text.pop();
+                self.prompt = Some((prompt, text));
+                return Ok(());
+            }
+            KeyCode::Char(c) => {
+                text.push(c);
+                self.prompt = Some((prompt, text));
+                return Ok(());
+            }
+            _ => {
+                self.prompt = Some((prompt, text));
+                return Ok(());
+            }
+        }
+
+        match prompt {
+            Prompt::Goto => match parse_offset(text.trim()) {
+                Ok(offset) => self.goto(offset)?,
+                Err(CliError(e)) => self.message = e.unwrap_or_default(),
+            },
+            Prompt::Search => {
+                self.search = Some(text.to_lowercase()).filter(|s| !s.is_empty());
+                self.find(true)?;
+            }
+            Prompt::Filter => match Filter::parse(&text) {
+                Ok(filter) => {
+                    self.filter = filter;
+                    self.filter_text = text;
+                    self.refilter()?;
+                }
+                Err(CliError(e)) => self.message = e.unwrap_or_default(),
+            },
+        }
+        Ok(())
+    }
+
+    // Move the cursor to the first row at or after a file offset.
+    fn goto(&mut self, offset: usize) -> io::Result<()> {
+        self.busy("Scanning...")?;
+        let page = self.pages.page_of(offset);
+        let found = match self.seek_forward(Pos { page, row: 0 }, |r| r.file_offset >= offset)? {
+            Some(p) => Some(p),
+            None => self.prev(None)?,
+        };
+        self.cursor = found.or(self.cursor);
+        self.message = HELP.to_string();
+        Ok(())
+    }
+
+    fn find(&mut self, forward: bool) -> io::Result<()> {
+        let needle = match &self.search {
+            Some(s) => s.clone(),
+            None => {
+                self.message = "No search (use /)".to_string();
+                return Ok(());
+            }
+        };
+        self.busy("Searching...")?;
+        let pred = |r: &Row| row_text(r).to_lowercase().contains(&needle);
+        let found = if forward {
+            self.seek_forward(self.cursor.map_or(Pos::START, Pos::after), pred)?
+        } else {
+            self.seek_backward(self.cursor.unwrap_or(Pos::END), pred)?
+        };
+        self.message = match found {
+            Some(p) => {
+                self.cursor = Some(p);
+                format!("/{}", needle)
+            }
+            None => format!("Not found: {}", needle),
+        };
+        Ok(())
+    }
+
+    fn find_error(&mut self, forward: bool) -> io::Result<()> {
+        self.busy("Searching...")?;
+        let pred = |r: &Row| matches!(r.entry, Entry::StpError(_) | Entry::TwpError(_));
+        let found = if forward {
+            self.seek_forward(self.cursor.map_or(Pos::START, Pos::after), pred)?
+        } else {
+            self.seek_backward(self.cursor.unwrap_or(Pos::END), pred)?
+        };
+        self.message = match found {
+            Some(p) => {
+                self.cursor = Some(p);
+                HELP.to_string()
+            }
+            None => "No more errors".to_string(),
+        };
+        Ok(())
+    }
+
+    // Keep the cursor on a visible row after the filter changes.
+    fn refilter(&mut self) -> io::Result<()> {
+        self.top = None;
+        let visible = match self.cursor {
+            Some(c) => {
+                let page = self.pages.page(c.page)?;
+                self.visible(&page.rows[c.row])
+            }
+            None => false,
+        };
+        if !visible {
+            self.cursor = match self.cursor {
+                Some(c) => self.next(Some(c))?.or(self.prev(Some(c))?),
+                None => self.next(None)?,
+            };
+        }
+        if self.cursor.is_none() {
+            self.message = "Nothing matches the filter".to_string();
+        }
+        Ok(())
+    }
+
+    // Scroll so the cursor is on screen and return the rows to show.
+    fn visible_rows(&mut self) -> io::Result<Vec<Pos>> {
+        let cursor = match self.cursor {
+            Some(c) => c,
+            None => return Ok(Vec::new()),
+        };
+        let height = self.list_height();
+        let mut top = match self.top {
+            Some(t) if t <= cursor => t,
+            _ => cursor,
+        };
+
+        let collect = |b: &mut Browser, top: Pos| -> io::Result<Vec<Pos>> {
+            let mut rows = vec![top];
+            while rows.len() < height {
+                match b.next(rows.last().copied())? {
+                    Some(p) => rows.push(p),
+                    None => break,
+                }
+            }
+            Ok(rows)
+        };
+
+        let mut rows = collect(self, top)?;
+        if !rows.contains(&cursor) {
+            // The cursor is below the screen: put it on the last line.
+            top = cursor;
+            for _ in 1..height {
+                match self.prev(Some(top))? {
+                    Some(p) => top = p,
+                    None => break,
+                }
+            }
+            rows = collect(self, top)?;
+        }
+        self.top = Some(top);
+        Ok(rows)
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let rows = self.visible_rows()?;
+        let mut out = io::stdout();
+        let width = self.width;
+        queue!(out, Clear(ClearType::All))?;
+
+        let mut title = format!(
+            "{}  {} bytes  decoded {}/{} pages",
+            self.path,
+            self.pages.len(),
+            self.pages.reached(),
+            self.pages.count()
+        );
+        if self.filter.is_some() {
+            title.push_str(&format!("  filter: {}", self.filter_text));
+        }
+        line(&mut out, 0, &title, width, Style::Title)?;
+
+        let mut selected: Option<(Rc<Page>, usize)> = None;
+        for (y, pos) in (1..).zip(rows.iter()) {
+            let page = self.pages.page(pos.page)?;
+            let row = &page.rows[pos.row];
+            let style = match &row.entry {
+                _ if Some(*pos) == self.cursor => Style::Selected,
+                Entry::Packet(_) => Style::Normal,
+                Entry::TwpError(e) if is_event(&e.reason) => Style::Event,
+                Entry::TwpEvent(_) => Style::Event,
+                _ => Style::Error,
+            };
+            line(&mut out, y, &row_line(row, &self.names), width, style)?;
+            if Some(*pos) == self.cursor {
+                selected = Some((page.clone(), pos.row));
+            }
+        }
+
+        let mut y = self.list_height() + 1;
+        line(&mut out, y, &"-".repeat(width), width, Style::Dim)?;
+        y += 1;
+        if let Some((page, i)) = &selected {
+            draw_hex(&mut out, y, width, page, &page.rows[*i])?;
+        }
+        y += HEX_LINES;
+        line(&mut out, y, &"-".repeat(width), width, Style::Dim)?;
+        y += 1;
+        if let Some((page, i)) = &selected {
+            for (j, text) in details(&page.rows[*i], &self.names).iter().enumerate() {
+                line(&mut out, y + j, text, width, Style::Normal)?;
+            }
+        }
+        y += DETAIL_LINES;
+
+        let status = match &self.prompt {
+            Some((Prompt::Goto, text)) => format!("Go to file offset: {}", text),
+            Some((Prompt::Search, text)) => format!("Search: {}", text),
+            Some((Prompt::Filter, text)) => {
+                format!("Filter (e.g. m=1,4-6 c=0x10-0x1f; empty clears): {}", text)
+            }
+            None => self.message.clone(),
+        };
+        line(&mut out, y, &status, width, Style::Title)?;
+        out.flush()
+    }
+}
+
+fn stream_name(stream: Option<Option<u8>>) -> String {
+    match stream {
+        None => String::new(),
+        Some(None) => "None".to_string(),
+        Some(Some(id)) => format!("{:#04X}", id),
+    }
+}
+
+fn row_text(row: &Row) -> String {
+    match &row.entry {
+        Entry::Packet(p) => format_packet(&p.packet),
+        Entry::StpError(e) => format!("** {}", e.reason),
+        Entry::TwpError(e) => format!("** {}", e),
+        Entry::TwpEvent(e) => event_text(e, row.file_offset),
+    }
+}
+
+fn row_line(row: &Row, names: &Names) -> String {
+    let channel = match row.channel {
+        Some((m, c)) => format!("{} {}", names.master_label(m), names.channel_label(m, c)),
+        None => String::new(),
+    };
+    format!(
+        "{:010X}  {:<4}  {:<12}  {}",
+        row.file_offset,
+        stream_name(row.stream),
+        channel,
+        row_text(row)
+    )
+}
+
+// The row's stream and nibble range, if it has one:
+fn nibble_range(row: &Row) -> Option<(Option<u8>, usize, usize)> {
+    match (&row.entry, row.stream) {
+        (Entry::Packet(p), Some(id)) => Some((id, p.start, p.span)),
+        (Entry::StpError(e), Some(id)) => Some((id, e.start, e.span)),
+        _ => None,
+    }
+}
+
+fn details(row: &Row, names: &Names) -> Vec<String> {
+    let mut lines = Vec::new();
+    match nibble_range(row) {
+        Some((id, start, span)) => lines.push(format!(
+            "Stream {}  nibbles {:#X}..{:#X} ({})  file offset {:#X}",
+            stream_name(Some(id)),
+            start,
+            start + span,
+            span,
+            row.file_offset
+        )),
+        None => lines.push(format!("File offset {:#X}", row.file_offset)),
+    }
+    if let Some((m, c)) = row.channel {
+        let mut text = format!("Master {}  Channel {}", m, c);
+        if !names.is_empty() {
+            text.push_str(&format!(
+                " ({} {})",
+                names.master_label(m),
+                names.channel_label(m, c)
+            ));
+        }
+        if let Entry::Packet(p) = &row.entry {
+            if let stp::Packet::Data { opcode, data, .. } = &p.packet {
+                if let Some(bits) = opcode.data_bits() {
+                    text.push_str(&format!("  Data {}", names.format_data(m, c, *data, bits)));
+                }
+            }
+        }
+        lines.push(text);
+    }
+    lines.push(row_text(row));
+    match &row.entry {
+        Entry::Packet(p) => lines.push(format!("{:?}", p.packet)),
+        Entry::StpError(e) => lines.push(format!("{:?}", e.reason)),
+        Entry::TwpError(e) => lines.push(format!("{:?}", e.reason)),
+        Entry::TwpEvent(e) => lines.push(format!("{:?}", e)),
+    }
+    lines.truncate(DETAIL_LINES);
+    lines
+}
+
+// Show the nibbles of the row's stream around it, highlighting the row's own nibbles.
+fn draw_hex(out: &mut Stdout, y: usize, width: usize, page: &Page, row: &Row) -> io::Result<()> {
+    let (id, start, span) = match nibble_range(row) {
+        Some(r) => r,
+        None => return line(out, y, "(no stream data)", width, Style::Dim),
+    };
+    let bytes: Vec<_> = page.bytes.iter().filter(|b| b.id == id).collect();
+    let first = match bytes.first() {
+        Some(b) => b.index,
+        None => return Ok(()),
+    };
+
+    // Start a line before the packet's line, if the page has it:
+    let packet_line = (start / 2).max(first) / 16;
+    let first_line = packet_line.saturating_sub(1).max(first / 16);
+    for l in 0..HEX_LINES {
+        let line_start = (first_line + l) * 16;
+        let mut cells = Vec::new();
+        for index in line_start..line_start + 16 {
+            let b = match index.checked_sub(first).and_then(|i| bytes.get(i)) {
+                Some(b) => b,
+                None => continue,
+            };
+            for (n, nibble) in [(index * 2, b.data & 0xF), (index * 2 + 1, b.data >> 4)] {
+                let sep = if index % 16 == 8 && n % 2 == 0 {
+                    "  "
+                } else {
+                    " "
+                };
+                let lit = (start..start + span).contains(&n);
+                cells.push((format!("{}{:x}", sep, nibble), lit));
+            }
+        }
+        if cells.is_empty() {
+            break;
+        }
+
+        queue!(out, MoveTo(0, (y + l) as u16))?;
+        let prefix = format!("{:012X} |", line_start * 2);
+        let mut used = prefix.len();
+        queue!(out, Print(prefix))?;
+        for (text, lit) in cells {
+            used += text.len();
+            if used > width {
+                break;
+            }
+            if lit {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(text),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(text))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+    Normal,
+    Selected,
+    Title,
+    Error,
+    Event,
+    Dim,
+}
+
+fn line(out: &mut Stdout, y: usize, text: &str, width: usize, style: Style) -> io::Result<()> {
+    let mut text: String = text.chars().take(width).collect();
+    queue!(out, MoveTo(0, y as u16))?;
+    match style {
+        Style::Normal => {}
+        Style::Selected | Style::Title => {
+            // Fill the whole line:
+            text = format!("{:<w$}", text, w = width);
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+        }
+        Style::Error => queue!(out, SetForegroundColor(Color::Red))?,
+        Style::Event => queue!(out, SetForegroundColor(Color::Yellow))?,
+        Style::Dim => queue!(out, SetAttribute(Attribute::Dim))?,
+    }
+    queue!(out, Print(text), SetAttribute(Attribute::Reset), ResetColor)
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use twp::builders::frame_stream;
+
+// The bytes of two streams, and a capture with one after the other:
+fn capture() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let first: Vec<u8> = (0..100).map(|i| (i * 7 % 251) as u8).collect();
+    let second: Vec<u8> = (0..40).map(|i| (i * 3 % 251) as u8).collect();
+    let mut capture = frame_stream(&first, 0x10, 2).unwrap();
+    capture.extend(frame_stream(&second, 0x20, 3).unwrap());
+    (first, second, capture)
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("stp-demux-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn demux(input: &Path, dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_stp"))
+        .args([
+            "demux",
+            input.to_str().unwrap(),
+            "-o",
+            dir.to_str().unwrap(),
+        ])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// Every run of the sidecar holds the stream's bytes at its offset, and the runs cover the stream.
+// The low bit of bytes at even frame positions is kept in the frame's last byte, so it's ignored:
+fn check_ranges(dir: &Path, stem: &str, stream: &[u8], capture: &[u8]) {
+    let ranges = fs::read_to_string(dir.join(format!("{}.ranges.csv", stem))).unwrap();
+    let mut lines = ranges.lines();
+    assert_eq!(lines.next(), Some("offset,file_offset,length"));
+    let mut next = 0;
+    for line in lines {
+        let row: Vec<usize> = line.split(',').map(|v| v.parse().unwrap()).collect();
+        let (offset, file_offset, len) = (row[0], row[1], row[2]);
+        assert_eq!(offset, next);
+        let high_bits = |b: &[u8]| b.iter().map(|b| b & !1).collect::<Vec<u8>>();
+        assert_eq!(
+            high_bits(&capture[file_offset..file_offset + len]),
+            high_bits(&stream[offset..offset + len])
+        );
+        next += len;
+    }
+    assert_eq!(next, stream.len());
+}
+
+#[test]
+fn streams() {
+    let dir = temp_dir("streams");
+    let (first, second, capture) = capture();
+    let input = dir.join("capture.twp");
+    fs::write(&input, &capture).unwrap();
+
+    let out = dir.join("all");
+    assert!(demux(&input, &out, &[]).status.success());
+    assert_eq!(fs::read(out.join("id_0x10.bin")).unwrap(), first);
+    assert_eq!(fs::read(out.join("id_0x20.bin")).unwrap(), second);
+    check_ranges(&out, "id_0x10", &first, &capture);
+    check_ranges(&out, "id_0x20", &second, &capture);
+
+    // --id selects streams:
+    let out = dir.join("selected");
+    assert!(demux(&input, &out, &["--id", "0x20"]).status.success());
+    let mut files: Vec<String> = fs::read_dir(&out)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["id_0x20.bin", "id_0x20.ranges.csv"]);
+    check_ranges(&out, "id_0x20", &second, &capture);
+
+    // IDs that don't fit in a byte are rejected:
+    let output = demux(&input, &dir.join("invalid"), &["--id", "300"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("invalid stream ID: 300"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
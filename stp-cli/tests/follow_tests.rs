@@ -0,0 +1,100 @@
+use std::fs::{self, OpenOptions};
+use std::io::{prelude::*, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use stp_core::asm::assemble_bytes;
+use twp::builders::frame_stream;
+
+// A TWP capture of a single STP stream (ID 0x10):
+fn capture() -> Vec<u8> {
+    let stp = assemble_bytes(
+        "async\nversion v2.2 natdelta be\nm8 0x2\nc8 0x3\nd32ts 0xdeadbeef ts=natdelta:2:0x30\n",
+    )
+    .unwrap();
+    frame_stream(&stp, 0x10, 2).unwrap()
+}
+
+// Long enough for the follower to poll the file a few times:
+const SETTLE: Duration = Duration::from_secs(1);
+
+// The lines read up to the next one starting with `prefix`:
+fn lines_until(lines: &Receiver<String>, prefix: &str) -> Vec<String> {
+    let mut read = Vec::new();
+    loop {
+        let line = lines.recv_timeout(Duration::from_secs(10)).unwrap();
+        let done = line.starts_with(prefix);
+        read.push(line);
+        if done {
+            return read;
+        }
+    }
+}
+
+#[test]
+fn follow() {
+    let dir = std::env::temp_dir().join(format!("stp-follow-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path: PathBuf = dir.join("trace.twp");
+    let capture = capture();
+    fs::write(&path, &capture).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stp"))
+        .args([
+            "messages",
+            path.to_str().unwrap(),
+            "--follow",
+            "--format",
+            "csv",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let (tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in stdout.lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    let message = ",D32TS,2,3,3735928559,";
+    assert!(lines_until(&lines, "message,")
+        .last()
+        .unwrap()
+        .contains(message));
+
+    // Appended data is decoded, up to a frame cut short by the end of the file:
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&capture[..capture.len() - 5]).unwrap();
+    drop(file);
+    thread::sleep(SETTLE);
+
+    // Truncating the file starts a new capture, which the partial frame doesn't spill into:
+    fs::write(&path, &capture).unwrap();
+    let read = lines_until(&lines, "message,");
+    assert!(read.iter().any(|l| l.contains(": file truncated,")));
+    assert!(read
+        .iter()
+        .any(|l| l.contains(",truncated frame: 11 bytes,")));
+    assert!(read.last().unwrap().contains(message));
+    thread::sleep(SETTLE);
+
+    // So does replacing it:
+    let rotated = dir.join("trace.twp.new");
+    fs::write(&rotated, &capture).unwrap();
+    fs::rename(&rotated, &path).unwrap();
+    let read = lines_until(&lines, "message,");
+    assert!(read
+        .iter()
+        .any(|l| l.starts_with("event,") && l.contains(": file replaced,")));
+    assert!(read.last().unwrap().contains(message));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
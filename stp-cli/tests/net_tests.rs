@@ -0,0 +1,133 @@
+use std::io::{prelude::*, BufReader};
+use std::net::TcpListener;
+use std::process::{Command, Stdio};
+use std::thread;
+use stp_core::asm::assemble_bytes;
+use twp::builders::frame_stream;
+
+// A TWP capture of a single STP stream (ID 0x10):
+fn capture() -> Vec<u8> {
+    let stp = assemble_bytes(
+        "async\nversion v2.2 natdelta be\nm8 0x2\nc8 0x3\nd32ts 0xdeadbeef ts=natdelta:2:0x30\n",
+    )
+    .unwrap();
+    frame_stream(&stp, 0x10, 2).unwrap()
+}
+
+// Replay the capture to the first client, a few bytes at a time:
+fn replay(listener: TcpListener) {
+    let (mut stream, _) = listener.accept().unwrap();
+    for chunk in capture().chunks(7) {
+        stream.write_all(chunk).unwrap();
+        stream.flush().unwrap();
+    }
+}
+
+#[test]
+fn connect() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = thread::spawn(move || replay(listener));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_stp"))
+        .args(["messages", "--connect", &addr, "--once", "--format", "csv"])
+        .output()
+        .unwrap();
+    server.join().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().nth(1).unwrap().starts_with("schema,"));
+    let messages: Vec<&str> = stdout
+        .lines()
+        .filter(|l| l.starts_with("message,"))
+        .collect();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains(",D32TS,2,3,3735928559,"));
+
+    // The connection coming and going are events, at the input offsets where they happened:
+    let events: Vec<&str> = stdout.lines().filter(|l| l.starts_with("event,")).collect();
+    assert_eq!(events.len(), 2);
+    assert!(events[0].contains(&format!(",0,,,,,,,,,,,,connected to {},", addr)));
+    assert!(events[1].contains(&format!(
+        ",{},,,,,,,,,,,,connection closed,",
+        capture().len()
+    )));
+}
+
+#[test]
+fn chunk_times() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = thread::spawn(move || replay(listener));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_stp"))
+        .args(["nibbles", "--connect", &addr, "--once", "--chunk-times"])
+        .args(["--format", "jsonl"])
+        .output()
+        .unwrap();
+    server.join().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().next(),
+        Some("{\"record\":\"schema\",\"version\":\"1\"}")
+    );
+    let chunks: Vec<&str> = stdout
+        .lines()
+        .filter(|l| l.starts_with("{\"record\":\"chunk\""))
+        .collect();
+    assert!(!chunks.is_empty());
+    assert!(chunks[0].contains("\"file_offset\":0,"));
+    assert!(chunks.iter().all(|c| c.contains("\"arrival\":")));
+    let bytes: usize = stdout.lines().filter(|l| l.contains("\"data\"")).count();
+    assert!(bytes > 0);
+}
+
+#[test]
+fn reconnect() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    // The first connection drops in the middle of a frame, the second sends the whole capture:
+    let server = thread::spawn(move || {
+        let capture = capture();
+        for data in [&capture[..capture.len() - 5], &capture] {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(data).unwrap();
+        }
+    });
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stp"))
+        .args(["messages", "--connect", &addr, "--format", "csv"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    server.join().unwrap();
+
+    // Without --once it keeps reconnecting, so read until the second capture's message:
+    let mut lines = Vec::new();
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        let line = line.unwrap();
+        let done = line.starts_with("message,");
+        lines.push(line);
+        if done {
+            break;
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let connected = lines
+        .iter()
+        .filter(|l| l.contains(",connected to "))
+        .count();
+    assert_eq!(connected, 2);
+    // The first connection's partial frame is reported, and doesn't spill into the second:
+    assert!(lines
+        .iter()
+        .any(|l| l.contains(",truncated frame: 11 bytes,")));
+    assert!(lines.last().unwrap().contains(",D32TS,2,3,3735928559,"));
+}
@@ -0,0 +1,108 @@
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use stp_core::asm::assemble_bytes;
+use twp::builders::frame_stream;
+
+const LINE: &str = "say \"hi\", then go";
+
+// A TWP capture of a stream (ID 0x10) writing LINE to master 1, channel 2, a byte at a time:
+fn capture() -> Vec<u8> {
+    let mut asm = String::from("async\nversion v2.2 natdelta be\nm8 0x1\nc8 0x2\n");
+    for b in LINE.bytes().chain(Some(b'\n')) {
+        asm.push_str(&format!("d8 {:#x}\n", b));
+    }
+    frame_stream(&assemble_bytes(&asm).unwrap(), 0x10, 2).unwrap()
+}
+
+fn write_capture(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("stp-output-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("capture.twp");
+    fs::write(&path, capture()).unwrap();
+    path
+}
+
+fn stp(args: &[&str], input: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_stp"))
+        .args(args)
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// The fields of a CSV row, with quoted fields unquoted:
+fn csv_fields(row: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    assert!(!quoted);
+    fields
+}
+
+#[test]
+fn jsonl() {
+    let input = write_capture("jsonl");
+    let out = stp(&["text", "--format", "jsonl"], &input);
+    let records: Vec<Value> = out
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2);
+
+    assert_eq!(records[0]["record"], "schema");
+    assert_eq!(records[0]["version"], "1");
+
+    let text = records[1].as_object().unwrap();
+    assert_eq!(text["record"], "text");
+    assert_eq!(text["stream"], 0x10);
+    assert_eq!(text["master"], 1);
+    assert_eq!(text["channel"], 2);
+    assert_eq!(text["text"], LINE);
+    // Fields that don't apply are left out:
+    assert!(!text.contains_key("severity"));
+    fs::remove_dir_all(input.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn csv() {
+    let input = write_capture("csv");
+    let out = stp(&["text", "--format", "csv"], &input);
+    let rows: Vec<Vec<String>> = out.lines().map(csv_fields).collect();
+    assert_eq!(rows.len(), 3);
+
+    // Every row has a field for each column in the header:
+    let header = &rows[0];
+    assert_eq!(header[..3], ["record", "stream", "offset"]);
+    assert_eq!(header.last().unwrap(), "symbol");
+    assert!(rows.iter().all(|r| r.len() == header.len()));
+    let field = |row: &Vec<String>, name: &str| {
+        let column = header.iter().position(|c| c == name).unwrap();
+        row[column].clone()
+    };
+
+    assert_eq!(field(&rows[1], "record"), "schema");
+    assert_eq!(field(&rows[1], "version"), "1");
+
+    assert_eq!(field(&rows[2], "record"), "text");
+    assert_eq!(field(&rows[2], "stream"), "16");
+    assert_eq!(field(&rows[2], "master"), "1");
+    assert_eq!(field(&rows[2], "channel"), "2");
+    assert_eq!(field(&rows[2], "text"), LINE);
+    assert_eq!(field(&rows[2], "severity"), "");
+    fs::remove_dir_all(input.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,47 @@
+use std::fs;
+use std::process::Command;
+use stp_core::asm::assemble_bytes;
+use twp::builders::frame_stream;
+
+// The STP of lines written to master 1, channel 2, each ended by a write with its timestamp:
+fn stream(lines: &[(&str, u64)]) -> Vec<u8> {
+    let mut asm = String::from("async\nversion v2.2 nat be\nm8 0x1\nc8 0x2\n");
+    for (text, ts) in lines {
+        for b in text.bytes() {
+            asm.push_str(&format!("d8 {:#x}\n", b));
+        }
+        asm.push_str(&format!("d8ts 0xa ts=nat:4:{:#x}\n", ts));
+    }
+    assemble_bytes(&asm).unwrap()
+}
+
+// The text of each line shown:
+fn text(args: &[&str]) -> Vec<String> {
+    let dir = std::env::temp_dir().join(format!("stp-text-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("capture.twp");
+    let mut capture = frame_stream(&stream(&[("a1", 10), ("a2", 30)]), 0x10, 2).unwrap();
+    capture.extend(frame_stream(&stream(&[("b1", 20), ("b2", 40)]), 0x20, 3).unwrap());
+    fs::write(&path, capture).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_stp"))
+        .arg("text")
+        .arg(&path)
+        .args(args)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|l| l.rsplit(' ').next().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn merge() {
+    // Each stream's lines are in time order, but the streams follow each other:
+    assert_eq!(text(&[]), ["a1", "a2", "b1", "b2"]);
+    assert_eq!(text(&["--merge"]), ["a1", "b1", "a2", "b2"]);
+}
@@ -0,0 +1,149 @@
+-- Wireshark dissector for the records written by `stp export --pcapng` (link type USER0).
+--
+-- Copy this file to Wireshark's personal Lua plugins folder (see Help > About Wireshark >
+-- Folders), or load it for a single run with `wireshark -X lua_script:stp.lua capture.pcapng`.
+-- The record format is described in stp-core/src/pcapng.rs.
+
+local stp = Proto("stp", "MIPI System Trace Protocol")
+
+local record_types = { [0] = "Packet", [1] = "Message", [2] = "Error" }
+
+local opcodes = {
+    [0x0] = "NULL", [0x1] = "M8", [0x2] = "MERR", [0x3] = "C8", [0x4] = "D8", [0x5] = "D16",
+    [0x6] = "D32", [0x7] = "D64", [0x8] = "D8MTS", [0x9] = "D16MTS", [0xA] = "D32MTS",
+    [0xB] = "D64MTS", [0xC] = "D4", [0xD] = "D4MTS", [0xE] = "FLAG_TS", [0xF1] = "M16",
+    [0xF2] = "GERR", [0xF3] = "C16", [0xF4] = "D8TS", [0xF5] = "D16TS", [0xF6] = "D32TS",
+    [0xF7] = "D64TS", [0xF8] = "D8M", [0xF9] = "D16M", [0xFA] = "D32M", [0xFB] = "D64M",
+    [0xFC] = "D4TS", [0xFD] = "D4M", [0xFE] = "FLAG", [0xF00] = "VERSION", [0xF01] = "NULL_TS",
+    [0xF02] = "USER", [0xF03] = "USER_TS", [0xF08] = "FREQ", [0xF09] = "FREQ_TS",
+    [0xF0F0] = "FREQ_40", [0xF0F1] = "FREQ_40_TS", [0xFFFF] = "ASYNC",
+}
+
+local FLAG_TIMESTAMP = 0x01
+local FLAG_TIMESTAMPED = 0x02
+local FLAG_CHANNEL = 0x04
+local FLAG_ID = 0x08
+local FLAG_PAYLOAD = 0x10
+local HEADER_SIZE = 38
+
+local f = stp.fields
+f.version = ProtoField.uint8("stp.version", "Format version")
+f.type = ProtoField.uint8("stp.type", "Record type", base.DEC, record_types)
+f.flags = ProtoField.uint8("stp.flags", "Flags", base.HEX)
+f.flag_timestamp = ProtoField.bool("stp.flags.timestamp", "Timestamp known", 8, nil, FLAG_TIMESTAMP)
+f.flag_timestamped = ProtoField.bool("stp.flags.timestamped", "Packet timestamped", 8, nil,
+    FLAG_TIMESTAMPED)
+f.flag_channel = ProtoField.bool("stp.flags.channel", "Channel known", 8, nil, FLAG_CHANNEL)
+f.flag_id = ProtoField.bool("stp.flags.id", "TWP ID present", 8, nil, FLAG_ID)
+f.flag_payload = ProtoField.bool("stp.flags.payload", "Payload present", 8, nil, FLAG_PAYLOAD)
+f.id = ProtoField.uint8("stp.id", "TWP stream ID", base.HEX)
+f.opcode = ProtoField.uint16("stp.opcode", "Opcode", base.HEX, opcodes)
+f.master = ProtoField.uint16("stp.master", "Master")
+f.channel = ProtoField.uint16("stp.channel", "Channel")
+f.span = ProtoField.uint16("stp.span", "Size (nibbles)")
+f.offset = ProtoField.uint64("stp.offset", "Stream offset (nibbles)", base.HEX)
+f.payload = ProtoField.uint64("stp.payload", "Payload", base.HEX)
+f.timestamp = ProtoField.uint64("stp.timestamp", "Timestamp (ticks)")
+f.raw_len = ProtoField.uint16("stp.raw_len", "Raw length")
+f.raw = ProtoField.bytes("stp.raw", "Raw bytes")
+f.error = ProtoField.string("stp.error", "Error")
+f.master_name = ProtoField.string("stp.master_name", "Master name")
+f.channel_name = ProtoField.string("stp.channel_name", "Channel name")
+f.value = ProtoField.string("stp.value", "Value")
+
+local function has(flags, flag)
+    return math.floor(flags / flag) % 2 == 1
+end
+
+-- The tab separated parts of a named record's text, with their offsets:
+local function text_parts(buf, start)
+    local parts = {}
+    local pos = start
+    for part in (buf(start):string() .. "\t"):gmatch("([^\t]*)\t") do
+        if #part > 0 then
+            parts[#parts + 1] = { pos = pos, text = part }
+        else
+            parts[#parts + 1] = false
+        end
+        pos = pos + #part + 1
+    end
+    return parts
+end
+
+function stp.dissector(buf, pinfo, tree)
+    if buf:len() < HEADER_SIZE then
+        return 0
+    end
+    pinfo.cols.protocol = "STP"
+    local t = tree:add(stp, buf())
+
+    t:add(f.version, buf(0, 1))
+    t:add(f.type, buf(1, 1))
+    local kind = buf(1, 1):uint()
+    local flags = buf(2, 1):uint()
+    local ft = t:add(f.flags, buf(2, 1))
+    ft:add(f.flag_timestamp, buf(2, 1))
+    ft:add(f.flag_timestamped, buf(2, 1))
+    ft:add(f.flag_channel, buf(2, 1))
+    ft:add(f.flag_id, buf(2, 1))
+    ft:add(f.flag_payload, buf(2, 1))
+    if has(flags, FLAG_ID) then
+        t:add(f.id, buf(3, 1))
+    end
+
+    local raw_len = buf(36, 2):uint()
+    local text_start = HEADER_SIZE + raw_len
+    local named = {}
+    if kind ~= 2 and buf:len() > text_start then
+        named = text_parts(buf, text_start)
+    end
+
+    local info = {}
+    local opcode = buf(4, 2):uint()
+    if kind ~= 2 then
+        t:add(f.opcode, buf(4, 2))
+    end
+    if has(flags, FLAG_CHANNEL) then
+        t:add(f.master, buf(6, 2))
+        t:add(f.channel, buf(8, 2))
+        local master = named[1] and named[1].text or string.format("M%d", buf(6, 2):uint())
+        local channel = named[2] and named[2].text or string.format("C%d", buf(8, 2):uint())
+        for i, field in ipairs({ f.master_name, f.channel_name }) do
+            if named[i] then
+                t:add(field, buf(named[i].pos, #named[i].text))
+            end
+        end
+        table.insert(info, master .. " " .. channel)
+    end
+    if kind ~= 2 then
+        table.insert(info, opcodes[opcode] or string.format("0x%x", opcode))
+    end
+    t:add(f.span, buf(10, 2))
+    t:add(f.offset, buf(12, 8))
+    if has(flags, FLAG_PAYLOAD) then
+        t:add(f.payload, buf(20, 8))
+        if named[3] then
+            t:add(f.value, buf(named[3].pos, #named[3].text))
+            table.insert(info, named[3].text)
+        else
+            table.insert(info, "0x" .. buf(20, 8):uint64():tohex():gsub("^0+(.)", "%1"))
+        end
+    end
+    if has(flags, FLAG_TIMESTAMP) then
+        t:add(f.timestamp, buf(28, 8))
+    end
+
+    t:add(f.raw_len, buf(36, 2))
+    if raw_len > 0 then
+        t:add(f.raw, buf(HEADER_SIZE, raw_len))
+    end
+    if kind == 2 and buf:len() > text_start then
+        t:add(f.error, buf(text_start))
+        table.insert(info, "Error: " .. buf(text_start):string())
+    end
+
+    pinfo.cols.info = table.concat(info, " ")
+    return buf:len()
+end
+
+DissectorTable.get("wtap_encap"):add(wtap.USER0, stp)
@@ -0,0 +1,566 @@
+//! A line-oriented text format for STP streams, with an assembler and disassembler.
+//!
+//! Each line holds one packet; `#` starts a comment:
+//!
+//! ```text
+//! async
+//! version v2.2 nat le
+//! m16 0x40
+//! c8 3
+//! d32ts 0xdeadbeef ts=nat:8:0x1234
+//! user 0x123 len=3
+//! raw f f 0
+//! ```
+//!
+//! Opcodes are the `OpCode` names, in any case.  Timestamps are written `ts=TYPE:LENGTH:VALUE`
+//! (`ts=legacy:VALUE` for STPv1 timestamps), with the length in nibbles.  `version` takes the
+//! version (`v1`, `v2.1`, `v2.2`), the timestamp type (`legacy`, `natdelta`, `nat`, `gray`) and,
+//! for v2.2, the endianness (`le` or `be`).  `raw` emits nibbles as is.
+//!
+//! The disassembler falls back to `raw` lines for anything it cannot reproduce exactly, so
+//! disassembling and then assembling a stream always gives back the same nibbles.
+
+use crate::nibble::swap_nibbles;
+use crate::stp::{self, OpCode, StpVersion, Timestamp, TimestampType};
+use crate::stp_decoder::StpDecoder;
+use std::fmt;
+use std::result;
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize, // Line number, starting at 1.
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub type Result<T> = result::Result<T, AsmError>;
+
+type LineResult<T> = result::Result<T, String>;
+
+/// Assemble text into nibbles.
+pub fn assemble(text: &str) -> Result<Vec<u8>> {
+    let mut encoder = Encoder::default();
+    let mut nibbles = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let r = match parse_line(line) {
+            Ok(Some(Line::Packet(packet))) => encoder.encode(&packet, &mut nibbles),
+            Ok(Some(Line::Raw(raw))) => {
+                nibbles.extend_from_slice(&raw);
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        r.map_err(|message| AsmError {
+            line: n + 1,
+            message,
+        })?;
+    }
+    Ok(nibbles)
+}
+
+/// Assemble text into bytes, least significant nibble first.  An odd number of nibbles is
+/// padded with a NULL packet.
+pub fn assemble_bytes(text: &str) -> Result<Vec<u8>> {
+    let nibbles = assemble(text)?;
+    Ok(nibbles
+        .chunks(2)
+        .map(|n| n[0] | n.get(1).unwrap_or(&0) << 4)
+        .collect())
+}
+
+/// Disassemble a stream of nibbles.
+pub fn disassemble(nibbles: &[u8]) -> String {
+    let mut results = Vec::new();
+    let mut decoder = StpDecoder::new();
+    decoder.decode_nibbles(nibbles, |r| results.push(r));
+    decoder.finish(|r| results.push(r));
+
+    let mut out = String::new();
+    let mut encoder = Encoder::default();
+    let mut cursor = 0;
+    for r in results {
+        let (start, span) = match &r {
+            Ok(p) => (p.start, p.span),
+            Err(e) => (e.start, e.span),
+        };
+        if start < cursor {
+            continue;
+        }
+        write_raw(&mut out, &nibbles[cursor..start], None);
+        let end = (start + span).min(nibbles.len());
+        cursor = end;
+
+        let p = match r {
+            Ok(p) => p,
+            Err(e) => {
+                write_raw(&mut out, &nibbles[start..end], Some(&e.reason.to_string()));
+                continue;
+            }
+        };
+
+        // Only use the packet syntax if it reproduces the original nibbles:
+        let mut encoded = Vec::new();
+        let line = format_packet(&p.packet);
+        if encoder.encode(&p.packet, &mut encoded).is_ok() && encoded == nibbles[start..end] {
+            out.push_str(&line);
+            out.push('\n');
+        } else {
+            write_raw(&mut out, &nibbles[start..end], Some(&line));
+        }
+    }
+    write_raw(&mut out, &nibbles[cursor..], None);
+    out
+}
+
+/// Disassemble a stream of bytes, least significant nibble first.
+///
+/// A NULL packet in the last nibble is left out: it is the padding `assemble_bytes` adds to make
+/// whole bytes, and adds back.
+pub fn disassemble_bytes(bytes: &[u8]) -> String {
+    let nibbles: Vec<u8> = bytes.iter().flat_map(|b| [b & 0xF, b >> 4]).collect();
+    let text = disassemble(&nibbles);
+    if let Some((0, rest)) = nibbles.split_last() {
+        let unpadded = disassemble(rest);
+        if text.strip_prefix(unpadded.as_str()) == Some("null\n") {
+            return unpadded;
+        }
+    }
+    text
+}
+
+// Write nibbles as 'raw' lines, with an optional comment on the first line:
+fn write_raw(out: &mut String, nibbles: &[u8], mut comment: Option<&str>) {
+    for chunk in nibbles.chunks(32) {
+        out.push_str("raw");
+        for n in chunk {
+            out.push_str(&format!(" {:x}", n));
+        }
+        if let Some(c) = comment.take() {
+            out.push_str(&format!("  # {}", c));
+        }
+        out.push('\n');
+    }
+}
+
+/// Format a packet as a line of text (without the newline).
+pub fn format_packet(packet: &stp::Packet) -> String {
+    let name = |opcode: OpCode| format!("{:?}", opcode).to_lowercase();
+    let hex = |value: u64, nibbles: usize| format!("{:#0w$x}", value, w = nibbles + 2);
+    let mut line = match packet {
+        stp::Packet::Async => "async".to_string(),
+        stp::Packet::Version {
+            version,
+            ts_type,
+            is_le,
+        } => {
+            let mut line = format!(
+                "version {} {}",
+                version_name(*version),
+                ts_type_name(*ts_type)
+            );
+            if *version == StpVersion::STPv2_2 {
+                line.push_str(if *is_le { " le" } else { " be" });
+            }
+            line
+        }
+        stp::Packet::Master { opcode, master } => format!("{} {:#x}", name(*opcode), master),
+        stp::Packet::Channel { opcode, channel } => format!("{} {:#x}", name(*opcode), channel),
+        stp::Packet::Error { opcode, data } => format!("{} {:#04x}", name(*opcode), data),
+        stp::Packet::Data { opcode, data, .. } => format!(
+            "{} {}",
+            name(*opcode),
+            hex(*data, data_nibbles(*opcode).unwrap_or(1))
+        ),
+        stp::Packet::Frequency {
+            opcode, frequency, ..
+        } => format!("{} {}", name(*opcode), frequency),
+        stp::Packet::User {
+            length, payload, ..
+        } => format!(
+            "{} {} len={}",
+            name(packet.opcode().unwrap()),
+            hex(*payload, *length as usize),
+            length
+        ),
+        stp::Packet::Null { .. } | stp::Packet::Flag { .. } => name(packet.opcode().unwrap()),
+    };
+
+    if let Some(ts) = packet_timestamp(packet) {
+        line.push_str(" ts=");
+        line.push_str(&match ts {
+            Timestamp::STPv1 { value } => format!("legacy:{:#04x}", value),
+            Timestamp::STPv2NATDELTA { length, value } => {
+                format!("natdelta:{}:{:#x}", length, value)
+            }
+            Timestamp::STPv2NAT { length, value } => format!("nat:{}:{:#x}", length, value),
+            Timestamp::STPv2GRAY { length, value } => format!("gray:{}:{:#x}", length, value),
+        });
+    }
+    line
+}
+
+fn version_name(version: StpVersion) -> &'static str {
+    match version {
+        StpVersion::STPv1 => "v1",
+        StpVersion::STPv2_1 => "v2.1",
+        StpVersion::STPv2_2 => "v2.2",
+    }
+}
+
+fn ts_type_name(ts_type: TimestampType) -> &'static str {
+    match ts_type {
+        TimestampType::STPv1LEGACY => "legacy",
+        TimestampType::STPv2NATDELTA => "natdelta",
+        TimestampType::STPv2NAT => "nat",
+        TimestampType::STPv2GRAY => "gray",
+    }
+}
+
+fn packet_timestamp(packet: &stp::Packet) -> Option<&Timestamp> {
+    match packet {
+        stp::Packet::Null { timestamp }
+        | stp::Packet::Data { timestamp, .. }
+        | stp::Packet::User { timestamp, .. }
+        | stp::Packet::Frequency { timestamp, .. }
+        | stp::Packet::Flag { timestamp } => timestamp.as_ref(),
+        _ => None,
+    }
+}
+
+// The size of an opcode's fixed size payload, in nibbles:
+fn data_nibbles(opcode: OpCode) -> Option<usize> {
+    match opcode {
+        OpCode::M8 | OpCode::C8 | OpCode::MERR | OpCode::GERR => Some(2),
+        OpCode::M16 | OpCode::C16 => Some(4),
+        OpCode::FREQ | OpCode::FREQ_TS => Some(8),
+        OpCode::FREQ_40 | OpCode::FREQ_40_TS => Some(10),
+        _ => opcode.data_bits().map(|bits| bits as usize / 4),
+    }
+}
+
+enum Line {
+    Packet(stp::Packet),
+    Raw(Vec<u8>),
+}
+
+fn parse_line(line: &str) -> LineResult<Option<Line>> {
+    let line = line.split('#').next().unwrap();
+    let mut words = line.split_whitespace();
+    let keyword = match words.next() {
+        Some(w) => w.to_lowercase(),
+        None => return Ok(None),
+    };
+    let args: Vec<&str> = words.collect();
+
+    let packet = match keyword.as_str() {
+        "async" => {
+            expect_args(&args, 0)?;
+            stp::Packet::Async
+        }
+        "version" => parse_version(&args)?,
+        "raw" => {
+            return args
+                .iter()
+                .map(|a| match u8::from_str_radix(a, 16) {
+                    Ok(n) if n < 0x10 => Ok(n),
+                    _ => Err(format!("invalid nibble: {}", a)),
+                })
+                .collect::<LineResult<Vec<u8>>>()
+                .map(|raw| Some(Line::Raw(raw)))
+        }
+        name => {
+            let opcode: OpCode = name.parse()?;
+            parse_packet(opcode, &args)?
+        }
+    };
+    Ok(Some(Line::Packet(packet)))
+}
+
+fn expect_args(args: &[&str], n: usize) -> LineResult<()> {
+    if args.len() == n {
+        Ok(())
+    } else {
+        Err(format!("expected {} argument(s), found {}", n, args.len()))
+    }
+}
+
+fn parse_version(args: &[&str]) -> LineResult<stp::Packet> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err("expected: version VERSION TIMESTAMP_TYPE [le|be]".to_string());
+    }
+    let version = match args[0].to_lowercase().as_str() {
+        "v1" => StpVersion::STPv1,
+        "v2.1" | "v2" => StpVersion::STPv2_1,
+        "v2.2" => StpVersion::STPv2_2,
+        v => return Err(format!("invalid version: {}", v)),
+    };
+    let ts_type = parse_ts_type(args[1])?;
+    let is_le = match args.get(2).map(|s| s.to_lowercase()) {
+        None => false,
+        Some(ref s) if s == "be" => false,
+        Some(ref s) if s == "le" => true,
+        Some(s) => return Err(format!("invalid endianness: {}", s)),
+    };
+    Ok(stp::Packet::Version {
+        version,
+        ts_type,
+        is_le,
+    })
+}
+
+fn parse_ts_type(s: &str) -> LineResult<TimestampType> {
+    match s.to_lowercase().as_str() {
+        "legacy" => Ok(TimestampType::STPv1LEGACY),
+        "natdelta" => Ok(TimestampType::STPv2NATDELTA),
+        "nat" => Ok(TimestampType::STPv2NAT),
+        "gray" => Ok(TimestampType::STPv2GRAY),
+        t => Err(format!("invalid timestamp type: {}", t)),
+    }
+}
+
+fn parse_value(s: &str) -> LineResult<u64> {
+    let r = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    r.map_err(|_| format!("invalid value: {}", s))
+}
+
+// Parse a value that must fit in `nibbles` nibbles:
+fn parse_sized(s: &str, nibbles: usize) -> LineResult<u64> {
+    let value = parse_value(s)?;
+    if nibbles < 16 && value >> (nibbles * 4) != 0 {
+        Err(format!("value does not fit in {} nibbles: {}", nibbles, s))
+    } else {
+        Ok(value)
+    }
+}
+
+fn parse_timestamp(s: &str) -> LineResult<Timestamp> {
+    let parts: Vec<&str> = s.split(':').collect();
+    match (parse_ts_type(parts[0])?, parts.len()) {
+        (TimestampType::STPv1LEGACY, 2) => Ok(Timestamp::STPv1 {
+            value: parse_sized(parts[1], 2)? as u8,
+        }),
+        (TimestampType::STPv1LEGACY, _) => Err("expected: ts=legacy:VALUE".to_string()),
+        (ts_type, 3) => {
+            let length = match parse_value(parts[1])? {
+                l @ (0..=12 | 14 | 16) => l as u8,
+                l => return Err(format!("invalid timestamp length: {}", l)),
+            };
+            let value = parse_sized(parts[2], length as usize)?;
+            Ok(match ts_type {
+                TimestampType::STPv2NATDELTA => Timestamp::STPv2NATDELTA { length, value },
+                TimestampType::STPv2NAT => Timestamp::STPv2NAT { length, value },
+                _ => Timestamp::STPv2GRAY { length, value },
+            })
+        }
+        _ => Err("expected: ts=TYPE:LENGTH:VALUE".to_string()),
+    }
+}
+
+fn parse_packet(opcode: OpCode, args: &[&str]) -> LineResult<stp::Packet> {
+    let mut values = Vec::new();
+    let mut timestamp = None;
+    let mut length = None;
+    for arg in args {
+        if let Some(ts) = arg.strip_prefix("ts=") {
+            timestamp = Some(parse_timestamp(ts)?);
+        } else if let Some(len) = arg.strip_prefix("len=") {
+            length = Some(parse_value(len)?);
+        } else {
+            values.push(*arg);
+        }
+    }
+
+    if opcode.has_timestamp() != timestamp.is_some() {
+        return Err(if timestamp.is_some() {
+            format!("{:?} does not take a timestamp", opcode)
+        } else {
+            format!("{:?} requires a timestamp (ts=...)", opcode)
+        });
+    }
+    if length.is_some() && !matches!(opcode, OpCode::USER | OpCode::USER_TS) {
+        return Err(format!("{:?} does not take a length", opcode));
+    }
+
+    let nibbles = data_nibbles(opcode);
+    let value = |expected: usize| -> LineResult<u64> {
+        expect_args(&values, expected)?;
+        match nibbles {
+            Some(n) if expected > 0 => parse_sized(values[0], n),
+            _ => Ok(0),
+        }
+    };
+
+    Ok(match opcode {
+        OpCode::NULL | OpCode::NULL_TS => {
+            value(0)?;
+            stp::Packet::Null { timestamp }
+        }
+        OpCode::FLAG | OpCode::FLAG_TS => {
+            value(0)?;
+            stp::Packet::Flag { timestamp }
+        }
+        OpCode::M8 | OpCode::M16 => stp::Packet::Master {
+            opcode,
+            master: value(1)? as u16,
+        },
+        OpCode::C8 | OpCode::C16 => stp::Packet::Channel {
+            opcode,
+            channel: value(1)? as u16,
+        },
+        OpCode::MERR | OpCode::GERR => stp::Packet::Error {
+            opcode,
+            data: value(1)? as u8,
+        },
+        OpCode::FREQ | OpCode::FREQ_TS | OpCode::FREQ_40 | OpCode::FREQ_40_TS => {
+            stp::Packet::Frequency {
+                opcode,
+                frequency: value(1)?,
+                timestamp,
+            }
+        }
+        OpCode::USER | OpCode::USER_TS => {
+            expect_args(&values, 1)?;
+            let payload = parse_value(values[0])?;
+            let min_length = ((64 - payload.leading_zeros() as u64).div_ceil(4)).max(1);
+            let length = length.unwrap_or(min_length);
+            if !(min_length..=16).contains(&length) {
+                return Err(format!("invalid USER length: {}", length));
+            }
+            stp::Packet::User {
+                length: length as u8,
+                payload,
+                timestamp,
+            }
+        }
+        OpCode::VERSION => return Err("use: version VERSION TIMESTAMP_TYPE [le|be]".to_string()),
+        _ => stp::Packet::Data {
+            opcode,
+            data: value(1)?,
+            timestamp,
+        },
+    })
+}
+
+// Encodes packets, tracking the endianness set by VERSION packets.
+#[derive(Default)]
+struct Encoder {
+    is_le: bool,
+}
+
+impl Encoder {
+    fn encode(&mut self, packet: &stp::Packet, out: &mut Vec<u8>) -> LineResult<()> {
+        match packet {
+            stp::Packet::Async => {
+                out.extend_from_slice(&[0xF; 21]);
+                out.push(0);
+                self.is_le = false;
+                return Ok(());
+            }
+            stp::Packet::Version {
+                version,
+                ts_type,
+                is_le,
+            } => {
+                let ts_bits = *ts_type as u8;
+                out.extend_from_slice(&[0xF, 0x0, 0x0]);
+                match version {
+                    StpVersion::STPv1 if *ts_type == TimestampType::STPv1LEGACY && !is_le => {
+                        out.push(0)
+                    }
+                    StpVersion::STPv1 => return Err("v1 only supports legacy be".to_string()),
+                    StpVersion::STPv2_1 if !is_le => out.push(ts_bits),
+                    StpVersion::STPv2_1 => return Err("v2.1 does not support le".to_string()),
+                    StpVersion::STPv2_2 => {
+                        let payload = if *is_le { 0x81 } else { 0x01 };
+                        out.extend_from_slice(&[0x8 | ts_bits, payload >> 4, payload & 0xF]);
+                        // Like the decoder, only v2.2 packets change the endianness:
+                        self.is_le = *is_le;
+                    }
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let opcode = packet.opcode().unwrap();
+        push_opcode(opcode, out);
+
+        match packet {
+            stp::Packet::Master { master: v, .. } | stp::Packet::Channel { channel: v, .. } => {
+                self.push_value(*v as u64, data_nibbles(opcode).unwrap(), out)
+            }
+            stp::Packet::Error { data, .. } => self.push_value(*data as u64, 2, out),
+            stp::Packet::Data { data, .. } => {
+                self.push_value(*data, data_nibbles(opcode).unwrap(), out)
+            }
+            stp::Packet::Frequency { frequency, .. } => {
+                self.push_value(*frequency, data_nibbles(opcode).unwrap(), out)
+            }
+            stp::Packet::User {
+                length, payload, ..
+            } => {
+                if *length == 0 || *length > 16 {
+                    return Err(format!("invalid USER length: {}", length));
+                }
+                out.push(length - 1);
+                self.push_value(*payload, *length as usize, out);
+            }
+            _ => {}
+        }
+
+        if let Some(ts) = packet_timestamp(packet) {
+            match *ts {
+                Timestamp::STPv1 { value } => self.push_value(value as u64, 2, out),
+                Timestamp::STPv2NATDELTA { length, value }
+                | Timestamp::STPv2NAT { length, value }
+                | Timestamp::STPv2GRAY { length, value } => {
+                    out.push(match length {
+                        0..=12 => length,
+                        14 => 0xD,
+                        16 => 0xE,
+                        _ => return Err(format!("invalid timestamp length: {}", length)),
+                    });
+                    if length > 0 {
+                        self.push_value(value, length as usize, out);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Push a value's nibbles, most significant first (or swapped, if little endian):
+    fn push_value(&self, value: u64, nibbles: usize, out: &mut Vec<u8>) {
+        let value = if self.is_le && nibbles > 1 {
+            swap_nibbles(value, nibbles)
+        } else {
+            value
+        };
+        for i in (0..nibbles).rev() {
+            out.push(((value >> (i * 4)) & 0xF) as u8);
+        }
+    }
+}
+
+// Opcodes are encoded as their hex digits, most significant first:
+fn push_opcode(opcode: OpCode, out: &mut Vec<u8>) {
+    let value = opcode as u16;
+    let digits = match value {
+        0..=0xF => 1,
+        0x10..=0xFF => 2,
+        0x100..=0xFFF => 3,
+        _ => 4,
+    };
+    for i in (0..digits).rev() {
+        out.push(((value >> (i * 4)) & 0xF) as u8);
+    }
+}
@@ -0,0 +1,348 @@
+//! Format strings for SyS-T catalog messages, read from the XML collateral files a build
+//! produces:
+//!
+//! ```xml
+//! <syst:Collateral xmlns:syst="http://www.mipi.org/1.0/sys-t">
+//!   <syst:Client Name="firmware">
+//!     <syst:Guids>
+//!       <syst:Guid ID="{494E5443-8A9C-4014-A65A-2F36A36D96E4}"
+//!                  Mask="{00000000-0000-0000-FF00-000000000000}"/>
+//!     </syst:Guids>
+//!     <syst:Modules>
+//!       <syst:Module ID="3"/>
+//!     </syst:Modules>
+//!     <syst:Catalog32>
+//!       <syst:Format ID="0x1"><![CDATA[boot stage %d, flags %#x\n]]></syst:Format>
+//!     </syst:Catalog32>
+//!     <syst:Catalog64>...</syst:Catalog64>
+//!     <syst:SourceFiles>
+//!       <syst:File ID="1"><![CDATA[src/boot.c]]></syst:File>
+//!     </syst:SourceFiles>
+//!   </syst:Client>
+//! </syst:Collateral>
+//! ```
+//!
+//! A client's catalogs are used for messages from the GUIDs (ignoring the bits set in their
+//! masks) and modules it lists, or for every message if it lists neither.  Reading collateral
+//! files requires the `config` feature.
+
+use crate::printf::{self, Arguments};
+use crate::syst::{self, Origin, Payload};
+use std::collections::HashMap;
+#[cfg(feature = "config")]
+use std::convert::TryFrom;
+use std::fmt;
+#[cfg(feature = "config")]
+use std::fs;
+#[cfg(feature = "config")]
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// No catalog for the message's origin has a format string with this ID.
+    UnknownId { id: u64, origin: Origin },
+    /// The packed arguments are too short for the format string.
+    Arguments { format: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownId { id, origin } => {
+                write!(f, "unknown catalog ID {:#x} from {}", id, origin)
+            }
+            Error::Arguments { format } => write!(f, "missing arguments for {:?}", format),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Client {
+    guids: Vec<([u8; 16], [u8; 16])>, // GUIDs and the bits to ignore when matching them.
+    modules: Vec<u8>,
+    catalog32: HashMap<u64, String>,
+    catalog64: HashMap<u64, String>,
+    files: HashMap<u32, String>,
+}
+
+impl Client {
+    fn matches(&self, origin: &Origin) -> bool {
+        if self.guids.is_empty() && self.modules.is_empty() {
+            return true;
+        }
+        match origin {
+            Origin::Guid(g) => self
+                .guids
+                .iter()
+                .any(|(guid, mask)| (0..16).all(|i| (g[i] ^ guid[i]) & !mask[i] == 0)),
+            Origin::ModuleUnit { module, .. } => self.modules.contains(module),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Catalog {
+    clients: Vec<Client>,
+    pointer_size: Option<usize>, // Overrides the size the message subtypes give.
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size in bytes of the writer's longs and pointers, for writers whose messages don't
+    /// give it right.  By default it comes from each message's subtype.
+    pub fn pointer_size(mut self, size: usize) -> Self {
+        self.pointer_size = Some(size);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Add the clients of a collateral file.
+    #[cfg(feature = "config")]
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.add_xml(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Add the clients of a collateral file's text.
+    #[cfg(feature = "config")]
+    pub fn add_xml(&mut self, text: &str) -> Result<(), String> {
+        let document = roxmltree::Document::parse(text).map_err(|e| e.to_string())?;
+        let root = document.root_element();
+        if root.tag_name().name() != "Collateral" {
+            return Err(format!(
+                "expected a Collateral element, found {}",
+                root.tag_name().name()
+            ));
+        }
+        for c in children(root, "Client") {
+            let mut client = Client::default();
+            for g in children(c, "Guids").flat_map(|e| children(e, "Guid")) {
+                let mask = match g.attribute("Mask") {
+                    Some(mask) => parse_guid(mask)?,
+                    None => [0; 16],
+                };
+                client.guids.push((parse_guid(attribute_id(g)?)?, mask));
+            }
+            for m in children(c, "Modules").flat_map(|e| children(e, "Module")) {
+                client.modules.push(id::<u8>(m)?);
+            }
+            for f in children(c, "Catalog32").flat_map(|e| children(e, "Format")) {
+                let format = c_unescape(&text_of(f));
+                client.catalog32.insert(id::<u32>(f)? as u64, format);
+            }
+            for f in children(c, "Catalog64").flat_map(|e| children(e, "Format")) {
+                client
+                    .catalog64
+                    .insert(id::<u64>(f)?, c_unescape(&text_of(f)));
+            }
+            for f in children(c, "SourceFiles").flat_map(|e| children(e, "File")) {
+                client.files.insert(id::<u32>(f)?, text_of(f));
+            }
+            self.clients.push(client);
+        }
+        Ok(())
+    }
+
+    /// The format string of a 32-bit or 64-bit catalog ID.
+    pub fn format(&self, origin: &Origin, id: u64, wide: bool) -> Option<&str> {
+        self.clients
+            .iter()
+            .filter(|c| c.matches(origin))
+            .find_map(|c| match wide {
+                false => c.catalog32.get(&id),
+                true => c.catalog64.get(&id),
+            })
+            .map(String::as_str)
+    }
+
+    /// The name of a source file, for messages with a file location.
+    pub fn file(&self, origin: &Origin, file: u32) -> Option<&str> {
+        self.clients
+            .iter()
+            .filter(|c| c.matches(origin))
+            .find_map(|c| c.files.get(&file))
+            .map(String::as_str)
+    }
+
+    /// The text of a message: catalog and printf strings are formatted with their arguments,
+    /// other payloads are shown as they are.
+    pub fn text(&self, m: &syst::Message) -> Result<String, Error> {
+        let (format, args) = match &m.payload {
+            Payload::Catalog { id, args } => {
+                let wide = m.header.subtype & 0x3 == 2;
+                match self.format(&m.origin, *id, wide) {
+                    Some(format) => (format, args),
+                    None => {
+                        return Err(Error::UnknownId {
+                            id: *id,
+                            origin: m.origin,
+                        })
+                    }
+                }
+            }
+            Payload::String {
+                subtype: 11 | 12,
+                text,
+                args,
+            } => (text.as_str(), args),
+            p => return Ok(p.to_string()),
+        };
+        // Catalog subtypes 5 and 6, and printf subtype 12, have 64-bit pointers:
+        let pointer_size = match m.header.subtype & 0x4 {
+            0 => 4,
+            _ => 8,
+        };
+        match printf(format, args, self.pointer_size.unwrap_or(pointer_size)) {
+            Some(text) => Ok(text.trim_end_matches('\n').to_string()),
+            None => Err(Error::Arguments {
+                format: format.to_string(),
+            }),
+        }
+    }
+}
+
+// The child elements with a name, whatever their namespace:
+#[cfg(feature = "config")]
+fn children<'a, 'input: 'a>(
+    e: roxmltree::Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> + 'a {
+    e.children()
+        .filter(move |c| c.is_element() && c.tag_name().name() == name)
+}
+
+// An element's text, with its CDATA sections:
+#[cfg(feature = "config")]
+fn text_of(e: roxmltree::Node) -> String {
+    e.descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect()
+}
+
+#[cfg(feature = "config")]
+fn attribute_id<'a>(e: roxmltree::Node<'a, '_>) -> Result<&'a str, String> {
+    e.attribute("ID")
+        .ok_or_else(|| format!("{} without an ID", e.tag_name().name()))
+}
+
+// A numeric ID, which must fit the IDs messages carry:
+#[cfg(feature = "config")]
+fn id<T: TryFrom<u64>>(e: roxmltree::Node) -> Result<T, String> {
+    let s = attribute_id(e)?;
+    T::try_from(parse_number(s)?)
+        .map_err(|_| format!("{} ID out of range: {}", e.tag_name().name(), s))
+}
+
+#[cfg(feature = "config")]
+fn parse_number(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{}: {}", e, s))
+}
+
+// Parse a GUID in its usual form, e.g. "{494E5443-8A9C-4014-A65A-2F36A36D96E4}", into the order
+// messages carry it (the first three fields little endian):
+#[cfg(feature = "config")]
+fn parse_guid(s: &str) -> Result<[u8; 16], String> {
+    let error = || format!("invalid GUID: {}", s);
+    let digits: String = s
+        .trim_matches(|c| c == '{' || c == '}')
+        .chars()
+        .filter(|c| *c != '-')
+        .collect();
+    if digits.len() != 32 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(error());
+    }
+    let order = [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15];
+    let mut guid = [0; 16];
+    for (i, b) in order.iter().enumerate() {
+        guid[*b] = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| error())?;
+    }
+    Ok(guid)
+}
+
+// Format strings are copied from the source, so they still have their C escapes:
+#[cfg(feature = "config")]
+fn c_unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('a') => '\x07',
+            Some('b') => '\x08',
+            Some('f') => '\x0c',
+            Some('v') => '\x0b',
+            Some('x') => {
+                let mut v = 0;
+                while let Some(d) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    v = (v * 16 + d) & 0xff;
+                    chars.next();
+                }
+                v as u8 as char
+            }
+            Some(d @ '0'..='7') => {
+                let mut v = d.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(d) => v = v * 8 + d,
+                        None => break,
+                    }
+                    chars.next();
+                }
+                (v & 0xff) as u8 as char
+            }
+            Some(c) => c, // Quotes, backslashes and question marks.
+            None => '\\',
+        };
+        out.push(escaped);
+    }
+    out
+}
+
+// The packed arguments of a message, little endian:
+struct Args<'a>(&'a [u8]);
+
+impl<'a> Arguments for Args<'a> {
+    fn int(&mut self, size: usize) -> Option<u64> {
+        if self.0.len() < size {
+            return None;
+        }
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(&self.0[..size]);
+        self.0 = &self.0[size..];
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    // Strings are packed in place, with their terminating NUL:
+    fn string(&mut self) -> Option<String> {
+        let end = self.0.iter().position(|b| *b == 0)?;
+        let s = String::from_utf8_lossy(&self.0[..end]).into_owned();
+        self.0 = &self.0[end + 1..];
+        Some(s)
+    }
+}
+
+/// Format a C printf string with its packed arguments: ints are 4 bytes, longs and pointers
+/// `pointer_size` bytes, long longs and doubles 8 bytes, and strings are packed in place.
+/// Returns `None` if the arguments run out.
+pub fn printf(format: &str, args: &[u8], pointer_size: usize) -> Option<String> {
+    printf::format(format, &mut Args(args), pointer_size)
+}
@@ -0,0 +1,372 @@
+//! Writes decoded packets as a Common Trace Format (CTF 1.8) trace, for Babeltrace and Trace
+//! Compass.
+//!
+//! A trace is a `metadata` file, describing the layout of the events in TSDL, and one binary stream
+//! file per STP master (or per TWP stream).  Event timestamps are the decoder's absolute
+//! timestamps, mapped to a clock running at the frequency of the first FREQ packet.  The metadata
+//! is written last, once the frequency is known.
+//!
+//! With `Names`, data and flag events end with the names of their master and channel, and data
+//! events with their payload in its configured format.
+
+use crate::message::MessageTracker;
+use crate::names::Names;
+use crate::stp;
+use crate::stp_decoder::{Error, Packet};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+const MAGIC: u32 = 0xC1FC_1FC1;
+const PACKET_SIZE: usize = 64 * 1024; // Event bytes per CTF packet, roughly.
+const DEFAULT_FREQUENCY: u64 = 1_000_000_000; // CTF's default: one tick per nanosecond.
+
+// Event class IDs:
+const EVENT_DATA: [(u8, u16); 5] = [(4, 0), (8, 1), (16, 2), (32, 3), (64, 4)];
+const EVENT_FLAG: u16 = 5;
+const EVENT_USER: u16 = 6;
+const EVENT_FREQUENCY: u16 = 7;
+const EVENT_ERROR: u16 = 8;
+
+/// How packets are divided into CTF streams.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamPer {
+    Master,
+    TwpId,
+}
+
+// The decoding state of a TWP stream:
+#[derive(Default)]
+struct Source {
+    tracker: MessageTracker,
+    time: u64, // Latest timestamp.
+}
+
+// A CTF stream being written:
+struct Stream<W: Write> {
+    out: W,
+    events: Vec<u8>, // Events of the current packet.
+    begin: u64,
+    end: u64,
+}
+
+/// Writes a CTF trace, opening its files (`metadata` and the streams) with `open`.
+pub struct CtfWriter<W: Write, F: FnMut(&str) -> io::Result<W>> {
+    open: F,
+    per: StreamPer,
+    sources: BTreeMap<Option<u8>, Source>,
+    streams: BTreeMap<(Option<u8>, Option<u16>), Stream<W>>,
+    frequency: Option<u64>,
+    names: Option<Names>,
+}
+
+impl<W: Write, F: FnMut(&str) -> io::Result<W>> CtfWriter<W, F> {
+    pub fn new(per: StreamPer, open: F) -> Self {
+        CtfWriter {
+            open,
+            per,
+            sources: BTreeMap::new(),
+            streams: BTreeMap::new(),
+            frequency: None,
+            names: None,
+        }
+    }
+
+    /// Name masters and channels in data and flag events, before writing any.
+    pub fn set_names(&mut self, names: Names) {
+        self.names = Some(names);
+    }
+
+    /// Use this clock frequency, in Hz, rather than the one from FREQ packets.
+    pub fn set_frequency(&mut self, frequency: u64) {
+        self.frequency = Some(frequency);
+    }
+
+    /// Write the events for a packet of TWP stream `id`.
+    pub fn packet(&mut self, id: Option<u8>, p: &Packet) -> io::Result<()> {
+        let source = self.sources.entry(id).or_default();
+        let message = source.tracker.process(p);
+        if let Some(ts) = source.tracker.timestamp() {
+            source.time = ts;
+        }
+        let (master, time) = (source.tracker.master(), source.time);
+
+        let mut fields = Fields::default();
+        let event = match (&p.packet, message) {
+            (stp::Packet::Data { .. }, Some(m)) => {
+                let bits = m.opcode.data_bits().unwrap_or(64);
+                fields
+                    .string(&format!("{:?}", m.opcode))
+                    .uint(m.master as u64, 2)
+                    .uint(m.channel as u64, 2)
+                    .uint(m.data.unwrap_or(0), (bits as usize / 8).max(1));
+                if let Some(names) = &self.names {
+                    let value = names.format_data(m.master, m.channel, m.data.unwrap_or(0), bits);
+                    fields.names(names, m.master, m.channel).string(&value);
+                }
+                let class = EVENT_DATA.iter().find(|(b, _)| *b == bits).unwrap().1;
+                (m.master, class)
+            }
+            (stp::Packet::Flag { .. }, Some(m)) => {
+                fields
+                    .string(&format!("{:?}", m.opcode))
+                    .uint(m.master as u64, 2)
+                    .uint(m.channel as u64, 2);
+                if let Some(names) = &self.names {
+                    fields.names(names, m.master, m.channel);
+                }
+                (m.master, EVENT_FLAG)
+            }
+            (
+                stp::Packet::User {
+                    length, payload, ..
+                },
+                _,
+            ) => {
+                fields.uint(*length as u64, 1).uint(*payload, 8);
+                (master, EVENT_USER)
+            }
+            (stp::Packet::Frequency { frequency, .. }, _) => {
+                if self.frequency.is_none() && *frequency > 0 {
+                    self.frequency = Some(*frequency);
+                }
+                fields.uint(*frequency, 8);
+                (master, EVENT_FREQUENCY)
+            }
+            (stp::Packet::Error { opcode, data }, _) => {
+                fields
+                    .string(&format!("{:?} {:#04x}", opcode, data))
+                    .uint(p.start as u64, 8);
+                (master, EVENT_ERROR)
+            }
+            _ => return Ok(()),
+        };
+        self.event(id, event.0, event.1, time, &fields)
+    }
+
+    /// Write an error event for a decoding error in TWP stream `id`.
+    pub fn error(&mut self, id: Option<u8>, e: &Error) -> io::Result<()> {
+        let source = self.sources.entry(id).or_default();
+        let (master, time) = (source.tracker.master(), source.time);
+        source.tracker.reset();
+        let mut fields = Fields::default();
+        fields.string(&e.reason.to_string()).uint(e.start as u64, 8);
+        self.event(id, master, EVENT_ERROR, time, &fields)
+    }
+
+    /// Write the remaining events and the metadata.
+    pub fn finish(mut self) -> io::Result<()> {
+        for (key, stream) in self.streams.iter_mut() {
+            stream.flush(*key)?;
+            stream.out.flush()?;
+        }
+        let frequency = self.frequency.unwrap_or(DEFAULT_FREQUENCY);
+        let metadata = metadata(frequency, self.names.is_some());
+        let mut out = (self.open)("metadata")?;
+        out.write_all(metadata.as_bytes())?;
+        out.flush()
+    }
+
+    fn event(
+        &mut self,
+        id: Option<u8>,
+        master: u16,
+        class: u16,
+        time: u64,
+        fields: &Fields,
+    ) -> io::Result<()> {
+        let key = match self.per {
+            StreamPer::Master => (id, Some(master)),
+            StreamPer::TwpId => (id, None),
+        };
+        if !self.streams.contains_key(&key) {
+            let out = (self.open)(&stream_name(key))?;
+            self.streams.insert(
+                key,
+                Stream {
+                    out,
+                    events: Vec::new(),
+                    begin: time,
+                    end: time,
+                },
+            );
+        }
+        let stream = self.streams.get_mut(&key).unwrap();
+
+        // CTF timestamps never decrease within a stream:
+        let time = time.max(stream.end);
+        if stream.events.is_empty() {
+            stream.begin = time;
+        }
+        stream.end = time;
+        let mut header = Fields::default();
+        header.uint(class as u64, 2).uint(time, 8);
+        stream.events.extend_from_slice(&header.0);
+        stream.events.extend_from_slice(&fields.0);
+        if stream.events.len() >= PACKET_SIZE {
+            stream.flush(key)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Stream<W> {
+    // Write the buffered events as a CTF packet:
+    fn flush(&mut self, (id, master): (Option<u8>, Option<u16>)) -> io::Result<()> {
+        if self.events.is_empty() {
+            return Ok(());
+        }
+        let mut header = Fields::default();
+        header.uint(MAGIC as u64, 4).uint(0, 4); // The only stream class.
+        let size = (header.0.len() + CONTEXT_SIZE + self.events.len()) as u64 * 8;
+        header
+            .uint(self.begin, 8)
+            .uint(self.end, 8)
+            .uint(size, 8) // Content size, in bits.
+            .uint(size, 8) // Packet size, in bits.
+            .uint(id.map_or(-1, |id| id as i64) as u64, 2)
+            .uint(master.map_or(-1, |m| m as i64) as u64, 4);
+        self.out.write_all(&header.0)?;
+        self.out.write_all(&self.events)?;
+        self.events.clear();
+        Ok(())
+    }
+}
+
+const CONTEXT_SIZE: usize = 8 + 8 + 8 + 8 + 2 + 4;
+
+fn stream_name((id, master): (Option<u8>, Option<u16>)) -> String {
+    let mut name = String::from("stream");
+    if let Some(id) = id {
+        name.push_str(&format!("_{:#04x}", id));
+    }
+    if let Some(master) = master {
+        name.push_str(&format!("_m{}", master));
+    }
+    name
+}
+
+// Little endian, byte aligned, event fields:
+#[derive(Default)]
+struct Fields(Vec<u8>);
+
+impl Fields {
+    fn uint(&mut self, v: u64, bytes: usize) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes()[..bytes]);
+        self
+    }
+
+    fn string(&mut self, s: &str) -> &mut Self {
+        self.0.extend(s.bytes().filter(|b| *b != 0));
+        self.0.push(0);
+        self
+    }
+
+    // The master's and channel's names, or empty strings:
+    fn names(&mut self, names: &Names, master: u16, channel: u16) -> &mut Self {
+        self.string(names.master(master).map_or("", |n| &n.name))
+            .string(names.channel(master, channel).map_or("", |n| &n.name))
+    }
+}
+
+fn metadata(frequency: u64, named: bool) -> String {
+    let mut events = String::new();
+    let mut event = |name: &str, id: u16, fields: &str| {
+        events.push_str(&format!(
+            "event {{\n\tname = \"{}\";\n\tid = {};\n\tstream_id = 0;\n\tfields := struct {{\n{}\t}};\n}};\n\n",
+            name, id, fields
+        ));
+    };
+    let names = if named {
+        "\t\tstring master_name;\n\t\tstring channel_name;\n"
+    } else {
+        ""
+    };
+    let value = if named { "\t\tstring value;\n" } else { "" };
+    for (bits, id) in EVENT_DATA.iter() {
+        event(
+            &format!("data{}", bits),
+            *id,
+            &format!(
+                "\t\tstring opcode;\n\t\tuint16_t master;\n\t\tuint16_t channel;\n\t\t\
+                 integer {{ size = {}; align = 8; signed = false; base = 16; }} data;\n{}{}",
+                (*bits).max(8),
+                names,
+                value
+            ),
+        );
+    }
+    event(
+        "flag",
+        EVENT_FLAG,
+        &format!(
+            "\t\tstring opcode;\n\t\tuint16_t master;\n\t\tuint16_t channel;\n{}",
+            names
+        ),
+    );
+    event(
+        "user",
+        EVENT_USER,
+        "\t\tuint8_t length;\n\t\tinteger { size = 64; align = 8; signed = false; base = 16; } payload;\n",
+    );
+    event("frequency", EVENT_FREQUENCY, "\t\tuint64_t frequency;\n");
+    event(
+        "error",
+        EVENT_ERROR,
+        "\t\tstring message;\n\t\tuint64_t offset;\n",
+    );
+
+    format!(
+        r#"/* CTF 1.8 */
+
+typealias integer {{ size = 8; align = 8; signed = false; }} := uint8_t;
+typealias integer {{ size = 16; align = 8; signed = false; }} := uint16_t;
+typealias integer {{ size = 32; align = 8; signed = false; }} := uint32_t;
+typealias integer {{ size = 64; align = 8; signed = false; }} := uint64_t;
+typealias integer {{ size = 16; align = 8; signed = true; }} := int16_t;
+typealias integer {{ size = 32; align = 8; signed = true; }} := int32_t;
+
+trace {{
+	major = 1;
+	minor = 8;
+	byte_order = le;
+	packet.header := struct {{
+		uint32_t magic;
+		uint32_t stream_id;
+	}};
+}};
+
+env {{
+	tracer_name = "stp";
+}};
+
+clock {{
+	name = "stp";
+	description = "STP timestamp counter";
+	freq = {};
+}};
+
+typealias integer {{
+	size = 64; align = 8; signed = false;
+	map = clock.stp.value;
+}} := stp_clock_t;
+
+stream {{
+	id = 0;
+	packet.context := struct {{
+		stp_clock_t timestamp_begin;
+		stp_clock_t timestamp_end;
+		uint64_t content_size;
+		uint64_t packet_size;
+		int16_t twp_id;
+		int32_t master;
+	}};
+	event.header := struct {{
+		uint16_t id;
+		stp_clock_t timestamp;
+	}};
+}};
+
+{}"#,
+        frequency, events
+    )
+}
@@ -0,0 +1,155 @@
+//! Decodes dictionary-based logs, where firmware keeps its printf format strings in a section of
+//! its ELF file instead of writing them out (requires the `elf` feature).
+//!
+//! Each log is a reassembled message: a 32-bit word with the address of its format string in the
+//! section, followed by the arguments packed without padding in the ELF file's byte order.  Ints
+//! are 4 bytes, longs and pointers the size of the file's addresses, and long longs and doubles 8
+//! bytes.  A `%s` argument is the address of a string in the file, and a `%p` argument is shown
+//! with the function it points into.
+
+use crate::printf::{self, Arguments};
+use crate::symbols::Symbols;
+use object::{Object, ObjectSection};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Short { len: usize },
+    UnknownAddress { address: u64 },
+    Arguments { format: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Short { len } => write!(f, "log of {} bytes has no format string address", len),
+            Error::UnknownAddress { address } => {
+                write!(f, "no format string at {:#x} in the dictionary", address)
+            }
+            Error::Arguments { format } => write!(f, "missing arguments for {:?}", format),
+        }
+    }
+}
+
+pub struct Dictionary {
+    section: (u64, Vec<u8>),      // Address and contents of the format strings.
+    strings: Vec<(u64, Vec<u8>)>, // Every section that %s arguments can point into.
+    is_le: bool,
+    pointer_size: usize,
+    symbols: Symbols,
+}
+
+impl Dictionary {
+    /// Load the format strings of a section of an ELF file.
+    pub fn load(path: &Path, section: &str) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_elf(&data, section).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_elf(data: &[u8], section: &str) -> Result<Self, String> {
+        let file = object::File::parse(data).map_err(|e| e.to_string())?;
+        let contents = |s: &object::Section| s.uncompressed_data().map(|d| d.into_owned());
+        let dictionary = file
+            .section_by_name(section)
+            .ok_or_else(|| format!("no {} section", section))?;
+        let dictionary = (
+            dictionary.address(),
+            contents(&dictionary).map_err(|e| e.to_string())?,
+        );
+        let strings = file
+            .sections()
+            .filter(|s| s.address() != 0)
+            .filter_map(|s| Some((s.address(), contents(&s).ok()?)))
+            .filter(|(_, data)| !data.is_empty())
+            .collect();
+
+        let mut symbols = Symbols::new();
+        symbols.add_elf(data, None, 0)?;
+        Ok(Self {
+            section: dictionary,
+            strings,
+            is_le: file.is_little_endian(),
+            pointer_size: if file.is_64() { 8 } else { 4 },
+            symbols,
+        })
+    }
+
+    /// The format string at an address of the dictionary section.
+    pub fn format(&self, address: u64) -> Option<String> {
+        string(&self.section, address)
+    }
+
+    /// Format a log message.
+    pub fn decode(&self, data: &[u8]) -> Result<String, Error> {
+        let mut args = Args {
+            data,
+            dictionary: self,
+        };
+        let address = args.int(4).ok_or(Error::Short { len: data.len() })?;
+        let format = self
+            .format(address)
+            .ok_or(Error::UnknownAddress { address })?;
+        match printf::format(&format, &mut args, self.pointer_size) {
+            Some(s) => Ok(s.strip_suffix('\n').unwrap_or(&s).to_string()),
+            None => Err(Error::Arguments { format }),
+        }
+    }
+}
+
+// The NUL-terminated string at an address of a section:
+fn string((start, data): &(u64, Vec<u8>), address: u64) -> Option<String> {
+    let offset = address.checked_sub(*start)?;
+    let data = data.get(usize::try_from(offset).ok()?..)?;
+    let end = data.iter().position(|b| *b == 0)?;
+    Some(String::from_utf8_lossy(&data[..end]).into_owned())
+}
+
+// The arguments of a log, in the ELF file's byte order:
+struct Args<'a> {
+    data: &'a [u8],
+    dictionary: &'a Dictionary,
+}
+
+impl<'a> Arguments for Args<'a> {
+    fn int(&mut self, size: usize) -> Option<u64> {
+        if self.data.len() < size {
+            return None;
+        }
+        let (v, rest) = self.data.split_at(size);
+        self.data = rest;
+        let mut bytes = [0; 8];
+        Some(match self.dictionary.is_le {
+            true => {
+                bytes[..size].copy_from_slice(v);
+                u64::from_le_bytes(bytes)
+            }
+            false => {
+                bytes[8 - size..].copy_from_slice(v);
+                u64::from_be_bytes(bytes)
+            }
+        })
+    }
+
+    // Strings are addresses in the file; others are shown as their address:
+    fn string(&mut self) -> Option<String> {
+        let address = self.int(self.dictionary.pointer_size)?;
+        let found = self
+            .dictionary
+            .strings
+            .iter()
+            .find_map(|s| string(s, address));
+        Some(found.unwrap_or_else(|| format!("<{:#x}>", address)))
+    }
+
+    fn pointer(&mut self, size: usize) -> Option<String> {
+        let address = self.int(size)?;
+        Some(match self.dictionary.symbols.resolve(0, address) {
+            Some(s) if s.offset != 0 => format!("{:#x} <{}+{:#x}>", address, s.name, s.offset),
+            Some(s) => format!("{:#x} <{}>", address, s.name),
+            None => format!("{:#x}", address),
+        })
+    }
+}
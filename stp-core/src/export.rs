@@ -0,0 +1,440 @@
+//! Exports messages to trace viewers: Chrome's trace event JSON and Perfetto's protobuf format.
+//!
+//! Each master becomes a process and each of its channels a thread.  Data writes are instant
+//! events carrying their payload.  A marked write (D8M, D32MTS, ...) starts a slice on its channel,
+//! which lasts until the next marked write or FLAG on that channel; a FLAG outside a slice is an
+//! instant event.
+//!
+//! Masters and channels are given the names, and payloads the formats, of an optional `Names`.
+//!
+//! Both formats are written as the messages arrive, so traces of any size can be exported.
+
+use crate::message::Message;
+use crate::names::Names;
+use crate::stp::OpCode;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// A named value attached to an event.
+pub enum Arg {
+    Uint(u64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    SliceBegin,
+    SliceEnd,
+    Instant,
+}
+
+/// Writes the tracks and events of a trace in a particular format.
+pub trait TraceSink {
+    /// Describe a process, before any of its threads.
+    fn process(&mut self, pid: u32, name: &str) -> io::Result<()>;
+
+    /// Describe a thread, before any of its events.
+    fn thread(&mut self, pid: u32, tid: u32, name: &str) -> io::Result<()>;
+
+    /// Write an event.  Times are in nanoseconds and never decrease for a given thread.
+    fn event(
+        &mut self,
+        pid: u32,
+        tid: u32,
+        kind: EventKind,
+        time: u64,
+        name: &str,
+        args: &[(&str, Arg)],
+    ) -> io::Result<()>;
+
+    /// Complete the trace.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+struct Thread {
+    pid: u32,
+    tid: u32,
+    slice: bool, // Is a slice open?
+}
+
+/// Turns messages into trace events for a TraceSink.
+pub struct TraceExporter<S: TraceSink> {
+    sink: S,
+    threads: BTreeMap<(Option<u8>, u16, u16), Thread>, // Keyed by (stream, master, channel).
+    processes: BTreeMap<(Option<u8>, u16), u32>,
+    names: Names,
+    time: u64, // Latest event time, in nanoseconds.
+}
+
+impl<S: TraceSink> TraceExporter<S> {
+    pub fn new(sink: S) -> Self {
+        TraceExporter {
+            sink,
+            threads: BTreeMap::new(),
+            processes: BTreeMap::new(),
+            names: Names::new(),
+            time: 0,
+        }
+    }
+
+    /// Name masters and channels, and format their payloads.
+    pub fn names(mut self, names: Names) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Export a message that happened at `time` microseconds.
+    ///
+    /// Messages without a time of their own (e.g. before the first timestamp) should be given the
+    /// time of the previous message.
+    pub fn message(&mut self, stream: Option<u8>, m: &Message, time: f64) -> io::Result<()> {
+        let time = (time.max(0.0) * 1000.0).round() as u64;
+        self.time = self.time.max(time);
+        let thread = self.thread(stream, m.master, m.channel)?;
+        let (pid, tid, open) = (thread.pid, thread.tid, thread.slice);
+
+        let payload = match (m.data, m.opcode.data_bits()) {
+            (Some(d), Some(bits)) => Some(self.names.format_data(m.master, m.channel, d, bits)),
+            _ => None,
+        };
+        let args: Vec<(&str, Arg)> = match (&payload, m.data) {
+            (Some(p), Some(d)) => vec![("payload", Arg::Str(p.clone())), ("value", Arg::Uint(d))],
+            _ => Vec::new(),
+        };
+        let name = format!("{:?}", m.opcode);
+
+        let marked = m.opcode.is_marked();
+        let flag = matches!(m.opcode, OpCode::FLAG | OpCode::FLAG_TS);
+        if (marked || flag) && open {
+            self.set_slice(stream, m, false);
+            self.sink
+                .event(pid, tid, EventKind::SliceEnd, time, &name, &[])?;
+            if flag {
+                return Ok(());
+            }
+        }
+
+        match payload {
+            Some(p) if marked => {
+                self.set_slice(stream, m, true);
+                let name = format!("{} {}", name, p);
+                self.sink
+                    .event(pid, tid, EventKind::SliceBegin, time, &name, &args)
+            }
+            _ => self
+                .sink
+                .event(pid, tid, EventKind::Instant, time, &name, &args),
+        }
+    }
+
+    /// End any open slices and complete the trace.
+    pub fn finish(mut self) -> io::Result<S> {
+        for t in self.threads.values().filter(|t| t.slice) {
+            self.sink
+                .event(t.pid, t.tid, EventKind::SliceEnd, self.time, "", &[])?;
+        }
+        self.sink.finish()?;
+        Ok(self.sink)
+    }
+
+    fn set_slice(&mut self, stream: Option<u8>, m: &Message, open: bool) {
+        if let Some(t) = self.threads.get_mut(&(stream, m.master, m.channel)) {
+            t.slice = open;
+        }
+    }
+
+    // Find, or describe, the thread for a channel:
+    fn thread(&mut self, stream: Option<u8>, master: u16, channel: u16) -> io::Result<&Thread> {
+        let key = (stream, master, channel);
+        if !self.threads.contains_key(&key) {
+            let pid = match self.processes.get(&(stream, master)) {
+                Some(pid) => *pid,
+                None => {
+                    // Process IDs are unique per stream and master; 0 is avoided.
+                    let pid = (stream.map_or(0, |id| id as u32 + 1) << 16 | master as u32) + 1;
+                    let name = match (self.names.master(master), stream) {
+                        (Some(n), Some(id)) => {
+                            format!("{} (master {}, stream {:#04x})", n.name, master, id)
+                        }
+                        (Some(n), None) => format!("{} (master {})", n.name, master),
+                        (None, Some(id)) => format!("Master {} (stream {:#04x})", master, id),
+                        (None, None) => format!("Master {}", master),
+                    };
+                    self.sink.process(pid, &name)?;
+                    self.processes.insert((stream, master), pid);
+                    pid
+                }
+            };
+            // Thread IDs must not collide with process IDs:
+            let tid = 0x1000_0000 + self.threads.len() as u32;
+            let name = match self.names.channel(master, channel) {
+                Some(n) => format!("{} (channel {})", n.name, channel),
+                None => format!("Channel {}", channel),
+            };
+            self.sink.thread(pid, tid, &name)?;
+            self.threads.insert(
+                key,
+                Thread {
+                    pid,
+                    tid,
+                    slice: false,
+                },
+            );
+        }
+        Ok(&self.threads[&key])
+    }
+}
+
+/// Writes Chrome's JSON trace event format (a JSON array of events).
+pub struct ChromeJson<W: Write> {
+    out: W,
+    first: bool,
+}
+
+impl<W: Write> ChromeJson<W> {
+    pub fn new(out: W) -> Self {
+        ChromeJson { out, first: true }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write(&mut self, event: &str) -> io::Result<()> {
+        let sep = if self.first { "[\n" } else { ",\n" };
+        self.first = false;
+        write!(self.out, "{}{}", sep, event)
+    }
+}
+
+fn json_string(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
+impl<W: Write> TraceSink for ChromeJson<W> {
+    fn process(&mut self, pid: u32, name: &str) -> io::Result<()> {
+        self.write(&format!(
+            "{{\"ph\":\"M\",\"name\":\"process_name\",\"pid\":{},\"args\":{{\"name\":{}}}}}",
+            pid,
+            json_string(name)
+        ))
+    }
+
+    fn thread(&mut self, pid: u32, tid: u32, name: &str) -> io::Result<()> {
+        self.write(&format!(
+            "{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":{},\"tid\":{},\"args\":{{\"name\":{}}}}}",
+            pid,
+            tid,
+            json_string(name)
+        ))
+    }
+
+    fn event(
+        &mut self,
+        pid: u32,
+        tid: u32,
+        kind: EventKind,
+        time: u64,
+        name: &str,
+        args: &[(&str, Arg)],
+    ) -> io::Result<()> {
+        let ph = match kind {
+            EventKind::SliceBegin => "\"B\"",
+            EventKind::SliceEnd => "\"E\"",
+            EventKind::Instant => "\"i\",\"s\":\"t\"",
+        };
+        let args: Vec<String> = args
+            .iter()
+            .map(|(k, v)| {
+                let v = match v {
+                    Arg::Uint(u) => u.to_string(),
+                    Arg::Str(s) => json_string(s),
+                };
+                format!("{}:{}", json_string(k), v)
+            })
+            .collect();
+        self.write(&format!(
+            "{{\"ph\":{},\"name\":{},\"pid\":{},\"tid\":{},\"ts\":{}.{:03},\"args\":{{{}}}}}",
+            ph,
+            json_string(name),
+            pid,
+            tid,
+            time / 1000,
+            time % 1000,
+            args.join(",")
+        ))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.first {
+            self.write("")?;
+        }
+        writeln!(self.out, "\n]")?;
+        self.out.flush()
+    }
+}
+
+// Protobuf field numbers of the Perfetto trace format (perfetto/trace/trace_packet.proto, etc.):
+const TRACE_PACKET: u32 = 1;
+const PACKET_TIMESTAMP: u32 = 8;
+const PACKET_SEQUENCE_ID: u32 = 10;
+const PACKET_TRACK_EVENT: u32 = 11;
+const PACKET_SEQUENCE_FLAGS: u32 = 13;
+const PACKET_TRACK_DESCRIPTOR: u32 = 60;
+const TRACK_UUID: u32 = 1;
+const TRACK_NAME: u32 = 2;
+const TRACK_PROCESS: u32 = 3;
+const TRACK_THREAD: u32 = 4;
+const PROCESS_PID: u32 = 1;
+const PROCESS_NAME: u32 = 6;
+const THREAD_PID: u32 = 1;
+const THREAD_TID: u32 = 2;
+const THREAD_NAME: u32 = 5;
+const EVENT_ANNOTATIONS: u32 = 4;
+const EVENT_TYPE: u32 = 9;
+const EVENT_TRACK_UUID: u32 = 11;
+const EVENT_NAME: u32 = 23;
+const ANNOTATION_UINT: u32 = 3;
+const ANNOTATION_STRING: u32 = 6;
+const ANNOTATION_NAME: u32 = 10;
+
+const SEQUENCE_ID: u64 = 1;
+const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+const SEQ_NEEDS_INCREMENTAL_STATE: u64 = 2;
+
+// A protobuf message under construction.
+#[derive(Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn uint(&mut self, field: u32, v: u64) -> &mut Self {
+        self.varint((field as u64) << 3);
+        self.varint(v);
+        self
+    }
+
+    fn bytes(&mut self, field: u32, b: &[u8]) -> &mut Self {
+        self.varint((field as u64) << 3 | 2);
+        self.varint(b.len() as u64);
+        self.0.extend_from_slice(b);
+        self
+    }
+
+    fn string(&mut self, field: u32, s: &str) -> &mut Self {
+        self.bytes(field, s.as_bytes())
+    }
+
+    fn message(&mut self, field: u32, m: &Proto) -> &mut Self {
+        self.bytes(field, &m.0)
+    }
+}
+
+/// Writes Perfetto's protobuf trace format, one TracePacket at a time.
+pub struct Perfetto<W: Write> {
+    out: W,
+    first: bool,
+}
+
+impl<W: Write> Perfetto<W> {
+    pub fn new(out: W) -> Self {
+        Perfetto { out, first: true }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn packet(&mut self, mut packet: Proto) -> io::Result<()> {
+        packet.uint(PACKET_SEQUENCE_ID, SEQUENCE_ID);
+        if self.first {
+            packet.uint(PACKET_SEQUENCE_FLAGS, SEQ_INCREMENTAL_STATE_CLEARED);
+            self.first = false;
+        }
+        let mut trace = Proto::default();
+        trace.message(TRACE_PACKET, &packet);
+        self.out.write_all(&trace.0)
+    }
+
+    fn track(&mut self, uuid: u64, name: &str, field: u32, descriptor: &Proto) -> io::Result<()> {
+        let mut track = Proto::default();
+        track
+            .uint(TRACK_UUID, uuid)
+            .string(TRACK_NAME, name)
+            .message(field, descriptor);
+        let mut packet = Proto::default();
+        packet.message(PACKET_TRACK_DESCRIPTOR, &track);
+        self.packet(packet)
+    }
+}
+
+impl<W: Write> TraceSink for Perfetto<W> {
+    fn process(&mut self, pid: u32, name: &str) -> io::Result<()> {
+        let mut process = Proto::default();
+        process
+            .uint(PROCESS_PID, pid as u64)
+            .string(PROCESS_NAME, name);
+        self.track(pid as u64, name, TRACK_PROCESS, &process)
+    }
+
+    fn thread(&mut self, pid: u32, tid: u32, name: &str) -> io::Result<()> {
+        let mut thread = Proto::default();
+        thread
+            .uint(THREAD_PID, pid as u64)
+            .uint(THREAD_TID, tid as u64)
+            .string(THREAD_NAME, name);
+        self.track(tid as u64, name, TRACK_THREAD, &thread)
+    }
+
+    fn event(
+        &mut self,
+        _pid: u32,
+        tid: u32,
+        kind: EventKind,
+        time: u64,
+        name: &str,
+        args: &[(&str, Arg)],
+    ) -> io::Result<()> {
+        let mut event = Proto::default();
+        event
+            .uint(
+                EVENT_TYPE,
+                match kind {
+                    EventKind::SliceBegin => 1,
+                    EventKind::SliceEnd => 2,
+                    EventKind::Instant => 3,
+                },
+            )
+            .uint(EVENT_TRACK_UUID, tid as u64);
+        if kind != EventKind::SliceEnd {
+            event.string(EVENT_NAME, name);
+        }
+        for (k, v) in args {
+            let mut annotation = Proto::default();
+            annotation.string(ANNOTATION_NAME, k);
+            match v {
+                Arg::Uint(u) => annotation.uint(ANNOTATION_UINT, *u),
+                Arg::Str(s) => annotation.string(ANNOTATION_STRING, s),
+            };
+            event.message(EVENT_ANNOTATIONS, &annotation);
+        }
+
+        let mut packet = Proto::default();
+        packet
+            .uint(PACKET_TIMESTAMP, time)
+            .message(PACKET_TRACK_EVENT, &event)
+            .uint(PACKET_SEQUENCE_FLAGS, SEQ_NEEDS_INCREMENTAL_STATE);
+        self.packet(packet)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
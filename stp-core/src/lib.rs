@@ -1,3 +1,25 @@
+pub mod asm;
+pub mod catalog;
+pub mod ctf;
+#[cfg(feature = "elf")]
+pub mod dictionary;
+pub mod export;
+pub mod message;
+pub mod names;
 pub mod nibble;
+#[cfg(feature = "twp")]
+pub mod pages;
+pub mod pcapng;
+#[cfg(feature = "twp")]
+pub mod pipeline;
+pub mod printf;
+pub mod reassembler;
+#[cfg(feature = "twp")]
+pub mod stats;
 pub mod stp;
 pub mod stp_decoder;
+#[cfg(feature = "elf")]
+pub mod symbols;
+pub mod syst;
+pub mod text;
+pub mod vcd;
@@ -0,0 +1,167 @@
+//! Attributes STP data writes to their master and channel.
+
+use crate::stp::{self, OpCode, Timestamp, TimestampType};
+use crate::stp_decoder::Packet;
+
+/// A data write (or flag) attributed to the master and channel it was written to.
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    pub master: u16,
+    pub channel: u16,
+    pub opcode: OpCode,
+    pub data: Option<u64>,      // None for FLAG packets.
+    pub timestamp: Option<u64>, // Absolute timestamp, if known.
+    pub timestamped: bool,      // Did the packet carry its own timestamp?
+    pub start: usize,           // Packet's starting nibble offset.
+    pub span: usize,            // Packet's size in nibbles.
+}
+
+/// Tracks the master, channel and timestamp state of a single STP stream.
+#[derive(Default, Clone)]
+pub struct MessageTracker {
+    master: u16,
+    channel: u16,
+    ts_type: Option<TimestampType>,
+    timestamp: Option<u64>, // Absolute timestamp, natural binary.
+    gray: u64,              // Last STPv2GRAY timestamp, gray coded.
+    frequency: Option<u64>,
+}
+
+impl MessageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the state with the next packet of the stream.
+    ///
+    /// Returns a Message for data and flag packets.
+    pub fn process(&mut self, packet: &Packet) -> Option<Message> {
+        let timestamp = match &packet.packet {
+            stp::Packet::Async => {
+                self.master = 0;
+                self.channel = 0;
+                return None;
+            }
+            stp::Packet::Version { ts_type, .. } => {
+                if self.ts_type != Some(*ts_type) {
+                    self.timestamp = None;
+                }
+                self.ts_type = Some(*ts_type);
+                return None;
+            }
+            stp::Packet::Master { opcode, master } => {
+                self.master = match opcode {
+                    OpCode::M8 => (self.master & 0xFF00) | (master & 0xFF),
+                    _ => *master,
+                };
+                self.channel = 0;
+                return None;
+            }
+            stp::Packet::Channel { opcode, channel } => {
+                self.channel = match opcode {
+                    OpCode::C8 => (self.channel & 0xFF00) | (channel & 0xFF),
+                    _ => *channel,
+                };
+                return None;
+            }
+            stp::Packet::Frequency {
+                frequency,
+                timestamp,
+                ..
+            } => {
+                self.frequency = Some(*frequency);
+                self.update_timestamp(timestamp);
+                return None;
+            }
+            stp::Packet::Null { timestamp } | stp::Packet::User { timestamp, .. } => {
+                self.update_timestamp(timestamp);
+                return None;
+            }
+            stp::Packet::Error { .. } => return None,
+            stp::Packet::Data { timestamp, .. } | stp::Packet::Flag { timestamp } => timestamp,
+        };
+        self.update_timestamp(timestamp);
+
+        let (opcode, data) = match &packet.packet {
+            stp::Packet::Data { opcode, data, .. } => (*opcode, Some(*data)),
+            _ => match timestamp {
+                Some(_) => (OpCode::FLAG_TS, None),
+                None => (OpCode::FLAG, None),
+            },
+        };
+
+        Some(Message {
+            master: self.master,
+            channel: self.channel,
+            opcode,
+            data,
+            timestamp: self.timestamp,
+            timestamped: timestamp.is_some(),
+            start: packet.start,
+            span: packet.span,
+        })
+    }
+
+    /// Forget the stream state, e.g. after a decode error.
+    pub fn reset(&mut self) {
+        *self = MessageTracker {
+            frequency: self.frequency,
+            ..MessageTracker::default()
+        };
+    }
+
+    /// The timestamp frequency in Hz, if a FREQ packet has been seen.
+    pub fn frequency(&self) -> Option<u64> {
+        self.frequency
+    }
+
+    /// The current master.
+    pub fn master(&self) -> u16 {
+        self.master
+    }
+
+    /// The current absolute timestamp, if known.
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    fn update_timestamp(&mut self, timestamp: &Option<Timestamp>) {
+        let ts = match timestamp {
+            Some(ts) => ts,
+            None => return,
+        };
+        self.timestamp = match *ts {
+            // Legacy timestamps only hold the low byte of a counter.
+            Timestamp::STPv1 { value } => Some(merge(self.timestamp.unwrap_or(0), value as u64, 2)),
+            Timestamp::STPv2NATDELTA { value, .. } => {
+                Some(self.timestamp.unwrap_or(0).wrapping_add(value))
+            }
+            Timestamp::STPv2NAT { length, value } => {
+                Some(merge(self.timestamp.unwrap_or(0), value, length))
+            }
+            Timestamp::STPv2GRAY { length, value } => {
+                self.gray = merge(self.gray, value, length);
+                Some(from_gray(self.gray))
+            }
+        };
+    }
+}
+
+// Replace the low `length` nibbles of `current` with `value`:
+fn merge(current: u64, value: u64, length: u8) -> u64 {
+    if length >= 16 {
+        value
+    } else {
+        let mask = (1u64 << (length as u32 * 4)) - 1;
+        (current & !mask) | (value & mask)
+    }
+}
+
+fn from_gray(mut gray: u64) -> u64 {
+    let mut shift = 1;
+    while shift < 64 {
+        gray ^= gray >> shift;
+        shift <<= 1;
+    }
+    gray
+}
@@ -0,0 +1,333 @@
+//! Names for masters and channels, with hints on how to show their data.
+//!
+//! Names can be read from a TOML or JSON file (with the `config` feature):
+//!
+//! ```toml
+//! [[master]]
+//! id = "32-47"          # A number, or a range of them.
+//! name = "modem"
+//!
+//! [[channel]]
+//! master = 32           # Optional: any master if omitted.
+//! id = 1
+//! name = "irq_enter"
+//! format = "signed"     # hex (the default), signed, ascii or float32.
+//! unit = "us"
+//! ```
+//!
+//! or from a Linux `stm` policy directory, where each node's `masters` and `channels` files give
+//! the ranges it writes to.  The first entry that matches a master or channel is used.
+
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How to show the data written to a channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Hex,
+    Signed,
+    Ascii,   // Bytes in the order a little-endian writer stored them.
+    Float32, // 64-bit writes are shown as doubles.
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(Format::Hex),
+            "signed" => Ok(Format::Signed),
+            "ascii" => Ok(Format::Ascii),
+            "float32" => Ok(Format::Float32),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// The name of a master or channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub name: String,
+    pub format: Option<Format>,
+    pub unit: Option<String>,
+}
+
+impl Name {
+    pub fn new(name: &str) -> Self {
+        Name {
+            name: name.to_string(),
+            format: None,
+            unit: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Names {
+    masters: Vec<(RangeInclusive<u16>, Name)>,
+    channels: Vec<(Option<RangeInclusive<u16>>, RangeInclusive<u16>, Name)>,
+}
+
+impl Names {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name a range of masters.
+    pub fn add_master(&mut self, masters: RangeInclusive<u16>, name: Name) {
+        self.masters.push((masters, name));
+    }
+
+    /// Name a range of channels, of a range of masters or of any master.
+    pub fn add_channel(
+        &mut self,
+        masters: Option<RangeInclusive<u16>>,
+        channels: RangeInclusive<u16>,
+        name: Name,
+    ) {
+        self.channels.push((masters, channels, name));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masters.is_empty() && self.channels.is_empty()
+    }
+
+    pub fn master(&self, master: u16) -> Option<&Name> {
+        self.masters
+            .iter()
+            .find(|(r, _)| r.contains(&master))
+            .map(|(_, name)| name)
+    }
+
+    pub fn channel(&self, master: u16, channel: u16) -> Option<&Name> {
+        self.channels
+            .iter()
+            .find(|(m, c, _)| {
+                m.as_ref().is_none_or(|m| m.contains(&master)) && c.contains(&channel)
+            })
+            .map(|(_, _, name)| name)
+    }
+
+    /// The master's name, or e.g. "M32".
+    pub fn master_label(&self, master: u16) -> String {
+        match self.master(master) {
+            Some(n) => n.name.clone(),
+            None => format!("M{}", master),
+        }
+    }
+
+    /// The channel's name, or e.g. "C1".
+    pub fn channel_label(&self, master: u16, channel: u16) -> String {
+        match self.channel(master, channel) {
+            Some(n) => n.name.clone(),
+            None => format!("C{}", channel),
+        }
+    }
+
+    /// Show `bits` of data written to a channel, in the channel's (or else its master's) format
+    /// and with its unit.
+    pub fn format_data(&self, master: u16, channel: u16, data: u64, bits: u8) -> String {
+        let (c, m) = (self.channel(master, channel), self.master(master));
+        let format = c
+            .and_then(|n| n.format)
+            .or_else(|| m.and_then(|n| n.format));
+        let value = format_value(format.unwrap_or(Format::Hex), data, bits);
+        let unit = c.and_then(|n| n.unit.as_ref());
+        match unit.or_else(|| m.and_then(|n| n.unit.as_ref())) {
+            Some(unit) => format!("{} {}", value, unit),
+            None => value,
+        }
+    }
+
+    /// Read the nodes of a Linux `stm` policy (e.g. `/config/stp-policy/dummy_stm.0.my-policy`),
+    /// naming channels after the nodes that write to them.
+    pub fn from_policy(dir: &Path) -> io::Result<Names> {
+        let mut names = Names::new();
+        add_policy_nodes(&mut names, dir, "")?;
+        Ok(names)
+    }
+
+    /// Read names from a TOML file, a JSON file (ending in `.json`) or a policy directory.
+    #[cfg(feature = "config")]
+    pub fn load(path: &Path) -> Result<Names, String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        if path.is_dir() {
+            return Names::from_policy(path).map_err(|e| error(&e));
+        }
+        let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+        match path.extension() {
+            Some(ext) if ext == "json" => Names::from_json(&text),
+            _ => Names::from_toml(&text),
+        }
+        .map_err(|e| error(&e))
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_toml(text: &str) -> Result<Names, String> {
+        let file: config::File = toml::from_str(text).map_err(|e| e.to_string())?;
+        file.names()
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_json(text: &str) -> Result<Names, String> {
+        let file: config::File = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        file.names()
+    }
+}
+
+fn format_value(format: Format, data: u64, bits: u8) -> String {
+    let hex = || format!("{:#0w$x}", data, w = bits as usize / 4 + 2);
+    match format {
+        Format::Hex => hex(),
+        Format::Signed => {
+            let shift = 64 - bits as u32;
+            (((data << shift) as i64) >> shift).to_string()
+        }
+        Format::Ascii if bits >= 8 => {
+            let bytes = &data.to_le_bytes()[..bits as usize / 8];
+            let text: String = bytes
+                .iter()
+                .map(|b| match b {
+                    0x20..=0x7e => *b as char,
+                    _ => '.',
+                })
+                .collect();
+            format!("\"{}\"", text)
+        }
+        Format::Float32 if bits == 32 => f32::from_bits(data as u32).to_string(),
+        Format::Float32 if bits == 64 => f64::from_bits(data).to_string(),
+        _ => hex(),
+    }
+}
+
+// Parse a range like "32-47" or "0x20":
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let number = |s: &str| {
+        let s = s.trim();
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .map_err(|e| format!("{}: {}", e, s))
+    };
+    match s.split_once('-') {
+        Some((start, end)) => Ok(number(start)?..=number(end)?),
+        None => {
+            let v = number(s)?;
+            Ok(v..=v)
+        }
+    }
+}
+
+// Add the policy nodes in a directory, and those nested within them:
+fn add_policy_nodes(names: &mut Names, dir: &Path, prefix: &str) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?
+        .collect::<io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|e| e.path().is_dir())
+        .collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let node = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        // Nested nodes are more specific, so they come first:
+        add_policy_nodes(names, &path, &format!("{}/", node))?;
+
+        let range = |file: &str| -> io::Result<Option<RangeInclusive<u16>>> {
+            let path = path.join(file);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let text = fs::read_to_string(&path)?;
+            let mut values = text.split_whitespace().map(parse_range);
+            match (values.next(), values.next()) {
+                (Some(Ok(first)), Some(Ok(last))) => Ok(Some(*first.start()..=*last.start())),
+                (Some(Ok(only)), None) => Ok(Some(only)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid range: {}", path.display()),
+                )),
+            }
+        };
+        if let (Some(masters), Some(channels)) = (range("masters")?, range("channels")?) {
+            names.add_channel(Some(masters), channels, Name::new(&node));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "config")]
+mod config {
+    use super::{parse_range, Format, Name, Names};
+    use serde::Deserialize;
+    use std::ops::RangeInclusive;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct File {
+        #[serde(default)]
+        master: Vec<Entry>,
+        #[serde(default)]
+        channel: Vec<Entry>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Entry {
+        master: Option<Ids>,
+        id: Ids,
+        name: String,
+        format: Option<String>,
+        unit: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Ids {
+        Number(u16),
+        Range(String),
+    }
+
+    impl Ids {
+        fn range(&self) -> Result<RangeInclusive<u16>, String> {
+            match self {
+                Ids::Number(n) => Ok(*n..=*n),
+                Ids::Range(s) => parse_range(s),
+            }
+        }
+    }
+
+    impl Entry {
+        fn name(&self) -> Result<Name, String> {
+            Ok(Name {
+                name: self.name.clone(),
+                format: self
+                    .format
+                    .as_deref()
+                    .map(str::parse::<Format>)
+                    .transpose()?,
+                unit: self.unit.clone(),
+            })
+        }
+    }
+
+    impl File {
+        pub fn names(&self) -> Result<Names, String> {
+            let mut names = Names::new();
+            for e in &self.master {
+                if e.master.is_some() {
+                    return Err(format!("master {}: unexpected master", e.name));
+                }
+                names.add_master(e.id.range()?, e.name()?);
+            }
+            for e in &self.channel {
+                let masters = e.master.as_ref().map(Ids::range).transpose()?;
+                names.add_channel(masters, e.id.range()?, e.name()?);
+            }
+            Ok(names)
+        }
+    }
+}
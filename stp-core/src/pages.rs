@@ -0,0 +1,316 @@
+//! Lazily decoded pages of a capture file, so captures larger than memory can be browsed
+//! (requires the `twp` feature).
+//!
+//! The file is split into fixed size pages.  Decoding a page for the first time records the
+//! decoder state at its end, so any page reached once can later be decoded again on its own.
+//! Only a few decoded pages are kept in memory.
+
+use crate::message::MessageTracker;
+use crate::stp_decoder::{self, StpDecoder};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{self, prelude::*, SeekFrom};
+use std::rc::Rc;
+use twp::parsers::{self, FrameDecoder};
+
+pub const PAGE_SIZE: usize = 256 * 1024;
+const CACHED_PAGES: usize = 8;
+const RECENT_BYTES: usize = 64; // Enough to locate the start of any packet.
+
+#[derive(Debug, PartialEq)]
+pub enum Entry {
+    Packet(stp_decoder::Packet),
+    StpError(stp_decoder::Error),
+    TwpError(parsers::Error),
+    TwpEvent(parsers::Event),
+}
+
+/// A packet, error or event, in capture order.
+#[derive(Debug, PartialEq)]
+pub struct Row {
+    pub stream: Option<Option<u8>>, // None for TWP errors and events.
+    pub entry: Entry,
+    pub file_offset: usize,
+    pub channel: Option<(u16, u16)>, // Master and channel of data writes and flags.
+}
+
+/// A byte of a TWP stream.
+#[derive(Debug, PartialEq)]
+pub struct StreamByte {
+    pub id: Option<u8>,
+    pub index: usize, // Offset within the stream, in bytes.
+    pub data: u8,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Page {
+    pub rows: Vec<Row>,
+    pub bytes: Vec<StreamByte>,
+}
+
+#[derive(Clone, Default)]
+struct StreamState {
+    decoder: StpDecoder,
+    tracker: MessageTracker,
+    len: usize,              // Bytes seen so far.
+    recent: VecDeque<usize>, // File offsets of the last few bytes.
+}
+
+// The decoder state at the start of a page:
+#[derive(Clone)]
+struct Checkpoint {
+    frames: FrameDecoder,
+    streams: BTreeMap<Option<u8>, StreamState>,
+}
+
+/// A row of the capture: a page number and an index within the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pos {
+    pub page: usize,
+    pub row: usize,
+}
+
+impl Pos {
+    pub const START: Pos = Pos { page: 0, row: 0 };
+    pub const END: Pos = Pos {
+        page: usize::MAX,
+        row: usize::MAX,
+    };
+
+    /// The position of the next row, which may be past the end of its page.
+    pub fn after(self) -> Pos {
+        Pos {
+            page: self.page,
+            row: self.row + 1,
+        }
+    }
+}
+
+pub struct Pages<R = File> {
+    file: R,
+    len: usize,
+    page_size: usize,
+    checkpoints: Vec<Checkpoint>, // One for each page reached so far.
+    cache: VecDeque<(usize, Rc<Page>)>,
+}
+
+impl<R: Read + Seek> Pages<R> {
+    pub fn new(mut file: R, frames: FrameDecoder) -> io::Result<Pages<R>> {
+        let len = file.seek(SeekFrom::End(0))? as usize;
+        Ok(Pages {
+            file,
+            len,
+            page_size: PAGE_SIZE,
+            checkpoints: vec![Checkpoint {
+                frames,
+                streams: BTreeMap::new(),
+            }],
+            cache: VecDeque::new(),
+        })
+    }
+
+    /// Use pages of another size than `PAGE_SIZE`.
+    pub fn page_size(mut self, size: usize) -> Self {
+        self.page_size = size.max(1);
+        self
+    }
+
+    /// The size of the file, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of pages (an empty file has one, empty, page).
+    pub fn count(&self) -> usize {
+        self.len.div_ceil(self.page_size).max(1)
+    }
+
+    /// The page holding a file offset (the last page for offsets past the end).
+    pub fn page_of(&self, offset: usize) -> usize {
+        (offset / self.page_size).min(self.count() - 1)
+    }
+
+    /// The number of pages whose starting decoder state is known, so that they can be decoded
+    /// on their own.
+    pub fn reached(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Decode a page, first decoding any earlier pages that have not been reached yet.
+    pub fn page(&mut self, n: usize) -> io::Result<Rc<Page>> {
+        if let Some(i) = self.cache.iter().position(|(p, _)| *p == n) {
+            let entry = self.cache.remove(i).unwrap();
+            self.cache.push_front(entry);
+            return Ok(self.cache[0].1.clone());
+        }
+
+        while self.checkpoints.len() <= n {
+            let last = self.checkpoints.len() - 1;
+            self.decode(last)?;
+        }
+        let page = Rc::new(self.decode(n)?);
+        self.cache.push_front((n, page.clone()));
+        self.cache.truncate(CACHED_PAGES);
+        Ok(page)
+    }
+
+    /// The first row at or after `start` matching `pred`.
+    pub fn find_forward<P>(&mut self, start: Pos, mut pred: P) -> io::Result<Option<Pos>>
+    where
+        P: FnMut(&Row) -> bool,
+    {
+        let Pos { mut page, mut row } = start;
+        while page < self.count() {
+            let p = self.page(page)?;
+            for (i, r) in p.rows.iter().enumerate().skip(row) {
+                if pred(r) {
+                    return Ok(Some(Pos { page, row: i }));
+                }
+            }
+            page += 1;
+            row = 0;
+        }
+        Ok(None)
+    }
+
+    /// The last row before `end` matching `pred`.
+    pub fn find_backward<P>(&mut self, end: Pos, mut pred: P) -> io::Result<Option<Pos>>
+    where
+        P: FnMut(&Row) -> bool,
+    {
+        let mut page = end.page.min(self.count() - 1);
+        let mut end = if page == end.page {
+            end.row
+        } else {
+            usize::MAX
+        };
+        loop {
+            let p = self.page(page)?;
+            for i in (0..end.min(p.rows.len())).rev() {
+                if pred(&p.rows[i]) {
+                    return Ok(Some(Pos { page, row: i }));
+                }
+            }
+            if page == 0 {
+                return Ok(None);
+            }
+            page -= 1;
+            end = usize::MAX;
+        }
+    }
+
+    fn decode(&mut self, n: usize) -> io::Result<Page> {
+        let start = n * self.page_size;
+        let mut buf = vec![0; self.page_size.min(self.len - start)];
+        self.file.seek(SeekFrom::Start(start as u64))?;
+        self.file.read_exact(&mut buf)?;
+
+        let mut state = self.checkpoints[n].clone();
+        let mut page = Page::default();
+        let streams = &mut state.streams;
+        let _ = state.frames.decode(&buf, |r| {
+            process(streams, &mut page, r);
+            Ok(())
+        });
+
+        if n + 1 == self.count() {
+            let _ = state.frames.finish(|r| {
+                process(streams, &mut page, r);
+                Ok(())
+            });
+            for (id, s) in streams.iter_mut() {
+                let mut results = Vec::new();
+                s.decoder.finish(|r| results.push(r));
+                let offset = s.recent.back().copied().unwrap_or(start);
+                for r in results {
+                    page.rows.push(s.row(*id, r, offset));
+                }
+            }
+        } else if self.checkpoints.len() == n + 1 {
+            self.checkpoints.push(state);
+        }
+        Ok(page)
+    }
+}
+
+// Pass a TWP result through the STP decoder for its stream:
+fn process(
+    streams: &mut BTreeMap<Option<u8>, StreamState>,
+    page: &mut Page,
+    r: parsers::Result<parsers::Data>,
+) {
+    let d = match r {
+        Ok(d) => d,
+        Err(e) => {
+            page.rows.push(Row {
+                stream: None,
+                file_offset: e.offset,
+                entry: Entry::TwpError(e),
+                channel: None,
+            });
+            return;
+        }
+    };
+    if let Some(event) = d.event {
+        page.rows.push(Row {
+            stream: None,
+            file_offset: d.offset,
+            entry: Entry::TwpEvent(event),
+            channel: None,
+        });
+        return;
+    }
+
+    let s = streams.entry(d.id).or_default();
+    page.bytes.push(StreamByte {
+        id: d.id,
+        index: s.len,
+        data: d.data,
+    });
+    s.len += 1;
+    s.recent.push_back(d.offset);
+    if s.recent.len() > RECENT_BYTES {
+        s.recent.pop_front();
+    }
+
+    let mut results = Vec::new();
+    s.decoder.decode_bytes(&[d.data], |r| results.push(r));
+    for r in results {
+        let start = match &r {
+            Ok(p) => p.start,
+            Err(e) => e.start,
+        };
+        // Find the file offset of the packet's first byte among the recent bytes:
+        let back = s.len - start / 2;
+        let offset = match s.recent.len().checked_sub(back) {
+            Some(i) => s.recent[i],
+            None => s.recent[0],
+        };
+        page.rows.push(s.row(d.id, r, offset));
+    }
+}
+
+impl StreamState {
+    fn row(&mut self, id: Option<u8>, r: stp_decoder::Result, file_offset: usize) -> Row {
+        let (entry, channel) = match r {
+            Ok(p) => {
+                let channel = self.tracker.process(&p).map(|m| (m.master, m.channel));
+                (Entry::Packet(p), channel)
+            }
+            Err(e) => {
+                self.tracker.reset();
+                (Entry::StpError(e), None)
+            }
+        };
+        Row {
+            stream: Some(id),
+            entry,
+            file_offset,
+            channel,
+        }
+    }
+}
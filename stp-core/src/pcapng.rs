@@ -0,0 +1,284 @@
+//! Writes decoded packets, errors and messages as a pcapng file, for Wireshark.
+//!
+//! Each TWP stream is an interface with the LINKTYPE_USER0 link type, and each record is an
+//! Enhanced Packet Block holding the following (big endian) fields:
+//!
+//! | Offset | Size | Field                                                               |
+//! |--------|------|---------------------------------------------------------------------|
+//! | 0      | 1    | Format version (1)                                                  |
+//! | 1      | 1    | Record type: 0 packet, 1 message, 2 decoding error                  |
+//! | 2      | 1    | Flags (see `FLAG_*`)                                                |
+//! | 3      | 1    | TWP stream ID                                                       |
+//! | 4      | 2    | Opcode, as its hex digits (0xFFFF for ASYNC and errors)             |
+//! | 6      | 2    | Master                                                              |
+//! | 8      | 2    | Channel                                                             |
+//! | 10     | 2    | Size in nibbles                                                     |
+//! | 12     | 8    | Offset of the first nibble within the stream                        |
+//! | 20     | 8    | Payload: data, USER payload, frequency, master or channel number    |
+//! | 28     | 8    | Absolute STP timestamp, in ticks                                    |
+//! | 36     | 2    | Length of the raw bytes                                             |
+//! | 38     |      | The stream bytes holding the packet, then the text (see below)      |
+//!
+//! Stream bytes hold two nibbles each, low nibble first, so when the first nibble offset is odd
+//! the packet starts at the high nibble of its first byte.
+//!
+//! The text of an error record describes the error.  Records with a master and channel may be
+//! named (see `Record::names`): their text is then the master's name, the channel's name and the
+//! payload in its configured format, separated by tabs.
+
+use crate::message::Message;
+use crate::names::{Name, Names};
+use crate::stp::{self, OpCode};
+use crate::stp_decoder::{Error, Packet};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// The link type of every interface (LINKTYPE_USER0).
+pub const LINKTYPE: u16 = 147;
+
+pub const FLAG_TIMESTAMP: u8 = 0x01; // The STP timestamp is known.
+pub const FLAG_TIMESTAMPED: u8 = 0x02; // The packet carried a timestamp of its own.
+pub const FLAG_CHANNEL: u8 = 0x04; // The master and channel are known.
+pub const FLAG_ID: u8 = 0x08; // The stream has a TWP ID.
+pub const FLAG_PAYLOAD: u8 = 0x10; // The payload field is valid.
+
+const VERSION: u8 = 1;
+const BLOCK_SHB: u32 = 0x0A0D_0D0A;
+const BLOCK_IDB: u32 = 1;
+const BLOCK_EPB: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const OPT_END: u16 = 0;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordType {
+    Packet = 0,
+    Message = 1,
+    Error = 2,
+}
+
+/// A record of a pcapng file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record<'a> {
+    pub kind: RecordType,
+    pub opcode: Option<OpCode>,
+    pub channel: Option<(u16, u16)>, // Master and channel.
+    pub payload: Option<u64>,
+    pub timestamp: Option<u64>, // Absolute STP timestamp.
+    pub timestamped: bool,
+    pub start: usize,
+    pub span: usize,
+    pub bytes: &'a [u8],
+    pub text: String,
+}
+
+impl<'a> Record<'a> {
+    /// A record of a packet, held in `bytes`.
+    pub fn packet(p: &Packet, bytes: &'a [u8]) -> Self {
+        let (payload, timestamped) = match &p.packet {
+            stp::Packet::Data {
+                data, timestamp, ..
+            } => (Some(*data), timestamp.is_some()),
+            stp::Packet::User {
+                payload, timestamp, ..
+            } => (Some(*payload), timestamp.is_some()),
+            stp::Packet::Frequency {
+                frequency,
+                timestamp,
+                ..
+            } => (Some(*frequency), timestamp.is_some()),
+            stp::Packet::Master { master, .. } => (Some(*master as u64), false),
+            stp::Packet::Channel { channel, .. } => (Some(*channel as u64), false),
+            stp::Packet::Error { data, .. } => (Some(*data as u64), false),
+            stp::Packet::Null { timestamp } | stp::Packet::Flag { timestamp } => {
+                (None, timestamp.is_some())
+            }
+            stp::Packet::Async | stp::Packet::Version { .. } => (None, false),
+        };
+        Record {
+            kind: RecordType::Packet,
+            opcode: p.packet.opcode(),
+            channel: None,
+            payload,
+            timestamp: None,
+            timestamped,
+            start: p.start,
+            span: p.span,
+            bytes,
+            text: String::new(),
+        }
+    }
+
+    /// A record of a message, held in `bytes`.
+    pub fn message(m: &Message, bytes: &'a [u8]) -> Self {
+        Record {
+            kind: RecordType::Message,
+            opcode: Some(m.opcode),
+            channel: Some((m.master, m.channel)),
+            payload: m.data,
+            timestamp: m.timestamp,
+            timestamped: m.timestamped,
+            start: m.start,
+            span: m.span,
+            bytes,
+            text: String::new(),
+        }
+    }
+
+    /// A record of a decoding error, whose nibbles are held in `bytes`.
+    pub fn error(e: &Error, bytes: &'a [u8]) -> Self {
+        Record {
+            kind: RecordType::Error,
+            opcode: None,
+            channel: None,
+            payload: None,
+            timestamp: None,
+            timestamped: false,
+            start: e.start,
+            span: e.span,
+            bytes,
+            text: e.reason.to_string(),
+        }
+    }
+
+    /// Name the record's master and channel, and format its payload, if it has a channel.
+    pub fn names(mut self, names: &Names) -> Self {
+        if let Some((master, channel)) = self.channel {
+            let bits = self.opcode.and_then(|o| o.data_bits());
+            let value = match (self.payload, bits) {
+                (Some(data), Some(bits)) => names.format_data(master, channel, data, bits),
+                _ => String::new(),
+            };
+            let name = |n: Option<&Name>| n.map_or(String::new(), |n| n.name.clone());
+            self.text = format!(
+                "{}\t{}\t{}",
+                name(names.master(master)),
+                name(names.channel(master, channel)),
+                value
+            );
+        }
+        self
+    }
+
+    fn encode(&self, id: Option<u8>) -> Vec<u8> {
+        let mut flags = 0;
+        let mut set = |flag, is_set| {
+            if is_set {
+                flags |= flag;
+            }
+        };
+        set(FLAG_TIMESTAMP, self.timestamp.is_some());
+        set(FLAG_TIMESTAMPED, self.timestamped);
+        set(FLAG_CHANNEL, self.channel.is_some());
+        set(FLAG_ID, id.is_some());
+        set(FLAG_PAYLOAD, self.payload.is_some());
+
+        let (master, channel) = self.channel.unwrap_or((0, 0));
+        let mut out = vec![VERSION, self.kind as u8, flags, id.unwrap_or(0)];
+        out.extend_from_slice(&self.opcode.map_or(0xFFFF, |o| o as u16).to_be_bytes());
+        out.extend_from_slice(&master.to_be_bytes());
+        out.extend_from_slice(&channel.to_be_bytes());
+        out.extend_from_slice(&(self.span.min(0xFFFF) as u16).to_be_bytes());
+        out.extend_from_slice(&(self.start as u64).to_be_bytes());
+        out.extend_from_slice(&self.payload.unwrap_or(0).to_be_bytes());
+        out.extend_from_slice(&self.timestamp.unwrap_or(0).to_be_bytes());
+        out.extend_from_slice(&(self.bytes.len() as u16).to_be_bytes());
+        out.extend_from_slice(self.bytes);
+        out.extend_from_slice(self.text.as_bytes());
+        out
+    }
+}
+
+/// Writes a pcapng file with an interface for each TWP stream.
+pub struct PcapngWriter<W: Write> {
+    out: W,
+    interfaces: BTreeMap<Option<u8>, u32>,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Start a pcapng file by writing its section header.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes()); // Major version
+        body.extend_from_slice(&0u16.to_le_bytes()); // Minor version
+        body.extend_from_slice(&(-1i64).to_le_bytes()); // Section length: unknown
+        option(&mut body, OPT_SHB_USERAPPL, b"stp");
+        option(&mut body, OPT_END, b"");
+        block(&mut out, BLOCK_SHB, &body)?;
+        Ok(PcapngWriter {
+            out,
+            interfaces: BTreeMap::new(),
+        })
+    }
+
+    /// Write a record of TWP stream `id`, at `time` nanoseconds.
+    pub fn write(&mut self, id: Option<u8>, time: u64, record: &Record) -> io::Result<()> {
+        let interface = self.interface(id)?;
+        let data = record.encode(id);
+        let mut body = Vec::new();
+        body.extend_from_slice(&interface.to_le_bytes());
+        body.extend_from_slice(&((time >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(time as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes()); // Captured length
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes()); // Original length
+        body.extend_from_slice(&data);
+        pad(&mut body);
+        block(&mut self.out, BLOCK_EPB, &body)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // Find, or describe, the interface for a stream:
+    fn interface(&mut self, id: Option<u8>) -> io::Result<u32> {
+        if let Some(interface) = self.interfaces.get(&id) {
+            return Ok(*interface);
+        }
+        let name = match id {
+            Some(id) => format!("stp stream {:#04x}", id),
+            None => "stp".to_string(),
+        };
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes()); // Reserved
+        body.extend_from_slice(&0u32.to_le_bytes()); // Snap length: unlimited
+        option(&mut body, OPT_IF_NAME, name.as_bytes());
+        option(&mut body, OPT_IF_TSRESOL, &[9]); // Nanoseconds
+        option(&mut body, OPT_END, b"");
+        block(&mut self.out, BLOCK_IDB, &body)?;
+
+        let interface = self.interfaces.len() as u32;
+        self.interfaces.insert(id, interface);
+        Ok(interface)
+    }
+}
+
+// Write a block, whose body is already padded to 32 bits:
+fn block<W: Write>(out: &mut W, kind: u32, body: &[u8]) -> io::Result<()> {
+    let len = (body.len() as u32 + 12).to_le_bytes();
+    out.write_all(&kind.to_le_bytes())?;
+    out.write_all(&len)?;
+    out.write_all(body)?;
+    out.write_all(&len)
+}
+
+fn option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    while !body.len().is_multiple_of(4) {
+        body.push(0);
+    }
+}
@@ -0,0 +1,225 @@
+//! Decodes TWP framed data into STP packets, one STP stream per TWP stream ID.
+
+use crate::stp_decoder::{self, StpDecoder};
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use twp::parsers::{self, FrameDecoder, OffsetMap};
+
+/// What the pipeline decodes, in capture order.
+#[derive(Debug, PartialEq)]
+pub enum Output {
+    /// An STP packet (or error) and the TWP stream it was found on.
+    Packet(Option<u8>, stp_decoder::Result),
+    /// An event marked by a special TWP stream ID, and its input offset.
+    Event(parsers::Event, usize),
+}
+
+pub struct Pipeline {
+    frames: FrameDecoder,
+    decoders: BTreeMap<Option<u8>, StpDecoder>,
+    results: Vec<stp_decoder::Result>,
+    offsets: Option<OffsetMap>,
+    recent: Option<(usize, BTreeMap<Option<u8>, Recent>)>,
+}
+
+// The last bytes of a stream:
+#[derive(Default)]
+struct Recent {
+    bytes: VecDeque<u8>,
+    len: usize, // Bytes seen so far.
+}
+
+impl Pipeline {
+    /// Create a new Pipeline.
+    ///
+    /// # Arguments
+    ///
+    ///  * `frames` - The decoder used to demultiplex the TWP frames.
+    pub fn new(frames: FrameDecoder) -> Self {
+        Pipeline {
+            frames,
+            decoders: BTreeMap::new(),
+            results: Vec::new(),
+            offsets: None,
+            recent: None,
+        }
+    }
+
+    /// Record where each stream byte was found in the input, so that packet offsets can be
+    /// mapped back to input offsets.
+    pub fn track_offsets(mut self) -> Self {
+        self.offsets = Some(OffsetMap::new());
+        self
+    }
+
+    /// Like `track_offsets`, but only the offsets of the last `len` bytes of each stream need be
+    /// kept, so that memory stays bounded on endless inputs.
+    pub fn track_recent_offsets(mut self, len: usize) -> Self {
+        self.offsets = Some(OffsetMap::with_limit(len));
+        self
+    }
+
+    /// The input offset of the byte holding a stream's nibble.
+    ///
+    /// Returns None unless offsets are being tracked, or if it is no longer kept.
+    pub fn file_offset(&self, id: Option<u8>, nibble: usize) -> Option<usize> {
+        self.offsets.as_ref()?.file_offset(id, nibble / 2)
+    }
+
+    /// Keep the last `len` bytes of each stream, so that the bytes of recent packets can be
+    /// retrieved.
+    pub fn keep_bytes(mut self, len: usize) -> Self {
+        self.recent = Some((len, BTreeMap::new()));
+        self
+    }
+
+    /// The stream bytes holding `span` nibbles of a stream, starting at nibble `start`.
+    ///
+    /// Returns None unless bytes are being kept, or if some of the bytes are no longer kept.
+    pub fn stream_bytes(&self, id: Option<u8>, start: usize, span: usize) -> Option<Vec<u8>> {
+        let recent = self.recent.as_ref()?.1.get(&id)?;
+        let first = start / 2;
+        let end = (start + span).div_ceil(2);
+        let kept = recent.len - recent.bytes.len();
+        if first < kept || end > recent.len {
+            return None;
+        }
+        Some(
+            recent
+                .bytes
+                .range(first - kept..end - kept)
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// The input byte ranges holding `span` nibbles of a stream, starting at nibble `start`.
+    ///
+    /// Returns an empty vector unless offsets are being tracked.
+    pub fn file_ranges(&self, id: Option<u8>, start: usize, span: usize) -> Vec<Range<usize>> {
+        match &self.offsets {
+            Some(offsets) if span > 0 => {
+                let first = start / 2;
+                offsets.file_ranges(id, first, (start + span - 1) / 2 + 1 - first)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Decode a slice of TWP framed bytes.
+    ///
+    /// STP results and TWP events are passed to the handler in capture order.  TWP errors are
+    /// passed to the handler as errors; if the handler returns an error, decoding stops.
+    pub fn decode<H>(&mut self, data: &[u8], mut handler: H) -> parsers::Result<()>
+    where
+        H: FnMut(parsers::Result<Output>) -> parsers::Result<()>,
+    {
+        let decoders = &mut self.decoders;
+        let results = &mut self.results;
+        let offsets = &mut self.offsets;
+        let recent = &mut self.recent;
+        self.frames.decode(data, |r| {
+            forward(decoders, results, offsets, recent, r, &mut handler)
+        })
+    }
+
+    /// Signal the end of the input.
+    ///
+    /// Finishes the TWP decoder and then every STP decoder, in stream ID order.
+    pub fn finish<H>(&mut self, mut handler: H) -> parsers::Result<()>
+    where
+        H: FnMut(parsers::Result<Output>) -> parsers::Result<()>,
+    {
+        let decoders = &mut self.decoders;
+        let results = &mut self.results;
+        let offsets = &mut self.offsets;
+        let recent = &mut self.recent;
+        self.frames
+            .finish(|r| forward(decoders, results, offsets, recent, r, &mut handler))?;
+
+        for (id, decoder) in decoders.iter_mut() {
+            decoder.finish(|r| results.push(r));
+            for r in results.drain(..) {
+                handler(Ok(Output::Packet(*id, r)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start over on a new capture, e.g. a new connection to a trace server.
+    ///
+    /// A partial frame and any partial packets are reported, then the TWP decoder waits for an
+    /// FSYNC and every STP decoder for an ASYNC.  Offsets carry on from the previous capture.
+    pub fn restart<H>(&mut self, mut handler: H) -> parsers::Result<()>
+    where
+        H: FnMut(parsers::Result<Output>) -> parsers::Result<()>,
+    {
+        let decoders = &mut self.decoders;
+        let results = &mut self.results;
+        let offsets = &mut self.offsets;
+        let recent = &mut self.recent;
+        self.frames
+            .restart(|r| forward(decoders, results, offsets, recent, r, &mut handler))?;
+
+        for (id, decoder) in decoders.iter_mut() {
+            decoder.restart(|r| results.push(r));
+            for r in results.drain(..) {
+                handler(Ok(Output::Packet(*id, r)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The TWP frame decoder.
+    pub fn frames(&self) -> &FrameDecoder {
+        &self.frames
+    }
+
+    /// The TWP stream IDs seen so far.
+    pub fn stream_ids(&self) -> impl Iterator<Item = Option<u8>> + '_ {
+        self.decoders.keys().copied()
+    }
+}
+
+// Pass a TWP byte to the STP decoder for its stream:
+fn forward<H>(
+    decoders: &mut BTreeMap<Option<u8>, StpDecoder>,
+    results: &mut Vec<stp_decoder::Result>,
+    offsets: &mut Option<OffsetMap>,
+    recent: &mut Option<(usize, BTreeMap<Option<u8>, Recent>)>,
+    r: parsers::Result<parsers::Data>,
+    handler: &mut H,
+) -> parsers::Result<()>
+where
+    H: FnMut(parsers::Result<Output>) -> parsers::Result<()>,
+{
+    let d = match r {
+        Ok(d) => d,
+        Err(e) => return handler(Err(e)),
+    };
+    if let Some(event) = d.event {
+        return handler(Ok(Output::Event(event, d.offset)));
+    }
+
+    if let Some(offsets) = offsets {
+        offsets.record(&d);
+    }
+    if let Some((limit, streams)) = recent {
+        let stream = streams.entry(d.id).or_default();
+        stream.bytes.push_back(d.data);
+        stream.len += 1;
+        if stream.bytes.len() > *limit {
+            stream.bytes.pop_front();
+        }
+    }
+
+    decoders
+        .entry(d.id)
+        .or_default()
+        .decode_bytes(&[d.data], |r| results.push(r));
+
+    for r in results.drain(..) {
+        handler(Ok(Output::Packet(d.id, r)))?;
+    }
+    Ok(())
+}
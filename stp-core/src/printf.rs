@@ -0,0 +1,234 @@
+//! A C printf implementation for format strings whose arguments were packed by the writer,
+//! such as those of SyS-T catalog messages and dictionary logs.
+
+use std::convert::TryFrom;
+
+/// The arguments of a format string, in the order they were packed.
+pub trait Arguments {
+    /// The next integer of `size` bytes.
+    fn int(&mut self, size: usize) -> Option<u64>;
+
+    /// The next string.
+    fn string(&mut self) -> Option<String>;
+
+    /// The next pointer, as shown by %p.
+    fn pointer(&mut self, size: usize) -> Option<String> {
+        self.int(size).map(|v| format!("0x{:x}", v))
+    }
+}
+
+// The largest width or precision used, as the arguments giving them come from trace data:
+const MAX_WIDTH: usize = 4096;
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    // Pad a converted value to the field width:
+    fn pad(&self, sign: &str, body: &str, zeros: bool) -> String {
+        let len = sign.len() + body.chars().count();
+        let fill = self.width.saturating_sub(len);
+        if self.left {
+            format!("{}{}{}", sign, body, " ".repeat(fill))
+        } else if self.zero && zeros {
+            format!("{}{}{}", sign, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), sign, body)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        }
+    }
+}
+
+/// Format a C printf string: ints are 4 byte arguments, longs and pointers `pointer_size`
+/// bytes, and long longs and doubles 8 bytes.  Returns `None` if the arguments run out.
+pub fn format(format: &str, args: &mut dyn Arguments, pointer_size: usize) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alt = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        let mut number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                return args.int(4).map(|v| v as i32 as i64);
+            }
+            let mut n = 0;
+            while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                n = (n * 10 + d as i64).min(MAX_WIDTH as i64);
+                chars.next();
+            }
+            Some(n)
+        };
+        // Like C, a negative width left-justifies and a negative precision is none:
+        let width = number(&mut chars)?;
+        spec.left |= width < 0;
+        spec.width = (width.unsigned_abs() as usize).min(MAX_WIDTH);
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let precision = number(&mut chars)?;
+            spec.precision = usize::try_from(precision).ok().map(|p| p.min(MAX_WIDTH));
+        }
+        let mut length = String::new();
+        while let Some(l) = chars.peek().filter(|c| "hljztLq".contains(**c)) {
+            length.push(*l);
+            chars.next();
+        }
+        let (size, bits) = match length.as_str() {
+            "hh" => (4, 8),
+            "h" => (4, 16),
+            "l" | "z" | "t" => (pointer_size, pointer_size * 8),
+            "ll" | "j" | "q" | "L" => (8, 64),
+            _ => (4, 32),
+        };
+
+        let conversion = match chars.next() {
+            Some(c) => c,
+            None => {
+                out.push('%');
+                break;
+            }
+        };
+        let field = match conversion {
+            '%' => "%".to_string(),
+            'd' | 'i' => {
+                let shift = 64 - bits as u32;
+                let v = ((args.int(size)? << shift) as i64) >> shift;
+                let digits = integer(v.unsigned_abs(), 10, false, spec.precision);
+                spec.pad(spec.sign(v < 0), &digits, spec.precision.is_none())
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                let v = args.int(size)? & (u64::MAX >> (64 - bits));
+                let (radix, prefix) = match conversion {
+                    'x' => (16, "0x"),
+                    'X' => (16, "0X"),
+                    'o' => (8, "0"),
+                    _ => (10, ""),
+                };
+                let digits = integer(v, radix, conversion == 'X', spec.precision);
+                let prefix = if spec.alt && v != 0 { prefix } else { "" };
+                spec.pad(prefix, &digits, spec.precision.is_none())
+            }
+            'c' => {
+                let c = args.int(4)? as u8 as char;
+                spec.pad("", &c.to_string(), false)
+            }
+            's' => {
+                let s = args.string()?;
+                let s: String = match spec.precision {
+                    Some(p) => s.chars().take(p).collect(),
+                    None => s,
+                };
+                spec.pad("", &s, false)
+            }
+            'p' => spec.pad("", &args.pointer(pointer_size)?, false),
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'a' | 'A' => {
+                let v = f64::from_bits(args.int(8)?);
+                let upper = conversion.is_ascii_uppercase();
+                let body = float(v.abs(), conversion.to_ascii_lowercase(), &spec);
+                let body = if upper { body.to_uppercase() } else { body };
+                spec.pad(spec.sign(v.is_sign_negative()), &body, v.is_finite())
+            }
+            // Anything else is shown as written:
+            c => format!("%{}{}", length, c),
+        };
+        out.push_str(&field);
+    }
+    Some(out)
+}
+
+fn integer(v: u64, radix: u32, upper: bool, precision: Option<usize>) -> String {
+    let digits = match (radix, upper) {
+        (16, false) => format!("{:x}", v),
+        (16, true) => format!("{:X}", v),
+        (8, _) => format!("{:o}", v),
+        _ => v.to_string(),
+    };
+    match precision {
+        Some(0) if v == 0 => String::new(),
+        Some(p) if p > digits.len() => format!("{}{}", "0".repeat(p - digits.len()), digits),
+        _ => digits,
+    }
+}
+
+// Format a non-negative double for %f, %e, %g or %a (shown like %e):
+fn float(v: f64, conversion: char, spec: &Spec) -> String {
+    if v.is_nan() {
+        return "nan".to_string();
+    }
+    if v.is_infinite() {
+        return "inf".to_string();
+    }
+    let precision = spec.precision.unwrap_or(6);
+    match conversion {
+        'f' => format!("{:.*}", precision, v),
+        'g' => {
+            let p = precision.max(1);
+            let exponent = exponent(v, p - 1);
+            let s = if exponent < -4 || exponent >= p as i32 {
+                scientific(v, p - 1)
+            } else {
+                format!("{:.*}", (p as i32 - 1 - exponent) as usize, v)
+            };
+            if spec.alt {
+                return s;
+            }
+            // Trailing zeros are dropped from the fraction:
+            let (mantissa, exp) = match s.find('e') {
+                Some(i) => s.split_at(i),
+                None => (s.as_str(), ""),
+            };
+            let mantissa = match mantissa.contains('.') {
+                true => mantissa.trim_end_matches('0').trim_end_matches('.'),
+                false => mantissa,
+            };
+            format!("{}{}", mantissa, exp)
+        }
+        _ => scientific(v, precision),
+    }
+}
+
+// The decimal exponent of a double once rounded to `precision` digits after the point:
+fn exponent(v: f64, precision: usize) -> i32 {
+    let s = format!("{:.*e}", precision, v);
+    s[s.find('e').unwrap() + 1..].parse().unwrap()
+}
+
+// Like C's %e: "1.500000e+00".
+fn scientific(v: f64, precision: usize) -> String {
+    let s = format!("{:.*e}", precision, v);
+    let (mantissa, exp) = s.split_at(s.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exp.abs())
+}
@@ -0,0 +1,264 @@
+//! Reassembles the messages software writes through STM as several data writes to one master and
+//! channel: unmarked writes (D8, D16, D32, D64) hold the start of a message, and a marked write
+//! (D*M, D*MTS) its last bytes.
+//!
+//! Writes are turned back into bytes in the writer's byte order, as given by the VERSION packet.
+//! 4-bit writes are not bytes, so they are left out (a D4M still ends its message).
+//!
+//! `Writes` keeps the messages being written to each master and channel; the `text` and `syst`
+//! collectors build their own kinds of message on it.
+
+use crate::message::{self, MessageTracker};
+use crate::stp;
+use crate::stp_decoder;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A complete message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub master: u16,
+    pub channel: u16,
+    pub data: Vec<u8>,
+    pub timestamp: Option<u64>, // Absolute timestamp of the marked write, if known.
+    pub timestamped: bool,      // Did the marked write carry its own timestamp?
+    pub start: usize,           // Starting nibble offset of the first write.
+    pub span: usize,            // Nibbles from the first write to the end of the marked one.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IncompleteReason {
+    Async,
+    Error, // A decoding error.
+}
+
+impl fmt::Display for IncompleteReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IncompleteReason::Async => write!(f, "interrupted by ASYNC"),
+            IncompleteReason::Error => write!(f, "interrupted by an error"),
+        }
+    }
+}
+
+/// The start of a message that was never ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Incomplete {
+    pub master: u16,
+    pub channel: u16,
+    pub data: Vec<u8>,
+    pub start: usize,
+    pub reason: IncompleteReason,
+}
+
+impl fmt::Display for Incomplete {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "incomplete message M{} C{} ({} bytes): {}, offset: {:#x}",
+            self.master,
+            self.channel,
+            self.data.len(),
+            self.reason,
+            self.start
+        )
+    }
+}
+
+pub type Result = std::result::Result<Message, Incomplete>;
+
+/// What a packet of a stream does to the messages being written to its masters and channels.
+pub enum Event<P> {
+    /// An ASYNC or a decoding error ended every message being written (`P`, by master and
+    /// channel), at the stream's last known timestamp.
+    Interrupted {
+        reason: IncompleteReason,
+        pending: BTreeMap<(u16, u16), P>,
+        timestamp: Option<u64>,
+    },
+    /// A data write or flag, with the bytes written in the writer's byte order (None for flags).
+    Write {
+        message: message::Message,
+        bytes: Option<Vec<u8>>,
+    },
+}
+
+/// The data writes of a single STP stream, and the messages of type `P` being built from them for
+/// each master and channel.  Protocols carried over STP messages (plain messages, text lines,
+/// SyS-T) share this, and differ in how the writes start and end their messages.
+pub struct Writes<P> {
+    tracker: MessageTracker,
+    is_le: bool,
+    pending: BTreeMap<(u16, u16), P>,
+}
+
+impl<P> Default for Writes<P> {
+    fn default() -> Self {
+        Writes {
+            tracker: MessageTracker::default(),
+            is_le: false,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<P> Writes<P> {
+    /// Follow the next decoded packet (or error) of the stream.  Returns None for packets that
+    /// neither write data nor interrupt the messages being written.
+    pub fn process(&mut self, r: &stp_decoder::Result) -> Option<Event<P>> {
+        let packet = match r {
+            Ok(p) => p,
+            Err(_) => return Some(self.interrupt(IncompleteReason::Error)),
+        };
+        match packet.packet {
+            stp::Packet::Async => {
+                let event = self.interrupt(IncompleteReason::Async);
+                self.tracker.process(packet);
+                return Some(event);
+            }
+            stp::Packet::Version { is_le, .. } => self.is_le = is_le,
+            _ => (),
+        }
+        let message = self.tracker.process(packet)?;
+        let bytes = match (message.data, message.opcode.data_bits()) {
+            (Some(data), Some(bits)) => Some(bytes(data, bits, self.is_le)),
+            _ => None,
+        };
+        Some(Event::Write { message, bytes })
+    }
+
+    /// Forget the stream state and take the messages being written, with the last known
+    /// timestamp.
+    pub fn reset(&mut self) -> (BTreeMap<(u16, u16), P>, Option<u64>) {
+        let timestamp = self.tracker.timestamp();
+        self.tracker.reset();
+        (std::mem::take(&mut self.pending), timestamp)
+    }
+
+    /// The messages being written, by master and channel.
+    pub fn pending(&mut self) -> &mut BTreeMap<(u16, u16), P> {
+        &mut self.pending
+    }
+
+    /// The stream's master, channel and timestamp state.
+    pub fn tracker(&self) -> &MessageTracker {
+        &self.tracker
+    }
+
+    fn interrupt(&mut self, reason: IncompleteReason) -> Event<P> {
+        let (pending, timestamp) = match reason {
+            IncompleteReason::Error => self.reset(),
+            IncompleteReason::Async => {
+                (std::mem::take(&mut self.pending), self.tracker.timestamp())
+            }
+        };
+        Event::Interrupted {
+            reason,
+            pending,
+            timestamp,
+        }
+    }
+}
+
+// A message being written:
+struct Pending {
+    data: Vec<u8>,
+    start: usize,
+}
+
+/// Reassembles the messages of a single STP stream.
+#[derive(Default)]
+pub struct Reassembler {
+    writes: Writes<Pending>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next decoded packet (or error) of the stream, passing on the messages it ends and
+    /// those it leaves incomplete.
+    pub fn process<F>(&mut self, r: &stp_decoder::Result, mut f: F)
+    where
+        F: FnMut(Result),
+    {
+        let (m, data) = match self.writes.process(r) {
+            Some(Event::Write {
+                message,
+                bytes: Some(data),
+            }) => (message, data),
+            Some(Event::Interrupted {
+                reason, pending, ..
+            }) => return incomplete(pending, reason, f),
+            _ => return, // Flags aren't part of messages.
+        };
+
+        let p = self
+            .writes
+            .pending()
+            .entry((m.master, m.channel))
+            .or_insert_with(|| Pending {
+                data: Vec::new(),
+                start: m.start,
+            });
+        p.data.extend_from_slice(&data);
+        if m.opcode.is_marked() {
+            let p = self
+                .writes
+                .pending()
+                .remove(&(m.master, m.channel))
+                .unwrap();
+            f(Ok(Message {
+                master: m.master,
+                channel: m.channel,
+                data: p.data,
+                timestamp: m.timestamp,
+                timestamped: m.timestamped,
+                start: p.start,
+                span: m.start + m.span - p.start,
+            }));
+        }
+    }
+
+    /// Give up on the messages still being reassembled, e.g. when the TWP framing around the
+    /// stream breaks, returning what was written of each.
+    pub fn reset(&mut self) -> Vec<Incomplete> {
+        let mut messages = Vec::new();
+        let (pending, _) = self.writes.reset();
+        incomplete(pending, IncompleteReason::Error, |r| {
+            messages.extend(r.err())
+        });
+        messages
+    }
+
+    /// The tracker the writes are attributed with, e.g. for the stream's timestamp frequency.
+    pub fn tracker(&self) -> &MessageTracker {
+        self.writes.tracker()
+    }
+}
+
+fn incomplete<F>(pending: BTreeMap<(u16, u16), Pending>, reason: IncompleteReason, mut f: F)
+where
+    F: FnMut(Result),
+{
+    for ((master, channel), p) in pending {
+        f(Err(Incomplete {
+            master,
+            channel,
+            data: p.data,
+            start: p.start,
+            reason,
+        }));
+    }
+}
+
+/// The bytes of a data write, in the writer's byte order.  4-bit writes have none.
+pub fn bytes(data: u64, bits: u8, is_le: bool) -> Vec<u8> {
+    let size = bits as usize / 8;
+    if is_le {
+        data.to_le_bytes()[..size].to_vec()
+    } else {
+        data.to_be_bytes()[8 - size..].to_vec()
+    }
+}
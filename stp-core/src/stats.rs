@@ -0,0 +1,92 @@
+//! Summarizes a capture decoded by a Pipeline.
+
+use crate::message::MessageTracker;
+use crate::pipeline::{Output, Pipeline};
+use crate::stp::{self, OpCode, StpVersion, TimestampType};
+use crate::stp_decoder;
+use std::collections::BTreeMap;
+use twp::parsers::{self, FrameStats};
+
+/// The number of writes to a master or channel and the data they carried.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub writes: usize,
+    pub bits: u64,
+}
+
+/// Counts for a single STP stream.
+#[derive(Default)]
+pub struct StpStats {
+    pub asyncs: usize,
+    pub packets: BTreeMap<OpCode, usize>,
+    pub versions: BTreeMap<(StpVersion, TimestampType), usize>,
+    pub errors: BTreeMap<&'static str, usize>,
+    pub masters: BTreeMap<u16, Usage>,
+    pub channels: BTreeMap<(u16, u16), Usage>, // Keyed by (master, channel).
+    tracker: MessageTracker,
+}
+
+/// Counts for a whole capture.
+#[derive(Default)]
+pub struct Stats {
+    pub frames: FrameStats,
+    pub twp_errors: BTreeMap<&'static str, usize>,
+    pub twp_events: BTreeMap<&'static str, usize>,
+    pub streams: BTreeMap<Option<u8>, StpStats>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a result produced by a Pipeline.
+    pub fn record(&mut self, r: &parsers::Result<Output>) {
+        match r {
+            Ok(Output::Packet(id, r)) => self.streams.entry(*id).or_default().record(r),
+            Ok(Output::Event(e, _)) => *self.twp_events.entry(e.name()).or_default() += 1,
+            Err(e) => *self.twp_errors.entry(e.reason.name()).or_default() += 1,
+        }
+    }
+
+    /// Copy the frame counts from the Pipeline.  Call once the input has been decoded.
+    pub fn finish(&mut self, pipeline: &Pipeline) {
+        self.frames = pipeline.frames().stats().clone();
+    }
+}
+
+impl StpStats {
+    fn record(&mut self, r: &stp_decoder::Result) {
+        let p = match r {
+            Ok(p) => p,
+            Err(e) => {
+                *self.errors.entry(e.reason.name()).or_default() += 1;
+                self.tracker.reset();
+                return;
+            }
+        };
+
+        match p.packet.opcode() {
+            Some(opcode) => *self.packets.entry(opcode).or_default() += 1,
+            None => self.asyncs += 1,
+        }
+        if let stp::Packet::Version {
+            version, ts_type, ..
+        } = &p.packet
+        {
+            *self.versions.entry((*version, *ts_type)).or_default() += 1;
+        }
+
+        // Only data writes count towards master and channel usage, not flags:
+        if let Some(m) = self.tracker.process(p).filter(|m| m.data.is_some()) {
+            let bits = m.opcode.data_bits().unwrap_or(0) as u64;
+            for usage in [
+                self.masters.entry(m.master).or_default(),
+                self.channels.entry((m.master, m.channel)).or_default(),
+            ] {
+                usage.writes += 1;
+                usage.bits += bits;
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use self::OpCode::*;
+use std::str::FromStr;
+
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
 pub enum StpVersion {
     STPv1 = 1,
     STPv2_1, // also covers STPv2.0
@@ -7,7 +10,7 @@ pub enum StpVersion {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
 pub enum OpCode {
     NULL = 0x0,
     M8 = 0x1,
@@ -48,8 +51,70 @@ pub enum OpCode {
     FREQ_40_TS = 0xF0F1,
 }
 
+const OPCODES: [OpCode; 37] = [
+    NULL, M8, MERR, C8, D8, D16, D32, D64, D8MTS, D16MTS, D32MTS, D64MTS, D4, D4MTS, FLAG_TS, M16,
+    GERR, C16, D8TS, D16TS, D32TS, D64TS, D8M, D16M, D32M, D64M, D4TS, D4M, FLAG, VERSION, NULL_TS,
+    USER, USER_TS, FREQ, FREQ_TS, FREQ_40, FREQ_40_TS,
+];
+
+impl OpCode {
+    /// The size of a data opcode's payload in bits.  Returns None for other opcodes.
+    pub fn data_bits(self) -> Option<u8> {
+        match self {
+            D4 | D4M | D4TS | D4MTS => Some(4),
+            D8 | D8M | D8TS | D8MTS => Some(8),
+            D16 | D16M | D16TS | D16MTS => Some(16),
+            D32 | D32M | D32TS | D32MTS => Some(32),
+            D64 | D64M | D64TS | D64MTS => Some(64),
+            _ => None,
+        }
+    }
+
+    /// Is this a marked data opcode (D*M, D*MTS)?
+    pub fn is_marked(self) -> bool {
+        matches!(
+            self,
+            D4M | D4MTS | D8M | D8MTS | D16M | D16MTS | D32M | D32MTS | D64M | D64MTS
+        )
+    }
+
+    /// Is the opcode followed by a timestamp?
+    pub fn has_timestamp(self) -> bool {
+        matches!(
+            self,
+            D4TS | D4MTS
+                | D8TS
+                | D8MTS
+                | D16TS
+                | D16MTS
+                | D32TS
+                | D32MTS
+                | D64TS
+                | D64MTS
+                | FLAG_TS
+                | NULL_TS
+                | USER_TS
+                | FREQ_TS
+                | FREQ_40_TS
+        )
+    }
+}
+
+impl FromStr for OpCode {
+    type Err = String;
+
+    /// Parse an opcode name (as printed by Debug), ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OPCODES
+            .iter()
+            .find(|op| format!("{:?}", op).eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("unknown opcode: {}", s))
+    }
+}
+
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
 pub enum TimestampType {
     STPv1LEGACY = 1,
     STPv2NATDELTA = 2,
@@ -108,3 +173,25 @@ pub enum Packet {
         timestamp: Option<Timestamp>,
     },
 }
+
+impl Packet {
+    /// The packet's opcode.  Returns None for ASYNC packets.
+    pub fn opcode(&self) -> Option<OpCode> {
+        let ts_opcode = |timestamp: &Option<Timestamp>, plain, with_ts| match timestamp {
+            Some(_) => with_ts,
+            None => plain,
+        };
+        match self {
+            Packet::Async => None,
+            Packet::Null { timestamp } => Some(ts_opcode(timestamp, NULL, NULL_TS)),
+            Packet::Version { .. } => Some(VERSION),
+            Packet::Master { opcode, .. }
+            | Packet::Channel { opcode, .. }
+            | Packet::Data { opcode, .. }
+            | Packet::Frequency { opcode, .. }
+            | Packet::Error { opcode, .. } => Some(*opcode),
+            Packet::User { timestamp, .. } => Some(ts_opcode(timestamp, USER, USER_TS)),
+            Packet::Flag { timestamp } => Some(ts_opcode(timestamp, FLAG, FLAG_TS)),
+        }
+    }
+}
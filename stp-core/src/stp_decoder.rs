@@ -1,5 +1,6 @@
 use crate::nibble::swap_nibbles;
 use crate::stp::{self, OpCode::*, StpVersion::*, TimestampType::*};
+use std::fmt;
 use std::result;
 
 #[derive(Debug, PartialEq)]
@@ -22,6 +23,38 @@ pub enum ErrorReason {
 
 use self::ErrorReason::*;
 
+impl ErrorReason {
+    /// The name of the error, without its details.
+    pub fn name(&self) -> &'static str {
+        match self {
+            InvalidAsync { .. } => "InvalidAsync",
+            TruncatedPacket { .. } => "TruncatedPacket",
+            MissingVersion => "MissingVersion",
+            InvalidOpCode { .. } => "InvalidOpCode",
+            InvalidTimestampType { .. } => "InvalidTimestampType",
+            InvalidTimestampSize => "InvalidTimestampSize",
+            InvalidVersion { .. } => "InvalidVersion",
+        }
+    }
+}
+
+impl fmt::Display for ErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidAsync { bad_nibble } => write!(f, "invalid async: {:#x}", bad_nibble),
+            TruncatedPacket { opcode: None } => write!(f, "truncated packet"),
+            TruncatedPacket {
+                opcode: Some(opcode),
+            } => write!(f, "truncated packet: {:?}", opcode),
+            MissingVersion => write!(f, "missing version"),
+            InvalidOpCode { value } => write!(f, "invalid opcode: {:#x}", value),
+            InvalidTimestampType { value } => write!(f, "invalid timestamp type: {:#x}", value),
+            InvalidTimestampSize => write!(f, "invalid timestamp size"),
+            InvalidVersion { value } => write!(f, "invalid version: {:#x}", value),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Error {
     pub reason: ErrorReason,
@@ -29,11 +62,18 @@ pub struct Error {
     pub span: usize,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, offset: {:#x}", self.reason, self.start)
+    }
+}
+
 pub type Result = result::Result<Packet, Error>;
 
 // Used internally.
 type PartialResult = result::Result<stp::Packet, ErrorReason>;
 
+#[derive(Clone)]
 enum DecoderState {
     Unsynced,          // The decoder is looking for a SYNC packet.
     OpCode,            // Decoding an opcode.
@@ -45,6 +85,7 @@ use self::DecoderState::*;
 
 const ASYNC_F_COUNT: u8 = 21;
 
+#[derive(Clone)]
 pub struct StpDecoder {
     state: DecoderState,                 // The state of the decoder.
     offset: usize,                       // Offset in nibbles.
@@ -56,10 +97,16 @@ pub struct StpDecoder {
     is_le: bool,                         // Are data payloads little endian?
 }
 
+impl Default for StpDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StpDecoder {
     /// Create a new StpDecoder.
     pub fn new() -> Self {
-        return StpDecoder {
+        StpDecoder {
             state: Unsynced,
             offset: 0,
             f_count: 0,
@@ -68,7 +115,7 @@ impl StpDecoder {
             opcode: None,
             ts_type: None,
             is_le: false,
-        };
+        }
     }
 
     /// Decode a slice of bytes.
@@ -123,6 +170,35 @@ impl StpDecoder {
         }
     }
 
+    /// Signal the end of the stream.
+    ///
+    /// Any buffered 0xf nibbles are decoded, and a packet left incomplete is reported as a
+    /// `TruncatedPacket` error.
+    pub fn finish<F>(&mut self, mut handler: F)
+    where
+        F: FnMut(Result),
+    {
+        for _ in 0..self.f_count {
+            self.do_decode_nibble(0xf, &mut handler);
+        }
+        self.f_count = 0;
+
+        if !matches!(self.state, Unsynced) && self.span > 0 {
+            self.truncated_packet_check(&mut handler);
+            self.set_state(Unsynced);
+        }
+    }
+
+    /// Like `finish`, for a stream that starts over (e.g. on a new connection): the decoder then
+    /// waits for an ASYNC.  Offsets carry on.
+    pub fn restart<F>(&mut self, handler: F)
+    where
+        F: FnMut(Result),
+    {
+        self.finish(handler);
+        self.set_state(Unsynced);
+    }
+
     fn do_decode_nibble(&mut self, nibble: u8, handler: &mut dyn FnMut(Result)) {
         self.span += 1;
 
@@ -223,7 +299,7 @@ impl StpDecoder {
         data_sz: usize,
         has_timestamp: bool,
     ) -> Option<PartialResult> {
-        if let None = self.ts_type {
+        if self.ts_type.is_none() {
             Some(Err(MissingVersion))
         } else {
             self.set_state(Data(DataDecoder::new(
@@ -242,7 +318,7 @@ impl StpDecoder {
         opcode: stp::OpCode,
         has_timestamp: bool,
     ) -> Option<PartialResult> {
-        if let None = self.ts_type {
+        if self.ts_type.is_none() {
             Some(Err(MissingVersion))
         } else {
             self.set_state(Data(DataDecoder::new_variable_data(
@@ -290,7 +366,7 @@ impl StpDecoder {
             6 => {
                 if let Version(prior_nibble) = self.state {
                     let payload = prior_nibble << 4 | nibble;
-                    self.is_le = if payload & 0x80 == 0x80 { true } else { false };
+                    self.is_le = payload & 0x80 == 0x80;
                     if payload & 0x7F == 0x01 {
                         Some(Ok(stp::Packet::Version {
                             version: STPv2_2,
@@ -389,6 +465,7 @@ impl StpDecoder {
 
 type TimestampResult = result::Result<stp::Timestamp, ErrorReason>;
 
+#[derive(Clone)]
 struct TimestampDecoder {
     ts: u64,
     ts_span: usize,
@@ -462,6 +539,7 @@ impl TimestampDecoder {
     }
 }
 
+#[derive(Clone)]
 struct DataDecoder {
     data: u64,
     data_sz: usize,
@@ -479,10 +557,7 @@ impl DataDecoder {
         span: usize,
         ts_type: Option<stp::TimestampType>,
     ) -> DataDecoder {
-        let ts_decoder = match ts_type {
-            Some(tt) => Some(TimestampDecoder::new(tt, is_le)),
-            None => None,
-        };
+        let ts_decoder = ts_type.map(|tt| TimestampDecoder::new(tt, is_le));
         DataDecoder {
             data: 0,
             data_sz,
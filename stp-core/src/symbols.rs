@@ -0,0 +1,168 @@
+//! Resolves the code addresses written to channels to the functions of ELF files, and to source
+//! lines when the files have DWARF line information (requires the `elf` feature).
+//!
+//! Each file can be limited to a range of masters, and given the offset it was loaded at: the
+//! address written is the file's address plus the offset.
+
+use crate::message::Message;
+use gimli::{EndianRcSlice, RunTimeEndian};
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Where an address is: in a function, and at a source line if known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub offset: u64, // From the start of the function.
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if self.offset != 0 {
+            write!(f, "+{:#x}", self.offset)?;
+        }
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, " ({}:{})", file, line),
+            (Some(file), None) => write!(f, " ({})", file),
+            _ => Ok(()),
+        }
+    }
+}
+
+type Reader = EndianRcSlice<RunTimeEndian>;
+
+// A loaded ELF file:
+struct Image {
+    masters: Option<RangeInclusive<u16>>,
+    offset: u64,
+    functions: Vec<(u64, u64, String)>, // Address, size and name, sorted by address.
+    thumb: bool,                        // Do addresses have the ARM Thumb bit?
+    lines: Option<addr2line::Context<Reader>>,
+}
+
+impl Image {
+    fn resolve(&self, address: u64) -> Option<Symbol> {
+        let mut address = address.wrapping_sub(self.offset);
+        if self.thumb {
+            address &= !1;
+        }
+        let i = self
+            .functions
+            .partition_point(|(start, _, _)| *start <= address)
+            .checked_sub(1)?;
+        let (start, size, name) = &self.functions[i];
+        if address - start >= (*size).max(1) {
+            return None;
+        }
+        let location = self
+            .lines
+            .as_ref()
+            .and_then(|c| c.find_location(address).ok().flatten());
+        Some(Symbol {
+            name: name.clone(),
+            offset: address - start,
+            file: location.as_ref().and_then(|l| l.file.map(str::to_string)),
+            line: location.and_then(|l| l.line),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Symbols {
+    images: Vec<Image>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// Add an ELF file, for a range of masters (or all of them) and loaded at an offset.
+    pub fn load(
+        &mut self,
+        path: &Path,
+        masters: Option<RangeInclusive<u16>>,
+        offset: u64,
+    ) -> Result<(), String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.add_elf(&data, masters, offset)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Add the contents of an ELF file.
+    pub fn add_elf(
+        &mut self,
+        data: &[u8],
+        masters: Option<RangeInclusive<u16>>,
+        offset: u64,
+    ) -> Result<(), String> {
+        let file = object::File::parse(data).map_err(|e| e.to_string())?;
+        let thumb = file.architecture() == object::Architecture::Arm;
+        let mut functions: Vec<(u64, u64, String)> = file
+            .symbols()
+            .filter(|s| s.kind() == SymbolKind::Text && s.is_definition())
+            .filter_map(|s| {
+                let address = if thumb { s.address() & !1 } else { s.address() };
+                let name = s.name().ok().filter(|n| !n.is_empty())?;
+                Some((address, s.size(), name.to_string()))
+            })
+            .collect();
+        functions.sort_by_key(|(address, _, _)| *address);
+
+        let lines = match file.section_by_name(".debug_info") {
+            Some(_) => {
+                let endian = match file.is_little_endian() {
+                    true => RunTimeEndian::Little,
+                    false => RunTimeEndian::Big,
+                };
+                let section = |id: gimli::SectionId| -> Result<Reader, gimli::Error> {
+                    let data = file
+                        .section_by_name(id.name())
+                        .and_then(|s| s.uncompressed_data().ok())
+                        .unwrap_or(Cow::Borrowed(&[]));
+                    Ok(EndianRcSlice::new(Rc::from(&*data), endian))
+                };
+                let dwarf = gimli::Dwarf::load(section).map_err(|e| e.to_string())?;
+                Some(addr2line::Context::from_dwarf(dwarf).map_err(|e| e.to_string())?)
+            }
+            None => None,
+        };
+
+        self.images.push(Image {
+            masters,
+            offset,
+            functions,
+            thumb,
+            lines,
+        });
+        Ok(())
+    }
+
+    /// The function an address written by a master is in, from the first file that has one.
+    pub fn resolve(&self, master: u16, address: u64) -> Option<Symbol> {
+        self.images
+            .iter()
+            .filter(|i| i.masters.as_ref().is_none_or(|m| m.contains(&master)))
+            .find_map(|i| i.resolve(address))
+    }
+
+    /// The function the data of a D32 or D64 write is in, if it is a code address.
+    pub fn resolve_message(&self, m: &Message) -> Option<Symbol> {
+        match (m.data, m.opcode.data_bits()) {
+            (Some(data), Some(32)) | (Some(data), Some(64)) => self.resolve(m.master, data),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,622 @@
+//! Decodes MIPI SyS-T messages from the data written to STP channels.
+//!
+//! A SyS-T message is written to a single master and channel: a timestamped (or marked) D32 write
+//! of its 32-bit header starts it, data writes follow, and a FLAG ends it.  Short messages are
+//! complete once their data has been written.  Writes are turned back into bytes in the writer's
+//! byte order, as given by the VERSION packet, and an ASYNC drops the messages being written.
+//!
+//! The message layout is the header, then the optional origin GUID, location, payload length and
+//! SyS-T timestamp, the payload, and an optional CRC-32C of everything before it.
+
+use crate::message::MessageTracker;
+use crate::reassembler::{Event, Writes};
+use crate::stp_decoder;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+
+/// The message type, the low four bits of the header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageType {
+    Build,
+    Short32,
+    String,
+    Catalog,
+    Raw,
+    Short64,
+    Clock,
+    Other(u8),
+}
+
+impl From<u32> for MessageType {
+    fn from(v: u32) -> Self {
+        match v & 0xf {
+            0 => MessageType::Build,
+            1 => MessageType::Short32,
+            2 => MessageType::String,
+            3 => MessageType::Catalog,
+            6 => MessageType::Raw,
+            7 => MessageType::Short64,
+            8 => MessageType::Clock,
+            v => MessageType::Other(v as u8),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Severity {
+    Max,
+    Fatal,
+    Error,
+    Warning,
+    Info,
+    User1,
+    User2,
+    Debug,
+}
+
+impl From<u32> for Severity {
+    fn from(v: u32) -> Self {
+        match v & 0x7 {
+            0 => Severity::Max,
+            1 => Severity::Fatal,
+            2 => Severity::Error,
+            3 => Severity::Warning,
+            4 => Severity::Info,
+            5 => Severity::User1,
+            6 => Severity::User2,
+            _ => Severity::Debug,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Max => "MAX",
+            Severity::Fatal => "FATAL",
+            Severity::Error => "ERROR",
+            Severity::Warning => "WARNING",
+            Severity::Info => "INFO",
+            Severity::User1 => "USER1",
+            Severity::User2 => "USER2",
+            Severity::Debug => "DEBUG",
+        };
+        f.pad(name)
+    }
+}
+
+/// The fields of a message header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    pub kind: MessageType,
+    pub severity: Severity,
+    pub location: bool,
+    pub length: bool,
+    pub checksum: bool,
+    pub timestamp: bool,
+    pub module: u8, // The origin, unless there is a GUID.
+    pub unit: u8,
+    pub guid: bool,
+    pub subtype: u8,
+}
+
+impl From<u32> for Header {
+    fn from(v: u32) -> Self {
+        let bit = |n: u32| v & (1 << n) != 0;
+        Header {
+            kind: MessageType::from(v),
+            severity: Severity::from(v >> 4),
+            location: bit(8),
+            length: bit(9),
+            checksum: bit(10),
+            timestamp: bit(11),
+            module: (v >> 12 & 0x7f) as u8,
+            unit: (v >> 19 & 0xf) as u8,
+            guid: bit(23),
+            subtype: (v >> 24 & 0x3f) as u8,
+        }
+    }
+}
+
+/// Where a message comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    ModuleUnit { module: u8, unit: u8 },
+    Guid([u8; 16]),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::ModuleUnit { module, unit } => write!(f, "{}:{}", module, unit),
+            Origin::Guid(g) => {
+                // GUIDs are shown in their usual form, the first three fields little endian.
+                let order = [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15];
+                for (i, b) in order.iter().enumerate() {
+                    if [4, 6, 8, 10].contains(&i) {
+                        write!(f, "-")?;
+                    }
+                    write!(f, "{:02x}", g[*b])?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Where a message was written from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    File { file: u32, line: u32 },
+    Address(u64),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::File { file, line } => write!(f, "file {}:{}", file, line),
+            Location::Address(a) => write!(f, "{:#x}", a),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    /// A string message: the subtype says what kind (1 generic, 2 function enter, 3 function
+    /// exit, 4 invalid parameter, 7 assertion, 11 and 12 printf).  Printf strings are followed by
+    /// their packed arguments.
+    String {
+        subtype: u8,
+        text: String,
+        args: Vec<u8>,
+    },
+    Build {
+        id: u64,
+        text: String,
+    },
+    Short(u64),
+    /// A catalog message: a format string ID and its packed arguments.
+    Catalog {
+        id: u64,
+        args: Vec<u8>,
+    },
+    Clock {
+        clock: u64,
+        frequency: u64,
+    },
+    Raw {
+        protocol: u8,
+        data: Vec<u8>,
+    },
+    Other {
+        data: Vec<u8>,
+    },
+}
+
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = |f: &mut fmt::Formatter, bytes: &[u8]| {
+            bytes.iter().try_for_each(|b| write!(f, " {:02x}", b))
+        };
+        match self {
+            Payload::String { subtype, text, .. } => {
+                let prefix = match subtype {
+                    2 => "enter: ",
+                    3 => "exit: ",
+                    4 => "invalid parameter: ",
+                    7 => "assertion failed: ",
+                    _ => "",
+                };
+                write!(f, "{}{}", prefix, text.trim_end_matches('\n'))
+            }
+            Payload::Build { id, text } if text.is_empty() => write!(f, "build {:#x}", id),
+            Payload::Build { id, text } => write!(f, "build {:#x} {}", id, text),
+            Payload::Short(v) => write!(f, "short {:#x}", v),
+            Payload::Catalog { id, args } => {
+                write!(f, "catalog {:#x}", id)?;
+                hex(f, args)
+            }
+            Payload::Clock { clock, frequency } => write!(f, "clock {} at {} Hz", clock, frequency),
+            Payload::Raw { protocol, data } => {
+                write!(f, "raw protocol {}", protocol)?;
+                hex(f, data)
+            }
+            Payload::Other { data } => {
+                write!(f, "data")?;
+                hex(f, data)
+            }
+        }
+    }
+}
+
+/// A decoded SyS-T message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub master: u16,
+    pub channel: u16,
+    pub stp_timestamp: Option<u64>, // Absolute STP timestamp of the header write.
+    pub start: usize,               // Starting nibble offset of the header write.
+    pub header: Header,
+    pub origin: Origin,
+    pub location: Option<Location>,
+    pub timestamp: Option<u64>, // The message's own timestamp.
+    pub crc: Option<u32>,       // Checked against the message.
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorReason {
+    /// The message ended before a field, or its payload length.
+    Truncated {
+        field: &'static str,
+    },
+    Checksum {
+        expected: u32,
+        actual: u32,
+    },
+    /// A new message started before the last one ended, or an ASYNC or error reset the stream.
+    Unterminated,
+    /// A 4-bit write to the message's master and channel before it ended.
+    Corrupt,
+}
+
+impl fmt::Display for ErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorReason::Truncated { field } => write!(f, "truncated message: no {}", field),
+            ErrorReason::Checksum { expected, actual } => write!(
+                f,
+                "checksum mismatch: expected {:#010x}, got {:#010x}",
+                expected, actual
+            ),
+            ErrorReason::Unterminated => write!(f, "unterminated message"),
+            ErrorReason::Corrupt => write!(f, "corrupt message: 4-bit write"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub master: u16,
+    pub channel: u16,
+    pub start: usize, // Starting nibble offset of the header write.
+    pub reason: ErrorReason,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SyS-T M{} C{}: {}, offset: {:#x}",
+            self.master, self.channel, self.reason, self.start
+        )
+    }
+}
+
+pub type Result = std::result::Result<Message, Error>;
+
+// A message being written:
+struct Pending {
+    bytes: Vec<u8>,
+    timestamp: Option<u64>,
+    start: usize,
+}
+
+/// Collects the data written to each master and channel of an STP stream into SyS-T messages.
+#[derive(Default)]
+pub struct Decoder {
+    writes: Writes<Pending>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next decoded packet (or error) of the stream, passing on the messages (or errors)
+    /// it ends.
+    pub fn process<F>(&mut self, r: &stp_decoder::Result, mut f: F)
+    where
+        F: FnMut(Result),
+    {
+        let (m, bytes) = match self.writes.process(r) {
+            Some(Event::Write { message, bytes }) => (message, bytes),
+            Some(Event::Interrupted { pending, .. }) => return unterminated(pending, f),
+            None => return,
+        };
+
+        let key = (m.master, m.channel);
+        let pending = self.writes.pending();
+        let (bytes, bits) = match (bytes, m.opcode.data_bits()) {
+            (Some(bytes), Some(bits)) if bits >= 8 => (bytes, bits),
+            (None, _) => {
+                if let Some(p) = pending.remove(&key) {
+                    f(decode(m.master, m.channel, p));
+                }
+                return;
+            }
+            _ => {
+                // 4-bit writes can't be part of a message:
+                if let Some(p) = pending.remove(&key) {
+                    f(Err(error(m.master, m.channel, &p, ErrorReason::Corrupt)));
+                }
+                return;
+            }
+        };
+
+        if bits == 32 && (m.timestamped || m.opcode.is_marked()) {
+            let next = Pending {
+                bytes: Vec::new(),
+                timestamp: m.timestamp,
+                start: m.start,
+            };
+            if let Some(p) = pending.insert(key, next) {
+                f(Err(error(
+                    m.master,
+                    m.channel,
+                    &p,
+                    ErrorReason::Unterminated,
+                )));
+            }
+        }
+        let p = match pending.get_mut(&key) {
+            Some(p) => p,
+            None => return, // Not part of a message.
+        };
+        p.bytes.extend_from_slice(&bytes);
+
+        // Short messages have no FLAG:
+        let header = u32::from_le_bytes(p.bytes[..4].try_into().unwrap());
+        let size = match MessageType::from(header) {
+            MessageType::Short32 => 4,
+            MessageType::Short64 => 12,
+            _ => return,
+        };
+        if p.bytes.len() >= size {
+            let p = pending.remove(&key).unwrap();
+            f(decode(m.master, m.channel, p));
+        }
+    }
+
+    /// Give up on the messages whose FLAG hasn't been seen yet, e.g. when the TWP framing around
+    /// the stream breaks, returning an `Unterminated` error for each.
+    pub fn reset(&mut self) -> Vec<Error> {
+        let mut errors = Vec::new();
+        let (pending, _) = self.writes.reset();
+        unterminated(pending, |r| errors.extend(r.err()));
+        errors
+    }
+
+    /// The tracker the writes are attributed with, giving the STP timestamps of the messages.
+    pub fn tracker(&self) -> &MessageTracker {
+        self.writes.tracker()
+    }
+}
+
+fn unterminated<F>(pending: BTreeMap<(u16, u16), Pending>, mut f: F)
+where
+    F: FnMut(Result),
+{
+    for ((master, channel), p) in pending {
+        f(Err(error(master, channel, &p, ErrorReason::Unterminated)));
+    }
+}
+
+fn error(master: u16, channel: u16, p: &Pending, reason: ErrorReason) -> Error {
+    Error {
+        master,
+        channel,
+        start: p.start,
+        reason,
+    }
+}
+
+// Reads the fields of a message:
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(
+        &mut self,
+        len: usize,
+        field: &'static str,
+    ) -> std::result::Result<&'a [u8], ErrorReason> {
+        if self.bytes.len() - self.pos < len {
+            return Err(ErrorReason::Truncated { field });
+        }
+        self.pos += len;
+        Ok(&self.bytes[self.pos - len..self.pos])
+    }
+
+    fn uint(&mut self, len: usize, field: &'static str) -> std::result::Result<u64, ErrorReason> {
+        let mut b = [0; 8];
+        b[..len].copy_from_slice(self.take(len, field)?);
+        Ok(u64::from_le_bytes(b))
+    }
+}
+
+fn decode(master: u16, channel: u16, p: Pending) -> Result {
+    decode_bytes(&p.bytes)
+        .map(
+            |(header, origin, location, timestamp, crc, payload)| Message {
+                master,
+                channel,
+                stp_timestamp: p.timestamp,
+                start: p.start,
+                header,
+                origin,
+                location,
+                timestamp,
+                crc,
+                payload,
+            },
+        )
+        .map_err(|reason| Error {
+            master,
+            channel,
+            start: p.start,
+            reason,
+        })
+}
+
+type Fields = (
+    Header,
+    Origin,
+    Option<Location>,
+    Option<u64>,
+    Option<u32>,
+    Payload,
+);
+
+fn decode_bytes(bytes: &[u8]) -> std::result::Result<Fields, ErrorReason> {
+    let mut r = Reader { bytes, pos: 0 };
+    let value = r.uint(4, "header")? as u32;
+    let header = Header::from(value);
+    let origin = Origin::ModuleUnit {
+        module: header.module,
+        unit: header.unit,
+    };
+    match header.kind {
+        MessageType::Short32 => {
+            return Ok((
+                header,
+                origin,
+                None,
+                None,
+                None,
+                Payload::Short((value >> 4) as u64),
+            ))
+        }
+        MessageType::Short64 => {
+            let data = r.uint(8, "payload")?;
+            return Ok((header, origin, None, None, None, Payload::Short(data)));
+        }
+        _ => (),
+    }
+
+    let origin = match header.guid {
+        true => Origin::Guid(r.take(16, "GUID")?.try_into().unwrap()),
+        false => origin,
+    };
+    let location = match header.location {
+        true => Some(match r.uint(1, "location")? {
+            0 => Location::File {
+                file: r.uint(2, "location")? as u32,
+                line: r.uint(2, "location")? as u32,
+            },
+            1 => Location::File {
+                file: r.uint(4, "location")? as u32,
+                line: r.uint(4, "location")? as u32,
+            },
+            2 => Location::Address(r.uint(4, "location")?),
+            _ => Location::Address(r.uint(8, "location")?),
+        }),
+        false => None,
+    };
+    let length = match header.length {
+        true => Some(r.uint(2, "payload length")? as usize),
+        false => None,
+    };
+    let timestamp = match header.timestamp {
+        true => Some(r.uint(8, "timestamp")?),
+        false => None,
+    };
+    let crc_size = if header.checksum { 4 } else { 0 };
+    let length = length.unwrap_or_else(|| (bytes.len() - r.pos).saturating_sub(crc_size));
+    let payload = r.take(length, "payload")?;
+    let crc = match header.checksum {
+        true => {
+            let expected = r.uint(4, "checksum")? as u32;
+            let actual = crc32c(&bytes[..r.pos - 4]);
+            if expected != actual {
+                return Err(ErrorReason::Checksum { expected, actual });
+            }
+            Some(expected)
+        }
+        false => None,
+    };
+
+    let payload = decode_payload(&header, payload)?;
+    Ok((header, origin, location, timestamp, crc, payload))
+}
+
+fn decode_payload(header: &Header, payload: &[u8]) -> std::result::Result<Payload, ErrorReason> {
+    let mut r = Reader {
+        bytes: payload,
+        pos: 0,
+    };
+    let subtype = header.subtype;
+    Ok(match header.kind {
+        MessageType::String => {
+            let (text, args) = c_string(payload);
+            Payload::String {
+                subtype,
+                text,
+                args: args.to_vec(),
+            }
+        }
+        MessageType::Build => match subtype {
+            0 => Payload::Build {
+                id: r.uint(4, "build ID")?,
+                text: String::new(),
+            },
+            1 => Payload::Build {
+                id: r.uint(8, "build ID")?,
+                text: String::new(),
+            },
+            _ => Payload::Build {
+                id: r.uint(8, "build ID")?,
+                text: c_string(&payload[8..]).0,
+            },
+        },
+        MessageType::Catalog => {
+            // Subtypes 1 and 5 have 32-bit IDs, 2 and 6 64-bit ones:
+            let size = if subtype & 0x3 == 2 { 8 } else { 4 };
+            Payload::Catalog {
+                id: r.uint(size, "catalog ID")?,
+                args: payload[size..].to_vec(),
+            }
+        }
+        MessageType::Clock => Payload::Clock {
+            clock: r.uint(8, "clock")?,
+            frequency: r.uint(8, "clock frequency")?,
+        },
+        MessageType::Raw => Payload::Raw {
+            protocol: subtype,
+            data: payload.to_vec(),
+        },
+        _ => Payload::Other {
+            data: payload.to_vec(),
+        },
+    })
+}
+
+// A NUL terminated (or unterminated) string, and the bytes after it:
+fn c_string(bytes: &[u8]) -> (String, &[u8]) {
+    match bytes.iter().position(|b| *b == 0) {
+        Some(end) => (
+            String::from_utf8_lossy(&bytes[..end]).into_owned(),
+            &bytes[end + 1..],
+        ),
+        None => (String::from_utf8_lossy(bytes).into_owned(), &[]),
+    }
+}
+
+/// The CRC-32C (Castagnoli) of `bytes`, as used by SyS-T.
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,123 @@
+//! Collects the text written to channels (e.g. by Linux's `stm_console`) into lines.
+//!
+//! The printable bytes written to each master and channel are gathered until a newline, or until
+//! a marked write ends a message.  Control characters, and the NUL padding of wide writes, are
+//! dropped.  A line's timestamp is that of the write that ends it, so the lines of a stream come
+//! out in time order.
+
+use crate::message::MessageTracker;
+use crate::reassembler::{Event, Writes};
+use crate::stp_decoder;
+use std::collections::BTreeMap;
+
+/// A line of text written to a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub master: u16,
+    pub channel: u16,
+    pub text: String,
+    pub timestamp: Option<u64>, // Absolute timestamp of the write that ended the line, if known.
+    pub start: usize,           // Starting nibble offset of the line's first write.
+}
+
+// A line being written:
+struct Pending {
+    bytes: Vec<u8>,
+    start: usize,
+}
+
+/// Collects the lines of a single STP stream.
+#[derive(Default)]
+pub struct TextCollector {
+    writes: Writes<Pending>,
+}
+
+impl TextCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next decoded packet (or error) of the stream, passing on the lines it ends.  An
+    /// ASYNC or an error ends every line being written.
+    pub fn process<F>(&mut self, r: &stp_decoder::Result, mut f: F)
+    where
+        F: FnMut(Line),
+    {
+        let (m, bytes) = match self.writes.process(r) {
+            Some(Event::Write {
+                message,
+                bytes: Some(bytes),
+            }) => (message, bytes),
+            Some(Event::Interrupted {
+                pending, timestamp, ..
+            }) => return flush(pending, timestamp, f),
+            _ => return,
+        };
+
+        let key = (m.master, m.channel);
+        let pending = self.writes.pending();
+        for b in bytes {
+            match b {
+                b'\n' => {
+                    // Empty lines are kept, unlike empty messages.
+                    let (bytes, start) = match pending.remove(&key) {
+                        Some(p) => (p.bytes, p.start),
+                        None => (Vec::new(), m.start),
+                    };
+                    f(line(key, bytes, m.timestamp, start))
+                }
+                b'\t' | 0x20..=0x7e | 0x80..=0xff => {
+                    let p = pending.entry(key).or_insert_with(|| Pending {
+                        bytes: Vec::new(),
+                        start: m.start,
+                    });
+                    p.bytes.push(b);
+                }
+                _ => (),
+            }
+        }
+        if m.opcode.is_marked() {
+            if let Some(p) = pending.remove(&key) {
+                f(line(key, p.bytes, m.timestamp, p.start));
+            }
+        }
+    }
+
+    /// End the lines still being written, at the stream's last known timestamp: at the end of
+    /// the input, or when the TWP framing around the stream breaks.
+    pub fn finish(&mut self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let (pending, timestamp) = self.writes.reset();
+        flush(pending, timestamp, |l| lines.push(l));
+        lines
+    }
+
+    /// The tracker the writes are attributed with, whose timestamps date the lines.
+    pub fn tracker(&self) -> &MessageTracker {
+        self.writes.tracker()
+    }
+}
+
+fn flush<F>(pending: BTreeMap<(u16, u16), Pending>, timestamp: Option<u64>, mut f: F)
+where
+    F: FnMut(Line),
+{
+    for (key, p) in pending {
+        f(line(key, p.bytes, timestamp, p.start));
+    }
+}
+
+fn line(
+    (master, channel): (u16, u16),
+    bytes: Vec<u8>,
+    timestamp: Option<u64>,
+    start: usize,
+) -> Line {
+    Line {
+        master,
+        channel,
+        text: String::from_utf8_lossy(&bytes).into_owned(),
+        timestamp,
+        start,
+    }
+}
@@ -0,0 +1,277 @@
+//! Writes channel activity as a Value Change Dump (VCD), for waveform viewers such as GTKWave.
+//!
+//! Each (master, channel) gets a data variable, as wide as the widest write to it, and 1-bit
+//! `flag` and `marked` signals that pulse for FLAG packets and marked data writes.  Each stream
+//! also has an `error` signal that pulses for decoding errors.  Pulses last one time unit.
+//! Named masters and channels (see `VcdWriter::names`) keep their numbers as a suffix, e.g.
+//! `modem_m32` and `irq_enter_c1`, since names may cover ranges.
+//!
+//! Variables must be declared before any value changes, so the changes are kept in memory and the
+//! whole file is written by `finish`.
+
+use crate::message::Message;
+use crate::names::Names;
+use crate::stp::OpCode;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// The time unit of a dump, e.g. `10 ns`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timescale {
+    magnitude: u32, // 1, 10 or 100.
+    exponent: i32,  // Of the unit, in seconds.
+}
+
+const UNITS: [(&str, i32); 6] = [
+    ("s", 0),
+    ("ms", -3),
+    ("us", -6),
+    ("ns", -9),
+    ("ps", -12),
+    ("fs", -15),
+];
+
+impl Timescale {
+    /// The number of time units in `seconds`.
+    pub fn units(&self, seconds: f64) -> u64 {
+        (seconds / (self.magnitude as f64 * 10f64.powi(self.exponent))).round() as u64
+    }
+}
+
+impl Default for Timescale {
+    fn default() -> Self {
+        Timescale {
+            magnitude: 1,
+            exponent: -9,
+        }
+    }
+}
+
+impl FromStr for Timescale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let magnitude = match &s[..split] {
+            "1" => 1,
+            "10" => 10,
+            "100" => 100,
+            _ => return Err(format!("invalid timescale: {}", s)),
+        };
+        let unit = s[split..].trim();
+        match UNITS.iter().find(|(name, _)| *name == unit) {
+            Some((_, exponent)) => Ok(Timescale {
+                magnitude,
+                exponent: *exponent,
+            }),
+            None => Err(format!("invalid timescale: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Timescale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = UNITS.iter().find(|(_, e)| *e == self.exponent).unwrap().0;
+        write!(f, "{} {}", self.magnitude, unit)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Signal {
+    Data,
+    Flag,
+    Marked,
+    Error,
+}
+
+// Variables are ordered by stream, then master and channel, to group them into scopes:
+type Key = (Option<u8>, Option<(u16, u16)>, Signal);
+
+struct Var {
+    index: usize,
+    width: u8,
+}
+
+/// Collects channel activity, then writes it as a VCD file.
+pub struct VcdWriter {
+    timescale: Timescale,
+    vars: BTreeMap<Key, Var>,
+    changes: Vec<(u64, usize, u64)>, // Time, variable index and value.
+    names: Names,
+}
+
+impl VcdWriter {
+    pub fn new(timescale: Timescale) -> Self {
+        VcdWriter {
+            timescale,
+            vars: BTreeMap::new(),
+            changes: Vec::new(),
+            names: Names::new(),
+        }
+    }
+
+    /// Name the scopes and variables of masters and channels.
+    pub fn names(mut self, names: Names) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Record a message at `time`, in time units.
+    pub fn message(&mut self, stream: Option<u8>, m: &Message, time: u64) {
+        let channel = Some((m.master, m.channel));
+        match (m.opcode, m.data) {
+            (OpCode::FLAG | OpCode::FLAG_TS, _) => {
+                self.pulse((stream, channel, Signal::Flag), time)
+            }
+            (opcode, Some(data)) => {
+                let width = opcode.data_bits().unwrap_or(64);
+                let index = self.var((stream, channel, Signal::Data), width);
+                self.changes.push((time, index, data));
+                if opcode.is_marked() {
+                    self.pulse((stream, channel, Signal::Marked), time);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Record a decoding error at `time`, in time units.
+    pub fn error(&mut self, stream: Option<u8>, time: u64) {
+        self.pulse((stream, None, Signal::Error), time);
+    }
+
+    /// Write the dump.
+    pub fn finish<W: Write>(mut self, mut out: W) -> io::Result<()> {
+        writeln!(out, "$comment STP channel activity $end")?;
+        writeln!(out, "$timescale {} $end", self.timescale)?;
+        writeln!(out, "$scope module stp $end")?;
+        let mut scope = None; // The open stream and master scopes.
+        for ((stream, channel, signal), var) in &self.vars {
+            let master = channel.map(|c| c.0);
+            if scope.map(|s: (Option<u8>, Option<u16>)| s.0) != Some(*stream) {
+                close_scopes(&mut out, scope)?;
+                if let Some(id) = stream {
+                    writeln!(out, "$scope module stream_{:02x} $end", id)?;
+                }
+                scope = Some((*stream, None));
+            }
+            if let Some((s, m)) = scope {
+                if m != master {
+                    close_scopes(&mut out, Some((None, m)))?;
+                    if let Some(m) = master {
+                        let name = match self.names.master(m) {
+                            Some(n) => format!("{}_m{}", sanitize(&n.name), m),
+                            None => format!("m{}", m),
+                        };
+                        writeln!(out, "$scope module {} $end", name)?;
+                    }
+                    scope = Some((s, master));
+                }
+            }
+
+            let c = match channel {
+                Some((m, c)) => match self.names.channel(*m, *c) {
+                    Some(n) => format!("{}_c{}", sanitize(&n.name), c),
+                    None => format!("c{}", c),
+                },
+                None => String::new(),
+            };
+            let name = match signal {
+                Signal::Data => format!("{} [{}:0]", c, var.width - 1),
+                Signal::Flag => format!("{}_flag", c),
+                Signal::Marked => format!("{}_marked", c),
+                Signal::Error => "error".to_string(),
+            };
+            writeln!(
+                out,
+                "$var wire {} {} {} $end",
+                var.width,
+                identifier(var.index),
+                name
+            )?;
+        }
+        close_scopes(&mut out, scope)?;
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+
+        // Initial values: data is unknown until written, pulses are low.
+        let mut widths = vec![1; self.vars.len()];
+        for var in self.vars.values() {
+            widths[var.index] = var.width;
+        }
+        writeln!(out, "#0")?;
+        writeln!(out, "$dumpvars")?;
+        for (index, width) in widths.iter().enumerate() {
+            match width {
+                1 => writeln!(out, "0{}", identifier(index))?,
+                _ => writeln!(out, "bx {}", identifier(index))?,
+            }
+        }
+        writeln!(out, "$end")?;
+
+        // Streams are decoded in capture order, so their times interleave:
+        self.changes.sort_by_key(|c| c.0);
+        let mut now = 0;
+        for (time, index, value) in &self.changes {
+            if *time != now {
+                writeln!(out, "#{}", time)?;
+                now = *time;
+            }
+            match widths[*index] {
+                1 => writeln!(out, "{}{}", value, identifier(*index))?,
+                _ => writeln!(out, "b{:b} {}", value, identifier(*index))?,
+            }
+        }
+        out.flush()
+    }
+
+    // Find, or add, a variable, widening it if need be:
+    fn var(&mut self, key: Key, width: u8) -> usize {
+        let index = self.vars.len();
+        let var = self.vars.entry(key).or_insert(Var { index, width });
+        var.width = var.width.max(width);
+        var.index
+    }
+
+    fn pulse(&mut self, key: Key, time: u64) {
+        let index = self.var(key, 1);
+        self.changes.push((time, index, 1));
+        self.changes.push((time + 1, index, 0));
+    }
+}
+
+// Close the scopes of a stream and master, if open:
+fn close_scopes<W: Write>(out: &mut W, scope: Option<(Option<u8>, Option<u16>)>) -> io::Result<()> {
+    if let Some((stream, master)) = scope {
+        if master.is_some() {
+            writeln!(out, "$upscope $end")?;
+        }
+        if stream.is_some() {
+            writeln!(out, "$upscope $end")?;
+        }
+    }
+    Ok(())
+}
+
+// Names may only hold letters, digits and underscores:
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// VCD identifiers are short strings of printable ASCII characters:
+fn identifier(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
@@ -0,0 +1,120 @@
+use stp_core::asm::{assemble, assemble_bytes, disassemble, disassemble_bytes, AsmError};
+use stp_core::stp::{self, OpCode, Timestamp};
+use stp_core::stp_decoder::{Packet, StpDecoder};
+
+const PROGRAM: &str = "\
+async
+version v2.2 nat le
+m16 0x40
+c8 0x3
+d32ts 0xdeadbeef ts=nat:8:0x1234
+d8 0x12
+d4m 0x7
+flag_ts ts=nat:0:0x0
+user 0x123 len=3
+freq 1000000
+merr 0x05
+version v2.1 natdelta
+d64mts 0x0123456789abcdef ts=natdelta:16:0xfedcba9876543210
+null_ts ts=natdelta:2:0x30
+version v1 legacy
+d16ts 0xbeef ts=legacy:0x42
+null
+";
+
+fn decode(nibbles: &[u8]) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut decoder = StpDecoder::new();
+    decoder.decode_nibbles(nibbles, |r| packets.push(r.unwrap()));
+    packets
+}
+
+#[test]
+fn assemble_packets() {
+    let nibbles =
+        assemble("async\nversion v2.2 nat le\nm16 0x1234\nd32ts 0xdeadbeef ts=nat:2:0x12\n")
+            .unwrap();
+    let packets: Vec<stp::Packet> = decode(&nibbles).into_iter().map(|p| p.packet).collect();
+    assert_eq!(packets.len(), 4);
+    assert_eq!(
+        packets[2],
+        stp::Packet::Master {
+            opcode: OpCode::M16,
+            master: 0x1234
+        }
+    );
+    assert_eq!(
+        packets[3],
+        stp::Packet::Data {
+            opcode: OpCode::D32TS,
+            data: 0xdeadbeef,
+            timestamp: Some(Timestamp::STPv2NAT {
+                length: 2,
+                value: 0x12
+            })
+        }
+    );
+}
+
+#[test]
+fn round_trip() {
+    let nibbles = assemble(PROGRAM).unwrap();
+    assert_eq!(disassemble(&nibbles), PROGRAM);
+
+    // Every packet decodes:
+    assert_eq!(decode(&nibbles).len(), PROGRAM.lines().count());
+
+    // As bytes, the NULL padding the nibbles to whole bytes is left out:
+    let unpadded = PROGRAM.strip_suffix("null\n").unwrap();
+    let bytes = assemble_bytes(unpadded).unwrap();
+    assert_eq!(disassemble_bytes(&bytes), unpadded);
+    // A NULL written in the last nibble can't be told from padding, but gives the same bytes:
+    assert_eq!(bytes, assemble_bytes(PROGRAM).unwrap());
+}
+
+#[test]
+fn comments_and_case() {
+    let a = assemble("# header\n\nASYNC  # sync\nVersion V2.2 NAT BE\nD8 18\n").unwrap();
+    let b = assemble("async\nversion v2.2 nat be\nd8 0x12\n").unwrap();
+    assert_eq!(a, b);
+}
+
+#[test]
+fn raw_nibbles() {
+    assert_eq!(assemble("raw f f 0\nraw 1 2").unwrap(), [0xF, 0xF, 0, 1, 2]);
+    // Odd nibble counts are padded with a NULL:
+    assert_eq!(assemble_bytes("raw f f 0").unwrap(), [0xFF, 0x00]);
+}
+
+#[test]
+fn disassemble_anything() {
+    // Garbage before the first ASYNC, an invalid opcode and a truncated packet:
+    let bytes = assemble_bytes(
+        "raw 2 1 4 3\nasync\nversion v2.2 natdelta be\nd8 0x12\nraw f 0 6\nd8 0x34\n\
+         async\nd16 0x5",
+    )
+    .unwrap();
+    let text = disassemble_bytes(&bytes);
+    assert!(text.starts_with("raw 2 1 4 3\nasync\nversion v2.2 natdelta be\nd8 0x12\n"));
+    assert!(text.contains("  # "));
+    assert_eq!(assemble_bytes(&text).unwrap(), bytes);
+}
+
+#[test]
+fn errors() {
+    let err = |text| assemble(text).unwrap_err();
+    assert_eq!(
+        err("async\nbogus 1"),
+        AsmError {
+            line: 2,
+            message: "unknown opcode: bogus".to_string()
+        }
+    );
+    assert_eq!(err("d8 0x123").line, 1);
+    assert_eq!(err("d8ts 0x12").line, 1);
+    assert_eq!(err("d8 0x12 ts=nat:2:0x1").line, 1);
+    assert_eq!(err("d8ts 0x12 ts=nat:13:0x1").line, 1);
+    assert_eq!(err("version v2.1 nat le").line, 1);
+    assert_eq!(err("raw 10").line, 1);
+    assert_eq!(err("user 0x1234 len=2").line, 1);
+}
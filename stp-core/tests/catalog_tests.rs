@@ -0,0 +1,277 @@
+use stp_core::catalog::printf;
+#[cfg(feature = "config")]
+use stp_core::catalog::{Catalog, Error};
+#[cfg(feature = "config")]
+use stp_core::syst::{Header, Message, Origin, Payload};
+
+#[cfg(feature = "config")]
+const COLLATERAL: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<!-- Generated by the build. -->
+<syst:Collateral xmlns:syst="http://www.mipi.org/1.0/sys-t">
+  <syst:Client Name="boot">
+    <syst:Guids>
+      <syst:Guid ID="{494E5443-8A9C-4014-A65A-2F36A36D96E4}"
+                 Mask="{00000000-0000-0000-FF00-000000000000}"/>
+    </syst:Guids>
+    <syst:Catalog32>
+      <syst:Format ID="0x1"><![CDATA[stage %d: %s & %#x
+]]></syst:Format>
+    </syst:Catalog32>
+    <syst:Catalog64>
+      <syst:Format ID="0x100000000">wide &lt;%u&gt;</syst:Format>
+    </syst:Catalog64>
+  </syst:Client>
+  <syst:Client Name="modem">
+    <syst:Modules><syst:Module ID="3"/></syst:Modules>
+    <syst:Catalog32>
+      <syst:Format ID="1">modem\t%ld\n</syst:Format>
+    </syst:Catalog32>
+    <syst:SourceFiles>
+      <syst:File ID="2"><![CDATA[src/modem.c]]></syst:File>
+    </syst:SourceFiles>
+  </syst:Client>
+</syst:Collateral>
+"#;
+
+#[cfg(feature = "config")]
+// "{494E5443-8A9C-4014-A65A-2F36A36D96E4}" in the order messages carry it:
+const GUID: [u8; 16] = [
+    0x43, 0x54, 0x4e, 0x49, 0x9c, 0x8a, 0x14, 0x40, 0xa6, 0x5a, 0x2f, 0x36, 0xa3, 0x6d, 0x96, 0xe4,
+];
+
+#[cfg(feature = "config")]
+fn catalog_message(origin: Origin, subtype: u8, id: u64, args: Vec<u8>) -> Message {
+    Message {
+        master: 5,
+        channel: 7,
+        stp_timestamp: None,
+        start: 0,
+        header: Header::from(3 | (subtype as u32) << 24),
+        origin,
+        location: None,
+        timestamp: None,
+        crc: None,
+        payload: Payload::Catalog { id, args },
+    }
+}
+
+#[test]
+fn formats() {
+    let args = |values: &[&[u8]]| values.concat();
+    assert_eq!(
+        printf(
+            "%d|%5d|%-5d|%05d|%+d|%.3d",
+            &args(&[
+                &(-3i32).to_le_bytes(),
+                &42i32.to_le_bytes(),
+                &42i32.to_le_bytes(),
+                &(-42i32).to_le_bytes(),
+                &7i32.to_le_bytes(),
+                &7i32.to_le_bytes()
+            ]),
+            8
+        )
+        .unwrap(),
+        "-3|   42|42   |-0042|+7|007"
+    );
+    assert_eq!(
+        printf(
+            "%x %#X %o %hhu %lld %lx %c 100%%",
+            &args(&[
+                &255u32.to_le_bytes(),
+                &255u32.to_le_bytes(),
+                &8u32.to_le_bytes(),
+                &0x1ffu32.to_le_bytes(),
+                &(-1i64).to_le_bytes(),
+                &0xdead_beefu32.to_le_bytes(),
+                &(b'A' as u32).to_le_bytes()
+            ]),
+            4
+        )
+        .unwrap(),
+        "ff 0XFF 10 255 -1 deadbeef A 100%"
+    );
+    assert_eq!(
+        printf(
+            "%f %.2f %e %g %g %8.3s| %p",
+            &args(&[
+                &1.5f64.to_le_bytes(),
+                &(-2.345f64).to_le_bytes(),
+                &1234.5f64.to_le_bytes(),
+                &0.0001f64.to_le_bytes(),
+                &1e20f64.to_le_bytes(),
+                b"hello\0",
+                &0x1000u64.to_le_bytes()
+            ]),
+            8
+        )
+        .unwrap(),
+        "1.500000 -2.35 1.234500e+03 0.0001 1e+20      hel| 0x1000"
+    );
+    assert_eq!(printf("%d %d", &1u32.to_le_bytes(), 8), None);
+    assert_eq!(printf("%s", b"no end", 8), None);
+}
+
+#[test]
+fn star_arguments() {
+    let args = |values: &[&[u8]]| values.concat();
+    // A negative width left-justifies, and a negative precision is ignored:
+    assert_eq!(
+        printf(
+            "%*d|%*d|%.*s|%.*s|",
+            &args(&[
+                &4i32.to_le_bytes(),
+                &1i32.to_le_bytes(),
+                &(-4i32).to_le_bytes(),
+                &1i32.to_le_bytes(),
+                &2i32.to_le_bytes(),
+                b"abc\0",
+                &(-1i32).to_le_bytes(),
+                b"abc\0",
+            ]),
+            8
+        )
+        .unwrap(),
+        "   1|1   |ab|abc|"
+    );
+    // Huge widths and precisions, from corrupt arguments or format strings, are clamped:
+    let s = printf(
+        "%*d|%.*s|%99999999999999999999d",
+        &args(&[
+            &i32::MIN.to_le_bytes(),
+            &1i32.to_le_bytes(),
+            &i32::MAX.to_le_bytes(),
+            b"abc\0",
+            &2i32.to_le_bytes(),
+        ]),
+        8,
+    )
+    .unwrap();
+    assert_eq!(s.len(), 4096 + "|abc|".len() + 4096);
+    assert!(s.starts_with("1 ") && s.ends_with(" 2"));
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn catalog() {
+    let mut catalog = Catalog::new();
+    assert!(catalog.is_empty());
+    catalog.add_xml(COLLATERAL).unwrap();
+
+    let args = [&2u32.to_le_bytes()[..], b"ok\0", &0x10u32.to_le_bytes()].concat();
+    let m = catalog_message(Origin::Guid(GUID), 1, 1, args);
+    assert_eq!(catalog.text(&m).unwrap(), "stage 2: ok & 0x10");
+
+    // The masked byte of the GUID may differ:
+    let mut guid = GUID;
+    guid[8] = 0x01;
+    let m = catalog_message(
+        Origin::Guid(guid),
+        2,
+        0x1_0000_0000,
+        9u32.to_le_bytes().to_vec(),
+    );
+    assert_eq!(catalog.text(&m).unwrap(), "wide <9>");
+
+    let module = Origin::ModuleUnit { module: 3, unit: 1 };
+    let m = catalog_message(module, 1, 1, (-5i32).to_le_bytes().to_vec());
+    assert_eq!(catalog.text(&m).unwrap(), "modem\t-5");
+    assert_eq!(catalog.file(&module, 2), Some("src/modem.c"));
+    assert_eq!(catalog.file(&Origin::Guid(GUID), 2), None);
+
+    // IDs from other origins, and arguments too short for their format:
+    guid[0] ^= 1;
+    let m = catalog_message(Origin::Guid(guid), 1, 1, Vec::new());
+    assert_eq!(
+        catalog.text(&m),
+        Err(Error::UnknownId {
+            id: 1,
+            origin: Origin::Guid(guid)
+        })
+    );
+    assert!(catalog.text(&m).unwrap_err().to_string().contains("0x1"));
+    let m = catalog_message(module, 1, 1, vec![1]);
+    assert!(matches!(catalog.text(&m), Err(Error::Arguments { .. })));
+
+    // Printf strings need no catalog:
+    let m = Message {
+        payload: Payload::String {
+            subtype: 11,
+            text: "%u%%\n".to_string(),
+            args: 50u32.to_le_bytes().to_vec(),
+        },
+        ..m
+    };
+    assert_eq!(Catalog::new().text(&m).unwrap(), "50%");
+
+    assert!(catalog.add_xml("<syst:Collateral>").is_err());
+    assert!(catalog.add_xml("<Other/>").is_err());
+    assert!(catalog
+        .add_xml("<Collateral><Client><Guids><Guid ID=\"{1234}\"/></Guids></Client></Collateral>")
+        .is_err());
+    // IDs too big for the messages that would use them:
+    for client in [
+        "<Modules><Module ID=\"300\"/></Modules>",
+        "<Catalog32><Format ID=\"0x100000000\">x</Format></Catalog32>",
+        "<SourceFiles><File ID=\"0x100000000\">a.c</File></SourceFiles>",
+    ] {
+        let xml = format!("<Collateral><Client>{}</Client></Collateral>", client);
+        let e = Catalog::new().add_xml(&xml).unwrap_err();
+        assert!(e.contains("out of range"), "{}", e);
+    }
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn pointer_sizes() {
+    let mut catalog = Catalog::new();
+    catalog
+        .add_xml(
+            r#"<Collateral><Client>
+                 <Catalog32><Format ID="1">%ld %p %zu</Format></Catalog32>
+                 <Catalog64><Format ID="1">wide %ld</Format></Catalog64>
+               </Client></Collateral>"#,
+        )
+        .unwrap();
+    let origin = Origin::ModuleUnit { module: 1, unit: 0 };
+    let p32 = [
+        (-1i32).to_le_bytes(),
+        0x1000u32.to_le_bytes(),
+        7u32.to_le_bytes(),
+    ]
+    .concat();
+    let p64 = [
+        (-1i64).to_le_bytes(),
+        0x1000u64.to_le_bytes(),
+        7u64.to_le_bytes(),
+    ]
+    .concat();
+
+    // CATID32_P32 and CATID32_P64:
+    let m = catalog_message(origin, 1, 1, p32.clone());
+    assert_eq!(catalog.text(&m).unwrap(), "-1 0x1000 7");
+    let m = catalog_message(origin, 5, 1, p64.clone());
+    assert_eq!(catalog.text(&m).unwrap(), "-1 0x1000 7");
+    // CATID64_P32 and CATID64_P64:
+    let m = catalog_message(origin, 2, 1, p32[..4].to_vec());
+    assert_eq!(catalog.text(&m).unwrap(), "wide -1");
+    let m = catalog_message(origin, 6, 1, p64[..8].to_vec());
+    assert_eq!(catalog.text(&m).unwrap(), "wide -1");
+
+    // Printf strings, P32 and P64:
+    let printf = |subtype: u8, args: &[u8]| Message {
+        header: Header::from(2 | (subtype as u32) << 24),
+        payload: Payload::String {
+            subtype,
+            text: "%lu".to_string(),
+            args: args.to_vec(),
+        },
+        ..catalog_message(origin, subtype, 0, Vec::new())
+    };
+    assert_eq!(catalog.text(&printf(11, &p32[4..8])).unwrap(), "4096");
+    assert_eq!(catalog.text(&printf(12, &p64[8..16])).unwrap(), "4096");
+
+    // Writers whose subtypes are wrong can be overridden:
+    let catalog = Catalog::new().pointer_size(8);
+    assert_eq!(catalog.text(&printf(11, &p64[8..16])).unwrap(), "4096");
+}
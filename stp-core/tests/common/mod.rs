@@ -0,0 +1,127 @@
+//! Fixtures shared by the integration tests.  Each test file uses only some of them.
+#![allow(dead_code)]
+
+use std::convert::TryInto;
+use stp_core::message::Message;
+use stp_core::stp::OpCode;
+
+/// A message without a timestamp.
+pub fn message(master: u16, channel: u16, opcode: OpCode, data: Option<u64>) -> Message {
+    Message {
+        master,
+        channel,
+        opcode,
+        data,
+        timestamp: None,
+        timestamped: false,
+        start: 0,
+        span: 0,
+    }
+}
+
+/// Little-endian integers at `pos` in `b`:
+pub fn u16_at(b: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(b[pos..pos + 2].try_into().unwrap())
+}
+
+pub fn u32_at(b: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(b[pos..pos + 4].try_into().unwrap())
+}
+
+pub fn u64_at(b: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(b[pos..pos + 8].try_into().unwrap())
+}
+
+/// A section of an ELF file built by `elf`: name, type, flags, address and contents.
+pub type Section<'a> = (&'a str, u32, u64, u64, &'a [u8]);
+
+/// An ELF64 x86-64 executable with functions (name, address, size) in a `.text` section at
+/// 0x1000, then the given sections.
+pub fn elf(is_le: bool, functions: &[(&str, u64, u64)], sections: &[Section]) -> Vec<u8> {
+    let n = |data: &mut Vec<u8>, value: u64, size: usize| match is_le {
+        true => data.extend_from_slice(&value.to_le_bytes()[..size]),
+        false => data.extend_from_slice(&value.to_be_bytes()[8 - size..]),
+    };
+
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; 24];
+    for (name, address, size) in functions {
+        n(&mut symtab, strtab.len() as u64, 4);
+        symtab.extend_from_slice(&[0x12, 0]); // A global function,
+        n(&mut symtab, 1, 2); // in .text.
+        n(&mut symtab, *address, 8);
+        n(&mut symtab, *size, 8);
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+
+    // The sections after the null one, each with its link, info, alignment and entry size:
+    let mut headers: Vec<(Section, [u64; 4])> = vec![
+        ((".text", 8, 6, 0x1000, &[]), [0, 0, 16, 0]),
+        ((".symtab", 2, 0, 0, &symtab), [3, 1, 8, 24]),
+        ((".strtab", 3, 0, 0, &strtab), [0, 0, 1, 0]),
+    ];
+    for section in sections {
+        headers.push((*section, [0, 0, 1, 0]));
+    }
+    let mut shstrtab = vec![0u8];
+    let mut names = Vec::new();
+    for name in headers.iter().map(|(s, _)| s.0).chain(Some(".shstrtab")) {
+        names.push(shstrtab.len() as u64);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+    headers.push(((".shstrtab", 3, 0, 0, &shstrtab), [0, 0, 1, 0]));
+
+    let mut data = vec![0x7f, b'E', b'L', b'F', 2, if is_le { 1 } else { 2 }, 1];
+    data.resize(16, 0);
+    let mut offsets = Vec::new();
+    let mut offset = 64;
+    for (section, _) in &headers {
+        offsets.push(offset as u64);
+        offset += section.4.len();
+    }
+    let shoff = (offset + 7) & !7;
+    for (value, size) in [
+        (2, 2),  // e_type: an executable.
+        (62, 2), // e_machine: x86-64.
+        (1, 4),
+        (0, 8),
+        (0, 8),
+        (shoff as u64, 8),
+        (0, 4),
+        (64, 2),
+        (56, 2),
+        (0, 2),
+        (64, 2),
+        (headers.len() as u64 + 1, 2), // Sections,
+        (headers.len() as u64, 2),     // and the one holding their names.
+    ] {
+        n(&mut data, value, size);
+    }
+    for (section, _) in &headers {
+        data.extend_from_slice(section.4);
+    }
+    data.resize(shoff + 64, 0); // Ending with the null section.
+
+    for (i, ((_, kind, flags, address, contents), [link, info, align, entsize])) in
+        headers.iter().enumerate()
+    {
+        // .text takes no space in the file, but covers 0x1000 bytes of addresses:
+        let (offset, size) = match kind {
+            8 => (0, 0x1000),
+            _ => (offsets[i], contents.len() as u64),
+        };
+        n(&mut data, names[i], 4);
+        n(&mut data, *kind as u64, 4);
+        n(&mut data, *flags, 8);
+        n(&mut data, *address, 8);
+        n(&mut data, offset, 8);
+        n(&mut data, size, 8);
+        n(&mut data, *link, 4);
+        n(&mut data, *info, 4);
+        n(&mut data, *align, 8);
+        n(&mut data, *entsize, 8);
+    }
+    data
+}
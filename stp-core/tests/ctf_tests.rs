@@ -0,0 +1,222 @@
+mod common;
+
+use common::{u16_at, u32_at, u64_at};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::process::Command;
+use std::rc::Rc;
+use stp_core::asm::assemble;
+use stp_core::ctf::{CtfWriter, StreamPer};
+use stp_core::stp_decoder::StpDecoder;
+
+type Files = Rc<RefCell<BTreeMap<String, Vec<u8>>>>;
+
+// A file of an in-memory trace:
+struct File(Files, String);
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut files = self.0.borrow_mut();
+        files.get_mut(&self.1).unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const PROGRAM: &str = "\
+async
+version v2.2 nat le
+freq 1000000
+m16 2
+c8 3
+d32ts 0x11 ts=nat:2:0x10
+d8 0x22
+flag_ts ts=nat:2:0x30
+m16 5
+user 0x123 len=3
+d16ts 0xbeef ts=nat:2:0x40
+";
+
+fn write_trace(program: &str, per: StreamPer) -> BTreeMap<String, Vec<u8>> {
+    let files = Files::default();
+    let open_files = files.clone();
+    let mut writer = CtfWriter::new(per, move |name| {
+        open_files.borrow_mut().insert(name.to_string(), Vec::new());
+        Ok(File(open_files.clone(), name.to_string()))
+    });
+
+    let mut results = Vec::new();
+    let mut decoder = StpDecoder::new();
+    decoder.decode_nibbles(&assemble(program).unwrap(), |r| results.push(r));
+    for r in results {
+        match r {
+            Ok(p) => writer.packet(Some(0x10), &p).unwrap(),
+            Err(e) => writer.error(Some(0x10), &e).unwrap(),
+        }
+    }
+    writer.finish().unwrap();
+    files.replace(BTreeMap::new())
+}
+
+// The event class IDs and timestamps of a stream with a single packet:
+fn events(b: &[u8], sizes: &BTreeMap<u16, usize>) -> Vec<(u16, u64)> {
+    assert_eq!(u32_at(b, 0), 0xC1FC_1FC1);
+    assert_eq!(u64_at(b, 24), b.len() as u64 * 8);
+    assert_eq!(u64_at(b, 32), b.len() as u64 * 8);
+    let mut events = Vec::new();
+    let mut pos = 46;
+    while pos < b.len() {
+        let (id, time) = (u16_at(b, pos), u64_at(b, pos + 2));
+        events.push((id, time));
+        pos += 10;
+        if id <= 5 {
+            pos = b[pos..].iter().position(|c| *c == 0).unwrap() + pos + 1;
+        }
+        pos += sizes[&id];
+    }
+    events
+}
+
+fn sizes() -> BTreeMap<u16, usize> {
+    vec![
+        (0, 5),
+        (1, 5),
+        (2, 6),
+        (3, 8),
+        (4, 12),
+        (5, 4),
+        (6, 9),
+        (7, 8),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[test]
+fn stream_per_master() {
+    let files = write_trace(PROGRAM, StreamPer::Master);
+    let names: Vec<&str> = files.keys().map(|k| k.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "metadata",
+            "stream_0x10_m0",
+            "stream_0x10_m2",
+            "stream_0x10_m5"
+        ]
+    );
+
+    let m0 = &files["stream_0x10_m0"];
+    assert_eq!(events(m0, &sizes()), vec![(7, 0)]);
+    assert_eq!(u64_at(m0, 46 + 10), 1_000_000);
+
+    let m2 = &files["stream_0x10_m2"];
+    assert_eq!(u16_at(m2, 40), 0x10); // TWP ID
+    assert_eq!(u32_at(m2, 42), 2); // Master
+    assert_eq!(u64_at(m2, 8), 0x10); // Timestamp begin
+    assert_eq!(u64_at(m2, 16), 0x30); // Timestamp end
+    assert_eq!(events(m2, &sizes()), vec![(3, 0x10), (1, 0x10), (5, 0x30)]);
+    // The first event's fields:
+    assert_eq!(&m2[56..62], b"D32TS\0");
+    assert_eq!(u16_at(m2, 62), 2);
+    assert_eq!(u16_at(m2, 64), 3);
+    assert_eq!(u32_at(m2, 66), 0x11);
+
+    let m5 = &files["stream_0x10_m5"];
+    assert_eq!(events(m5, &sizes()), vec![(6, 0x30), (2, 0x40)]);
+}
+
+#[test]
+fn stream_per_twp_id() {
+    let files = write_trace(PROGRAM, StreamPer::TwpId);
+    let names: Vec<&str> = files.keys().map(|k| k.as_str()).collect();
+    assert_eq!(names, vec!["metadata", "stream_0x10"]);
+    let stream = &files["stream_0x10"];
+    assert_eq!(u32_at(stream, 42), 0xffff_ffff); // No master
+    assert_eq!(
+        events(stream, &sizes()),
+        vec![
+            (7, 0),
+            (3, 0x10),
+            (1, 0x10),
+            (5, 0x30),
+            (6, 0x30),
+            (2, 0x40)
+        ]
+    );
+}
+
+#[test]
+fn errors() {
+    // A decoding error, then a master error packet:
+    let program = "async\nversion v2.2 nat le\nm8 1\nd8ts 0x1 ts=nat:2:0x20\nraw 0 0 f\nasync\nversion v2.2 nat le\nmerr 0x05\n";
+    let files = write_trace(program, StreamPer::TwpId);
+    let stream = &files["stream_0x10"];
+    let mut pos = 46 + 10 + b"D8TS\0".len() + 5;
+    let mut messages = Vec::new();
+    while pos < stream.len() {
+        assert_eq!(u16_at(stream, pos), 8);
+        assert_eq!(u64_at(stream, pos + 2), 0x20);
+        pos += 10;
+        let end = stream[pos..].iter().position(|c| *c == 0).unwrap() + pos;
+        messages.push(String::from_utf8(stream[pos..end].to_vec()).unwrap());
+        pos = end + 1 + 8;
+    }
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1], "MERR 0x05");
+}
+
+#[test]
+fn metadata() {
+    let files = write_trace(PROGRAM, StreamPer::Master);
+    let metadata = String::from_utf8(files["metadata"].clone()).unwrap();
+    assert!(metadata.starts_with("/* CTF 1.8 */\n"));
+    assert!(metadata.contains("\tfreq = 1000000;\n"));
+    for name in &[
+        "data4",
+        "data8",
+        "data16",
+        "data32",
+        "data64",
+        "flag",
+        "user",
+        "frequency",
+        "error",
+    ] {
+        assert!(metadata.contains(&format!("\tname = \"{}\";\n", name)));
+    }
+
+    // Without a FREQ packet, the clock counts nanoseconds:
+    let files = write_trace("async\nversion v2.2 nat le\nd8 0x1\n", StreamPer::Master);
+    let metadata = String::from_utf8(files["metadata"].clone()).unwrap();
+    assert!(metadata.contains("\tfreq = 1000000000;\n"));
+}
+
+// Babeltrace reads the trace back.  It needs babeltrace2 on the PATH, so run it where that is
+// installed with `cargo test --test ctf_tests -- --ignored`:
+#[test]
+#[ignore]
+fn babeltrace() {
+    let dir = std::env::temp_dir().join(format!("stp-ctf-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, contents) in write_trace(PROGRAM, StreamPer::Master) {
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+    let output = Command::new("babeltrace2").arg(&dir).output();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let output = output.expect("babeltrace2");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    for name in &["frequency", "data32", "data8", "flag", "user", "data16"] {
+        let found = stdout
+            .lines()
+            .filter(|l| l.contains(&format!(" {}: ", name)));
+        assert_eq!(found.count(), 1, "{}\n{}", name, stdout);
+    }
+}
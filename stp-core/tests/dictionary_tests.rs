@@ -0,0 +1,104 @@
+#![cfg(feature = "elf")]
+
+mod common;
+
+use stp_core::dictionary::{Dictionary, Error};
+
+// NUL-terminated strings, and where each one starts:
+fn strings(strings: &[&str]) -> (Vec<u8>, Vec<u64>) {
+    let mut data = vec![0u8];
+    let mut offsets = Vec::new();
+    for s in strings {
+        offsets.push(data.len() as u64);
+        data.extend_from_slice(s.as_bytes());
+        data.push(0);
+    }
+    (data, offsets)
+}
+
+// An executable with a `.stp_log` section of format strings, a `.rodata` section at 0x2000 and
+// functions in `.text` at 0x1000:
+fn elf(is_le: bool, formats: &[u8], rodata: &[u8], functions: &[(&str, u64, u64)]) -> Vec<u8> {
+    let sections = [
+        (".stp_log", 1, 0, 0, formats),
+        (".rodata", 1, 2, 0x2000, rodata),
+    ];
+    common::elf(is_le, functions, &sections)
+}
+
+// A log: the format string's address, then its arguments.
+fn log(address: u64, args: &[&[u8]]) -> Vec<u8> {
+    let mut data = (address as u32).to_le_bytes().to_vec();
+    for a in args {
+        data.extend_from_slice(a);
+    }
+    data
+}
+
+#[test]
+fn logs() {
+    let (formats, f) = strings(&[
+        "boot %s v%d.%02d\n",
+        "temp %.1f C, %ld samples",
+        "fault at %p (%p), code %#x",
+        "%s",
+    ]);
+    let (rodata, s) = strings(&["modem"]);
+    let data = elf(true, &formats, &rodata, &[("handler", 0x1040, 0x20)]);
+    let dictionary = Dictionary::from_elf(&data, ".stp_log").unwrap();
+    assert_eq!(dictionary.format(f[3]).as_deref(), Some("%s"));
+
+    let string = (0x2000 + s[0]).to_le_bytes();
+    assert_eq!(
+        dictionary.decode(&log(f[0], &[&string, &[1, 0, 0, 0], &[5, 0, 0, 0]])),
+        Ok("boot modem v1.05".to_string())
+    );
+    assert_eq!(
+        dictionary.decode(&log(
+            f[1],
+            &[&36.55f64.to_le_bytes(), &(-2i64).to_le_bytes()]
+        )),
+        Ok("temp 36.5 C, -2 samples".to_string())
+    );
+    assert_eq!(
+        dictionary.decode(&log(
+            f[2],
+            &[
+                &0x1044u64.to_le_bytes(),
+                &0x3000u64.to_le_bytes(),
+                &[0x0e, 0, 0, 0]
+            ]
+        )),
+        Ok("fault at 0x1044 <handler+0x4> (0x3000), code 0xe".to_string())
+    );
+    // Strings outside the file are shown as their address:
+    assert_eq!(
+        dictionary.decode(&log(f[3], &[&0x4000u64.to_le_bytes()])),
+        Ok("<0x4000>".to_string())
+    );
+
+    assert_eq!(dictionary.decode(&[1, 0]), Err(Error::Short { len: 2 }));
+    assert_eq!(
+        dictionary.decode(&log(0x100, &[])),
+        Err(Error::UnknownAddress { address: 0x100 })
+    );
+    assert_eq!(
+        dictionary.decode(&log(f[1], &[&[0; 4]])),
+        Err(Error::Arguments {
+            format: "temp %.1f C, %ld samples".to_string()
+        })
+    );
+
+    assert!(Dictionary::from_elf(&data, ".defmt").is_err());
+    assert!(Dictionary::from_elf(b"not an ELF file", ".stp_log").is_err());
+}
+
+#[test]
+fn big_endian() {
+    let (formats, f) = strings(&["%hd items, %c"]);
+    let data = elf(false, &formats, &[], &[]);
+    let dictionary = Dictionary::from_elf(&data, ".stp_log").unwrap();
+    let mut log = (f[0] as u32).to_be_bytes().to_vec();
+    log.extend_from_slice(&[0, 0, 0xff, 0xfe, 0, 0, 0, b'x']);
+    assert_eq!(dictionary.decode(&log), Ok("-2 items, x".to_string()));
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use common::message;
+use stp_core::export::{ChromeJson, Perfetto, TraceExporter};
+use stp_core::message::Message;
+use stp_core::stp::OpCode;
+
+fn messages() -> Vec<(Message, f64)> {
+    vec![
+        (message(2, 3, OpCode::D32MTS, Some(0x11)), 16.0),
+        (message(2, 3, OpCode::D32, Some(0x22)), 16.0),
+        (message(2, 3, OpCode::FLAG_TS, None), 48.0),
+        (message(2, 4, OpCode::FLAG_TS, None), 80.0),
+        (message(2, 4, OpCode::D8MTS, Some(0x01)), 90.5),
+    ]
+}
+
+#[test]
+fn chrome_json() {
+    let mut exporter = TraceExporter::new(ChromeJson::new(Vec::new()));
+    for (m, time) in messages() {
+        exporter.message(Some(0x10), &m, time).unwrap();
+    }
+    let out = exporter.finish().unwrap().into_inner();
+    let lines: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+    let pid = (0x11 << 16 | 2) + 1;
+    assert_eq!(
+        lines,
+        vec![
+            "[".to_string(),
+            format!("{{\"ph\":\"M\",\"name\":\"process_name\",\"pid\":{},\"args\":{{\"name\":\"Master 2 (stream 0x10)\"}}}},", pid),
+            format!("{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":{},\"tid\":268435456,\"args\":{{\"name\":\"Channel 3\"}}}},", pid),
+            format!("{{\"ph\":\"B\",\"name\":\"D32MTS 0x00000011\",\"pid\":{},\"tid\":268435456,\"ts\":16.000,\"args\":{{\"payload\":\"0x00000011\",\"value\":17}}}},", pid),
+            format!("{{\"ph\":\"i\",\"s\":\"t\",\"name\":\"D32\",\"pid\":{},\"tid\":268435456,\"ts\":16.000,\"args\":{{\"payload\":\"0x00000022\",\"value\":34}}}},", pid),
+            format!("{{\"ph\":\"E\",\"name\":\"FLAG_TS\",\"pid\":{},\"tid\":268435456,\"ts\":48.000,\"args\":{{}}}},", pid),
+            format!("{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":{},\"tid\":268435457,\"args\":{{\"name\":\"Channel 4\"}}}},", pid),
+            format!("{{\"ph\":\"i\",\"s\":\"t\",\"name\":\"FLAG_TS\",\"pid\":{},\"tid\":268435457,\"ts\":80.000,\"args\":{{}}}},", pid),
+            format!("{{\"ph\":\"B\",\"name\":\"D8MTS 0x01\",\"pid\":{},\"tid\":268435457,\"ts\":90.500,\"args\":{{\"payload\":\"0x01\",\"value\":1}}}},", pid),
+            format!("{{\"ph\":\"E\",\"name\":\"\",\"pid\":{},\"tid\":268435457,\"ts\":90.500,\"args\":{{}}}}", pid),
+            "]".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn chrome_json_empty() {
+    let exporter = TraceExporter::new(ChromeJson::new(Vec::new()));
+    let out = exporter.finish().unwrap().into_inner();
+    assert_eq!(std::str::from_utf8(&out).unwrap(), "[\n\n]\n");
+}
+
+// A decoded protobuf field: a varint or a length-delimited value.
+#[derive(Debug, PartialEq)]
+enum Field {
+    Uint(u64),
+    Bytes(Vec<u8>),
+}
+
+fn varint(buf: &[u8], pos: &mut usize) -> u64 {
+    let mut v = 0;
+    let mut shift = 0;
+    loop {
+        let b = buf[*pos];
+        *pos += 1;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return v;
+        }
+        shift += 7;
+    }
+}
+
+fn fields(buf: &[u8]) -> Vec<(u64, Field)> {
+    let mut pos = 0;
+    let mut fields = Vec::new();
+    while pos < buf.len() {
+        let key = varint(buf, &mut pos);
+        let field = match key & 7 {
+            0 => Field::Uint(varint(buf, &mut pos)),
+            2 => {
+                let len = varint(buf, &mut pos) as usize;
+                pos += len;
+                Field::Bytes(buf[pos - len..pos].to_vec())
+            }
+            t => panic!("unexpected wire type {}", t),
+        };
+        fields.push((key >> 3, field));
+    }
+    fields
+}
+
+fn field(fields: &[(u64, Field)], number: u64) -> &Field {
+    &fields.iter().find(|(n, _)| *n == number).unwrap().1
+}
+
+fn bytes(f: &Field) -> &[u8] {
+    match f {
+        Field::Bytes(b) => b,
+        f => panic!("expected bytes, found {:?}", f),
+    }
+}
+
+#[test]
+fn perfetto() {
+    let mut exporter = TraceExporter::new(Perfetto::new(Vec::new()));
+    for (m, time) in messages() {
+        exporter.message(None, &m, time).unwrap();
+    }
+    let out = exporter.finish().unwrap().into_inner();
+
+    let packets: Vec<Vec<(u64, Field)>> = fields(&out)
+        .into_iter()
+        .map(|(n, f)| {
+            assert_eq!(n, 1);
+            fields(bytes(&f))
+        })
+        .collect();
+    // Process, thread, 3 events, thread, 3 events:
+    assert_eq!(packets.len(), 9);
+    assert!(packets.iter().all(|p| *field(p, 10) == Field::Uint(1)));
+    assert_eq!(*field(&packets[0], 13), Field::Uint(1));
+
+    let process = fields(bytes(field(&packets[0], 60)));
+    assert_eq!(*field(&process, 1), Field::Uint(3));
+    let descriptor = fields(bytes(field(&process, 3)));
+    assert_eq!(*field(&descriptor, 1), Field::Uint(3));
+    assert_eq!(bytes(field(&descriptor, 6)), b"Master 2");
+
+    let thread = fields(bytes(field(&packets[1], 60)));
+    assert_eq!(*field(&thread, 1), Field::Uint(0x1000_0000));
+    let descriptor = fields(bytes(field(&thread, 4)));
+    assert_eq!(*field(&descriptor, 1), Field::Uint(3));
+    assert_eq!(*field(&descriptor, 2), Field::Uint(0x1000_0000));
+    assert_eq!(bytes(field(&descriptor, 5)), b"Channel 3");
+
+    // The slice begin, with its payload annotations:
+    assert_eq!(*field(&packets[2], 8), Field::Uint(16_000));
+    let event = fields(bytes(field(&packets[2], 11)));
+    assert_eq!(*field(&event, 9), Field::Uint(1));
+    assert_eq!(*field(&event, 11), Field::Uint(0x1000_0000));
+    assert_eq!(bytes(field(&event, 23)), b"D32MTS 0x00000011");
+    let annotations: Vec<Vec<(u64, Field)>> = event
+        .iter()
+        .filter(|(n, _)| *n == 4)
+        .map(|(_, f)| fields(bytes(f)))
+        .collect();
+    assert_eq!(annotations.len(), 2);
+    assert_eq!(bytes(field(&annotations[0], 10)), b"payload");
+    assert_eq!(bytes(field(&annotations[0], 6)), b"0x00000011");
+    assert_eq!(bytes(field(&annotations[1], 10)), b"value");
+    assert_eq!(*field(&annotations[1], 3), Field::Uint(0x11));
+
+    // The instant data write, then the slice end at the FLAG:
+    let event = fields(bytes(field(&packets[3], 11)));
+    assert_eq!(*field(&event, 9), Field::Uint(3));
+    let event = fields(bytes(field(&packets[4], 11)));
+    assert_eq!(*field(&event, 9), Field::Uint(2));
+    assert_eq!(*field(&packets[4], 8), Field::Uint(48_000));
+
+    // The slice left open is ended by finish():
+    let event = fields(bytes(field(&packets[8], 11)));
+    assert_eq!(*field(&event, 9), Field::Uint(2));
+    assert_eq!(*field(&event, 11), Field::Uint(0x1000_0001));
+    assert_eq!(*field(&packets[8], 8), Field::Uint(90_500));
+}
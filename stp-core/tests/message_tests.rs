@@ -0,0 +1,189 @@
+use stp_core::message::{Message, MessageTracker};
+use stp_core::stp::{self, OpCode, StpVersion, Timestamp, TimestampType};
+use stp_core::stp_decoder::Packet;
+
+fn packet(packet: stp::Packet) -> Packet {
+    Packet {
+        packet,
+        start: 0,
+        span: 1,
+    }
+}
+
+fn version(ts_type: TimestampType) -> Packet {
+    packet(stp::Packet::Version {
+        version: StpVersion::STPv2_2,
+        ts_type,
+        is_le: false,
+    })
+}
+
+fn data(opcode: OpCode, data: u64, timestamp: Option<Timestamp>) -> Packet {
+    packet(stp::Packet::Data {
+        opcode,
+        data,
+        timestamp,
+    })
+}
+
+fn master(opcode: OpCode, master: u16) -> Packet {
+    packet(stp::Packet::Master { opcode, master })
+}
+
+fn channel(opcode: OpCode, channel: u16) -> Packet {
+    packet(stp::Packet::Channel { opcode, channel })
+}
+
+// Feed packets to a tracker and return the (master, channel, timestamp) of each message:
+fn attribute(packets: &[Packet]) -> Vec<(u16, u16, Option<u64>)> {
+    let mut tracker = MessageTracker::new();
+    packets
+        .iter()
+        .filter_map(|p| tracker.process(p))
+        .map(|m| (m.master, m.channel, m.timestamp))
+        .collect()
+}
+
+#[test]
+fn master_channel() {
+    let packets = [
+        packet(stp::Packet::Async),
+        version(TimestampType::STPv2NATDELTA),
+        data(OpCode::D8, 1, None),
+        master(OpCode::M16, 0x1234),
+        channel(OpCode::C16, 0x5678),
+        data(OpCode::D8, 2, None),
+        // C8 only replaces the low byte of the channel:
+        channel(OpCode::C8, 0x9A),
+        data(OpCode::D8, 3, None),
+        // A master packet resets the channel:
+        master(OpCode::M8, 0x56),
+        data(OpCode::D8, 4, None),
+        // So does an ASYNC:
+        packet(stp::Packet::Async),
+        data(OpCode::D8, 5, None),
+    ];
+    assert_eq!(
+        attribute(&packets),
+        vec![
+            (0, 0, None),
+            (0x1234, 0x5678, None),
+            (0x1234, 0x569A, None),
+            (0x1256, 0, None),
+            (0, 0, None),
+        ]
+    );
+}
+
+#[test]
+fn message_fields() {
+    let mut tracker = MessageTracker::new();
+    tracker.process(&master(OpCode::M8, 16));
+    tracker.process(&channel(OpCode::C8, 3));
+    let m = tracker.process(&Packet {
+        packet: stp::Packet::Data {
+            opcode: OpCode::D32M,
+            data: 0xdeadbeef,
+            timestamp: None,
+        },
+        start: 10,
+        span: 10,
+    });
+    assert_eq!(
+        m,
+        Some(Message {
+            master: 16,
+            channel: 3,
+            opcode: OpCode::D32M,
+            data: Some(0xdeadbeef),
+            timestamp: None,
+            timestamped: false,
+            start: 10,
+            span: 10,
+        })
+    );
+
+    let m = tracker.process(&packet(stp::Packet::Flag {
+        timestamp: Some(Timestamp::STPv2NAT {
+            length: 2,
+            value: 0x12,
+        }),
+    }));
+    let m = m.unwrap();
+    assert_eq!(
+        (m.opcode, m.data, m.timestamp),
+        (OpCode::FLAG_TS, None, Some(0x12))
+    );
+    assert!(m.timestamped);
+
+    assert_eq!(tracker.process(&master(OpCode::M8, 1)), None);
+}
+
+#[test]
+fn natdelta_timestamps() {
+    let ts = |value| Some(Timestamp::STPv2NATDELTA { length: 2, value });
+    let packets = [
+        version(TimestampType::STPv2NATDELTA),
+        data(OpCode::D8TS, 0, ts(0x10)),
+        data(OpCode::D8, 0, None),
+        data(OpCode::D8TS, 0, ts(0x08)),
+    ];
+    assert_eq!(
+        attribute(&packets),
+        vec![(0, 0, Some(0x10)), (0, 0, Some(0x10)), (0, 0, Some(0x18))]
+    );
+}
+
+#[test]
+fn nat_timestamps() {
+    let ts = |length, value| Some(Timestamp::STPv2NAT { length, value });
+    let packets = [
+        version(TimestampType::STPv2NAT),
+        data(OpCode::D8TS, 0, ts(16, 0x1234)),
+        // Only the low nibbles are replaced:
+        data(OpCode::D8TS, 0, ts(1, 0x9)),
+        data(OpCode::D8TS, 0, ts(0, 0)),
+    ];
+    assert_eq!(
+        attribute(&packets),
+        vec![
+            (0, 0, Some(0x1234)),
+            (0, 0, Some(0x1239)),
+            (0, 0, Some(0x1239))
+        ]
+    );
+}
+
+#[test]
+fn gray_timestamps() {
+    let ts = |length, value| Some(Timestamp::STPv2GRAY { length, value });
+    let packets = [
+        version(TimestampType::STPv2GRAY),
+        // Gray code 0b1101 is 9, 0b1100 is 8:
+        data(OpCode::D8TS, 0, ts(16, 0b1101)),
+        data(OpCode::D8TS, 0, ts(1, 0b1100)),
+    ];
+    assert_eq!(attribute(&packets), vec![(0, 0, Some(9)), (0, 0, Some(8))]);
+}
+
+#[test]
+fn frequency_and_reset() {
+    let mut tracker = MessageTracker::new();
+    tracker.process(&packet(stp::Packet::Frequency {
+        opcode: OpCode::FREQ,
+        frequency: 1_000_000,
+        timestamp: Some(Timestamp::STPv2NATDELTA {
+            length: 1,
+            value: 5,
+        }),
+    }));
+    tracker.process(&master(OpCode::M8, 7));
+    assert_eq!(tracker.frequency(), Some(1_000_000));
+    assert_eq!(tracker.timestamp(), Some(5));
+
+    tracker.reset();
+    assert_eq!(tracker.frequency(), Some(1_000_000));
+    assert_eq!(tracker.timestamp(), None);
+    let m = tracker.process(&data(OpCode::D4, 1, None)).unwrap();
+    assert_eq!(m.master, 0);
+}
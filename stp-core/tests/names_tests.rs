@@ -0,0 +1,125 @@
+use std::fs;
+use stp_core::names::{Format, Name, Names};
+
+fn names() -> Names {
+    let mut names = Names::new();
+    names.add_master(32..=47, Name::new("modem"));
+    names.add_channel(
+        Some(32..=32),
+        1..=1,
+        Name {
+            name: "irq_enter".to_string(),
+            format: Some(Format::Signed),
+            unit: Some("us".to_string()),
+        },
+    );
+    names.add_channel(None, 1..=3, Name::new("any"));
+    names
+}
+
+#[test]
+fn labels() {
+    let names = names();
+    assert_eq!(names.master_label(40), "modem");
+    assert_eq!(names.master_label(48), "M48");
+    assert_eq!(names.channel_label(32, 1), "irq_enter");
+    assert_eq!(names.channel_label(33, 1), "any");
+    assert_eq!(names.channel_label(33, 4), "C4");
+    assert!(Names::new().is_empty());
+}
+
+#[test]
+fn formats() {
+    let names = names();
+    assert_eq!(names.format_data(32, 1, 0xfffd, 16), "-3 us");
+    assert_eq!(names.format_data(32, 2, 0xfffd, 16), "0xfffd");
+    assert_eq!(names.format_data(1, 1, 0x5, 4), "0x5");
+
+    let mut names = Names::new();
+    for (channel, format) in [(0, Format::Ascii), (1, Format::Float32), (2, Format::Hex)] {
+        let name = Name {
+            format: Some(format),
+            ..Name::new("c")
+        };
+        names.add_channel(None, channel..=channel, name);
+    }
+    assert_eq!(names.format_data(0, 0, 0x0a_6948, 32), "\"Hi..\"");
+    assert_eq!(names.format_data(0, 0, 0x7, 4), "0x7");
+    assert_eq!(names.format_data(0, 1, 0x3fc0_0000, 32), "1.5");
+    assert_eq!(names.format_data(0, 1, 0x4004_0000_0000_0000, 64), "2.5");
+    assert_eq!(names.format_data(0, 2, 0x12, 16), "0x0012");
+    assert_eq!("float32".parse(), Ok(Format::Float32));
+    assert!("octal".parse::<Format>().is_err());
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn toml() {
+    let names = Names::from_toml(
+        r#"
+[[master]]
+id = "0x20-0x2f"
+name = "modem"
+format = "signed"
+
+[[channel]]
+master = 32
+id = 1
+name = "irq_enter"
+unit = "us"
+
+[[channel]]
+id = "2-3"
+name = "any"
+"#,
+    )
+    .unwrap();
+    assert_eq!(names.master_label(0x2f), "modem");
+    assert_eq!(names.channel_label(32, 1), "irq_enter");
+    assert_eq!(names.channel_label(7, 3), "any");
+    // The channel's unit, with its master's format:
+    assert_eq!(names.format_data(32, 1, 0xff, 8), "-1 us");
+
+    assert!(Names::from_toml("[[master]]\nid = 1\nname = \"x\"\nformat = \"octal\"\n").is_err());
+    assert!(Names::from_toml("[[master]]\nid = \"1-x\"\nname = \"x\"\n").is_err());
+    assert!(Names::from_toml("[[master]]\nid = 1\nnam = \"x\"\n").is_err());
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn json() {
+    let names = Names::from_json(
+        r#"{"master": [{"id": 5, "name": "gpu"}],
+            "channel": [{"master": "5", "id": 0, "name": "frame", "format": "ascii"}]}"#,
+    )
+    .unwrap();
+    assert_eq!(names.master_label(5), "gpu");
+    assert_eq!(names.channel_label(5, 0), "frame");
+    assert_eq!(names.format_data(5, 0, 0x4b4f, 16), "\"OK\"");
+}
+
+#[test]
+fn policy() {
+    let dir = std::env::temp_dir().join(format!("stp-policy-{}", std::process::id()));
+    let node = |path: &str, masters: &str, channels: &str| {
+        let path = dir.join(path);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("masters"), masters).unwrap();
+        fs::write(path.join("channels"), channels).unwrap();
+    };
+    node("default", "32 47\n", "0 63\n");
+    node("default/irq", "32 32\n", "5 6\n");
+    node("modem", "48 48\n", "0 0\n");
+    fs::create_dir_all(dir.join("empty")).unwrap();
+
+    let names = Names::from_policy(&dir).unwrap();
+    assert_eq!(names.channel_label(32, 5), "default/irq");
+    assert_eq!(names.channel_label(33, 5), "default");
+    assert_eq!(names.channel_label(48, 0), "modem");
+    assert_eq!(names.channel_label(48, 1), "C1");
+    assert_eq!(names.master_label(32), "M32");
+
+    fs::write(dir.join("modem/channels"), "x\n").unwrap();
+    assert!(Names::from_policy(&dir).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
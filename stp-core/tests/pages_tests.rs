@@ -0,0 +1,141 @@
+#![cfg(feature = "twp")]
+
+use std::io::Cursor;
+use stp_core::asm::assemble_bytes;
+use stp_core::pages::{Entry, Pages, Pos, Row};
+use twp::builders::frame_stream;
+use twp::parsers::FrameDecoder;
+
+// Two TWP streams of timestamped writes to a few masters and channels:
+fn capture() -> Vec<u8> {
+    let mut capture = Vec::new();
+    for (id, masters) in [(0x10, 1..4), (0x20, 4..7)] {
+        let mut program = "async\nversion v2.2 nat le\n".to_string();
+        for (i, master) in masters.cycle().take(60).enumerate() {
+            program += &format!(
+                "m8 {}\nc8 {}\nd32ts {:#x} ts=nat:4:{:#x}\n",
+                master,
+                i % 5,
+                i * 0x1111,
+                i * 0x10
+            );
+        }
+        let stp = assemble_bytes(&program).unwrap();
+        capture.extend(frame_stream(&stp, id, 4).unwrap());
+    }
+    capture
+}
+
+// Pages small enough that frames and packets straddle them, and the last one is partial:
+const PAGE_SIZE: usize = 50;
+
+fn pages(capture: &[u8], page_size: usize) -> Pages<Cursor<Vec<u8>>> {
+    Pages::new(
+        Cursor::new(capture.to_vec()),
+        FrameDecoder::new(false, None),
+    )
+    .unwrap()
+    .page_size(page_size)
+}
+
+// The rows of every page, decoded in order:
+fn rows(pages: &mut Pages<Cursor<Vec<u8>>>) -> Vec<Vec<String>> {
+    (0..pages.count())
+        .map(|n| {
+            let page = pages.page(n).unwrap();
+            page.rows.iter().map(|r| format!("{:?}", r)).collect()
+        })
+        .collect()
+}
+
+#[test]
+fn linear() {
+    let capture = capture();
+    assert_ne!(capture.len() % PAGE_SIZE, 0);
+
+    // The pages together hold what decoding the whole file at once does:
+    let whole = rows(&mut pages(&capture, capture.len())).concat();
+    let mut small = pages(&capture, PAGE_SIZE);
+    assert_eq!(small.count(), capture.len().div_ceil(PAGE_SIZE));
+    assert_eq!(rows(&mut small).concat(), whole);
+    assert!(whole.len() > 240);
+    assert!(whole.iter().all(|r| !r.contains("Error")));
+
+    let bytes: usize = (0..small.count())
+        .map(|n| small.page(n).unwrap().bytes.len())
+        .sum();
+    let data_bytes = pages(&capture, capture.len()).page(0).unwrap().bytes.len();
+    assert_eq!(bytes, data_bytes);
+}
+
+#[test]
+fn jump() {
+    let capture = capture();
+    let expected = rows(&mut pages(&capture, PAGE_SIZE));
+
+    // Jumping straight to a page decodes the ones before it first:
+    let mut pages = pages(&capture, PAGE_SIZE);
+    let n = pages.count() / 2;
+    let page = pages.page(n).unwrap();
+    let page: Vec<String> = page.rows.iter().map(|r| format!("{:?}", r)).collect();
+    assert_eq!(page, expected[n]);
+    assert_eq!(pages.reached(), n + 2); // Through the page after it.
+
+    // So does jumping to the last, partial, page, which also ends any packet left unfinished:
+    let last = pages.count() - 1;
+    let page = pages.page(last).unwrap();
+    let page: Vec<String> = page.rows.iter().map(|r| format!("{:?}", r)).collect();
+    assert_eq!(page, expected[last]);
+    assert_eq!(pages.page_of(capture.len() + 100), last);
+    assert_eq!(pages.page_of(PAGE_SIZE), 1);
+}
+
+#[test]
+fn checkpoints() {
+    let capture = capture();
+    let mut pages = pages(&capture, PAGE_SIZE);
+    let first = pages.page(3).unwrap();
+
+    // Push page 3 out of the cache, then decode it again from its checkpoint:
+    for n in 4..pages.count() {
+        pages.page(n).unwrap();
+    }
+    let again = pages.page(3).unwrap();
+    assert!(!std::rc::Rc::ptr_eq(&first, &again));
+    assert_eq!(*first, *again);
+    assert_eq!(pages.reached(), pages.count());
+}
+
+#[test]
+fn find() {
+    let capture = capture();
+    let mut pages = pages(&capture, PAGE_SIZE);
+    let on = |master: u16| move |r: &Row| matches!(r.channel, Some((m, _)) if m == master);
+
+    // The first and last writes of the second stream, found across pages:
+    let first = pages.find_forward(Pos::START, on(4)).unwrap().unwrap();
+    let last = pages.find_backward(Pos::END, on(6)).unwrap().unwrap();
+    assert!(first.page > 0 && first < last);
+    let page = pages.page(first.page).unwrap();
+    assert_eq!(page.rows[first.row].stream, Some(Some(0x20)));
+
+    // Searches start after, or end before, the rows they are given:
+    let next = pages.find_forward(first.after(), on(4)).unwrap().unwrap();
+    assert!(next > first);
+    assert_eq!(pages.find_backward(first, on(4)).unwrap(), None);
+    assert_eq!(
+        pages
+            .find_forward(Pos::START, |r| matches!(r.entry, Entry::TwpError(_)))
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn empty() {
+    let mut pages = pages(&[], PAGE_SIZE);
+    assert!(pages.is_empty());
+    assert_eq!(pages.count(), 1);
+    assert!(pages.page(0).unwrap().rows.is_empty());
+    assert_eq!(pages.find_backward(Pos::END, |_| true).unwrap(), None);
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::{u16_at, u32_at};
+use std::convert::TryInto;
+use stp_core::message::MessageTracker;
+use stp_core::names::{Format, Name, Names};
+use stp_core::pcapng::{PcapngWriter, Record, RecordType, LINKTYPE};
+use stp_core::stp::{self, OpCode, Timestamp};
+use stp_core::stp_decoder::{Error, ErrorReason, Packet};
+
+fn be_u64_at(b: &[u8], pos: usize) -> u64 {
+    u64::from_be_bytes(b[pos..pos + 8].try_into().unwrap())
+}
+
+// Split a pcapng file into (block type, body) pairs:
+fn blocks(b: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while pos < b.len() {
+        let len = u32_at(b, pos + 4) as usize;
+        assert_eq!(len % 4, 0);
+        assert_eq!(u32_at(b, pos + len - 4) as usize, len);
+        blocks.push((u32_at(b, pos), &b[pos + 8..pos + len - 4]));
+        pos += len;
+    }
+    blocks
+}
+
+fn data_packet() -> Packet {
+    Packet {
+        packet: stp::Packet::Data {
+            opcode: OpCode::D16TS,
+            data: 0xbeef,
+            timestamp: Some(Timestamp::STPv2NAT {
+                length: 2,
+                value: 0x42,
+            }),
+        },
+        start: 45,
+        span: 8,
+    }
+}
+
+#[test]
+fn blocks_and_interfaces() {
+    let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+    let p = data_packet();
+    let bytes = [0xf0, 0xb5, 0xee, 0x1f, 0x24];
+    writer
+        .write(Some(0x10), 1, &Record::packet(&p, &bytes))
+        .unwrap();
+    writer.write(None, 2, &Record::packet(&p, &bytes)).unwrap();
+    writer
+        .write(Some(0x10), 0x1_0000_0003, &Record::packet(&p, &bytes))
+        .unwrap();
+    let out = writer.into_inner();
+
+    let blocks = blocks(&out);
+    let kinds: Vec<u32> = blocks.iter().map(|(k, _)| *k).collect();
+    assert_eq!(kinds, vec![0x0A0D_0D0A, 1, 6, 1, 6, 6]);
+    assert_eq!(u32_at(blocks[0].1, 0), 0x1A2B_3C4D);
+
+    // Each stream has its own interface, with nanosecond timestamps:
+    let idb = blocks[1].1;
+    assert_eq!(u16_at(idb, 0), LINKTYPE);
+    assert_eq!(u16_at(idb, 8), 2); // if_name
+    assert_eq!(&idb[12..12 + u16_at(idb, 10) as usize], b"stp stream 0x10");
+    assert_eq!(&idb[28..33], &[9, 0, 1, 0, 9]); // if_tsresol
+    assert_eq!(&blocks[3].1[12..15], b"stp");
+
+    let interfaces: Vec<u32> = [2, 4, 5].iter().map(|i| u32_at(blocks[*i].1, 0)).collect();
+    assert_eq!(interfaces, vec![0, 1, 0]);
+    let epb = blocks[5].1;
+    assert_eq!((u32_at(epb, 4), u32_at(epb, 8)), (1, 3));
+}
+
+#[test]
+fn records() {
+    let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+    let p = data_packet();
+    let bytes = [0xf0, 0xb5, 0xee, 0x1f, 0x24];
+    let mut tracker = MessageTracker::new();
+    let m = tracker.process(&p).unwrap();
+    writer
+        .write(Some(0x10), 0, &Record::message(&m, &bytes))
+        .unwrap();
+    let e = Error {
+        reason: ErrorReason::InvalidOpCode { value: 0xf06 },
+        start: 80,
+        span: 3,
+    };
+    writer
+        .write(None, 0, &Record::error(&e, &[0x60, 0x0f]))
+        .unwrap();
+    let out = writer.into_inner();
+    let blocks = blocks(&out);
+
+    let epb = blocks[2].1;
+    let len = u32_at(epb, 12) as usize;
+    assert_eq!(u32_at(epb, 16) as usize, len);
+    let r = &epb[20..20 + len];
+    assert_eq!(r[..4], [1, RecordType::Message as u8, 0x1f, 0x10]);
+    assert_eq!(r[4..12], [0x00, 0xf5, 0, 0, 0, 0, 0, 8]);
+    assert_eq!(be_u64_at(r, 12), 45);
+    assert_eq!(be_u64_at(r, 20), 0xbeef);
+    assert_eq!(be_u64_at(r, 28), 0x42);
+    assert_eq!(r[36..38], [0, 5]);
+    assert_eq!(r[38..], bytes);
+
+    let epb = blocks[4].1;
+    let r = &epb[20..20 + u32_at(epb, 12) as usize];
+    assert_eq!(r[..6], [1, RecordType::Error as u8, 0, 0, 0xff, 0xff]);
+    assert_eq!(be_u64_at(r, 12), 80);
+    assert_eq!(r[36..40], [0, 2, 0x60, 0x0f]);
+    assert_eq!(&r[40..], b"invalid opcode: 0xf06");
+}
+
+#[test]
+fn names() {
+    let mut names = Names::new();
+    names.add_channel(
+        None,
+        0..=0,
+        Name {
+            format: Some(Format::Signed),
+            ..Name::new("temp")
+        },
+    );
+    let mut tracker = MessageTracker::new();
+    let m = tracker.process(&data_packet()).unwrap();
+    let record = Record::message(&m, &[]).names(&names);
+    assert_eq!(record.text, "\ttemp\t-16657");
+
+    // Records without a channel are not named:
+    let p = data_packet();
+    assert_eq!(Record::packet(&p, &[]).names(&names).text, "");
+}
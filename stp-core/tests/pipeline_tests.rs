@@ -0,0 +1,284 @@
+#![cfg(feature = "twp")]
+
+use stp_core::pipeline::{Output, Pipeline};
+use stp_core::stats::{Stats, Usage};
+use stp_core::stp::{self, OpCode, StpVersion, TimestampType};
+use stp_core::stp_decoder::{self, Error, ErrorReason::*, Packet};
+use twp::builders::FrameBuilder;
+use twp::parsers::{self, FrameDecoder};
+
+const ASYNC_NIBBLES: [u8; 22] = [
+    0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf, 0xf,
+    0xf, 0xf, 0x0,
+];
+const VERSION_NIBBLES: [u8; 6] = [0xf, 0x0, 0x0, 0xA, 0x0, 0x1];
+
+// Pack nibbles into bytes, least significant nibble first:
+fn to_bytes(nibbles: &[u8]) -> Vec<u8> {
+    nibbles.chunks(2).map(|n| n[0] | n[1] << 4).collect()
+}
+
+fn stream(payload: &[u8]) -> Vec<u8> {
+    let mut nibbles = ASYNC_NIBBLES.to_vec();
+    nibbles.extend_from_slice(&VERSION_NIBBLES);
+    nibbles.extend_from_slice(payload);
+    to_bytes(&nibbles)
+}
+
+fn synced_packets(start: usize) -> Vec<stp_core::stp_decoder::Result> {
+    vec![
+        Ok(Packet {
+            packet: stp::Packet::Async,
+            start,
+            span: 22,
+        }),
+        Ok(Packet {
+            packet: stp::Packet::Version {
+                version: StpVersion::STPv2_2,
+                ts_type: TimestampType::STPv2NATDELTA,
+                is_le: false,
+            },
+            start: start + 22,
+            span: 6,
+        }),
+    ]
+}
+
+// The outputs expected for packets (or errors) found on TWP streams:
+fn packets(packets: Vec<(Option<u8>, stp_decoder::Result)>) -> Vec<Output> {
+    packets
+        .into_iter()
+        .map(|(id, r)| Output::Packet(id, r))
+        .collect()
+}
+
+fn run(frames: &[u8]) -> (Vec<Output>, Vec<parsers::Error>) {
+    run_pipeline(&mut Pipeline::new(FrameDecoder::new(true, None)), frames)
+}
+
+fn run_pipeline(pipeline: &mut Pipeline, frames: &[u8]) -> (Vec<Output>, Vec<parsers::Error>) {
+    let mut outputs = Vec::new();
+    let mut errors = Vec::new();
+    let mut handler = |r: parsers::Result<Output>| {
+        match r {
+            Ok(o) => outputs.push(o),
+            Err(e) => errors.push(e),
+        }
+        Ok(())
+    };
+
+    assert_eq!(pipeline.decode(frames, &mut handler), Ok(()));
+    assert_eq!(pipeline.finish(&mut handler), Ok(()));
+    (outputs, errors)
+}
+
+fn interleaved_frames() -> Vec<u8> {
+    let s1 = stream(&[0x4, 0x1, 0x2, 0x0]);
+    let s2 = stream(&[0x5, 0x1, 0x2, 0x3, 0x4, 0x0]);
+    assert_eq!(s1.len(), 16);
+    assert_eq!(s2.len(), 17);
+
+    let mut i = 0;
+    let mut j = 0;
+    FrameBuilder::new(3)
+        .id(1)
+        .data_span_with(8, || {
+            i += 1;
+            s1[i - 1]
+        })
+        .id(2)
+        .data_span_with(17, || {
+            j += 1;
+            s2[j - 1]
+        })
+        .id(1)
+        .data_span_with(8, || {
+            i += 1;
+            s1[i - 1]
+        })
+        .id(0)
+        .build()
+}
+
+// Two interleaved streams are decoded independently and reported in capture order:
+#[test]
+fn interleaved_streams() {
+    let (outputs, errors) = run(&interleaved_frames());
+    assert!(errors.is_empty());
+
+    let mut exp = Vec::new();
+    for r in synced_packets(0) {
+        exp.push((Some(2), r));
+    }
+    exp.push((
+        Some(2),
+        Ok(Packet {
+            packet: stp::Packet::Data {
+                opcode: OpCode::D16,
+                data: 0x1234,
+                timestamp: None,
+            },
+            start: 28,
+            span: 5,
+        }),
+    ));
+    exp.push((
+        Some(2),
+        Ok(Packet {
+            packet: stp::Packet::Null { timestamp: None },
+            start: 33,
+            span: 1,
+        }),
+    ));
+    for r in synced_packets(0) {
+        exp.push((Some(1), r));
+    }
+    exp.push((
+        Some(1),
+        Ok(Packet {
+            packet: stp::Packet::Data {
+                opcode: OpCode::D8,
+                data: 0x12,
+                timestamp: None,
+            },
+            start: 28,
+            span: 3,
+        }),
+    ));
+    exp.push((
+        Some(1),
+        Ok(Packet {
+            packet: stp::Packet::Null { timestamp: None },
+            start: 31,
+            span: 1,
+        }),
+    ));
+
+    assert_eq!(outputs, packets(exp));
+}
+
+// A packet left incomplete at the end of the input is reported by finish:
+#[test]
+fn truncated_at_finish() {
+    let s1 = stream(&[0x6, 0x1, 0x2, 0x3]);
+    let mut i = 0;
+    let frames = FrameBuilder::new(1)
+        .id(1)
+        .data_span_with(s1.len(), || {
+            i += 1;
+            s1[i - 1]
+        })
+        .id(0x7D)
+        .data(0)
+        .id(0)
+        .build();
+
+    let (outputs, errors) = run(&frames);
+
+    // The trigger is an event, not an error:
+    let mut exp = packets(
+        synced_packets(0)
+            .into_iter()
+            .map(|r| (Some(1), r))
+            .collect(),
+    );
+    exp.push(Output::Event(parsers::Event::Trigger(0), 19));
+    exp.push(Output::Packet(
+        Some(1),
+        Err(Error {
+            reason: TruncatedPacket { opcode: None },
+            start: 28,
+            span: 4,
+        }),
+    ));
+    assert_eq!(outputs, exp);
+    assert_eq!(errors, vec![]);
+}
+
+// Packet offsets map back to offsets within the framed input:
+#[test]
+fn file_offsets() {
+    let mut pipeline = Pipeline::new(FrameDecoder::new(true, None));
+    run_pipeline(&mut pipeline, &interleaved_frames());
+    assert_eq!(pipeline.file_offset(Some(2), 0), None);
+
+    let mut pipeline = Pipeline::new(FrameDecoder::new(true, None)).track_offsets();
+    run_pipeline(&mut pipeline, &interleaved_frames());
+
+    // Stream 1 starts after the ID in the first frame; stream 2 after the (delayed) ID change.
+    assert_eq!(pipeline.file_offset(Some(1), 0), Some(1));
+    assert_eq!(pipeline.file_offset(Some(1), 13), Some(7));
+    assert_eq!(pipeline.file_offset(Some(1), 15), Some(9));
+    assert_eq!(pipeline.file_offset(Some(1), 16), Some(29));
+    assert_eq!(pipeline.file_offset(Some(2), 0), Some(10));
+    assert_eq!(pipeline.file_offset(Some(2), 10), Some(16));
+
+    // The D16 packet on stream 2 spans nibbles 28..33:
+    assert_eq!(pipeline.file_ranges(Some(2), 28, 5), vec![25..28]);
+    assert_eq!(pipeline.file_ranges(Some(2), 28, 0), vec![]);
+}
+
+// The last bytes of each stream are kept on request:
+#[test]
+fn stream_bytes() {
+    let mut pipeline = Pipeline::new(FrameDecoder::new(true, None));
+    run_pipeline(&mut pipeline, &interleaved_frames());
+    assert_eq!(pipeline.stream_bytes(Some(2), 28, 5), None);
+
+    let mut pipeline = Pipeline::new(FrameDecoder::new(true, None)).keep_bytes(3);
+    run_pipeline(&mut pipeline, &interleaved_frames());
+    assert_eq!(
+        pipeline.stream_bytes(Some(2), 28, 5),
+        Some(vec![0x15, 0x32, 0x04])
+    );
+    assert_eq!(pipeline.stream_bytes(Some(2), 29, 1), Some(vec![0x15]));
+    assert_eq!(pipeline.stream_bytes(Some(2), 26, 4), None);
+    assert_eq!(pipeline.stream_bytes(Some(2), 32, 4), None);
+    assert_eq!(pipeline.stream_bytes(Some(3), 0, 1), None);
+}
+
+// Stats count frames, packets and errors per stream in a single pass:
+#[test]
+fn stats() {
+    let mut pipeline = Pipeline::new(FrameDecoder::new(true, None));
+    let mut stats = Stats::new();
+    let mut frames = interleaved_frames();
+    frames.truncate(frames.len() - 1); // The last frame is now partial.
+    frames.extend_from_slice(&parsers::FSYNC);
+    let mut handler = |r: parsers::Result<Output>| {
+        stats.record(&r);
+        Ok(())
+    };
+    assert_eq!(pipeline.decode(&frames, &mut handler), Ok(()));
+    assert_eq!(pipeline.finish(&mut handler), Ok(()));
+    stats.finish(&pipeline);
+
+    assert_eq!((stats.frames.frames, stats.frames.fsyncs), (2, 1));
+    assert_eq!(stats.twp_errors.get("PartialFrame"), Some(&1));
+
+    let s1 = &stats.frames.streams[&Some(1)];
+    let s2 = &stats.frames.streams[&Some(2)];
+    assert_eq!((s1.frames, s1.bytes), (2, 10));
+    assert_eq!((s2.frames, s2.bytes), (2, 17));
+
+    let s2 = &stats.streams[&Some(2)];
+    assert_eq!(s2.asyncs, 1);
+    assert_eq!(
+        s2.versions
+            .get(&(StpVersion::STPv2_2, TimestampType::STPv2NATDELTA)),
+        Some(&1)
+    );
+    assert_eq!(s2.packets.get(&OpCode::D16), Some(&1));
+    assert_eq!(s2.packets.get(&OpCode::NULL), Some(&1));
+    assert_eq!(
+        s2.masters.get(&0),
+        Some(&Usage {
+            writes: 1,
+            bits: 16
+        })
+    );
+    assert_eq!(s2.channels.get(&(0, 0)).map(|u| u.writes), Some(1));
+
+    // Stream 1 is cut short before its ASYNC completes, so it produces no STP results:
+    assert!(!stats.streams.contains_key(&Some(1)));
+}
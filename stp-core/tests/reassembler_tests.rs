@@ -0,0 +1,130 @@
+use stp_core::asm::assemble;
+use stp_core::reassembler::{IncompleteReason, Reassembler, Result};
+use stp_core::stp_decoder::{self, ErrorReason, StpDecoder};
+
+fn reassemble(program: &str) -> Vec<Result> {
+    let mut reassembler = Reassembler::new();
+    let mut results = Vec::new();
+    StpDecoder::new().decode_nibbles(&assemble(program).unwrap(), |r| {
+        reassembler.process(&r, |m| results.push(m))
+    });
+    results
+}
+
+#[test]
+fn messages() {
+    let results = reassemble(
+        "async
+version v2.2 nat be
+m16 5
+c16 7
+d32 0x01020304
+m16 6
+d8m 0xaa
+m16 5
+c16 7
+d16 0x0506
+d8mts 0x07 ts=nat:4:0x100
+d16m 0x0809
+",
+    );
+    let messages: Vec<_> = results.into_iter().map(|r| r.unwrap()).collect();
+    assert_eq!(messages.len(), 3);
+
+    // A single marked write is a message of its own:
+    assert_eq!((messages[0].master, messages[0].channel), (6, 0));
+    assert_eq!(messages[0].data, vec![0xaa]);
+
+    // Writes to another master don't interrupt a message:
+    let m = &messages[1];
+    assert_eq!((m.master, m.channel), (5, 7));
+    assert_eq!(m.data, vec![1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!((m.timestamp, m.timestamped), (Some(0x100), true));
+    assert!(m.span > 0);
+
+    assert_eq!(messages[2].data, vec![8, 9]);
+    assert_eq!(
+        (messages[2].timestamp, messages[2].timestamped),
+        (Some(0x100), false)
+    );
+}
+
+#[test]
+fn little_endian() {
+    let results = reassemble(
+        "async
+version v2.2 nat le
+m16 1
+c16 2
+d64 0x0807060504030201
+d4 0x5
+d32m 0x0c0b0a09
+flag
+",
+    );
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].as_ref().unwrap().data,
+        (1..=12).collect::<Vec<u8>>()
+    );
+}
+
+#[test]
+fn incomplete() {
+    let results = reassemble(
+        "async
+version v2.2 nat le
+m16 1
+c16 2
+d16 0x0201
+c16 3
+d8 0x3
+async
+version v2.2 nat le
+m16 1
+c16 2
+d8m 0x4
+",
+    );
+    let incomplete: Vec<_> = results[..2]
+        .iter()
+        .map(|r| r.as_ref().unwrap_err())
+        .collect();
+    assert_eq!(
+        (incomplete[0].channel, incomplete[0].data.clone()),
+        (2, vec![1, 2])
+    );
+    assert_eq!(
+        (incomplete[1].channel, incomplete[1].data.clone()),
+        (3, vec![3])
+    );
+    assert!(incomplete
+        .iter()
+        .all(|i| i.master == 1 && i.reason == IncompleteReason::Async));
+    assert_eq!(results[2].as_ref().unwrap().data, vec![4]);
+    assert_eq!(results.len(), 3);
+
+    // Decoding errors and resets also drop the messages being written:
+    let mut reassembler = Reassembler::new();
+    let mut results = Vec::new();
+    StpDecoder::new().decode_nibbles(
+        &assemble("async\nversion v2.2 nat le\nm16 1\nd8 0x1\n").unwrap(),
+        |r| reassembler.process(&r, |m| results.push(m)),
+    );
+    assert!(results.is_empty());
+    let error = Err(stp_decoder::Error {
+        reason: ErrorReason::MissingVersion,
+        start: 0,
+        span: 1,
+    });
+    reassembler.process(&error, |m| results.push(m));
+    let i = results[0].as_ref().unwrap_err();
+    assert_eq!(
+        (i.reason, i.data.clone()),
+        (IncompleteReason::Error, vec![1])
+    );
+    assert!(i
+        .to_string()
+        .starts_with("incomplete message M1 C0 (1 bytes)"));
+    assert!(reassembler.reset().is_empty());
+}
@@ -290,13 +290,13 @@ const D64M_NIBBLES: [u8; 18] = [
 ];
 
 fn is_data(r: &Result) -> bool {
-    match r {
+    matches!(
+        r,
         Ok(Packet {
             packet: stp::Packet::Data { .. },
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 
 #[test]
@@ -666,13 +666,13 @@ const M8_NIBBLES: [u8; 3] = [0x1, 0x1, 0x2];
 const M16_NIBBLES: [u8; 6] = [0xf, 0x1, 0x1, 0x2, 0x3, 0x4];
 
 fn is_master(r: &Result) -> bool {
-    match r {
+    matches!(
+        r,
         Ok(Packet {
             packet: stp::Packet::Master { .. },
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 #[test]
 fn master_test() {
@@ -738,13 +738,13 @@ const C8_NIBBLES: [u8; 3] = [0x3, 0x1, 0x2];
 const C16_NIBBLES: [u8; 6] = [0xf, 0x3, 0x1, 0x2, 0x3, 0x4];
 
 fn is_channel(r: &Result) -> bool {
-    match r {
+    matches!(
+        r,
         Ok(Packet {
             packet: stp::Packet::Channel { .. },
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 #[test]
 fn channel_test() {
@@ -810,13 +810,13 @@ const MER_NIBBLES: [u8; 3] = [0x2, 0x1, 0x2];
 const GER_NIBBLES: [u8; 4] = [0xf, 0x2, 0x1, 0x2];
 
 fn is_error(r: &Result) -> bool {
-    match r {
+    matches!(
+        r,
         Ok(Packet {
             packet: stp::Packet::Error { .. },
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 #[test]
 fn error_test() {
@@ -882,13 +882,13 @@ const FLAG_NIBBLES: [u8; 2] = [0xF, 0xE];
 const FLAG_TS_NIBBLES: [u8; 4] = [0xE, 0x2, 0x1, 0x2];
 
 fn is_flag(r: &Result) -> bool {
-    match r {
+    matches!(
+        r,
         Ok(Packet {
             packet: stp::Packet::Flag { .. },
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 
 #[test]
@@ -979,10 +979,7 @@ fn zero_ts_test() {
 }
 
 fn is_decode_error(r: &Result) -> bool {
-    match r {
-        Err(_) => true,
-        _ => false,
-    }
+    r.is_err()
 }
 
 const D4MTS_INVALID_TS_NIBBLES: [u8; 4] = [0xD, 0x1, 0xF, 0x0];
@@ -1014,13 +1011,13 @@ fn invalid_ts_test() {
 }
 
 fn is_null(r: &Result) -> bool {
-    match r {
+    matches!(
+        r,
         Ok(Packet {
             packet: stp::Packet::Null { .. },
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 
 const NULL_TS: [u8; 5] = [0xF, 0x0, 0x1, 0x1, 0x2];
@@ -1071,13 +1068,13 @@ fn null_test() {
 }
 
 fn is_user(r: &Result) -> bool {
-    match r {
+    matches!(
+        r,
         Ok(Packet {
             packet: stp::Packet::User { .. },
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 
 const USER_NIBBLES: [u8; 8] = [0xf, 0x0, 0x2, 0x3, 0x1, 0x2, 0x3, 0x4];
@@ -1126,3 +1123,53 @@ fn user_test() {
 
     assert_eq!(results, exp);
 }
+
+#[test]
+fn finish_test() {
+    let mut results = Vec::<Result>::new();
+    let mut exp = Vec::<Result>::new();
+    let mut decoder = StpDecoder::new();
+    let mut stream = Vec::<u8>::new();
+
+    stream.extend_from_slice(&ASYNC_NIBBLES);
+    stream.extend_from_slice(&VERSION_NIBBLES);
+    stream.extend_from_slice(&D32_NIBBLES[..4]);
+
+    decoder.decode_nibbles(&stream, |r| results.push(r));
+    assert_eq!(results.len(), 2);
+
+    decoder.finish(|r| results.push(r));
+    decoder.finish(|r| results.push(r));
+    assert_eq!(results.len(), 3);
+
+    exp.push(Err(Error {
+        reason: TruncatedPacket { opcode: None },
+        start: 28,
+        span: 4,
+    }));
+    assert_eq!(results[2..], exp[..]);
+}
+
+#[test]
+fn finish_buffered_f_test() {
+    let mut results = Vec::<Result>::new();
+    let mut exp = Vec::<Result>::new();
+    let mut decoder = StpDecoder::new();
+    let mut stream = Vec::<u8>::new();
+
+    stream.extend_from_slice(&ASYNC_NIBBLES);
+    stream.extend_from_slice(&VERSION_NIBBLES);
+    stream.extend_from_slice(&NULL_TS[..1]);
+
+    decoder.decode_nibbles(&stream, |r| results.push(r));
+    assert_eq!(results.len(), 2);
+
+    decoder.finish(|r| results.push(r));
+
+    exp.push(Err(Error {
+        reason: TruncatedPacket { opcode: None },
+        start: 28,
+        span: 1,
+    }));
+    assert_eq!(results[2..], exp[..]);
+}
@@ -4,3 +4,43 @@ use stp_core::stp::OpCode;
 fn opcode_name() {
     println!("{:?}", OpCode::D8);
 }
+
+#[test]
+fn opcode_from_str() {
+    assert_eq!("D32M".parse(), Ok(OpCode::D32M));
+    assert_eq!("flag_ts".parse(), Ok(OpCode::FLAG_TS));
+    assert!("D128".parse::<OpCode>().is_err());
+}
+
+#[test]
+fn opcode_properties() {
+    assert_eq!(OpCode::D16MTS.data_bits(), Some(16));
+    assert_eq!(OpCode::M8.data_bits(), None);
+    assert!(OpCode::D4M.is_marked());
+    assert!(!OpCode::D4TS.is_marked());
+    assert!(OpCode::D4TS.has_timestamp());
+    assert!(!OpCode::D64M.has_timestamp());
+}
+
+#[test]
+fn packet_opcode() {
+    use stp_core::stp::{Packet, Timestamp};
+    let ts = Some(Timestamp::STPv2NAT {
+        length: 1,
+        value: 0,
+    });
+    assert_eq!(Packet::Async.opcode(), None);
+    assert_eq!(
+        Packet::Null { timestamp: None }.opcode(),
+        Some(OpCode::NULL)
+    );
+    assert_eq!(
+        Packet::Flag { timestamp: ts }.opcode(),
+        Some(OpCode::FLAG_TS)
+    );
+    let master = Packet::Master {
+        opcode: OpCode::M16,
+        master: 1,
+    };
+    assert_eq!(master.opcode(), Some(OpCode::M16));
+}
@@ -0,0 +1,103 @@
+#![cfg(feature = "elf")]
+
+mod common;
+
+use object::{Object, ObjectSymbol};
+use stp_core::message::Message;
+use stp_core::stp::OpCode;
+use stp_core::symbols::Symbols;
+
+// A little-endian executable with just a symbol table of functions (name, address, size):
+fn elf(functions: &[(&str, u64, u64)]) -> Vec<u8> {
+    common::elf(true, functions, &[])
+}
+
+fn write(master: u16, opcode: OpCode, data: u64) -> Message {
+    Message {
+        master,
+        channel: 0,
+        opcode,
+        data: Some(data),
+        timestamp: None,
+        timestamped: false,
+        start: 0,
+        span: 1,
+    }
+}
+
+#[test]
+fn functions() {
+    let data = elf(&[
+        ("bar", 0x1020, 0x10),
+        ("foo", 0x1000, 0x20),
+        ("label", 0x1100, 0),
+    ]);
+    let mut symbols = Symbols::new();
+    assert!(symbols.is_empty());
+    symbols.add_elf(&data, None, 0).unwrap();
+
+    let name = |address| symbols.resolve(0, address).map(|s| s.to_string());
+    assert_eq!(name(0x1000).as_deref(), Some("foo"));
+    assert_eq!(name(0x1024).as_deref(), Some("bar+0x4"));
+    assert_eq!(name(0x1100).as_deref(), Some("label"));
+    assert_eq!(name(0x1030), None);
+    assert_eq!(name(0x1101), None);
+    assert_eq!(name(0xfff), None);
+    let s = symbols.resolve(0, 0x1001).unwrap();
+    assert_eq!((s.offset, s.file, s.line), (1, None, None));
+
+    // Only 32-bit and 64-bit writes hold addresses:
+    assert!(symbols
+        .resolve_message(&write(3, OpCode::D32, 0x1000))
+        .is_some());
+    assert!(symbols
+        .resolve_message(&write(3, OpCode::D16, 0x1000))
+        .is_none());
+
+    assert!(Symbols::new().add_elf(b"not an ELF file", None, 0).is_err());
+}
+
+#[test]
+fn masters() {
+    let mut symbols = Symbols::new();
+    symbols
+        .add_elf(&elf(&[("modem", 0x1000, 0x10)]), Some(32..=47), 0x8000_0000)
+        .unwrap();
+    symbols
+        .add_elf(&elf(&[("app", 0x1000, 0x10)]), None, 0)
+        .unwrap();
+    let name = |master, address| symbols.resolve(master, address).map(|s| s.name);
+    assert_eq!(name(40, 0x8000_1008).as_deref(), Some("modem"));
+    assert_eq!(name(40, 0x1008).as_deref(), Some("app"));
+    assert_eq!(name(5, 0x8000_1008), None);
+}
+
+#[inline(never)]
+fn marker() -> u32 {
+    std::hint::black_box(42)
+}
+
+#[test]
+fn lines() {
+    assert_eq!(marker(), 42);
+
+    // This test's own debug information has the marker's source line:
+    let path = std::env::current_exe().unwrap();
+    let data = std::fs::read(&path).unwrap();
+    let file = object::File::parse(&*data).unwrap();
+    let address = file
+        .symbols()
+        .find(|s| s.name().is_ok_and(|n| n.contains("symbols_tests6marker")))
+        .map(|s| s.address());
+    let address = match address {
+        Some(a) if file.section_by_name(".debug_info").is_some() => a,
+        _ => return, // Stripped.
+    };
+
+    let mut symbols = Symbols::new();
+    symbols.load(&path, None, 0).unwrap();
+    let s = symbols.resolve(0, address).unwrap();
+    assert!(s.name.contains("marker"));
+    assert!(s.file.unwrap().ends_with("symbols_tests.rs"));
+    assert!(s.line.is_some());
+}
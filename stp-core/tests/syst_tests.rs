@@ -0,0 +1,267 @@
+use std::convert::TryInto;
+use stp_core::asm::assemble;
+use stp_core::stp_decoder::{self, StpDecoder};
+use stp_core::syst::{
+    crc32c, Decoder, ErrorReason, Location, MessageType, Origin, Payload, Result, Severity,
+};
+
+const LOCATION: u32 = 1 << 8;
+const LENGTH: u32 = 1 << 9;
+const CHECKSUM: u32 = 1 << 10;
+const TIMESTAMP: u32 = 1 << 11;
+const GUID: u32 = 1 << 23;
+
+// A SyS-T message with a header and optional fields, in the order they are written:
+fn message(header: u32, fields: &[&[u8]], checksum: bool) -> Vec<u8> {
+    let mut bytes = header.to_le_bytes().to_vec();
+    for f in fields {
+        bytes.extend_from_slice(f);
+    }
+    if checksum {
+        let crc = crc32c(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+    }
+    bytes
+}
+
+// The STP writes of a message on master 5, channel 7: a timestamped header, then the largest
+// writes that fit, then a FLAG.
+fn writes(bytes: &[u8], flag: bool) -> String {
+    writes_in(bytes, flag, true)
+}
+
+// The same, from a writer of either byte order:
+fn writes_in(bytes: &[u8], flag: bool, is_le: bool) -> String {
+    let word = |b: &[u8]| {
+        let mut v = [0; 8];
+        match is_le {
+            true => v[..b.len()].copy_from_slice(b),
+            false => v[8 - b.len()..].copy_from_slice(b),
+        }
+        match is_le {
+            true => u64::from_le_bytes(v),
+            false => u64::from_be_bytes(v),
+        }
+    };
+    let mut program = format!("d32ts {:#x} ts=nat:2:0x10\n", word(&bytes[..4]));
+    let mut rest = &bytes[4..];
+    while !rest.is_empty() {
+        let size = [8, 4, 2, 1].iter().find(|s| **s <= rest.len()).unwrap();
+        program.push_str(&format!("d{} {:#x}\n", size * 8, word(&rest[..*size])));
+        rest = &rest[*size..];
+    }
+    if flag {
+        program.push_str("flag\n");
+    }
+    program
+}
+
+fn packets(program: &str) -> Vec<stp_decoder::Result> {
+    let program = format!("async\nversion v2.2 nat le\nm16 5\nc16 7\n{}", program);
+    let mut packets = Vec::new();
+    StpDecoder::new().decode_nibbles(&assemble(&program).unwrap(), |r| packets.push(r));
+    packets
+}
+
+fn decode(program: &str) -> Vec<Result> {
+    let mut decoder = Decoder::new();
+    let mut results = Vec::new();
+    for p in packets(program) {
+        decoder.process(&p, |r| results.push(r));
+    }
+    results
+}
+
+#[test]
+fn crc() {
+    assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+}
+
+#[test]
+fn string() {
+    // An INFO string from module 3, unit 2, with every optional field:
+    let header =
+        2 | 4 << 4 | LOCATION | LENGTH | CHECKSUM | TIMESTAMP | 3 << 12 | 2 << 19 | 1 << 24;
+    let text = b"hello\0";
+    let bytes = message(
+        header,
+        &[
+            &[0],
+            &[0x12, 0, 0x34, 0],
+            &(text.len() as u16).to_le_bytes(),
+            &0x1122_3344_5566_7788u64.to_le_bytes(),
+            text,
+        ],
+        true,
+    );
+    let results = decode(&writes(&bytes, true));
+    assert_eq!(results.len(), 1);
+    let m = results[0].as_ref().unwrap();
+    assert_eq!((m.master, m.channel, m.stp_timestamp), (5, 7, Some(0x10)));
+    assert_eq!(m.header.kind, MessageType::String);
+    assert_eq!(m.header.severity, Severity::Info);
+    assert_eq!(m.origin, Origin::ModuleUnit { module: 3, unit: 2 });
+    assert_eq!(
+        m.location,
+        Some(Location::File {
+            file: 0x12,
+            line: 0x34
+        })
+    );
+    assert_eq!(m.timestamp, Some(0x1122_3344_5566_7788));
+    assert!(m.crc.is_some());
+    assert_eq!(
+        m.payload,
+        Payload::String {
+            subtype: 1,
+            text: "hello".to_string(),
+            args: Vec::new()
+        }
+    );
+}
+
+#[test]
+fn checksum() {
+    let mut bytes = message(2 | 2 << 4 | CHECKSUM, &[b"oops\0"], true);
+    bytes[5] ^= 1;
+    let results = decode(&writes(&bytes, true));
+    match &results[0] {
+        Err(e) => assert!(matches!(e.reason, ErrorReason::Checksum { .. })),
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn payloads() {
+    let guid: [u8; 16] = (0..16).collect::<Vec<u8>>().try_into().unwrap();
+    let mut build = 0x1234u64.to_le_bytes().to_vec();
+    build.extend_from_slice(b"v1.2\0");
+    let mut catalog = 0xabcdu32.to_le_bytes().to_vec();
+    catalog.extend_from_slice(&7u32.to_le_bytes());
+    let clock = [100u64.to_le_bytes(), 1_000_000u64.to_le_bytes()].concat();
+
+    let program = [
+        writes(&message(2 << 24, &[&build], false), true), // A long build message.
+        writes(
+            &message(3 | GUID | 1 << 24, &[&guid, &catalog], false),
+            true,
+        ),
+        writes(&message(8 | 1 << 24, &[&clock], false), true),
+        writes(&message(6 | 9 << 24, &[&[1, 2, 3]], false), true),
+        // Short messages end without a FLAG:
+        writes(&message(1 | 0xabc << 4, &[], false), false),
+        writes(&message(7, &[&0x55u64.to_le_bytes()], false), false),
+    ]
+    .concat();
+    let payloads: Vec<Payload> = decode(&program)
+        .into_iter()
+        .map(|r| r.unwrap().payload)
+        .collect();
+    assert_eq!(
+        payloads,
+        vec![
+            Payload::Build {
+                id: 0x1234,
+                text: "v1.2".to_string()
+            },
+            Payload::Catalog {
+                id: 0xabcd,
+                args: vec![7, 0, 0, 0]
+            },
+            Payload::Clock {
+                clock: 100,
+                frequency: 1_000_000
+            },
+            Payload::Raw {
+                protocol: 9,
+                data: vec![1, 2, 3]
+            },
+            Payload::Short(0xabc),
+            Payload::Short(0x55),
+        ]
+    );
+
+    let results = decode(&writes(
+        &message(3 | GUID | 1 << 24, &[&guid, &catalog], false),
+        true,
+    ));
+    assert_eq!(
+        results[0].as_ref().unwrap().origin.to_string(),
+        "03020100-0504-0706-0809-0a0b0c0d0e0f"
+    );
+}
+
+#[test]
+fn errors() {
+    // A message cut short by the next one, and one too short for its length:
+    let first = writes(&message(2, &[b"cut"], false), false);
+    let second = writes(
+        &message(2 | LENGTH, &[&9u16.to_le_bytes(), b"abc"], false),
+        true,
+    );
+    let results = decode(&(first.clone() + &second));
+    let reasons: Vec<ErrorReason> = results.into_iter().map(|r| r.unwrap_err().reason).collect();
+    assert_eq!(
+        reasons,
+        vec![
+            ErrorReason::Unterminated,
+            ErrorReason::Truncated { field: "payload" }
+        ]
+    );
+
+    // Resetting reports messages still being written:
+    let mut decoder = Decoder::new();
+    for p in packets(&first) {
+        decoder.process(&p, |r| panic!("{:?}", r));
+    }
+    let errors = decoder.reset();
+    assert_eq!(
+        (errors.len(), errors[0].master, errors[0].channel),
+        (1, 5, 7)
+    );
+    assert!(decoder.reset().is_empty());
+}
+
+#[test]
+fn big_endian() {
+    let bytes = message(2 | 2 << 4 | CHECKSUM | 1 << 24, &[b"big endian\0"], true);
+    let program = format!("version v2.2 nat be\n{}", writes_in(&bytes, true, false));
+    let results = decode(&program);
+    assert_eq!(results.len(), 1);
+    let m = results[0].as_ref().unwrap();
+    assert!(m.crc.is_some());
+    assert_eq!(
+        m.payload,
+        Payload::String {
+            subtype: 1,
+            text: "big endian".to_string(),
+            args: Vec::new()
+        }
+    );
+}
+
+#[test]
+fn interrupted() {
+    let first = writes(&message(2, &[b"cut"], false), false);
+    let second = writes(&message(2, &[b"whole\0"], false), true);
+
+    // An ASYNC drops the message being written, which isn't glued onto the next one:
+    let results = decode(&format!(
+        "{}async\nversion v2.2 nat le\nm16 5\nc16 7\n{}",
+        first, second
+    ));
+    let reasons = |results: Vec<Result>| -> Vec<Option<ErrorReason>> {
+        results
+            .into_iter()
+            .map(|r| r.err().map(|e| e.reason))
+            .collect()
+    };
+    assert_eq!(
+        reasons(results),
+        vec![Some(ErrorReason::Unterminated), None]
+    );
+
+    // So does a 4-bit write, reported as corrupting it:
+    let results = decode(&format!("{}d4 0x5\nflag\n{}", first, second));
+    assert_eq!(reasons(results), vec![Some(ErrorReason::Corrupt), None]);
+}
@@ -0,0 +1,76 @@
+use stp_core::asm::assemble;
+use stp_core::stp_decoder::StpDecoder;
+use stp_core::text::{Line, TextCollector};
+
+fn collect(program: &str) -> (Vec<Line>, TextCollector) {
+    let mut collector = TextCollector::new();
+    let mut lines = Vec::new();
+    let program = format!("async\nversion v2.2 nat le\n{}", program);
+    StpDecoder::new().decode_nibbles(&assemble(&program).unwrap(), |r| {
+        collector.process(&r, |l| lines.push(l))
+    });
+    (lines, collector)
+}
+
+fn texts(lines: &[Line]) -> Vec<(u16, u16, &str, Option<u64>)> {
+    lines
+        .iter()
+        .map(|l| (l.master, l.channel, l.text.as_str(), l.timestamp))
+        .collect()
+}
+
+#[test]
+fn lines() {
+    // Two writers, one byte by byte and one with wide writes:
+    let (lines, _) = collect(
+        "m16 1
+c16 2
+d8ts 0x68 ts=nat:4:0x10
+d8 0x69
+m16 3
+c16 4
+d32ts 0x0a6b6f ts=nat:4:0x20
+m16 1
+c16 2
+d16ts 0x0a21 ts=nat:4:0x30
+d8 0x0a
+m16 3
+c16 4
+d64 0x6f6e0d0765
+d8mts 0x2e ts=nat:4:0x40
+",
+    );
+    assert_eq!(
+        texts(&lines),
+        vec![
+            (3, 4, "ok", Some(0x20)),
+            (1, 2, "hi!", Some(0x30)),
+            (1, 2, "", Some(0x30)),
+            // Control characters and padding are dropped, and marked writes end lines:
+            (3, 4, "eno.", Some(0x40)),
+        ]
+    );
+    assert!(lines[1].start < lines[0].start);
+}
+
+#[test]
+fn unfinished() {
+    let (lines, mut collector) = collect(
+        "m16 1
+c16 2
+d8ts 0x61 ts=nat:4:0x10
+async
+version v2.2 nat be
+m16 1
+c16 2
+d16ts 0x6263 ts=nat:4:0x20
+",
+    );
+    // An ASYNC ends the lines being written:
+    assert_eq!(texts(&lines), vec![(1, 2, "a", Some(0x10))]);
+
+    // Big-endian writers, and lines left at the end:
+    let rest = collector.finish();
+    assert_eq!(texts(&rest), vec![(1, 2, "bc", Some(0x20))]);
+    assert!(collector.finish().is_empty());
+}
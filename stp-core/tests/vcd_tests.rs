@@ -0,0 +1,110 @@
+mod common;
+
+use common::message;
+use stp_core::names::{Name, Names};
+use stp_core::stp::OpCode;
+use stp_core::vcd::{Timescale, VcdWriter};
+
+#[test]
+fn timescales() {
+    let t: Timescale = "10ns".parse().unwrap();
+    assert_eq!(t.to_string(), "10 ns");
+    assert_eq!(t.units(1e-6), 100);
+    let t: Timescale = "100 us".parse().unwrap();
+    assert_eq!(t.to_string(), "100 us");
+    assert_eq!(t.units(1.0), 10_000);
+    assert_eq!(Timescale::default().to_string(), "1 ns");
+    assert_eq!(
+        "20ns".parse::<Timescale>(),
+        Err("invalid timescale: 20ns".to_string())
+    );
+    assert_eq!(
+        "1 min".parse::<Timescale>(),
+        Err("invalid timescale: 1 min".to_string())
+    );
+}
+
+#[test]
+fn dump() {
+    let mut vcd = VcdWriter::new("1us".parse().unwrap());
+    vcd.message(Some(2), &message(1, 5, OpCode::D8MTS, Some(0x81)), 10);
+    vcd.message(Some(1), &message(3, 0, OpCode::FLAG, None), 4);
+    vcd.message(Some(2), &message(1, 5, OpCode::D16, Some(0x1234)), 11);
+    vcd.error(Some(1), 11);
+    vcd.message(Some(2), &message(1, 2, OpCode::D4, Some(0x3)), 12);
+    let mut out = Vec::new();
+    vcd.finish(&mut out).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\
+$comment STP channel activity $end
+$timescale 1 us $end
+$scope module stp $end
+$scope module stream_01 $end
+$var wire 1 $ error $end
+$scope module m3 $end
+$var wire 1 # c0_flag $end
+$upscope $end
+$upscope $end
+$scope module stream_02 $end
+$scope module m1 $end
+$var wire 4 % c2 [3:0] $end
+$var wire 16 ! c5 [15:0] $end
+$var wire 1 \" c5_marked $end
+$upscope $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+bx !
+0\"
+0#
+0$
+bx %
+$end
+#4
+1#
+#5
+0#
+#10
+b10000001 !
+1\"
+#11
+0\"
+b1001000110100 !
+1$
+#12
+0$
+b11 %
+"
+    );
+}
+
+#[test]
+fn empty() {
+    let mut out = Vec::new();
+    VcdWriter::new(Timescale::default())
+        .finish(&mut out)
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.ends_with("$upscope $end\n$enddefinitions $end\n#0\n$dumpvars\n$end\n"));
+}
+
+#[test]
+fn names() {
+    let mut names = Names::new();
+    names.add_master(1..=1, Name::new("modem core"));
+    names.add_channel(None, 5..=5, Name::new("irq-enter"));
+    let mut vcd = VcdWriter::new(Timescale::default()).names(names);
+    vcd.message(None, &message(1, 5, OpCode::D8, Some(1)), 0);
+    vcd.message(None, &message(2, 6, OpCode::FLAG, None), 0);
+    let mut out = Vec::new();
+    vcd.finish(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(
+        out.contains("$scope module modem_core_m1 $end\n$var wire 8 ! irq_enter_c5 [7:0] $end\n")
+    );
+    assert!(out.contains("$scope module m2 $end\n$var wire 1 \" c6_flag $end\n"));
+}
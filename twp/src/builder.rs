@@ -13,7 +13,7 @@ use FrameBuilderError::*;
 pub type Result = result::Result<(), FrameBuilderError>;
 
 pub fn set_stream_id(frames: &mut [u8], offset: usize, id: u8, immediate: bool) -> Result {
-    if offset % 2 != 0 || offset >= frames.len() {
+    if !offset.is_multiple_of(2) || offset >= frames.len() {
         return Err(InvalidOffset(offset));
     }
 
@@ -21,7 +21,7 @@ pub fn set_stream_id(frames: &mut [u8], offset: usize, id: u8, immediate: bool)
         return Err(InvalidStreamId(offset, id));
     }
 
-    if immediate == false && offset % 16 == 14 {
+    if !immediate && offset % 16 == 14 {
         return Err(InvalidDelayedId(offset, id));
     }
 
@@ -43,7 +43,7 @@ pub fn set_stream_data(frames: &mut [u8], offset: usize, data: u8) -> Result {
         return Err(InvalidOffset(offset));
     }
 
-    if offset % 2 == 0 {
+    if offset.is_multiple_of(2) {
         let aux_offset = offset - (offset % 16) + 15;
         frames[offset] = data & 0xFE;
 
@@ -115,7 +115,7 @@ impl FrameBuilder {
             return Err(MissingData(self.offset));
         }
 
-        if self.offset % 2 == 0 {
+        if self.offset.is_multiple_of(2) {
             self.set_id_direct(value, true)?;
         } else {
             self.offset -= 1;
@@ -170,16 +170,68 @@ impl FrameBuilder {
     }
 }
 
-const FSYNC: [u8; 4] = [0x7F, 0xFF, 0xFF, 0xFF];
+const FSYNC: [u8; 4] = [0xFF, 0xFF, 0xFF, 0x7F];
+const HSYNC: [u8; 2] = [0xFF, 0x7F];
 
-pub fn insert_fsync(frames: &mut Vec<u8>, offset: usize) -> Result {
+fn insert_sync(frames: &mut Vec<u8>, offset: usize, sync: &[u8]) -> Result {
     if offset >= frames.len() {
         Err(InvalidOffset(offset))
     } else {
-        frames.reserve(4);
+        frames.reserve(sync.len());
         let mut v = frames.split_off(offset);
-        frames.extend_from_slice(&FSYNC);
+        frames.extend_from_slice(sync);
         frames.append(&mut v);
         Ok(())
     }
 }
+
+pub fn insert_fsync(frames: &mut Vec<u8>, offset: usize) -> Result {
+    insert_sync(frames, offset, &FSYNC)
+}
+
+pub fn insert_hsync(frames: &mut Vec<u8>, offset: usize) -> Result {
+    insert_sync(frames, offset, &HSYNC)
+}
+
+/// Frame a single stream's bytes.
+///
+/// Every `fsync_interval` frames an FSYNC is emitted and the stream ID is repeated, so that a
+/// decoder can pick up the stream from any FSYNC.  An interval of 0 emits a single FSYNC at the
+/// start.  The last frame is padded with null (ID 0x00) data.
+///
+/// # Arguments
+///
+///  * `data` - The stream's bytes.
+///  * `id` - The stream ID.
+///  * `fsync_interval` - The number of frames between FSYNCs.
+pub fn frame_stream(
+    data: &[u8],
+    id: u8,
+    fsync_interval: usize,
+) -> result::Result<Vec<u8>, FrameBuilderError> {
+    // The first frame after an FSYNC holds the stream ID and 14 bytes, the others 15 bytes:
+    let group_len = match fsync_interval {
+        0 => data.len().max(1),
+        n => 14 + 15 * (n - 1),
+    };
+
+    let mut frames = Vec::new();
+    for group in data.chunks(group_len) {
+        let mut builder = FrameBuilder::new(group.len() / 15 + 1);
+        builder.set_id(id)?;
+        for byte in group {
+            builder.set_data(*byte)?;
+        }
+
+        let used = (group.len() + 1) % 15;
+        if used != 0 {
+            builder.set_id(0)?;
+            for _ in used + 1..15 {
+                builder.set_data(0)?;
+            }
+        }
+        frames.extend_from_slice(&FSYNC);
+        frames.extend(builder.build());
+    }
+    Ok(frames)
+}
@@ -0,0 +1,134 @@
+//! Unwraps circular (ETB/ETR) trace buffers into chronological order.
+
+use super::frame_parser::IdOptions;
+use super::layer_parser::FrameDecoder;
+use super::types::{Data, Error, ErrorReason::*, Result};
+
+/// A trace buffer dumped from a CoreSight ETB or ETR.
+///
+/// The formatter writes frames to the buffer on 16 byte boundaries.  Once the buffer wraps the
+/// oldest data sits at the write pointer, and anything written before it has been overwritten.
+pub struct CircularBuffer<'a> {
+    buffer: &'a [u8],
+    write_ptr: Option<usize>,
+    wrapped: bool,
+}
+
+impl<'a> CircularBuffer<'a> {
+    /// Create a new CircularBuffer.
+    ///
+    /// # Arguments
+    ///
+    ///  * `buffer` - The raw contents of the trace buffer.
+    ///  * `write_ptr` - The buffer offset the next write would have gone to, if known.
+    ///  * `wrapped` - Has the buffer wrapped?
+    ///
+    /// Without a write pointer a wrapped buffer is assumed to already be in chronological order,
+    /// but to start at an arbitrary point; decoding waits for the first FSYNC.  The write pointer
+    /// of a buffer that never wrapped is at most its length (a full buffer).
+    pub fn new(buffer: &'a [u8], write_ptr: Option<usize>, wrapped: bool) -> Self {
+        let write_ptr = match wrapped {
+            true => write_ptr.map(|wp| wp % buffer.len().max(1)),
+            false => write_ptr.map(|wp| wp.min(buffer.len())),
+        };
+        CircularBuffer {
+            buffer,
+            write_ptr,
+            wrapped,
+        }
+    }
+
+    /// The buffer offset of the oldest byte.
+    pub fn start(&self) -> usize {
+        match self.write_ptr {
+            Some(wp) if self.wrapped => wp,
+            _ => 0,
+        }
+    }
+
+    /// The number of valid bytes in the buffer.
+    pub fn len(&self) -> usize {
+        match self.write_ptr {
+            Some(wp) if !self.wrapped => wp,
+            _ => self.buffer.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the valid data as (at most) two slices in chronological order.
+    pub fn as_slices(&self) -> (&'a [u8], &'a [u8]) {
+        let start = self.start();
+        if start == 0 {
+            (&self.buffer[..self.len()], &[])
+        } else {
+            (&self.buffer[start..], &self.buffer[..start])
+        }
+    }
+
+    /// Decode the buffer in chronological order.
+    ///
+    /// Offsets reported to the handler are offsets within the raw buffer.
+    pub fn decode<H>(&self, handler: H) -> Result<()>
+    where
+        H: FnMut(Result<Data>) -> Result<()>,
+    {
+        self.decode_with(IdOptions::default(), handler)
+    }
+
+    /// Decode the buffer in chronological order using the given stream ID options.
+    ///
+    /// If the buffer wrapped, a `Discontinuity` error is reported at the oldest byte: the stream
+    /// ID and any data written before that point are lost.  A partial frame left between the
+    /// write pointer and the next frame boundary is reported as a `PartialFrame` error.
+    pub fn decode_with<H>(&self, options: IdOptions, mut handler: H) -> Result<()>
+    where
+        H: FnMut(Result<Data>) -> Result<()>,
+    {
+        let len = self.buffer.len();
+        let start = self.start();
+        let (first, second) = self.as_slices();
+
+        // Frames start on 16 byte boundaries within the buffer, so when the write pointer is known
+        // the first frame boundary is known too.  Otherwise, look for an FSYNC.
+        let (skip, aligned) = match self.write_ptr {
+            Some(_) => ((16 - start % 16) % 16, true),
+            None => (0, !self.wrapped),
+        };
+
+        if self.wrapped {
+            handler(Err(Error {
+                offset: start,
+                reason: Discontinuity,
+            }))?;
+        }
+
+        if skip > 0 {
+            handler(Err(Error {
+                offset: start,
+                reason: PartialFrame(skip),
+            }))?;
+        }
+
+        // Translate offsets within the unwrapped data into offsets within the buffer:
+        let base = start + skip;
+        let mut remap = |mut r: Result<Data>| {
+            match r {
+                Err(ref mut e) => e.offset = (base + e.offset) % len,
+                Ok(ref mut d) => d.offset = (base + d.offset) % len,
+            }
+            handler(r)
+        };
+
+        let mut decoder = FrameDecoder::with_options(aligned, None, options);
+        if skip < first.len() {
+            decoder.decode(&first[skip..], &mut remap)?;
+            decoder.decode(second, &mut remap)?;
+        } else {
+            decoder.decode(second.get(skip - first.len()..).unwrap_or(&[]), &mut remap)?;
+        }
+        decoder.finish(&mut remap)
+    }
+}
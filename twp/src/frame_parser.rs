@@ -1,15 +1,69 @@
 //! Parses TWP frames into data values.
 
-use super::types::{Data, Error, ErrorReason::*, Result};
+use super::types::{Data, Error, ErrorReason::*, Event, Result};
 use std::convert::TryInto;
 
+/// The null trace source: used by the formatter for padding.
+pub const NULL_ID: u8 = 0x00;
+
+/// Data written to this stream marks a trigger.
+pub const TRIGGER_ID: u8 = 0x7D;
+
+/// Never a valid stream ID; an ID byte of 0xFF would mimic an FSYNC.
+pub const INVALID_ID: u8 = 0x7F;
+
+/// Returns true if `id` is reserved by the CoreSight architecture.
+pub fn is_reserved_id(id: u8) -> bool {
+    matches!(id, 0x70..=0x7C | 0x7E)
+}
+
+/// Controls how the special CoreSight stream IDs are reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdOptions {
+    /// Pass data written to the null stream (ID 0x00) to the handler.  When false it is dropped.
+    pub null_data: bool,
+    /// Report data written to the trigger stream (ID 0x7D) as `Trigger` events rather than data.
+    pub triggers: bool,
+    /// Report a `ReservedStreamId` event whenever a reserved ID is selected.
+    pub reserved_warnings: bool,
+}
+
+impl Default for IdOptions {
+    fn default() -> Self {
+        IdOptions {
+            null_data: false,
+            triggers: true,
+            reserved_warnings: true,
+        }
+    }
+}
+
 /// Decode a series of frames.
 ///
 /// # Arguments
 ///
 ///  * `frames` - A stream of bytes representing contiguous 16 byte frames.
 ///  * `stream_id` - The starting stream ID.
-pub fn decode_frames<H>(frames: &[u8], stream_id: Option<u8>, mut handler: H) -> Result<Option<u8>>
+pub fn decode_frames<H>(frames: &[u8], stream_id: Option<u8>, handler: H) -> Result<Option<u8>>
+where
+    H: FnMut(Result<Data>) -> Result<()>,
+{
+    decode_frames_with(frames, stream_id, &IdOptions::default(), handler)
+}
+
+/// Decode a series of frames using the given stream ID options.
+///
+/// # Arguments
+///
+///  * `frames` - A stream of bytes representing contiguous 16 byte frames.
+///  * `stream_id` - The starting stream ID.
+///  * `options` - How the special stream IDs are reported.
+pub fn decode_frames_with<H>(
+    frames: &[u8],
+    stream_id: Option<u8>,
+    options: &IdOptions,
+    mut handler: H,
+) -> Result<Option<u8>>
 where
     H: FnMut(Result<Data>) -> Result<()>,
 {
@@ -18,14 +72,14 @@ where
     let mut iter = frames.chunks_exact(16);
 
     for frame in &mut iter {
-        id = decode_frame_offset(frame.try_into().unwrap(), id, &mut handler, offset)?;
+        id = decode_frame_offset(frame.try_into().unwrap(), id, options, &mut handler, offset)?;
         offset += 16;
     }
 
     let remainder = iter.remainder().len();
     if remainder > 0 {
         handler(Err(Error {
-            offset: offset,
+            offset,
             reason: PartialFrame(remainder),
         }))?;
     }
@@ -39,9 +93,24 @@ where
 ///
 ///  * `frame` - The frame of data to be decoded.
 ///  * `stream` - The starting stream ID.
-pub fn decode_frame<H>(
+pub fn decode_frame<H>(frame: &[u8; 16], stream_id: Option<u8>, handler: H) -> Result<Option<u8>>
+where
+    H: FnMut(Result<Data>) -> Result<()>,
+{
+    decode_frame_with(frame, stream_id, &IdOptions::default(), handler)
+}
+
+/// Decode a single frame of data using the given stream ID options.
+///
+/// # Arguments
+///
+///  * `frame` - The frame of data to be decoded.
+///  * `stream` - The starting stream ID.
+///  * `options` - How the special stream IDs are reported.
+pub fn decode_frame_with<H>(
     frame: &[u8; 16],
     stream_id: Option<u8>,
+    options: &IdOptions,
     mut handler: H,
 ) -> Result<Option<u8>>
 where
@@ -55,7 +124,7 @@ where
             offset: 15,
             reason: InvalidAuxByte(aux_byte),
         }))?;
-        aux_byte = aux_byte & 0x7F;
+        aux_byte &= 0x7F;
     }
 
     let mut cur_stream = stream_id;
@@ -66,35 +135,35 @@ where
             // Even byte: ID change OR data.
             let aux_bit = (aux_byte >> (i / 2)) & 0x01;
             if byte & 0x01 == 1 {
-                if *byte == 0xFF {
+                let new_stream = byte >> 1;
+                if new_stream == INVALID_ID {
                     handler(Err(Error {
                         offset: i,
-                        reason: InvalidStreamId(0x7F),
+                        reason: InvalidStreamId(new_stream),
+                    }))?;
+                } else if options.reserved_warnings && is_reserved_id(new_stream) {
+                    handler(Ok(Data {
+                        id: Some(new_stream),
+                        data: new_stream,
+                        offset: i,
+                        event: Some(Event::ReservedStreamId(new_stream)),
                     }))?;
                 }
                 // Id Change.
                 if aux_bit == 1 {
                     // Delayed ID Change.
-                    next_stream = Some(byte >> 1);
+                    next_stream = Some(new_stream);
                 } else {
                     // Immediate ID change.
-                    cur_stream = Some(byte >> 1);
+                    cur_stream = Some(new_stream);
                 }
             } else {
-                handler(Ok(Data {
-                    id: cur_stream,
-                    data: byte | aux_bit,
-                    offset: i,
-                }))?;
+                report_data(cur_stream, byte | aux_bit, i, options, &mut handler)?;
             }
         } else {
             // Odd byte: Data only.
-            handler(Ok(Data {
-                id: cur_stream,
-                data: *byte,
-                offset: i,
-            }))?;
-            if let Some(_) = next_stream {
+            report_data(cur_stream, *byte, i, options, &mut handler)?;
+            if next_stream.is_some() {
                 cur_stream = next_stream;
                 next_stream = None;
             }
@@ -104,16 +173,45 @@ where
     Ok(cur_stream)
 }
 
+// Report a data byte, or the event it marks on a special stream ID:
+fn report_data<H>(
+    id: Option<u8>,
+    data: u8,
+    offset: usize,
+    options: &IdOptions,
+    handler: &mut H,
+) -> Result<()>
+where
+    H: FnMut(Result<Data>) -> Result<()>,
+{
+    match id {
+        Some(NULL_ID) if !options.null_data => Ok(()),
+        Some(TRIGGER_ID) if options.triggers => handler(Ok(Data {
+            id,
+            data,
+            offset,
+            event: Some(Event::Trigger(data)),
+        })),
+        _ => handler(Ok(Data {
+            id,
+            data,
+            offset,
+            event: None,
+        })),
+    }
+}
+
 pub fn decode_frame_offset<H>(
     frame: &[u8; 16],
     stream_id: Option<u8>,
+    options: &IdOptions,
     mut handler: H,
     offset: usize,
 ) -> Result<Option<u8>>
 where
     H: FnMut(Result<Data>) -> Result<()>,
 {
-    decode_frame(frame, stream_id, |mut r| {
+    decode_frame_with(frame, stream_id, options, |mut r| {
         match r {
             Err(ref mut e) => e.offset += offset,
             Ok(ref mut d) => d.offset += offset,
@@ -1,29 +1,98 @@
-use super::frame_parser::decode_frame_offset;
+use super::frame_parser::{decode_frame_with, IdOptions};
 use super::types::{Data, Error, ErrorReason::*, Result};
+use std::collections::BTreeMap;
 
+/// The width of the TPIU trace port the data was captured from.
+///
+/// Wider ports also emit syncs on 16 bit boundaries (a lone halfword sync can shift a full sync
+/// off a 32 bit one), so they are decoded as `Bits16`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortWidth {
+    Bits8,
+    Bits16,
+}
+
+impl PortWidth {
+    // The byte boundary on which synchronization packets start:
+    fn sync_alignment(self) -> usize {
+        match self {
+            PortWidth::Bits8 => 1,
+            PortWidth::Bits16 => 2,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct FrameDecoder {
     frame: [u8; 16],
+    offsets: [usize; 16], // File offset of each byte in 'frame'.
     frame_idx: usize,
     ff_count: usize,
     aligned: bool,
     stream_id: Option<u8>,
-    offset: usize,
+    initial: (bool, Option<u8>), // Alignment and stream ID to start over with.
+    offset: usize, // File offset of the next unprocessed (or first buffered 0xFF) byte.
+    options: IdOptions,
+    port_width: PortWidth,
+    halfword_sync: bool,
+    stats: FrameStats,
+}
+
+/// Frame and byte counts for a single stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StreamStats {
+    pub frames: usize, // Number of frames holding data for the stream.
+    pub bytes: usize,  // Number of data bytes reported for the stream.
+}
+
+/// Counts kept by a FrameDecoder.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrameStats {
+    pub frames: usize, // Number of complete frames decoded.
+    pub fsyncs: usize, // Number of FSYNCs seen.
+    pub hsyncs: usize, // Number of halfword syncs stripped.
+    pub streams: BTreeMap<Option<u8>, StreamStats>,
 }
 
 pub const FSYNC: [u8; 4] = [0xFF, 0xFF, 0xFF, 0x7F];
 
+/// Halfword synchronization packet, inserted by a TPIU running in continuous mode.
+pub const HSYNC: [u8; 2] = [0xFF, 0x7F];
+
 impl FrameDecoder {
     pub fn new(aligned: bool, stream_id: Option<u8>) -> FrameDecoder {
+        FrameDecoder::with_options(aligned, stream_id, IdOptions::default())
+    }
+
+    pub fn with_options(aligned: bool, stream_id: Option<u8>, options: IdOptions) -> FrameDecoder {
         FrameDecoder {
             frame: [0; 16],
+            offsets: [0; 16],
             frame_idx: 0,
             ff_count: 0,
             aligned,
             stream_id,
+            initial: (aligned, stream_id),
             offset: 0,
+            options,
+            port_width: PortWidth::Bits8,
+            halfword_sync: false,
+            stats: FrameStats::default(),
         }
     }
 
+    /// Set the width of the trace port the data was captured from.
+    pub fn port_width(mut self, port_width: PortWidth) -> FrameDecoder {
+        self.port_width = port_width;
+        self
+    }
+
+    /// Recognize and strip halfword syncs (TPIU continuous mode).
+    pub fn halfword_sync(mut self, enable: bool) -> FrameDecoder {
+        self.halfword_sync = enable;
+        self
+    }
+
     pub fn decode<H>(&mut self, data: &[u8], mut handler: H) -> Result<()>
     where
         H: FnMut(Result<Data>) -> Result<()>,
@@ -31,17 +100,26 @@ impl FrameDecoder {
         for d in data {
             if *d == 0xFF && self.ff_count < 3 {
                 self.ff_count += 1;
-            } else if *d == 0x7F && self.ff_count == 3 {
+            } else if *d == 0x7F && self.ff_count == 3 && self.is_sync_start() {
                 self.aligned = true;
                 if self.frame_idx > 0 {
                     handler(Err(Error {
-                        offset: self.offset,
+                        offset: self.offsets[0],
                         reason: PartialFrame(self.frame_idx),
                     }))?;
                 }
-                self.offset += 4 + self.frame_idx;
+                self.offset += FSYNC.len();
+                self.stats.fsyncs += 1;
                 self.frame_idx = 0;
                 self.ff_count = 0;
+            } else if *d == 0x7F && self.is_halfword_sync() {
+                // Any 0xFF bytes ahead of the sync belong to the frame:
+                for _ in 1..self.ff_count {
+                    self.process_byte(0xFF, &mut handler)?;
+                }
+                self.offset += HSYNC.len();
+                self.stats.hsyncs += 1;
+                self.ff_count = 0;
             } else if self.aligned {
                 if *d != 0xFF {
                     for _ in 0..self.ff_count {
@@ -50,8 +128,12 @@ impl FrameDecoder {
                     self.ff_count = 0;
                 }
                 self.process_byte(*d, &mut handler)?;
-            } else {
+            } else if *d == 0xFF {
+                // The oldest buffered 0xFF can no longer be part of an FSYNC:
                 self.offset += 1;
+            } else {
+                self.offset += self.ff_count + 1;
+                self.ff_count = 0;
             }
         }
         Ok(())
@@ -70,18 +152,92 @@ impl FrameDecoder {
         Ok(())
     }
 
+    /// Start over on a new capture from the same port, e.g. a new connection to a trace server.
+    ///
+    /// A partial frame is reported as a `PartialFrame` error, and the alignment and stream ID go
+    /// back to what the decoder was created with.  Offsets and stats carry on.
+    pub fn restart<H>(&mut self, mut handler: H) -> Result<()>
+    where
+        H: FnMut(Result<Data>) -> Result<()>,
+    {
+        let partial = self.frame_idx;
+        self.offset += self.ff_count;
+        self.frame_idx = 0;
+        self.ff_count = 0;
+        (self.aligned, self.stream_id) = self.initial;
+        if partial > 0 {
+            handler(Err(Error {
+                offset: self.offsets[0],
+                reason: PartialFrame(partial),
+            }))?;
+        }
+        Ok(())
+    }
+
+    /// Frame, sync and per stream counts gathered so far.
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    // Can a sync packet start at the first buffered 0xFF?
+    fn is_sync_start(&self) -> bool {
+        if self.aligned {
+            // Everything since the last FSYNC is a multiple of 16 bits:
+            self.port_width == PortWidth::Bits8 || self.frame_idx.is_multiple_of(2)
+        } else {
+            self.offset.is_multiple_of(self.port_width.sync_alignment())
+        }
+    }
+
+    // Would a 0x7F complete a halfword sync?  The 0xFF must sit on a 16 bit boundary.
+    fn is_halfword_sync(&self) -> bool {
+        self.halfword_sync
+            && self.aligned
+            && self.ff_count > 0
+            && (self.frame_idx + self.ff_count - 1).is_multiple_of(2)
+    }
+
     fn process_byte<H>(&mut self, byte: u8, mut handler: H) -> Result<()>
     where
         H: FnMut(Result<Data>) -> Result<()>,
     {
         self.frame[self.frame_idx] = byte;
+        self.offsets[self.frame_idx] = self.offset;
         self.frame_idx += 1;
+        self.offset += 1;
         if self.frame_idx == self.frame.len() {
-            let offset = self.offset;
-            self.offset += self.frame.len();
+            let offsets = self.offsets;
             self.frame_idx = 0;
-            self.stream_id =
-                decode_frame_offset(&self.frame, self.stream_id, &mut handler, offset)?;
+            self.stats.frames += 1;
+
+            // A frame holds at most 8 ID changes, so at most 9 runs of bytes with the same ID:
+            let mut runs = [(None, 0); 9];
+            let mut n = 0;
+            let r = decode_frame_with(&self.frame, self.stream_id, &self.options, |mut r| {
+                match r {
+                    Err(ref mut e) => e.offset = offsets[e.offset],
+                    Ok(ref mut d) if d.event.is_some() => d.offset = offsets[d.offset],
+                    Ok(ref mut d) => {
+                        d.offset = offsets[d.offset];
+                        if n > 0 && runs[n - 1].0 == d.id {
+                            runs[n - 1].1 += 1;
+                        } else if n < runs.len() {
+                            runs[n] = (d.id, 1);
+                            n += 1;
+                        }
+                    }
+                }
+                handler(r)
+            });
+
+            for (i, &(id, bytes)) in runs[..n].iter().enumerate() {
+                let stream = self.stats.streams.entry(id).or_default();
+                stream.bytes += bytes;
+                if runs[..i].iter().all(|&(other, _)| other != id) {
+                    stream.frames += 1;
+                }
+            }
+            self.stream_id = r?;
         }
         Ok(())
     }
@@ -1,4 +1,18 @@
 pub mod builder;
+pub mod circular;
 pub mod frame_parser;
 pub mod layer_parser;
+pub mod offset_map;
 pub mod types;
+
+pub mod builders {
+    pub use crate::builder::*;
+}
+
+pub mod parsers {
+    pub use crate::circular::*;
+    pub use crate::frame_parser::*;
+    pub use crate::layer_parser::*;
+    pub use crate::offset_map::*;
+    pub use crate::types::*;
+}
@@ -0,0 +1,130 @@
+//! Maps offsets within demultiplexed streams back to offsets within the framed input.
+
+use super::types::Data;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+// A stream's bytes are stored as runs: each entry records the stream offset at which a run of
+// bytes, contiguous in the input, starts and the input offset of its first byte.  A run ends where
+// the next one starts.  With a limit, runs that end before the last `limit` bytes are dropped.
+#[derive(Default)]
+struct Runs {
+    runs: VecDeque<(usize, usize)>,
+    len: usize,
+}
+
+impl Runs {
+    fn push(&mut self, offset: usize, limit: Option<usize>) {
+        match self.runs.back() {
+            Some(&(start, file_start)) if file_start + (self.len - start) == offset => {}
+            _ => {
+                self.runs.push_back((self.len, offset));
+                if let Some(limit) = limit {
+                    while self.runs.len() > 1 && self.runs[1].0 + limit <= self.len {
+                        self.runs.pop_front();
+                    }
+                }
+            }
+        }
+        self.len += 1;
+    }
+
+    fn find(&self, offset: usize) -> Option<usize> {
+        if offset >= self.len {
+            return None;
+        }
+        let i = self.runs.partition_point(|&(start, _)| start <= offset);
+        let (start, file_start) = self.runs[i.checked_sub(1)?];
+        Some(file_start + offset - start)
+    }
+}
+
+/// Records where each demultiplexed byte was found in the framed input.
+///
+/// Only one entry is kept per run of bytes that were contiguous in the input, which in practice
+/// means roughly one per frame per stream.  On endless inputs, `with_limit` bounds them.
+#[derive(Default)]
+pub struct OffsetMap {
+    streams: HashMap<Option<u8>, Runs>,
+    limit: Option<usize>,
+}
+
+impl OffsetMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A map that only needs the offsets of the last `len` bytes of each stream: older ones may
+    /// be forgotten.
+    pub fn with_limit(len: usize) -> Self {
+        OffsetMap {
+            limit: Some(len),
+            ..Self::default()
+        }
+    }
+
+    /// Record the next byte of a stream.  Events are not part of a stream, so are skipped.
+    pub fn record(&mut self, data: &Data) {
+        if data.event.is_some() {
+            return;
+        }
+        let limit = self.limit;
+        self.streams
+            .entry(data.id)
+            .or_default()
+            .push(data.offset, limit);
+    }
+
+    /// The number of bytes recorded for a stream.
+    pub fn stream_len(&self, id: Option<u8>) -> usize {
+        self.streams.get(&id).map_or(0, |r| r.len)
+    }
+
+    /// The stream IDs recorded so far.
+    pub fn ids(&self) -> impl Iterator<Item = Option<u8>> + '_ {
+        self.streams.keys().copied()
+    }
+
+    /// The runs of a stream's bytes that were contiguous in the input, as (stream offset, input
+    /// byte range) pairs.  Runs forgotten because of a limit are left out.
+    pub fn runs(&self, id: Option<u8>) -> Vec<(usize, Range<usize>)> {
+        let runs = match self.streams.get(&id) {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+        let ends = runs.runs.iter().skip(1).map(|&(start, _)| start);
+        runs.runs
+            .iter()
+            .zip(ends.chain(std::iter::once(runs.len)))
+            .map(|(&(start, file_start), end)| (start, file_start..file_start + end - start))
+            .collect()
+    }
+
+    /// The input offset of the byte at `offset` within the stream, if it is still known.
+    pub fn file_offset(&self, id: Option<u8>, offset: usize) -> Option<usize> {
+        self.streams.get(&id)?.find(offset)
+    }
+
+    /// The input byte ranges holding `len` bytes of the stream, starting at `offset`.
+    ///
+    /// Bytes that have not been recorded, or have been forgotten, are omitted.
+    pub fn file_ranges(&self, id: Option<u8>, offset: usize, len: usize) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let runs = match self.streams.get(&id) {
+            Some(r) => r,
+            None => return ranges,
+        };
+
+        for o in offset..(offset + len).min(runs.len) {
+            let file_offset = match runs.find(o) {
+                Some(o) => o,
+                None => continue,
+            };
+            match ranges.last_mut() {
+                Some(r) if r.end == file_offset => r.end += 1,
+                _ => ranges.push(file_offset..file_offset + 1),
+            }
+        }
+        ranges
+    }
+}
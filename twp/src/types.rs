@@ -8,17 +8,32 @@ pub enum ErrorReason {
     InvalidStreamId(u8),
     InvalidAuxByte(u8),
     PartialFrame(usize),
+    Discontinuity,
     Stop,
 }
 
 use self::ErrorReason::*;
 
+impl ErrorReason {
+    /// The name of the error, without its details.
+    pub fn name(&self) -> &'static str {
+        match self {
+            InvalidStreamId(_) => "InvalidStreamId",
+            InvalidAuxByte(_) => "InvalidAuxByte",
+            PartialFrame(_) => "PartialFrame",
+            Discontinuity => "Discontinuity",
+            Stop => "Stop",
+        }
+    }
+}
+
 impl fmt::Display for ErrorReason {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             InvalidStreamId(id) => write!(f, "invalid stream id: {:#x}", id),
             InvalidAuxByte(byte) => write!(f, "invalid aux byte: {:#x}", byte),
             PartialFrame(size) => write!(f, "truncated frame: {} bytes", size),
+            Discontinuity => write!(f, "discontinuity: earlier data was overwritten"),
             Stop => write!(f, "stopped"),
         }
     }
@@ -38,8 +53,35 @@ impl fmt::Display for Error {
 
 pub type Result<S> = result::Result<S, Error>;
 
+/// Something marked by a special stream ID, reported in place of stream data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Trigger(u8),          // A byte written to the trigger stream.
+    ReservedStreamId(u8), // A reserved stream ID was selected.
+}
+
+impl Event {
+    /// The name of the event, without its details.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Trigger(_) => "Trigger",
+            Event::ReservedStreamId(_) => "ReservedStreamId",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Trigger(byte) => write!(f, "trigger: {:#x}", byte),
+            Event::ReservedStreamId(id) => write!(f, "reserved stream id: {:#x}", id),
+        }
+    }
+}
+
 pub struct Data {
     pub id: Option<u8>,
     pub data: u8,
     pub offset: usize,
+    pub event: Option<Event>, // Set for events, which are not part of the stream.
 }
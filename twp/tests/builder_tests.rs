@@ -191,3 +191,15 @@ fn fancy_span() {
 
     assert_eq!(frames, exp);
 }
+
+#[test]
+fn syncs() {
+    let mut frames = vec![0; 16];
+    assert_eq!(insert_fsync(&mut frames, 16), Err(InvalidOffset(16)));
+    assert_eq!(insert_hsync(&mut frames, 2), Ok(()));
+    assert_eq!(insert_fsync(&mut frames, 0), Ok(()));
+
+    let mut exp = vec![0xFF, 0xFF, 0xFF, 0x7F, 0, 0, 0xFF, 0x7F];
+    exp.extend_from_slice(&[0; 14]);
+    assert_eq!(frames, exp);
+}
@@ -1,10 +1,14 @@
 use std::collections::HashMap;
 use std::result;
 use twp::builders::*;
-use twp::parsers::{decode_frames, Data, Error, ErrorReason::*, FrameDecoder, FSYNC};
+use twp::parsers::{
+    decode_frames, decode_frames_with, CircularBuffer, Data, Error, ErrorReason::*, Event,
+    FrameDecoder, IdOptions, OffsetMap, PortWidth, StreamStats, FSYNC, HSYNC,
+};
 
 struct Recorder {
     data: HashMap<Option<u8>, Vec<u8>>,
+    events: Vec<(usize, Event)>,
     errors: Option<Vec<Error>>,
 }
 
@@ -12,6 +16,7 @@ impl Recorder {
     fn new(continue_on_error: bool) -> Recorder {
         Recorder {
             data: HashMap::new(),
+            events: Vec::new(),
             errors: if continue_on_error {
                 Some(Vec::new())
             } else {
@@ -22,6 +27,14 @@ impl Recorder {
 
     fn record(&mut self, r: result::Result<Data, Error>) -> result::Result<(), Error> {
         match r {
+            Ok(Data {
+                event: Some(e),
+                offset,
+                ..
+            }) => {
+                self.events.push((offset, e));
+                Ok(())
+            }
             Ok(d) => {
                 self.data
                     .entry(d.id)
@@ -285,7 +298,7 @@ fn unsynced_frames() {
     assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
     assert_eq!(decoder.finish(|d| recorder.record(d)), Ok(()));
 
-    assert_eq!(recorder.data.is_empty(), true);
+    assert!(recorder.data.is_empty());
 }
 
 // An FSYNC followed by two frames worth of data.
@@ -479,6 +492,496 @@ fn aux_ff() {
     assert_eq!(recorder.offsets, exp_offsets);
 }
 
+// Data written to the null stream is dropped by default:
+#[test]
+fn null_id_dropped() {
+    let frames = FrameBuilder::new(1)
+        .id(1)
+        .data_span(2, 1)
+        .id(0)
+        .data_span(4, 0xAA)
+        .id(2)
+        .data_span(6, 2)
+        .build();
+    let mut recorder = Recorder::new(false);
+
+    assert_eq!(
+        decode_frames(&frames, None, |d| recorder.record(d)),
+        Ok(Some(2))
+    );
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 2]);
+    exp.insert(Some(2), vec![2; 6]);
+
+    assert_eq!(recorder.data, exp);
+}
+
+// Data written to the null stream can be passed through:
+#[test]
+fn null_id_kept() {
+    let frames = FrameBuilder::new(1)
+        .id(1)
+        .data_span(2, 1)
+        .id(0)
+        .data_span(4, 0xAA)
+        .id(2)
+        .data_span(6, 2)
+        .build();
+    let mut recorder = Recorder::new(false);
+    let options = IdOptions {
+        null_data: true,
+        ..IdOptions::default()
+    };
+
+    assert_eq!(
+        decode_frames_with(&frames, None, &options, |d| recorder.record(d)),
+        Ok(Some(2))
+    );
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 2]);
+    exp.insert(Some(0), vec![0xAA; 4]);
+    exp.insert(Some(2), vec![2; 6]);
+
+    assert_eq!(recorder.data, exp);
+}
+
+// A trigger is reported along with its offset:
+#[test]
+fn trigger() {
+    let frames = FrameBuilder::new(1)
+        .id(1)
+        .data_span(3, 1)
+        .id(0x7D)
+        .data(0)
+        .id(1)
+        .data_span(8, 1)
+        .build();
+    let mut recorder = OffsetRecorder::new(true);
+
+    assert_eq!(
+        decode_frames(&frames, None, |d| recorder.record(d)),
+        Ok(Some(1))
+    );
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 11]);
+    assert_eq!(recorder.r.data, exp);
+    assert_eq!(recorder.r.events, vec![(5, Event::Trigger(0))]);
+    assert_eq!(recorder.r.errors.unwrap(), vec![]);
+}
+
+// Triggers can be treated as ordinary data:
+#[test]
+fn trigger_as_data() {
+    let frames = FrameBuilder::new(1).id(0x7D).data_span(14, 0).build();
+    let mut recorder = Recorder::new(false);
+    let options = IdOptions {
+        triggers: false,
+        ..IdOptions::default()
+    };
+
+    assert_eq!(
+        decode_frames_with(&frames, None, &options, |d| recorder.record(d)),
+        Ok(Some(0x7D))
+    );
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(0x7D), vec![0; 14]);
+    assert_eq!(recorder.data, exp);
+}
+
+// Selecting a reserved ID produces an event, but the data is still reported:
+#[test]
+fn reserved_id() {
+    let frames = FrameBuilder::new(1).id(0x70).data_span(14, 3).build();
+    let mut recorder = Recorder::new(true);
+
+    assert_eq!(
+        decode_frames(&frames, None, |d| recorder.record(d)),
+        Ok(Some(0x70))
+    );
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(0x70), vec![3; 14]);
+    assert_eq!(recorder.data, exp);
+    assert_eq!(recorder.events, vec![(0, Event::ReservedStreamId(0x70))]);
+    assert_eq!(recorder.errors.unwrap(), vec![]);
+}
+
+// Reserved ID events can be disabled:
+#[test]
+fn reserved_id_quiet() {
+    let frames = FrameBuilder::new(1).id(0x7C).data_span(14, 3).build();
+    let mut decoder = FrameDecoder::with_options(
+        true,
+        None,
+        IdOptions {
+            reserved_warnings: false,
+            ..IdOptions::default()
+        },
+    );
+    let mut recorder = Recorder::new(false);
+
+    assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
+    assert_eq!(decoder.finish(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(0x7C), vec![3; 14]);
+    assert_eq!(recorder.data, exp);
+    assert_eq!(recorder.events, vec![]);
+}
+
+// Halfword syncs between frames are stripped:
+#[test]
+fn halfword_sync_between_frames() {
+    let mut decoder = FrameDecoder::new(false, None).halfword_sync(true);
+    let mut recorder = OffsetRecorder::new(false);
+    let mut frames = FSYNC.to_vec();
+    frames.extend(FrameBuilder::new(1).id(1).data_span(14, 1).build());
+    frames.extend_from_slice(&HSYNC);
+    frames.extend_from_slice(&HSYNC);
+    frames.extend(FrameBuilder::new(1).id(2).data_span(14, 2).build());
+
+    assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
+    assert_eq!(decoder.finish(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 14]);
+    exp.insert(Some(2), vec![2; 14]);
+    assert_eq!(recorder.r.data, exp);
+
+    let mut exp_offsets = HashMap::new();
+    exp_offsets.insert(Some(1), (5..5 + 14).collect());
+    exp_offsets.insert(Some(2), (25..25 + 14).collect());
+    assert_eq!(recorder.offsets, exp_offsets);
+
+    let stats = decoder.stats();
+    assert_eq!((stats.frames, stats.fsyncs, stats.hsyncs), (2, 1, 2));
+    let stream = StreamStats {
+        frames: 1,
+        bytes: 14,
+    };
+    assert_eq!(stats.streams.get(&Some(1)), Some(&stream));
+    assert_eq!(stats.streams.get(&Some(2)), Some(&stream));
+}
+
+// A halfword sync in the middle of a frame is stripped and the offsets skip over it:
+#[test]
+fn halfword_sync_mid_frame() {
+    let mut decoder = FrameDecoder::new(true, None).halfword_sync(true);
+    let mut recorder = OffsetRecorder::new(false);
+    let mut frames = FrameBuilder::new(1).id(1).data_span(14, 1).build();
+    insert_hsync(&mut frames, 6).unwrap();
+
+    assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
+    assert_eq!(decoder.finish(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 14]);
+    assert_eq!(recorder.r.data, exp);
+
+    let mut exp_offsets = HashMap::new();
+    exp_offsets.insert(Some(1), (1..6).chain(8..17).collect());
+    assert_eq!(recorder.offsets, exp_offsets);
+}
+
+// A halfword sync following an AUX byte of 0xFF:
+#[test]
+fn halfword_sync_after_aux_ff() {
+    let mut decoder = FrameDecoder::new(true, Some(8)).halfword_sync(true);
+    let mut recorder = OffsetRecorder::new(false);
+    let mut frames = FrameBuilder::new(2).data(2).id(2).data_span(13, 1).build();
+    frames.extend_from_slice(&HSYNC);
+    frames.extend(FrameBuilder::new(1).id(4).data_span(14, 4).build());
+
+    assert_eq!(frames[15], 0xFF);
+
+    assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
+    assert_eq!(decoder.finish(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(8), vec![2]);
+    exp.insert(Some(2), vec![1; 13]);
+    exp.insert(Some(4), vec![4; 14]);
+    assert_eq!(recorder.r.data, exp);
+
+    let mut exp_offsets = HashMap::new();
+    exp_offsets.insert(Some(8), (1..2).collect());
+    exp_offsets.insert(Some(2), (2..15).collect());
+    exp_offsets.insert(Some(4), (19..19 + 14).collect());
+    assert_eq!(recorder.offsets, exp_offsets);
+}
+
+// Without halfword sync support the sync bytes are treated as frame data:
+#[test]
+fn halfword_sync_disabled() {
+    let mut decoder = FrameDecoder::new(true, None);
+    let mut recorder = Recorder::new(true);
+    let mut frames = FrameBuilder::new(1).id(1).data_span(14, 1).build();
+    insert_hsync(&mut frames, 6).unwrap();
+
+    assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
+    assert_eq!(decoder.finish(|d| recorder.record(d)), Ok(()));
+
+    assert_ne!(recorder.data.get(&Some(1)), Some(&vec![1; 14]));
+}
+
+// On a 16 bit port an FSYNC must start on a 16 bit boundary:
+#[test]
+fn port_width_alignment() {
+    let mut frames = vec![0];
+    frames.extend_from_slice(&FSYNC);
+    frames.extend(FrameBuilder::new(1).id(1).data_span(14, 1).build());
+
+    let mut decoder = FrameDecoder::new(false, None);
+    let mut recorder = Recorder::new(false);
+    assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
+    assert_eq!(recorder.data.get(&Some(1)), Some(&vec![1; 14]));
+
+    let mut decoder = FrameDecoder::new(false, None).port_width(PortWidth::Bits16);
+    let mut recorder = Recorder::new(false);
+    assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
+    assert!(recorder.data.is_empty());
+
+    let mut decoder = FrameDecoder::new(false, None).port_width(PortWidth::Bits16);
+    let mut recorder = OffsetRecorder::new(false);
+    assert_eq!(decoder.decode(&frames[1..], |d| recorder.record(d)), Ok(()));
+    let mut exp_offsets = HashMap::new();
+    exp_offsets.insert(Some(1), (5..5 + 14).collect());
+    assert_eq!(recorder.offsets, exp_offsets);
+}
+
+fn three_frames() -> Vec<u8> {
+    FrameBuilder::new(3)
+        .id(1)
+        .data_span(14, 1)
+        .id(2)
+        .data_span(14, 2)
+        .id(3)
+        .data_span(14, 3)
+        .build()
+}
+
+// A wrapped buffer whose write pointer sits on a frame boundary:
+#[test]
+fn circular_wrapped() {
+    let frames = three_frames();
+    let mut buffer = frames[32..].to_vec();
+    buffer.extend_from_slice(&frames[..32]);
+    let mut recorder = OffsetRecorder::new(true);
+
+    let circular = CircularBuffer::new(&buffer, Some(16), true);
+    assert_eq!(circular.decode(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 14]);
+    exp.insert(Some(2), vec![2; 14]);
+    exp.insert(Some(3), vec![3; 14]);
+    assert_eq!(recorder.r.data, exp);
+
+    let errors = vec![Error {
+        offset: 16,
+        reason: Discontinuity,
+    }];
+    assert_eq!(recorder.r.errors.unwrap(), errors);
+
+    let mut exp_offsets = HashMap::new();
+    exp_offsets.insert(Some(1), (17..31).collect());
+    exp_offsets.insert(Some(2), (33..47).collect());
+    exp_offsets.insert(Some(3), (1..15).collect());
+    assert_eq!(recorder.offsets, exp_offsets);
+}
+
+// A wrapped buffer whose write pointer is part way through a frame:
+#[test]
+fn circular_wrapped_partial() {
+    let frames = three_frames();
+    let mut recorder = OffsetRecorder::new(true);
+
+    let circular = CircularBuffer::new(&frames, Some(20), true);
+    assert_eq!(circular.decode(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 14]);
+    exp.insert(Some(3), vec![3; 14]);
+    assert_eq!(recorder.r.data, exp);
+
+    let errors = vec![
+        Error {
+            offset: 20,
+            reason: Discontinuity,
+        },
+        Error {
+            offset: 20,
+            reason: PartialFrame(12),
+        },
+    ];
+    assert_eq!(recorder.r.errors.unwrap(), errors);
+
+    let mut exp_offsets = HashMap::new();
+    exp_offsets.insert(Some(1), (1..15).collect());
+    exp_offsets.insert(Some(3), (33..47).collect());
+    assert_eq!(recorder.offsets, exp_offsets);
+}
+
+// A buffer that never wrapped: only the data below the write pointer is valid.
+#[test]
+fn circular_unwrapped() {
+    let frames = three_frames();
+    let mut recorder = Recorder::new(false);
+
+    let circular = CircularBuffer::new(&frames, Some(32), false);
+    assert_eq!(circular.len(), 32);
+    assert_eq!(circular.decode(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 14]);
+    exp.insert(Some(2), vec![2; 14]);
+    assert_eq!(recorder.data, exp);
+}
+
+// A buffer filled right up to its end without wrapping:
+#[test]
+fn circular_unwrapped_full() {
+    let frames = three_frames();
+    let mut recorder = Recorder::new(false);
+
+    let circular = CircularBuffer::new(&frames, Some(frames.len()), false);
+    assert_eq!(circular.len(), frames.len());
+    assert_eq!(circular.start(), 0);
+    assert_eq!(circular.decode(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(1), vec![1; 14]);
+    exp.insert(Some(2), vec![2; 14]);
+    exp.insert(Some(3), vec![3; 14]);
+    assert_eq!(recorder.data, exp);
+
+    // Write pointers past the end are limited to it:
+    let circular = CircularBuffer::new(&frames, Some(frames.len() + 16), false);
+    assert_eq!(circular.len(), frames.len());
+}
+
+// A wrapped buffer without a write pointer waits for an FSYNC:
+#[test]
+fn circular_wrapped_no_pointer() {
+    let frames = three_frames();
+    let mut buffer = frames[8..16].to_vec();
+    buffer.extend_from_slice(&FSYNC);
+    buffer.extend_from_slice(&frames[16..]);
+    let mut recorder = OffsetRecorder::new(true);
+
+    let circular = CircularBuffer::new(&buffer, None, true);
+    assert_eq!(circular.decode(|d| recorder.record(d)), Ok(()));
+
+    let mut exp = HashMap::new();
+    exp.insert(Some(2), vec![2; 14]);
+    exp.insert(Some(3), vec![3; 14]);
+    assert_eq!(recorder.r.data, exp);
+
+    let errors = vec![Error {
+        offset: 0,
+        reason: Discontinuity,
+    }];
+    assert_eq!(recorder.r.errors.unwrap(), errors);
+
+    let mut exp_offsets = HashMap::new();
+    exp_offsets.insert(Some(2), (13..27).collect());
+    exp_offsets.insert(Some(3), (29..43).collect());
+    assert_eq!(recorder.offsets, exp_offsets);
+}
+
+// Stream offsets map back to file offsets, skipping syncs, IDs and AUX bytes:
+#[test]
+fn offset_map() {
+    let mut decoder = FrameDecoder::new(false, None).halfword_sync(true);
+    let mut recorder = OffsetRecorder::new(false);
+    let mut map = OffsetMap::new();
+    let mut frames = FSYNC.to_vec();
+    frames.extend(
+        FrameBuilder::new(2)
+            .id(1)
+            .data_span(4, 1)
+            .id(2)
+            .data_span(10, 2)
+            .id(1)
+            .data_span(14, 1)
+            .build(),
+    );
+    insert_hsync(&mut frames, 4 + 16 + 4).unwrap();
+
+    assert_eq!(
+        decoder.decode(&frames, |d| {
+            map.record(d.as_ref().unwrap());
+            recorder.record(d)
+        }),
+        Ok(())
+    );
+
+    for (id, offsets) in &recorder.offsets {
+        assert_eq!(map.stream_len(*id), offsets.len());
+        for (i, offset) in offsets.iter().enumerate() {
+            assert_eq!(map.file_offset(*id, i), Some(*offset));
+        }
+        assert_eq!(map.file_offset(*id, offsets.len()), None);
+    }
+    assert_eq!(map.stream_len(Some(3)), 0);
+    assert_eq!(map.file_offset(Some(3), 0), None);
+
+    // The second frame starts at 20, with a halfword sync at 24:
+    assert_eq!(
+        &recorder.offsets[&Some(1)][..8],
+        &[5, 6, 7, 9, 22, 23, 26, 27]
+    );
+    assert_eq!(map.file_ranges(Some(1), 2, 4), vec![7..8, 9..10, 22..24]);
+    assert_eq!(map.file_ranges(Some(1), 30, 8), vec![51..53]);
+    assert_eq!(map.file_ranges(Some(4), 0, 8), vec![]);
+
+    // Runs cover the stream's bytes in order:
+    let mut ids: Vec<Option<u8>> = map.ids().collect();
+    ids.sort();
+    assert_eq!(ids, vec![Some(1), Some(2)]);
+    for (id, offsets) in &recorder.offsets {
+        let mut stream_offset = 0;
+        let mut from_runs = Vec::new();
+        for (start, range) in map.runs(*id) {
+            assert_eq!(start, stream_offset);
+            stream_offset += range.len();
+            from_runs.extend(range);
+        }
+        assert_eq!(&from_runs, offsets);
+    }
+    assert_eq!(&map.runs(Some(1))[..2], &[(0, 5..8), (3, 9..10)]);
+    assert_eq!(map.runs(Some(4)), vec![]);
+}
+
+// With a limit, only the offsets of a stream's last bytes are kept:
+#[test]
+fn offset_map_limit() {
+    let mut map = OffsetMap::with_limit(10);
+    for i in 0..100 {
+        // Every byte is a run of its own:
+        map.record(&Data {
+            id: Some(1),
+            data: 0,
+            offset: i * 2,
+            event: None,
+        });
+    }
+    assert_eq!(map.stream_len(Some(1)), 100);
+    for i in 90..100 {
+        assert_eq!(map.file_offset(Some(1), i), Some(i * 2));
+    }
+    assert_eq!(map.file_offset(Some(1), 50), None);
+    assert!(map.runs(Some(1)).len() <= 11);
+    assert_eq!(
+        map.file_ranges(Some(1), 0, 92),
+        vec![178..179, 180..181, 182..183]
+    );
+}
+
 struct OffsetRecorder {
     r: Recorder,
     offsets: HashMap<Option<u8>, Vec<usize>>,
@@ -493,7 +996,7 @@ impl OffsetRecorder {
     }
 
     fn record(&mut self, r: result::Result<Data, Error>) -> result::Result<(), Error> {
-        if let Ok(ref d) = r {
+        if let Ok(d @ Data { event: None, .. }) = &r {
             self.offsets
                 .entry(d.id)
                 .and_modify(|v| v.push(d.offset))
@@ -502,3 +1005,27 @@ impl OffsetRecorder {
         self.r.record(r)
     }
 }
+
+// A framed stream decodes back to the original bytes, with an FSYNC per group of frames:
+#[test]
+fn frame_stream_round_trip() {
+    let data: Vec<u8> = (0..100).map(|i| (i * 7) as u8).collect();
+
+    for (interval, fsyncs) in [(0, 1), (1, 8), (2, 4)] {
+        let frames = frame_stream(&data, 5, interval).unwrap();
+        assert_eq!(frames.len() % 4, 0);
+
+        let mut decoder = FrameDecoder::new(false, None);
+        let mut recorder = Recorder::new(false);
+        assert_eq!(decoder.decode(&frames, |d| recorder.record(d)), Ok(()));
+        assert_eq!(decoder.finish(|d| recorder.record(d)), Ok(()));
+
+        let mut exp = HashMap::new();
+        exp.insert(Some(5), data.clone());
+        assert_eq!(recorder.data, exp);
+        assert_eq!(decoder.stats().fsyncs, fsyncs);
+    }
+
+    assert_eq!(frame_stream(&[], 5, 0), Ok(vec![]));
+    assert!(frame_stream(&data, 0x7F, 0).is_err());
+}